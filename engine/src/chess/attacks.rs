@@ -0,0 +1,88 @@
+// Precomputed attack tables. Leaper attacks (knights, kings and pawns) are looked up directly, sliding
+// attacks are walked ray by ray against an occupation bitboard.
+
+const KNIGHT_OFFSETS: [(i32, i32); 8] = [(-2, -1), (-2, 1), (-1, -2), (-1, 2), (1, -2), (1, 2), (2, -1), (2, 1)];
+const KING_OFFSETS: [(i32, i32); 8] = [(-1, -1), (-1, 0), (-1, 1), (0, -1), (0, 1), (1, -1), (1, 0), (1, 1)];
+const WHITE_PAWN_OFFSETS: [(i32, i32); 2] = [(-1, 1), (1, 1)];
+const BLACK_PAWN_OFFSETS: [(i32, i32); 2] = [(-1, -1), (1, -1)];
+
+pub const BISHOP_RAYS: [(i32, i32); 4] = [(-1, -1), (1, -1), (-1, 1), (1, 1)];
+pub const ROOK_RAYS: [(i32, i32); 4] = [(0, -1), (-1, 0), (1, 0), (0, 1)];
+
+pub const KNIGHT_ATTACKS: [i64; 64] = generate_leaper_attacks(&KNIGHT_OFFSETS);
+pub const KING_ATTACKS: [i64; 64] = generate_leaper_attacks(&KING_OFFSETS);
+/// Indexed by color, then square. `PAWN_ATTACKS[0][e4]` holds the squares a white pawn on e4 attacks.
+pub const PAWN_ATTACKS: [[i64; 64]; 2] = [generate_leaper_attacks(&WHITE_PAWN_OFFSETS), generate_leaper_attacks(&BLACK_PAWN_OFFSETS)];
+
+const fn generate_leaper_attacks(offsets: &[(i32, i32)]) -> [i64; 64] {
+    let mut table = [0i64; 64];
+
+    let mut index = 0;
+    while index < 64 {
+        let file = index % 8;
+        let rank = index / 8;
+
+        let mut offset_index = 0;
+        while offset_index < offsets.len() {
+            let target_file = file + offsets[offset_index].0;
+            let target_rank = rank + offsets[offset_index].1;
+
+            if target_file >= 0 && target_file < 8 && target_rank >= 0 && target_rank < 8 {
+                table[index as usize] |= 1 << (target_rank * 8 + target_file);
+            }
+
+            offset_index += 1;
+        }
+
+        index += 1;
+    }
+
+    table
+}
+
+/// Walks every ray from `index`, stopping at (and including) the first occupied square.
+pub fn sliding_attacks(index: i32, occupation: i64, rays: &[(i32, i32)]) -> i64 {
+    let mut attacks = 0;
+
+    for (file_step, rank_step) in rays {
+        let mut file = index % 8 + file_step;
+        let mut rank = index / 8 + rank_step;
+
+        while (0..8).contains(&file) && (0..8).contains(&rank) {
+            let target = rank * 8 + file;
+            attacks |= 1 << target;
+
+            if occupation & (1 << target) != 0 {
+                break;
+            }
+
+            file += file_step;
+            rank += rank_step;
+        }
+    }
+
+    attacks
+}
+
+#[inline]
+pub fn bishop_attacks(index: i32, occupation: i64) -> i64 {
+    sliding_attacks(index, occupation, &BISHOP_RAYS)
+}
+
+#[inline]
+pub fn rook_attacks(index: i32, occupation: i64) -> i64 {
+    sliding_attacks(index, occupation, &ROOK_RAYS)
+}
+
+#[inline]
+pub fn queen_attacks(index: i32, occupation: i64) -> i64 {
+    bishop_attacks(index, occupation) | rook_attacks(index, occupation)
+}
+
+/// Removes the least significant set bit from the bitboard and returns its index.
+#[inline]
+pub fn pop_lsb(bitboard: &mut i64) -> i32 {
+    let index = bitboard.trailing_zeros() as i32;
+    *bitboard &= bitboard.wrapping_sub(1);
+    index
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use super::datagen;
+use super::dtm;
+use super::eco;
+use super::explorer;
+use super::polyglot;
+use super::tune;
+
+/// Runs a command given on the command line, like `datagen --games 100`. Without a command the engine speaks UCI.
+pub fn run(args: &[String]) -> Result<(), String> {
+    let Some(command) = args.first() else {
+        return Err("No command given.".to_string());
+    };
+
+    match command.as_str() {
+        "datagen" => datagen::run(&Flags::parse(&args[1..])?),
+        "tune" => tune::run(&Flags::parse(&args[1..])?),
+        "tbgen" => dtm::run(&Flags::parse(&args[1..])?),
+        "book" => polyglot::run(&Flags::parse(&args[1..])?),
+        "makebook" => polyglot::run_build(&Flags::parse(&args[1..])?),
+        "index" => explorer::run_index(&Flags::parse(&args[1..])?),
+        "explore" => explorer::run_explore(&Flags::parse(&args[1..])?),
+        "eco" => eco::run(&Flags::parse(&args[1..])?),
+        _ => Err(format!("Unknown command {}. Commands are: datagen, tune, tbgen, book, makebook, index, explore, eco.", command)),
+    }
+}
+
+/// The `--name value` flags of a command, and the arguments that aren't flags.
+#[derive(Debug, Default)]
+pub struct Flags {
+    values: HashMap<String, String>,
+    pub positional: Vec<String>,
+}
+
+impl Flags {
+    pub fn parse(args: &[String]) -> Result<Flags, String> {
+        let mut flags = Flags::default();
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(name) => {
+                    let value = args.next().ok_or_else(|| format!("--{} needs a value.", name))?;
+                    flags.values.insert(name.to_string(), value.clone());
+                }
+                None => flags.positional.push(arg.clone()),
+            }
+        }
+
+        Ok(flags)
+    }
+
+    /// The value of a flag, or `default` when it wasn't given.
+    pub fn get<T: FromStr>(&self, name: &str, default: T) -> Result<T, String> {
+        match self.values.get(name) {
+            Some(value) => value.parse().map_err(|_| format!("Invalid value {} for --{}.", value, name)),
+            None => Ok(default),
+        }
+    }
+
+    /// The value of a flag that has no sensible default.
+    pub fn require(&self, name: &str) -> Result<String, String> {
+        self.values.get(name).cloned().ok_or_else(|| format!("--{} is required.", name))
+    }
+}
+
+// Unit Tests! //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags() {
+        let args: Vec<String> = ["--games", "20", "input.txt", "--output", "out.bin"].iter().map(|arg| arg.to_string()).collect();
+        let flags = Flags::parse(&args).expect("The flags should parse.");
+
+        assert_eq!(flags.get("games", 1), Ok(20));
+        assert_eq!(flags.get("nodes", 5000), Ok(5000), "A missing flag should give the default.");
+        assert_eq!(flags.require("output"), Ok("out.bin".to_string()));
+        assert!(flags.require("seed").is_err());
+        assert_eq!(flags.positional, vec!["input.txt".to_string()]);
+
+        assert!(flags.get::<u64>("output", 0).is_err(), "A file name isn't a number.");
+        assert!(Flags::parse(&["--games".to_string()]).is_err(), "A flag without a value should be rejected.");
+    }
+}
@@ -0,0 +1,476 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
+
+use super::attacks;
+use super::cli::Flags;
+use super::fen;
+use super::game::Game;
+use super::moves::{self, PromotionOption};
+use super::ordering;
+use super::piece::{self, Color};
+use super::position::{self, Position};
+use super::search::{self, SearchLimits, Searcher, MATE_BOUND};
+use super::zobrist;
+
+/// Openings the search already thinks are this lopsided are thrown away, they'd only teach that won positions win.
+const MAX_OPENING_SCORE: i32 = 400;
+const MAX_OPENING_ATTEMPTS: usize = 100;
+/// A game is adjudicated once both sides agree it's this far gone for `WIN_ADJUDICATION_PLIES` plies in a row.
+const WIN_ADJUDICATION_SCORE: i32 = 2000;
+const WIN_ADJUDICATION_PLIES: usize = 4;
+/// Games still going after this many plies are called a draw.
+const MAX_GAME_PLIES: usize = 400;
+
+/// The size of a sample in the binary format.
+pub const RECORD_SIZE: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DataFormat {
+    Text,
+    Binary,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameResult {
+    BlackWin,
+    Draw,
+    WhiteWin,
+}
+
+impl GameResult {
+    /// 1 for a White win, 0.5 for a draw and 0 for a Black win.
+    pub fn white_score(&self) -> f64 {
+        match self {
+            GameResult::BlackWin => 0.0,
+            GameResult::Draw => 0.5,
+            GameResult::WhiteWin => 1.0,
+        }
+    }
+}
+
+/// A position from a game, with what the search thought of it and how the game ended.
+#[derive(Clone)]
+pub struct Sample {
+    pub position: Position,
+    /// Centipawns from White's point of view.
+    pub score: i32,
+    pub result: GameResult,
+}
+
+#[derive(Clone, Debug)]
+pub struct DatagenOptions {
+    pub games: usize,
+    /// The node limit of every search.
+    pub nodes: u64,
+    pub threads: usize,
+    /// Every game's openings are picked from this and the game's number, so a run can be repeated exactly with
+    /// any number of threads.
+    pub seed: u64,
+    /// Random moves played from the start position before the engine takes over.
+    pub random_plies: usize,
+    pub hash_mb: usize,
+    pub format: DataFormat,
+}
+
+impl Default for DatagenOptions {
+    fn default() -> Self {
+        DatagenOptions {
+            games: 100,
+            nodes: 5000,
+            threads: 1,
+            seed: 0,
+            random_plies: 8,
+            hash_mb: 16,
+            format: DataFormat::Text,
+        }
+    }
+}
+
+/// `datagen --output <file> [--games N] [--nodes N] [--threads N] [--seed N] [--random-plies N] [--hash MB]
+/// [--format text|binary]`
+pub fn run(flags: &Flags) -> Result<(), String> {
+    let defaults = DatagenOptions::default();
+    let format = match flags.get("format", "text".to_string())?.as_str() {
+        "text" => DataFormat::Text,
+        "binary" => DataFormat::Binary,
+        other => return Err(format!("Unknown format {}, it should be text or binary.", other)),
+    };
+    let options = DatagenOptions {
+        games: flags.get("games", defaults.games)?,
+        nodes: flags.get("nodes", defaults.nodes)?,
+        threads: flags.get("threads", defaults.threads)?.max(1),
+        seed: flags.get("seed", defaults.seed)?,
+        random_plies: flags.get("random-plies", defaults.random_plies)?,
+        hash_mb: flags.get("hash", defaults.hash_mb)?.max(1),
+        format,
+    };
+
+    let path = flags.require("output")?;
+    let file = File::create(&path).map_err(|error| format!("Couldn't create {}: {}.", path, error))?;
+    let mut writer = BufWriter::new(file);
+
+    let start = Instant::now();
+    let samples = generate(&options, &mut writer, |games_done, samples| {
+        if games_done % 10 == 0 || games_done == options.games {
+            eprintln!("{}/{} games, {} positions, {:.0}s", games_done, options.games, samples, start.elapsed().as_secs_f64());
+        }
+    })
+    .map_err(|error| format!("Couldn't write to {}: {}.", path, error))?;
+
+    eprintln!("Wrote {} positions to {}.", samples, path);
+    Ok(())
+}
+
+/// Plays the games on `options.threads` threads and writes their samples in the order of the games, whichever
+/// thread finishes first. Returns the number of samples written.
+pub fn generate(options: &DatagenOptions, writer: &mut impl Write, mut progress: impl FnMut(usize, usize)) -> io::Result<usize> {
+    let next_game = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel::<(usize, Vec<Sample>)>();
+
+    thread::scope(|scope| {
+        for _ in 0..options.threads {
+            let sender = sender.clone();
+            let next_game = &next_game;
+            scope.spawn(move || loop {
+                let game_index = next_game.fetch_add(1, Ordering::Relaxed);
+                if game_index >= options.games || sender.send((game_index, play_game(options, game_index))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        let mut finished = BTreeMap::new();
+        let mut games_written = 0;
+        let mut samples_written = 0;
+
+        for (game_index, samples) in receiver {
+            finished.insert(game_index, samples);
+
+            while let Some(samples) = finished.remove(&games_written) {
+                for sample in &samples {
+                    match options.format {
+                        DataFormat::Text => writeln!(writer, "{}", to_text(sample))?,
+                        DataFormat::Binary => writer.write_all(&encode(sample))?,
+                    }
+                }
+
+                games_written += 1;
+                samples_written += samples.len();
+                progress(games_written, samples_written);
+            }
+        }
+
+        writer.flush()?;
+        Ok(samples_written)
+    })
+}
+
+/// A small, fast generator for picking opening moves.
+struct Random {
+    state: u64,
+}
+
+impl Random {
+    fn new(seed: u64, game_index: usize) -> Self {
+        Random { state: zobrist::splitmix64(seed ^ (game_index as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)).1 }
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        let (state, number) = zobrist::splitmix64(self.state);
+        self.state = state;
+        (number % bound as u64) as usize
+    }
+}
+
+/// Plays one game of the engine against itself and returns the quiet positions from it.
+pub fn play_game(options: &DatagenOptions, game_index: usize) -> Vec<Sample> {
+    let mut random = Random::new(options.seed, game_index);
+    let mut searcher = Searcher::new(options.hash_mb);
+    let limits = SearchLimits { nodes: Some(options.nodes), ..Default::default() };
+
+    for attempt in 0..MAX_OPENING_ATTEMPTS {
+        let Some(game) = random_opening(&mut random, options.random_plies) else { continue };
+
+        searcher.clear();
+        searcher.set_game_history(game.history.clone());
+        let score = searcher.search(&game.position, &limits).score;
+        if score.abs() > MAX_OPENING_SCORE && attempt + 1 < MAX_OPENING_ATTEMPTS {
+            continue;
+        }
+
+        return play_out(&mut searcher, game, &limits);
+    }
+
+    Vec::new()
+}
+
+/// Plays random legal moves from the start position. Gives up on openings that end the game.
+fn random_opening(random: &mut Random, plies: usize) -> Option<Game> {
+    let mut game = Game::new();
+
+    for _ in 0..plies {
+        let legal_moves = moves::get_legal_moves(&game.position);
+        if legal_moves.is_empty() {
+            return None;
+        }
+        game.play_move(legal_moves[random.below(legal_moves.len())]);
+    }
+
+    if moves::get_legal_moves(&game.position).is_empty() { None } else { Some(game) }
+}
+
+fn play_out(searcher: &mut Searcher, mut game: Game, limits: &SearchLimits) -> Vec<Sample> {
+    let mut positions: Vec<(Position, i32)> = Vec::new();
+    let mut decisive_plies = 0;
+
+    let result = loop {
+        let position = game.position.clone();
+        let in_check = position::is_in_check(&position, &position.side_to_move);
+
+        if moves::get_legal_moves(&position).is_empty() {
+            break match (in_check, position.side_to_move) {
+                (true, Color::White) => GameResult::BlackWin,
+                (true, Color::Black) => GameResult::WhiteWin,
+                _ => GameResult::Draw,
+            };
+        }
+        if is_drawn(&game) || game.moves.len() >= MAX_GAME_PLIES {
+            break GameResult::Draw;
+        }
+
+        searcher.set_game_history(game.history.clone());
+        let result = searcher.search(&position, limits);
+        let Some(best_move) = result.best_move else { break GameResult::Draw };
+        let white_score = if position.side_to_move == Color::White { result.score } else { -result.score };
+
+        decisive_plies = if result.score.abs() >= WIN_ADJUDICATION_SCORE { decisive_plies + 1 } else { 0 };
+        if decisive_plies >= WIN_ADJUDICATION_PLIES {
+            break if white_score > 0 { GameResult::WhiteWin } else { GameResult::BlackWin };
+        }
+
+        // Positions where the best move wins material or escapes a check say more about tactics than about the
+        // position, and the evaluation never sees those anyway, since the search resolves them first.
+        let is_quiet = !in_check && !ordering::is_capture(&position, &best_move) && best_move.promotion == PromotionOption::None;
+        if is_quiet && result.score.abs() < MATE_BOUND {
+            positions.push((position, white_score));
+        }
+
+        game.play_move(best_move);
+    };
+
+    positions.into_iter()
+        .map(|(position, score)| Sample { position, score, result })
+        .collect()
+}
+
+/// Draws by the fifty move rule, lack of material, or a position coming back even once.
+fn is_drawn(game: &Game) -> bool {
+    let position = &game.position;
+    position.half_move_clock >= 100
+        || search::is_insufficient_material(position)
+        || game.history.iter().rev().take(position.half_move_clock as usize).any(|hash| *hash == position.hash)
+}
+
+/// `<fen> | <score> | <result>`, where the result is 1.0, 0.5 or 0.0 for a White win, a draw and a Black win.
+pub fn to_text(sample: &Sample) -> String {
+    format!("{} | {} | {:.1}", fen::fen_from_position(&sample.position), sample.score, sample.result.white_score())
+}
+
+pub fn from_text(line: &str) -> Result<Sample, String> {
+    let parts: Vec<&str> = line.split('|').map(str::trim).collect();
+    let [fen_string, score, result] = parts[..] else {
+        return Err(format!("Expected a FEN, a score and a result in {}.", line));
+    };
+
+    Ok(Sample {
+        position: fen::try_position_from_fen(fen_string).map_err(|error| format!("Invalid FEN {}: {}", fen_string, error))?,
+        score: score.parse().map_err(|_| format!("Invalid score {}.", score))?,
+        result: match result {
+            "1.0" | "1" | "1-0" => GameResult::WhiteWin,
+            "0.5" | "1/2-1/2" => GameResult::Draw,
+            "0.0" | "0" | "0-1" => GameResult::BlackWin,
+            _ => return Err(format!("Invalid result {}.", result)),
+        },
+    })
+}
+
+// The binary format is 32 bytes per sample, all numbers little endian:
+//
+//   occupancy      u64, the occupied squares
+//   pieces         16 bytes, the piece id of every occupied square from a1 up, 4 bits each, low bits first
+//   flags          u8, castling rights in the low 4 bits in the order of `Position::castling_rooks`, and the side
+//                  to move in the top bit, set for Black
+//   en passant     u8, the square or 64 for none
+//   halfmove clock u8
+//   fullmove       u16
+//   score          i16, centipawns from White's point of view
+//   result         u8, 0 for a Black win, 1 for a draw and 2 for a White win
+//   padding        1 byte
+
+pub fn encode(sample: &Sample) -> [u8; RECORD_SIZE] {
+    let position = &sample.position;
+    let mut record = [0u8; RECORD_SIZE];
+
+    record[0..8].copy_from_slice(&position.occupation_bitboard.to_le_bytes());
+
+    let mut occupied = position.occupation_bitboard;
+    let mut nibble = 0;
+    while occupied != 0 {
+        let index = attacks::pop_lsb(&mut occupied);
+
+        let (piece, color) = position::get_piece_at(position, index);
+        record[8 + nibble / 2] |= (piece::piece_id(&piece, &color) as u8) << (4 * (nibble % 2));
+        nibble += 1;
+    }
+
+    let castling = position.castling_rooks.iter().enumerate().fold(0, |flags, (index, file)| flags | (((*file != position::NO_CASTLING) as u8) << index));
+    let black_to_move = if position.side_to_move == Color::Black { 0x80 } else { 0 };
+    record[24] = castling | black_to_move;
+    record[25] = if position.en_passant_index >= 0 { position.en_passant_index as u8 } else { 64 };
+    record[26] = position.half_move_clock.clamp(0, 255) as u8;
+    record[27..29].copy_from_slice(&(position.full_move_clock.clamp(0, u16::MAX as i32) as u16).to_le_bytes());
+    record[29..31].copy_from_slice(&(sample.score.clamp(i16::MIN as i32, i16::MAX as i32) as i16).to_le_bytes());
+    record[31] = match sample.result {
+        GameResult::BlackWin => 0,
+        GameResult::Draw => 1,
+        GameResult::WhiteWin => 2,
+    };
+
+    record
+}
+
+pub fn decode(record: &[u8; RECORD_SIZE]) -> Result<Sample, String> {
+    let mut position = Position::new();
+
+    let mut occupied = i64::from_le_bytes(record[0..8].try_into().unwrap());
+    if occupied.count_ones() > 32 {
+        return Err("A sample has more than 32 pieces.".to_string());
+    }
+
+    let mut nibble = 0;
+    while occupied != 0 {
+        let index = attacks::pop_lsb(&mut occupied);
+
+        let piece_id = (record[8 + nibble / 2] >> (4 * (nibble % 2))) & 0xf;
+        if piece_id >= 12 {
+            return Err(format!("Invalid piece id {} in a sample.", piece_id));
+        }
+        position::set_piece_at(&mut position, index, piece::piece_from_id(piece_id as i32), &piece::color_from_id(piece_id as i32));
+        nibble += 1;
+    }
+
+    position.side_to_move = if record[24] & 0x80 != 0 { Color::Black } else { Color::White };
+    // The record only says which rights there are, so they're taken to be for the outer rooks, as `KQkq` would be.
+    for (index, (color, side)) in moves::CASTLING_RIGHTS.into_iter().enumerate() {
+        let file = position::outer_rook_file(&position, color, side).filter(|_| record[24] & (1 << index) != 0);
+        position::set_castling_rook(&mut position, color, side, file.unwrap_or(position::NO_CASTLING));
+    }
+    position.en_passant_index = if record[25] < 64 { record[25] as i32 } else { -1 };
+    position.half_move_clock = record[26] as i32;
+    position.full_move_clock = u16::from_le_bytes([record[27], record[28]]) as i32;
+    position.hash = zobrist::hash_position(&position);
+
+    Ok(Sample {
+        position,
+        score: i16::from_le_bytes([record[29], record[30]]) as i32,
+        result: match record[31] {
+            0 => GameResult::BlackWin,
+            1 => GameResult::Draw,
+            2 => GameResult::WhiteWin,
+            other => return Err(format!("Invalid result {} in a sample.", other)),
+        },
+    })
+}
+
+/// Reads samples written by `datagen`, binary if the file name ends in `.bin` and text otherwise.
+pub fn read_samples(path: &str) -> Result<Vec<Sample>, String> {
+    let file = File::open(path).map_err(|error| format!("Couldn't open {}: {}.", path, error))?;
+    let mut reader = BufReader::new(file);
+    let mut samples = Vec::new();
+
+    if path.ends_with(".bin") {
+        let mut record = [0u8; RECORD_SIZE];
+        loop {
+            match reader.read_exact(&mut record) {
+                Ok(()) => samples.push(decode(&record)?),
+                Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(error) => return Err(format!("Couldn't read {}: {}.", path, error)),
+            }
+        }
+    } else {
+        for (number, line) in reader.lines().enumerate() {
+            let line = line.map_err(|error| format!("Couldn't read {}: {}.", path, error))?;
+            if !line.trim().is_empty() {
+                samples.push(from_text(&line).map_err(|error| format!("Line {} of {}: {}", number + 1, path, error))?);
+            }
+        }
+    }
+
+    Ok(samples)
+}
+
+// Unit Tests! //
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::*;
+
+    #[test]
+    fn test_formats_round_trip() {
+        for fen_string in [fen::STARTING_FEN, "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b Kq - 3 17", "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3"] {
+            let sample = Sample { position: fen::position_from_fen(fen_string), score: -137, result: GameResult::Draw };
+
+            let decoded = decode(&encode(&sample)).expect("An encoded sample should decode.");
+            assert_eq!(fen::fen_from_position(&decoded.position), fen_string);
+            assert_eq!(decoded.position.hash, sample.position.hash);
+            assert_eq!((decoded.score, decoded.result), (sample.score, sample.result));
+
+            let parsed = from_text(&to_text(&sample)).expect("A sample written as text should parse.");
+            assert_eq!(fen::fen_from_position(&parsed.position), fen_string);
+            assert_eq!((parsed.score, parsed.result), (sample.score, sample.result));
+        }
+    }
+
+    #[test]
+    fn test_reports_bad_lines() {
+        let path = env::temp_dir().join(format!("macbeth-datagen-{}.txt", std::process::id()));
+        let good = to_text(&Sample { position: fen::position_from_fen(fen::STARTING_FEN), score: 12, result: GameResult::Draw });
+        fs::write(&path, format!("{}\n\n8/8/8/8/8/8/8/k6K w - - x 1 | 0 | 0.5\n", good)).unwrap();
+
+        let error = read_samples(path.to_str().unwrap()).err().expect("A broken FEN should fail the file.");
+        fs::remove_file(&path).unwrap();
+        assert!(error.starts_with("Line 3 of "), "The error should say which line is broken, not {}.", error);
+        assert!(from_text("8/8/8/8/8/8/8/k6K w - e3 0 1 | 0 | 0.5").is_err(), "An en passant square on the wrong rank should be rejected.");
+    }
+
+    #[test]
+    fn test_generation_is_deterministic() {
+        let options = DatagenOptions { games: 3, nodes: 200, hash_mb: 1, format: DataFormat::Binary, ..Default::default() };
+
+        let mut single_thread = Vec::new();
+        let samples = generate(&options, &mut single_thread, |_, _| {}).expect("Writing to memory can't fail.");
+        assert!(samples > 0, "Three games should give some quiet positions.");
+        assert_eq!(single_thread.len(), samples * RECORD_SIZE);
+
+        let mut three_threads = Vec::new();
+        generate(&DatagenOptions { threads: 3, ..options.clone() }, &mut three_threads, |_, _| {}).expect("Writing to memory can't fail.");
+        assert!(single_thread == three_threads, "The same seed should give the same data with any number of threads.");
+
+        let mut other_seed = Vec::new();
+        generate(&DatagenOptions { seed: 1, ..options }, &mut other_seed, |_, _| {}).expect("Writing to memory can't fail.");
+        assert!(single_thread != other_seed, "Another seed should give other games.");
+
+        for record in single_thread.chunks_exact(RECORD_SIZE) {
+            let sample = decode(record.try_into().unwrap()).expect("Generated samples should decode.");
+            assert!(!position::is_in_check(&sample.position, &sample.position.side_to_move), "Positions in check aren't quiet.");
+        }
+    }
+}
@@ -1,5 +1,3 @@
-use piece::Piece;
-
 use super::*;
 
 pub fn ascii(position: &position::Position) -> String {
@@ -28,26 +26,22 @@ pub fn ascii(position: &position::Position) -> String {
 }
 
 pub fn get_piece_ascii(piece: piece::Piece, color: piece::Color) -> char {
-    let mut piece_ascii = ' ';
-
-    match piece {
-        piece::Piece::Empty => piece_ascii = '.',
-
-        piece::Piece::Pawn => piece_ascii = 'p',
-        piece::Piece::Knight => piece_ascii = 'n',
-        piece::Piece::Bishop => piece_ascii = 'b',
-        piece::Piece::Rook => piece_ascii = 'r',
-        piece::Piece::Queen => piece_ascii = 'q',
-        piece::Piece::King => piece_ascii = 'k',
-
-        _ => piece_ascii = '.'
-    }
+    let mut piece_ascii = match piece {
+        piece::Piece::Empty => '.',
+
+        piece::Piece::Pawn => 'p',
+        piece::Piece::Knight => 'n',
+        piece::Piece::Bishop => 'b',
+        piece::Piece::Rook => 'r',
+        piece::Piece::Queen => 'q',
+        piece::Piece::King => 'k',
+    };
 
     if color == piece::Color::White {
         piece_ascii = piece_ascii.to_ascii_uppercase();
     }
 
-    return piece_ascii;
+    piece_ascii
 }
 
 pub fn piece_from_notation(piece: char) -> (piece::Piece, piece::Color) {
@@ -77,7 +71,7 @@ pub fn coordinate_name_to_board_index(coordinate: String) -> Option<i32> {
         return None;
     }
 
-    let file = coordinate.chars().nth(0)?;
+    let file = coordinate.chars().next()?;
     let file_index = match file {
         'a'..='h' => file as i32 - 'a' as i32,
         _ => return None,
@@ -86,17 +80,56 @@ pub fn coordinate_name_to_board_index(coordinate: String) -> Option<i32> {
     // Convert rank (number) part to an index (0 to 7)
     let rank = coordinate.chars().nth(1)?;
     let rank_index = match rank {
-        '1'..='8' => 7 - (rank as i32 - '1' as i32),
+        '1'..='8' => rank as i32 - '1' as i32,
         _ => return None,
     };
 
     Some(rank_index * 8 + file_index)
 }
 
-pub fn list_moves(moves: &Vec<moves::Move>) -> String {
+pub fn list_moves(moves: &[moves::Move]) -> String {
     let formatted_moves: Vec<String> = moves.iter()
         .map(|mv| mv.to_string())
         .collect();
 
-    format!("{}", formatted_moves.join(", "))
-}
\ No newline at end of file
+    formatted_moves.join(", ")
+}
+
+/// Renders an evaluation trace as a table, to print beside `ascii` with `side_by_side`. Every value is in centipawns.
+pub fn trace_table(trace: &eval::Trace) -> String {
+    let separator = "-------------------+-------------+-------------+-------------\n";
+    let mut table = String::new();
+
+    table.push_str("       Term        |    White    |    Black    |    Total\n");
+    table.push_str("                   |   MG    EG  |   MG    EG  |   MG    EG\n");
+    table.push_str(separator);
+
+    for term in &trace.terms {
+        let total = term.white - term.black;
+        table.push_str(&format!("{:<18} | {:>5} {:>5} | {:>5} {:>5} | {:>5} {:>5}\n",
+            term.name, term.white.mg, term.white.eg, term.black.mg, term.black.eg, total.mg, total.eg));
+    }
+
+    table.push_str(separator);
+    table.push_str(&format!("{:<18} |             |             | {:>5} {:>5}\n", "Total", trace.total.mg, trace.total.eg));
+    table.push_str(&format!("\nPhase: {}/{}\n", trace.phase, eval::MAX_PHASE));
+    table.push_str(&format!("Blended: {} (White's view)\n", trace.blended));
+    table.push_str(&format!("Evaluation: {} (side to move)", trace.score));
+
+    table
+}
+
+/// Puts two blocks of text next to each other, `left` padded to its widest line so `right` starts in one column.
+pub fn side_by_side(left: &str, right: &str) -> String {
+    let width = left.lines().map(|line| line.chars().count()).max().unwrap_or(0);
+    let (left, right): (Vec<&str>, Vec<&str>) = (left.lines().collect(), right.lines().collect());
+
+    let lines: Vec<String> = (0..left.len().max(right.len()))
+        .map(|index| {
+            let (left, right) = (left.get(index).copied().unwrap_or(""), right.get(index).copied().unwrap_or(""));
+            format!("{:<width$}    {}", left, right, width = width).trim_end().to_string()
+        })
+        .collect();
+
+    lines.join("\n")
+}
@@ -0,0 +1,1022 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+use super::attacks;
+use super::cli::Flags;
+use super::moves;
+use super::piece::{self, Color, Piece};
+use super::position::{self, Position};
+use super::syzygy;
+use super::tablebase::{Tablebase, Wdl};
+
+// Distance to mate tables for the endings of three and four pieces, worked out by retrograde analysis: the mates
+// are found first, then every pass goes back a ply from the positions settled in the one before. A position is won
+// in n+1 plies once one of its moves reaches a position lost in n, and lost in n+1 once the last of its moves reaches
+// a won one. Captures and promotions leave the table, so they're looked up in the tables generated before it.
+// The same passes with every capture and pawn move leaving the table give the distance to zeroing that the Syzygy
+// tables `tbgen --syzygy true` writes keep.
+//
+// A table holds one entry per index, for both sides to move. The white king is moved into the a1-d1-d4 triangle by
+// the board's symmetries, or onto files a to d when there are pawns, and a position's index is the smallest of
+// the indexes its symmetries have. Indexes that aren't the smallest for their position, or that aren't legal
+// positions at all, are never probed. En passant and castling aren't in the tables, so positions with either aren't
+// probed, and the fifty move rule is ignored. En passant captures are still worked out while generating, for the
+// positions before a double push that allows one.
+//
+// Table file format, all numbers little endian:
+//
+//   magic          4 bytes, "MBTB"
+//   version        u32, 1
+//   signature      u8 length, then the signature like "KQvKR"
+//   entries        u32
+//   blocks         u32, then u32 x blocks + 1 offsets into the data where each block starts
+//   data           `BLOCK_SIZE` entries per block, as runs of a u8 length and a u8 entry
+//
+// An entry is 0 for a draw and otherwise the plies to mate plus one. Wins take an odd number of plies and losses an
+// even number, so even entries are wins and odd ones losses.
+
+const MAGIC: &[u8; 4] = b"MBTB";
+const VERSION: u32 = 1;
+pub const EXTENSION: &str = "mbtb";
+
+/// Entries per compressed block. A probe reads through at most one block.
+const BLOCK_SIZE: usize = 4096;
+
+const DRAW: u8 = 0;
+
+/// The a1-d1-d4 triangle numbered 0 to 9, -1 elsewhere.
+const TRIANGLE: [i8; 64] = triangle();
+
+const fn triangle() -> [i8; 64] {
+    let mut table = [-1; 64];
+    let mut next = 0;
+    let mut square = 0;
+    while square < 64 {
+        if square % 8 <= 3 && square / 8 <= square % 8 {
+            table[square] = next;
+            next += 1;
+        }
+        square += 1;
+    }
+    table
+}
+
+/// The pieces of an ending besides the kings, strongest first on each side.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Material {
+    pub white: Vec<Piece>,
+    pub black: Vec<Piece>,
+}
+
+impl Material {
+    pub fn of(position: &Position) -> Material {
+        let side = |color: &Color| -> Vec<Piece> {
+            [Piece::Queen, Piece::Rook, Piece::Bishop, Piece::Knight, Piece::Pawn].iter()
+                .flat_map(|piece| std::iter::repeat_n(*piece, position.bitboards[piece::piece_id(piece, color) as usize].count_ones() as usize))
+                .collect()
+        };
+
+        Material { white: side(&Color::White), black: side(&Color::Black) }
+    }
+
+    /// Reads a signature like `KQvKR`.
+    pub fn parse(signature: &str) -> Option<Material> {
+        let (white, black) = signature.split_once('v')?;
+        let side = |side: &str| -> Option<Vec<Piece>> {
+            let mut pieces = side.strip_prefix('K')?.chars().map(|letter| match letter {
+                'Q' => Some(Piece::Queen),
+                'R' => Some(Piece::Rook),
+                'B' => Some(Piece::Bishop),
+                'N' => Some(Piece::Knight),
+                'P' => Some(Piece::Pawn),
+                _ => None,
+            }).collect::<Option<Vec<Piece>>>()?;
+            pieces.sort_by_key(|piece| std::cmp::Reverse(*piece as i32));
+            Some(pieces)
+        };
+
+        Some(Material { white: side(white)?, black: side(black)? })
+    }
+
+    pub fn signature(&self) -> String {
+        let side = |pieces: &[Piece]| -> String {
+            pieces.iter().map(|piece| match piece {
+                Piece::Queen => 'Q',
+                Piece::Rook => 'R',
+                Piece::Bishop => 'B',
+                Piece::Knight => 'N',
+                _ => 'P',
+            }).collect()
+        };
+
+        format!("K{}vK{}", side(&self.white), side(&self.black))
+    }
+
+    /// Pieces on the board, kings included.
+    pub fn piece_count(&self) -> usize {
+        2 + self.white.len() + self.black.len()
+    }
+
+    fn pawn_count(&self) -> usize {
+        self.white.iter().chain(&self.black).filter(|piece| **piece == Piece::Pawn).count()
+    }
+
+    /// Tables are kept with the stronger side as White, and the same ending with the colors swapped is looked up in
+    /// them with the board flipped.
+    fn is_canonical(&self) -> bool {
+        let strength = |pieces: &[Piece]| -> (usize, Vec<i32>) { (pieces.len(), pieces.iter().map(|piece| *piece as i32).collect()) };
+        strength(&self.white) >= strength(&self.black)
+    }
+
+    pub fn flipped(&self) -> Material {
+        Material { white: self.black.clone(), black: self.white.clone() }
+    }
+}
+
+/// Every ending of three or four pieces, or only three with `max_pieces` 3, with the stronger side as White, in an
+/// order where captures and promotions only lead to endings earlier in the list.
+pub fn endings(max_pieces: usize) -> Vec<Material> {
+    const PIECES: [Piece; 5] = [Piece::Queen, Piece::Rook, Piece::Bishop, Piece::Knight, Piece::Pawn];
+
+    // The pieces besides the kings, strongest first, which are then split every way between the sides.
+    let mut sets: Vec<Vec<Piece>> = PIECES.iter().map(|piece| vec![*piece]).collect();
+    if max_pieces >= 4 {
+        for (first, piece) in PIECES.iter().enumerate() {
+            sets.extend(PIECES[first..].iter().map(|other| vec![*piece, *other]));
+        }
+    }
+
+    let mut endings: Vec<Material> = Vec::new();
+    for set in &sets {
+        for split in 0..1u32 << set.len() {
+            let material = Material {
+                white: set.iter().enumerate().filter(|(bit, _)| split & (1 << bit) == 0).map(|(_, piece)| *piece).collect(),
+                black: set.iter().enumerate().filter(|(bit, _)| split & (1 << bit) != 0).map(|(_, piece)| *piece).collect(),
+            };
+            if material.is_canonical() && !endings.contains(&material) {
+                endings.push(material);
+            }
+        }
+    }
+
+    endings.sort_by_key(|material| (material.piece_count(), material.pawn_count()));
+    endings
+}
+
+/// Generates the tables of every ending of up to `--pieces` pieces into the `--output` directory, along with Syzygy
+/// WDL and DTZ files with `--syzygy true`. Tables already there are read instead of generated again, so an interrupted
+/// run can carry on.
+pub fn run(flags: &Flags) -> Result<(), String> {
+    let max_pieces = flags.get("pieces", 4)?;
+    if !(3..=4).contains(&max_pieces) {
+        return Err("--pieces should be 3 or 4.".to_string());
+    }
+    let directory = flags.require("output")?;
+    let syzygy = flags.get("syzygy", false)?;
+    fs::create_dir_all(&directory).map_err(|error| format!("Couldn't create {}: {}.", directory, error))?;
+
+    let mut tablebase = DtmTablebase::load(&directory)?;
+    let start = Instant::now();
+    for material in endings(max_pieces) {
+        if !tablebase.has(&material) {
+            let entries = tablebase.generate(&material)?;
+            let path = Path::new(&directory).join(format!("{}.{}", material.signature(), EXTENSION));
+            let bytes = write_table(&material, &entries);
+            fs::write(&path, &bytes).map_err(|error| format!("Couldn't write {}: {}.", path.display(), error))?;
+
+            let longest = entries.iter().filter(|entry| **entry != DRAW && **entry % 2 == 0).max().map_or(0, |entry| decode_entry(*entry).1);
+            eprintln!("{}: {} entries in {} bytes, longest win {} plies, {:.0}s", material.signature(), entries.len(), bytes.len(), longest, start.elapsed().as_secs_f64());
+        }
+
+        if syzygy && !Path::new(&directory).join(format!("{}.rtbz", material.signature())).exists() {
+            write_syzygy(&material, &tablebase, Path::new(&directory))?;
+            eprintln!("{}: Syzygy tables written, {:.0}s", material.signature(), start.elapsed().as_secs_f64());
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the Syzygy WDL and DTZ files of an ending into a directory. Its table and the ones its captures and
+/// promotions lead to have to be in the tablebase.
+pub fn write_syzygy(material: &Material, tablebase: &DtmTablebase, directory: &Path) -> Result<(), String> {
+    let layout = Layout::new(material);
+    let results = distance_to_zeroing(material, tablebase)?;
+    // Cursed wins would need results that know about the fifty move rule, which none of these endings have.
+    if results.iter().flatten().any(|(result, plies)| *result != Wdl::Draw && *plies > 100) {
+        return Err(format!("{} has results more than 100 plies from zeroing.", material.signature()));
+    }
+    let result = |position: &Position| results[layout.index_of(position, false)];
+
+    let wdl = syzygy::write_table(material, false, |position| result(position).map(|(result, _)| match result {
+        Wdl::Win => 4,
+        Wdl::Loss => 0,
+        _ => 2,
+    }))?;
+    let dtz = syzygy::write_table(material, true, |position| {
+        result(position).filter(|(result, _)| *result != Wdl::Draw).map(|(_, plies)| plies.max(1) as u16 - 1)
+    })?;
+
+    for (extension, bytes) in [("rtbw", wdl), ("rtbz", dtz)] {
+        let path = directory.join(format!("{}.{}", material.signature(), extension));
+        fs::write(&path, bytes).map_err(|error| format!("Couldn't write {}: {}.", path.display(), error))?;
+    }
+    Ok(())
+}
+
+/// How the positions of an ending are numbered.
+#[derive(Debug)]
+struct Layout {
+    /// The pieces besides the kings, White's then Black's, with identical pieces next to each other.
+    pieces: Vec<(Piece, Color)>,
+    has_pawns: bool,
+    size: usize,
+}
+
+impl Layout {
+    fn new(material: &Material) -> Layout {
+        let pieces: Vec<(Piece, Color)> = material.white.iter().map(|piece| (*piece, Color::White))
+            .chain(material.black.iter().map(|piece| (*piece, Color::Black)))
+            .collect();
+        let has_pawns = pieces.iter().any(|(piece, _)| *piece == Piece::Pawn);
+
+        let mut layout = Layout { pieces, has_pawns, size: 0 };
+        layout.size = 2 * layout.king_range() * 64 * layout.pieces.iter().map(|(piece, _)| square_range(*piece)).product::<usize>();
+        layout
+    }
+
+    fn king_range(&self) -> usize {
+        if self.has_pawns { 32 } else { 10 }
+    }
+
+    /// The squares of the pieces in the position, kings first, with the colors swapped and the board flipped when
+    /// the table has the ending the other way around.
+    fn squares(&self, position: &Position, flip: bool) -> Vec<i32> {
+        let mut bitboards = position.bitboards;
+        let mut take = |piece: Piece, color: Color| -> i32 {
+            let color = if flip { piece::opponent_of(&color) } else { color };
+            let square = attacks::pop_lsb(&mut bitboards[piece::piece_id(&piece, &color) as usize]);
+            if flip { square ^ 56 } else { square }
+        };
+
+        let mut squares = vec![take(Piece::King, Color::White), take(Piece::King, Color::Black)];
+        squares.extend(self.pieces.iter().map(|(piece, color)| take(*piece, *color)));
+        squares
+    }
+
+    /// The position's index, ignoring en passant.
+    fn index_of(&self, position: &Position, flip: bool) -> usize {
+        let side = (position.side_to_move == Color::Black) != flip;
+        self.index(side as usize, &self.squares(position, flip))
+    }
+
+    /// The smallest index of the squares under any symmetry of the board. Pawns only allow mirroring the files.
+    fn index(&self, side: usize, squares: &[i32]) -> usize {
+        let symmetries = if self.has_pawns { 2 } else { 8 };
+        let mut moved = squares.to_vec();
+
+        (0..symmetries).filter_map(|symmetry| {
+            for (square, original) in moved.iter_mut().zip(squares) {
+                *square = transform(*original, symmetry);
+            }
+            self.raw_index(side, &mut moved)
+        }).min().expect("Some symmetry always moves the white king into place.")
+    }
+
+    /// The index of the squares as they are, if the white king is where the layout wants it.
+    fn raw_index(&self, side: usize, squares: &mut [i32]) -> Option<usize> {
+        let king = if self.has_pawns {
+            Some(squares[0] / 8 * 4 + squares[0] % 8).filter(|_| squares[0] % 8 <= 3)?
+        } else {
+            Some(TRIANGLE[squares[0] as usize] as i32).filter(|king| *king >= 0)?
+        };
+
+        // Identical pieces could be listed either way around, so they're put in order.
+        let mut start = 0;
+        while start < self.pieces.len() {
+            let end = (start..self.pieces.len()).find(|other| self.pieces[*other] != self.pieces[start]).unwrap_or(self.pieces.len());
+            squares[2 + start..2 + end].sort_unstable();
+            start = end;
+        }
+
+        let mut index = (side * self.king_range() + king as usize) * 64 + squares[1] as usize;
+        for ((piece, _), square) in self.pieces.iter().zip(&squares[2..]) {
+            index = index * square_range(*piece) + (square - square_offset(*piece)) as usize;
+        }
+        Some(index)
+    }
+
+    fn decode(&self, mut index: usize) -> (usize, Vec<i32>) {
+        let mut squares = vec![0; 2 + self.pieces.len()];
+        for (slot, (piece, _)) in self.pieces.iter().enumerate().rev() {
+            squares[2 + slot] = (index % square_range(*piece)) as i32 + square_offset(*piece);
+            index /= square_range(*piece);
+        }
+
+        squares[1] = (index % 64) as i32;
+        index /= 64;
+        let king = (index % self.king_range()) as i32;
+        squares[0] = if self.has_pawns { king / 4 * 8 + king % 4 } else { TRIANGLE.iter().position(|slot| *slot as i32 == king).unwrap() as i32 };
+
+        (index / self.king_range(), squares)
+    }
+
+    /// The position at an index, if it's legal and this is its smallest index.
+    fn position(&self, index: usize) -> Option<Position> {
+        let (side, squares) = self.decode(index);
+
+        let mut position = Position::new();
+        let kings = [(Piece::King, Color::White), (Piece::King, Color::Black)];
+        for ((piece, color), square) in kings.iter().chain(&self.pieces).zip(&squares) {
+            if position::is_square_occupied(&position, *square) {
+                return None;
+            }
+            position::set_piece_at(&mut position, *square, *piece, color);
+        }
+        position.side_to_move = if side == 0 { Color::White } else { Color::Black };
+
+        if position::is_in_check(&position, &piece::opponent_of(&position.side_to_move)) || self.index(side, &squares) != index {
+            return None;
+        }
+        Some(position)
+    }
+
+    /// The index of a position right after a double push, and its en passant square as it is on the board with that
+    /// index. Positions that only differ in en passant share an index, so the square tells them apart.
+    fn en_passant_key(&self, position: &Position) -> (usize, i32) {
+        let side = (position.side_to_move == Color::Black) as usize;
+        let squares = self.squares(position, false);
+
+        (0..2).filter_map(|symmetry| {
+            let mut moved: Vec<i32> = squares.iter().map(|square| transform(*square, symmetry)).collect();
+            self.raw_index(side, &mut moved).map(|index| (index, transform(position.en_passant_index, symmetry)))
+        }).min().expect("Mirroring the files always moves the white king into place.")
+    }
+
+    /// The indexes of the positions a move by the side that just moved could have come from, without captures or
+    /// promotions, which come from other tables, along with the en passant square a double push would have left. Each
+    /// index is listed once however many moves lead from it.
+    fn predecessors(&self, position: &Position) -> Vec<(usize, Option<i32>)> {
+        let mover = piece::opponent_of(&position.side_to_move);
+        let occupation = position.occupation_bitboard;
+        let empty = !occupation;
+        let mut found = Vec::new();
+
+        let mut pieces = position::get_color_bitboard(position, &mover);
+        while pieces != 0 {
+            let square = attacks::pop_lsb(&mut pieces);
+            let (piece, _) = position::get_piece_at(position, square);
+
+            let mut origins = match piece {
+                Piece::Pawn => {
+                    let (back, rank, double_push_rank) = if mover == Color::White { (-8, square / 8, 3) } else { (8, 7 - square / 8, 4) };
+                    let mut origins = 0;
+                    if rank >= 2 && empty & (1 << (square + back)) != 0 {
+                        origins |= 1 << (square + back);
+                        if square / 8 == double_push_rank && empty & (1 << (square + 2 * back)) != 0 {
+                            origins |= 1 << (square + 2 * back);
+                        }
+                    }
+                    origins
+                }
+                Piece::Knight => attacks::KNIGHT_ATTACKS[square as usize] & empty,
+                Piece::Bishop => attacks::bishop_attacks(square, occupation) & empty,
+                Piece::Rook => attacks::rook_attacks(square, occupation) & empty,
+                Piece::Queen => attacks::queen_attacks(square, occupation) & empty,
+                _ => attacks::KING_ATTACKS[square as usize] & empty,
+            };
+
+            while origins != 0 {
+                let origin = attacks::pop_lsb(&mut origins);
+                let mut before = position.clone();
+                position::remove_piece_at(&mut before, square);
+                position::set_piece_at(&mut before, origin, piece, &mover);
+                before.side_to_move = mover;
+
+                if !position::is_in_check(&before, &position.side_to_move) {
+                    let double_push = piece == Piece::Pawn && (origin - square).abs() == 16;
+                    found.push((self.index_of(&before, false), Some((origin + square) / 2).filter(|_| double_push)));
+                }
+            }
+        }
+
+        found.sort_unstable();
+        found.dedup();
+        found
+    }
+}
+
+/// Pawns can't stand on the first or last rank, so they only need 48 squares.
+fn square_range(piece: Piece) -> usize {
+    if piece == Piece::Pawn { 48 } else { 64 }
+}
+
+fn square_offset(piece: Piece) -> i32 {
+    if piece == Piece::Pawn { 8 } else { 0 }
+}
+
+/// One of the eight symmetries of the board: bit 0 mirrors the files, bit 1 the ranks and bit 2 the diagonal.
+fn transform(square: i32, symmetry: usize) -> i32 {
+    let (mut file, mut rank) = (square % 8, square / 8);
+    if symmetry & 1 != 0 {
+        file = 7 - file;
+    }
+    if symmetry & 2 != 0 {
+        rank = 7 - rank;
+    }
+    if symmetry & 4 != 0 {
+        (file, rank) = (rank, file);
+    }
+    rank * 8 + file
+}
+
+fn mate_entry(plies: usize) -> u8 {
+    u8::try_from(plies + 1).expect("Mates in endings of four pieces are far shorter than 255 plies.")
+}
+
+/// The result and plies to mate an entry stands for, for the side to move.
+fn decode_entry(entry: u8) -> (Wdl, i32) {
+    match entry {
+        DRAW => (Wdl::Draw, 0),
+        entry if entry % 2 == 0 => (Wdl::Win, entry as i32 - 1),
+        entry => (Wdl::Loss, entry as i32 - 1),
+    }
+}
+
+enum Entries {
+    Plain(Vec<u8>),
+    Compressed { offsets: Vec<u32>, data: Vec<u8> },
+}
+
+struct Table {
+    layout: Layout,
+    entries: Entries,
+}
+
+impl Table {
+    fn entry(&self, index: usize) -> u8 {
+        match &self.entries {
+            Entries::Plain(entries) => entries[index],
+            Entries::Compressed { offsets, data } => {
+                let block = index / BLOCK_SIZE;
+                let mut skip = index % BLOCK_SIZE;
+                for run in data[offsets[block] as usize..offsets[block + 1] as usize].chunks_exact(2) {
+                    if skip < run[0] as usize {
+                        return run[1];
+                    }
+                    skip -= run[0] as usize;
+                }
+                unreachable!("Blocks are checked to cover all their entries when the table is read.")
+            }
+        }
+    }
+}
+
+/// The generated tables, probed in the search through `Tablebase` and used by the generator to look up captures
+/// and promotions.
+#[derive(Default)]
+pub struct DtmTablebase {
+    tables: HashMap<Material, Table>,
+    max_pieces: usize,
+}
+
+impl DtmTablebase {
+    /// Reads every table file in a directory.
+    pub fn load(directory: &str) -> Result<DtmTablebase, String> {
+        let mut tablebase = DtmTablebase::default();
+        let entries = fs::read_dir(directory).map_err(|error| format!("Couldn't read {}: {}.", directory, error))?;
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_some_and(|extension| extension == EXTENSION) {
+                let bytes = fs::read(&path).map_err(|error| format!("Couldn't read {}: {}.", path.display(), error))?;
+                let (material, table) = read_table(&bytes).map_err(|error| format!("{}: {}", path.display(), error))?;
+                tablebase.insert(material, table);
+            }
+        }
+
+        Ok(tablebase)
+    }
+
+    /// Generates the table of an ending and adds it, returning its entries. The tables of every ending a capture or
+    /// promotion leads to have to be here already.
+    pub fn generate(&mut self, material: &Material) -> Result<Vec<u8>, String> {
+        let entries = generate(material, self)?;
+        self.insert(material.clone(), Table { layout: Layout::new(material), entries: Entries::Plain(entries.clone()) });
+        Ok(entries)
+    }
+
+    fn insert(&mut self, material: Material, table: Table) {
+        self.max_pieces = self.max_pieces.max(material.piece_count());
+        self.tables.insert(material, table);
+    }
+
+    pub fn has(&self, material: &Material) -> bool {
+        self.tables.contains_key(material)
+    }
+
+    /// The entry for a position, if its ending is here and it has no castling rights or en passant square. The
+    /// layouts take one king of each color and pawns on the second to seventh ranks for granted.
+    fn entry(&self, position: &Position) -> Option<u8> {
+        const BACK_RANKS: i64 = 0xff | 0xff << 56;
+        if position.en_passant_index >= 0 || position::has_castling_rights(position) {
+            return None;
+        }
+        let pawns = position.bitboards[piece::piece_id(&Piece::Pawn, &Color::White) as usize] | position.bitboards[piece::piece_id(&Piece::Pawn, &Color::Black) as usize];
+        if pawns & BACK_RANKS != 0 || [Color::White, Color::Black].iter().any(|color| position.bitboards[piece::piece_id(&Piece::King, color) as usize].count_ones() != 1) {
+            return None;
+        }
+
+        let material = Material::of(position);
+        if material.white.is_empty() && material.black.is_empty() {
+            return Some(DRAW);
+        }
+
+        let flip = !material.is_canonical();
+        let table = self.tables.get(&if flip { material.flipped() } else { material })?;
+        Some(table.entry(table.layout.index_of(position, flip)))
+    }
+
+    /// The result of a position for the side to move, with its en passant captures worked out from the tables.
+    fn result_with_en_passant(&self, position: &Position) -> Option<Wdl> {
+        let mut without_en_passant = position.clone();
+        without_en_passant.en_passant_index = -1;
+        let mut result = decode_entry(self.entry(&without_en_passant)?).0;
+
+        for mv in moves::get_legal_moves(position) {
+            if mv.to == position.en_passant_index && position::get_piece_at(position, mv.from).0 == Piece::Pawn {
+                let mut child = position.clone();
+                moves::make_move(&mut child, &mv);
+                result = result.max(decode_entry(self.entry(&child)?).0.flip());
+            }
+        }
+        Some(result)
+    }
+}
+
+impl Tablebase for DtmTablebase {
+    fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    fn probe_wdl(&self, position: &Position) -> Option<Wdl> {
+        self.entry(position).map(|entry| decode_entry(entry).0)
+    }
+
+    /// The tables only know the distance to mate.
+    fn probe_dtz(&self, _position: &Position) -> Option<i32> {
+        None
+    }
+
+    fn probe_dtm(&self, position: &Position) -> Option<i32> {
+        self.entry(position).map(decode_entry).filter(|(result, _)| *result != Wdl::Draw).map(|(_, plies)| plies)
+    }
+}
+
+// Generation flags.
+const LEGAL: u8 = 1;
+const SETTLED: u8 = 2;
+/// A move out of the table draws or wins, so the position can't be lost however its other moves turn out.
+const CANNOT_LOSE: u8 = 4;
+
+/// Where a move out of the table leads: the result for the side to move after it, and the plies until that result.
+type Exit = (Wdl, usize);
+
+fn generate(material: &Material, tablebase: &DtmTablebase) -> Result<Vec<u8>, String> {
+    let layout = Layout::new(material);
+    let results = retrograde(&layout, |position, child, mv| {
+        if child.occupation_bitboard.count_ones() == position.occupation_bitboard.count_ones() && mv.promotion == moves::PromotionOption::None {
+            return Ok(None);
+        }
+
+        let entry = tablebase.entry(child).ok_or_else(|| format!("{} needs the table of {}.", material.signature(), Material::of(child).signature()))?;
+        let (result, plies) = decode_entry(entry);
+        Ok(Some((result, plies as usize)))
+    })?;
+
+    let mut entries: Vec<u8> = results.iter().map(|result| match result {
+        Some((Wdl::Win | Wdl::Loss, plies)) => mate_entry(*plies as usize),
+        _ => DRAW,
+    }).collect();
+
+    // Entries that are never probed copy the one before them, which makes longer runs to compress.
+    for index in 1..layout.size {
+        if results[index].is_none() {
+            entries[index] = entries[index - 1];
+        }
+    }
+
+    Ok(entries)
+}
+
+/// The plies to the next capture or pawn move of every position of an ending, the way Syzygy tables count them: a
+/// win or loss is as far as the zeroing move the winner is after, and a mate counts as one. `None` for indexes that
+/// aren't probed. The ending's own table and the ones its captures and promotions lead to have to be here already.
+pub fn distance_to_zeroing(material: &Material, tablebase: &DtmTablebase) -> Result<Vec<Option<(Wdl, u8)>>, String> {
+    retrograde(&Layout::new(material), |position, child, mv| {
+        if child.occupation_bitboard.count_ones() == position.occupation_bitboard.count_ones() && position::get_piece_at(position, mv.from).0 != Piece::Pawn {
+            return Ok(None);
+        }
+
+        let result = tablebase.result_with_en_passant(child).ok_or_else(|| format!("{} needs the table of {}.", material.signature(), Material::of(child).signature()))?;
+        Ok(Some((result, 0)))
+    })
+}
+
+/// Solves every position of a layout back from its mates and the moves `exit` says leave the table, returning the
+/// result and plies to the end for each index that's probed, and `None` for the others.
+fn retrograde(layout: &Layout, exit: impl Fn(&Position, &Position, &moves::Move) -> Result<Option<Exit>, String>) -> Result<Vec<Option<(Wdl, u8)>>, String> {
+    let mut results: Vec<Option<(Wdl, u8)>> = vec![None; layout.size];
+    let mut flags = vec![0u8; layout.size];
+    // The positions each position's moves lead to inside the table that aren't settled as won yet.
+    let mut unsettled_moves = vec![0u8; layout.size];
+    // The longest a loss through a move out of the table takes, since a lost position holds out as long as it can.
+    let mut longest_exit = vec![0usize; layout.size];
+    // Positions to settle, by plies to the end, and whether they're won.
+    let mut queue: Vec<Vec<(usize, bool)>> = Vec::new();
+    let schedule = |queue: &mut Vec<Vec<(usize, bool)>>, plies: usize, index: usize, is_win: bool| {
+        if queue.len() <= plies {
+            queue.resize(plies + 1, Vec::new());
+        }
+        queue[plies].push((index, is_win));
+    };
+
+    // The table ignores en passant, but a double push the opponent can take en passant doesn't lead to the position
+    // in the table: it leads to one with the same moves and the en passant captures as well. Those are solved along
+    // with the table, numbered after it, and only the positions before the double push see them.
+    let mut en_passant_indexes: HashMap<(usize, i32), usize> = HashMap::new();
+    let mut en_passant_positions: Vec<Position> = Vec::new();
+    let mut double_pushes_to: Vec<Vec<usize>> = Vec::new();
+    let mut with_en_passant: HashMap<usize, Vec<usize>> = HashMap::new();
+
+    let mut index = 0;
+    while index < layout.size + en_passant_positions.len() {
+        let position = if index < layout.size { layout.position(index) } else { Some(en_passant_positions[index - layout.size].clone()) };
+        let Some(position) = position else {
+            index += 1;
+            continue;
+        };
+        flags[index] = LEGAL;
+        results[index] = Some((Wdl::Draw, 0));
+
+        let legal_moves = moves::get_legal_moves(&position);
+        if legal_moves.is_empty() {
+            if position::is_in_check(&position, &position.side_to_move) {
+                schedule(&mut queue, 0, index, false);
+            } else {
+                flags[index] |= SETTLED;
+            }
+            index += 1;
+            continue;
+        }
+
+        let mut children = Vec::with_capacity(legal_moves.len());
+        let mut fastest_exit: Option<usize> = None;
+        for mv in &legal_moves {
+            let mut child = position.clone();
+            moves::make_move(&mut child, mv);
+
+            match exit(&position, &child, mv)? {
+                None if child.en_passant_index < 0 || !can_take_en_passant(&child) => children.push(layout.index_of(&child, false)),
+                None => {
+                    let key = layout.en_passant_key(&child);
+                    let child_index = *en_passant_indexes.entry(key).or_insert_with(|| {
+                        en_passant_positions.push(child);
+                        double_pushes_to.push(Vec::new());
+                        with_en_passant.entry(key.0).or_default().push(layout.size + en_passant_positions.len() - 1);
+                        layout.size + en_passant_positions.len() - 1
+                    });
+                    if child_index >= results.len() {
+                        results.push(None);
+                        flags.push(0);
+                        unsettled_moves.push(0);
+                        longest_exit.push(0);
+                    }
+                    double_pushes_to[child_index - layout.size].push(index);
+                    children.push(child_index);
+                }
+                Some((Wdl::Loss, plies)) => {
+                    fastest_exit = Some(fastest_exit.map_or(plies + 1, |fastest| fastest.min(plies + 1)));
+                    flags[index] |= CANNOT_LOSE;
+                }
+                Some((Wdl::Win, plies)) => longest_exit[index] = longest_exit[index].max(plies + 1),
+                Some(_) => flags[index] |= CANNOT_LOSE,
+            }
+        }
+
+        children.sort_unstable();
+        children.dedup();
+        unsettled_moves[index] = children.len() as u8;
+
+        if let Some(plies) = fastest_exit {
+            schedule(&mut queue, plies, index, true);
+        } else if children.is_empty() && flags[index] & CANNOT_LOSE == 0 {
+            schedule(&mut queue, longest_exit[index], index, false);
+        }
+        index += 1;
+    }
+
+    let mut plies = 0;
+    while plies < queue.len() {
+        for (index, is_win) in std::mem::take(&mut queue[plies]) {
+            if flags[index] & SETTLED != 0 {
+                continue;
+            }
+            flags[index] |= SETTLED;
+            let result = if is_win { Wdl::Win } else { Wdl::Loss };
+            results[index] = Some((result, u8::try_from(plies).map_err(|_| format!("A result takes {} plies, more than the tables keep.", plies))?));
+
+            // A position with an en passant square is only reached by its double pushes. A position in the table is
+            // reached by every move into it, from the position before it with or without an en passant square,
+            // except double pushes that could be taken en passant.
+            let predecessors: Vec<usize> = if index >= layout.size {
+                double_pushes_to[index - layout.size].clone()
+            } else {
+                let position = layout.position(index).expect("Only legal positions are queued.");
+                layout.predecessors(&position).into_iter()
+                    .filter(|(_, en_passant_square)| en_passant_square.is_none_or(|square| {
+                        let mut after_double_push = position.clone();
+                        after_double_push.en_passant_index = square;
+                        !en_passant_indexes.contains_key(&layout.en_passant_key(&after_double_push))
+                    }))
+                    .flat_map(|(predecessor, _)| std::iter::once(predecessor).chain(with_en_passant.get(&predecessor).into_iter().flatten().copied()))
+                    .collect()
+            };
+
+            for predecessor in predecessors {
+                if flags[predecessor] & SETTLED != 0 {
+                    continue;
+                }
+
+                if !is_win {
+                    schedule(&mut queue, plies + 1, predecessor, true);
+                } else if flags[predecessor] & CANNOT_LOSE == 0 {
+                    unsettled_moves[predecessor] -= 1;
+                    if unsettled_moves[predecessor] == 0 {
+                        schedule(&mut queue, (plies + 1).max(longest_exit[predecessor]), predecessor, false);
+                    }
+                }
+            }
+        }
+        plies += 1;
+    }
+
+    // Only the table itself is kept; positions with an en passant square aren't probed.
+    results.truncate(layout.size);
+    Ok(results)
+}
+
+/// Whether the side to move can take the pawn that just moved two squares.
+fn can_take_en_passant(position: &Position) -> bool {
+    moves::get_legal_moves(position).iter()
+        .any(|mv| mv.to == position.en_passant_index && position::get_piece_at(position, mv.from).0 == Piece::Pawn)
+}
+
+/// The table file for the entries of an ending.
+pub fn write_table(material: &Material, entries: &[u8]) -> Vec<u8> {
+    let mut offsets: Vec<u32> = Vec::new();
+    let mut data: Vec<u8> = Vec::new();
+
+    for block in entries.chunks(BLOCK_SIZE) {
+        offsets.push(data.len() as u32);
+
+        let mut start = 0;
+        while start < block.len() {
+            let length = block[start..].iter().take(255).take_while(|entry| **entry == block[start]).count();
+            data.extend([length as u8, block[start]]);
+            start += length;
+        }
+    }
+    offsets.push(data.len() as u32);
+
+    let signature = material.signature();
+    let mut bytes = Vec::with_capacity(data.len() + offsets.len() * 4 + 32);
+    bytes.extend(MAGIC);
+    bytes.extend(VERSION.to_le_bytes());
+    bytes.push(signature.len() as u8);
+    bytes.extend(signature.as_bytes());
+    bytes.extend((entries.len() as u32).to_le_bytes());
+    bytes.extend(((offsets.len() - 1) as u32).to_le_bytes());
+    for offset in offsets {
+        bytes.extend(offset.to_le_bytes());
+    }
+    bytes.extend(data);
+    bytes
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, count: usize) -> Result<&[u8], String> {
+        let end = self.offset.checked_add(count).filter(|end| *end <= self.bytes.len()).ok_or("The table file is truncated.")?;
+        let taken = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+fn read_table(bytes: &[u8]) -> Result<(Material, Table), String> {
+    let mut reader = Reader { bytes, offset: 0 };
+
+    if reader.take(4)? != MAGIC {
+        return Err("Not a table file.".to_string());
+    }
+    let version = reader.u32()?;
+    if version != VERSION {
+        return Err(format!("Unsupported table version {}.", version));
+    }
+
+    let signature_length = reader.take(1)?[0] as usize;
+    let signature = String::from_utf8_lossy(reader.take(signature_length)?).to_string();
+    let material = Material::parse(&signature).filter(|material| material.is_canonical() && (3..=4).contains(&material.piece_count()))
+        .ok_or_else(|| format!("Unknown ending {}.", signature))?;
+    let layout = Layout::new(&material);
+
+    let size = reader.u32()? as usize;
+    let blocks = reader.u32()? as usize;
+    if size != layout.size || blocks != size.div_ceil(BLOCK_SIZE) {
+        return Err(format!("The table of {} has the wrong size.", signature));
+    }
+
+    let offsets: Vec<u32> = reader.take((blocks + 1) * 4)?.chunks_exact(4).map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap())).collect();
+    let data = bytes[reader.offset..].to_vec();
+
+    for block in 0..blocks {
+        let (start, end) = (offsets[block] as usize, offsets[block + 1] as usize);
+        let expected = BLOCK_SIZE.min(size - block * BLOCK_SIZE);
+        let covered = data.get(start..end).filter(|runs| runs.len() % 2 == 0)
+            .map(|runs| runs.chunks_exact(2).map(|run| run[0] as usize).sum::<usize>());
+        if covered != Some(expected) {
+            return Err(format!("The table of {} is corrupt.", signature));
+        }
+    }
+
+    Ok((material, Table { layout, entries: Entries::Compressed { offsets, data } }))
+}
+
+// Unit Tests! //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::fen;
+
+    #[test]
+    fn test_endings() {
+        let three: Vec<String> = endings(3).iter().map(|material| material.signature()).collect();
+        assert_eq!(three, vec!["KQvK", "KRvK", "KBvK", "KNvK", "KPvK"]);
+
+        let four = endings(4);
+        assert_eq!(four.len(), 35, "There are 5 endings of three pieces and 30 of four.");
+        assert!(four.iter().all(|material| Material::parse(&material.signature()).as_ref() == Some(material)));
+
+        let position = |signature: &str| four.iter().position(|material| material.signature() == signature).unwrap();
+        assert!(position("KPvK") < position("KQvKP") && position("KQvKP") < position("KPvKP"), "Promotions should lead to endings made earlier.");
+        assert!(!Material::parse("KvKQ").unwrap().is_canonical());
+    }
+
+    #[test]
+    fn test_symmetries_share_an_index() {
+        let material = Material::parse("KRvKN").unwrap();
+        let layout = Layout::new(&material);
+
+        let fens = [
+            "8/8/8/5k2/8/2n5/8/1K5R w - - 0 1",
+            "8/8/8/2k5/8/5n2/8/R5K1 w - - 0 1",
+            "1K5R/8/2n5/8/5k2/8/8/8 w - - 0 1",
+            "R7/8/4k3/8/8/2n5/K7/8 w - - 0 1",
+        ];
+        let indexes: Vec<usize> = fens.iter().map(|fen| layout.index_of(&fen::position_from_fen(fen), false)).collect();
+        assert!(indexes.iter().all(|index| *index == indexes[0]), "Mirrored and rotated boards should have one index.");
+
+        let position = layout.position(indexes[0]).expect("The smallest index of a legal position is its own.");
+        assert_eq!(layout.index_of(&position, false), indexes[0]);
+
+        // The same ending with the colors swapped is probed with the board flipped.
+        let flipped = fen::position_from_fen("1k5r/8/2N5/8/5K2/8/8/8 b - - 0 1");
+        assert_eq!(layout.index_of(&flipped, !Material::of(&flipped).is_canonical()), indexes[0]);
+    }
+
+    #[test]
+    fn test_generates_and_reads_back_kqk() {
+        let material = Material::parse("KQvK").unwrap();
+        let mut tablebase = DtmTablebase::default();
+        let entries = tablebase.generate(&material).expect("KQvK only needs KvK, which is always a draw.");
+
+        let longest = entries.iter().filter(|entry| **entry % 2 == 0).map(|entry| decode_entry(*entry).1).max();
+        assert_eq!(longest, Some(19), "The longest KQvK mate is in 10 moves.");
+
+        let probe = |fen: &str| (tablebase.probe_wdl(&fen::position_from_fen(fen)), tablebase.probe_dtm(&fen::position_from_fen(fen)));
+        assert_eq!(probe("k7/8/1K6/8/8/8/8/7Q w - - 0 1"), (Some(Wdl::Win), Some(1)));
+        assert_eq!(probe("k6Q/8/1K6/8/8/8/8/8 b - - 0 1"), (Some(Wdl::Loss), Some(0)));
+        assert_eq!(probe("K7/8/1k6/8/8/8/8/7q b - - 0 1"), (Some(Wdl::Win), Some(1)), "Black's queen should be found with the board flipped.");
+        assert_eq!(probe("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1"), (Some(Wdl::Draw), None), "Stalemate is a draw.");
+        assert_eq!(probe("k7/1Q6/8/8/8/8/8/7K b - - 0 1"), (Some(Wdl::Draw), None), "The king takes the queen.");
+
+        let bytes = write_table(&material, &entries);
+        assert!(bytes.len() < entries.len() / 2, "The table should compress.");
+        let (read_material, table) = read_table(&bytes).expect("The table should read back.");
+        assert_eq!(read_material, material);
+        assert!((0..entries.len()).all(|index| table.entry(index) == entries[index]));
+
+        assert!(read_table(&bytes[..bytes.len() - 1]).is_err(), "A truncated table should be rejected.");
+        assert!(read_table(b"MBNN").is_err());
+    }
+
+    #[test]
+    fn test_impossible_positions_are_not_probed() {
+        let mut tablebase = DtmTablebase::default();
+        for signature in ["KQvK", "KRvK", "KBvK", "KNvK", "KPvK"] {
+            tablebase.generate(&Material::parse(signature).unwrap()).expect("The endings should come after the ones they lead to.");
+        }
+
+        let mut back_rank_pawn = fen::position_from_fen("7k/8/8/8/8/8/7P/K7 w - - 0 1");
+        position::remove_piece_at(&mut back_rank_pawn, 15);
+        position::set_piece_at(&mut back_rank_pawn, 7, Piece::Pawn, &Color::White);
+        assert_eq!(tablebase.probe_wdl(&back_rank_pawn), None, "A pawn on the first rank isn't in the tables.");
+
+        let mut no_king = fen::position_from_fen("7k/8/8/8/8/8/8/KQ6 w - - 0 1");
+        position::remove_piece_at(&mut no_king, 63);
+        assert_eq!(tablebase.probe_wdl(&no_king), None, "A position without both kings isn't in the tables.");
+    }
+
+    #[test]
+    #[ignore = "solves KPvKP, which takes minutes; run it with cargo test --release -- --ignored"]
+    fn test_en_passant_in_kpvkp() {
+        let mut tablebase = DtmTablebase::default();
+        for material in endings(3) {
+            tablebase.generate(&material).expect("The endings of three pieces only need each other.");
+        }
+        // The endings a promotion leads to are stood in for by draws. What matters here is en passant, and the table
+        // has to agree with itself about it whatever the promotions are worth.
+        for signature in ["KQvKP", "KRvKP", "KBvKP", "KNvKP"] {
+            let material = Material::parse(signature).unwrap();
+            let layout = Layout::new(&material);
+            let entries = Entries::Plain(vec![DRAW; layout.size]);
+            tablebase.insert(material, Table { layout, entries });
+        }
+
+        let material = Material::parse("KPvKP").unwrap();
+        let entries = tablebase.generate(&material).expect("KPvKP's captures and promotions are all here.");
+        let layout = Layout::new(&material);
+
+        // The entry of a position worked out from its moves, looking up where they lead in the tables.
+        fn solve(position: &Position, layout: &Layout, entries: &[u8], tablebase: &DtmTablebase) -> u8 {
+            let legal_moves = moves::get_legal_moves(position);
+            if legal_moves.is_empty() {
+                return if position::is_in_check(position, &position.side_to_move) { mate_entry(0) } else { DRAW };
+            }
+
+            let results: Vec<(Wdl, i32)> = legal_moves.iter().map(|mv| {
+                let mut child = position.clone();
+                moves::make_move(&mut child, mv);
+                let entry = if child.en_passant_index >= 0 && can_take_en_passant(&child) {
+                    solve(&child, layout, entries, tablebase)
+                } else if Material::of(&child) == Material::of(position) {
+                    entries[layout.index_of(&child, false)]
+                } else {
+                    tablebase.entry(&child).expect("Captures and promotions lead to the other tables.")
+                };
+                decode_entry(entry)
+            }).collect();
+
+            if let Some(plies) = results.iter().filter(|(result, _)| *result == Wdl::Loss).map(|(_, plies)| *plies).min() {
+                mate_entry(plies as usize + 1)
+            } else if results.iter().any(|(result, _)| *result == Wdl::Draw) {
+                DRAW
+            } else {
+                mate_entry(results.iter().map(|(_, plies)| *plies).max().unwrap() as usize + 1)
+            }
+        }
+
+        let mut changed = 0;
+        for index in 0..layout.size {
+            let Some(position) = layout.position(index) else { continue };
+            let double_pushes = moves::get_legal_moves(&position).into_iter().filter_map(|mv| {
+                let mut child = position.clone();
+                moves::make_move(&mut child, &mv);
+                Some(child).filter(|child| child.en_passant_index >= 0 && can_take_en_passant(child))
+            }).collect::<Vec<Position>>();
+            if double_pushes.is_empty() {
+                continue;
+            }
+
+            assert_eq!(entries[index], solve(&position, &layout, &entries, &tablebase), "{} doesn't agree with its moves.", fen::fen_from_position(&position));
+            for child in double_pushes {
+                if solve(&child, &layout, &entries, &tablebase) != entries[layout.index_of(&child, false)] {
+                    changed += 1;
+                }
+            }
+        }
+        assert!(changed > 0, "Some double pushes should be worth something else because of en passant.");
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::sync::OnceLock;
+
+use super::cli::Flags;
+use super::fen;
+use super::moves::{self, Move};
+use super::pgn::{self, PgnGame, PgnReader};
+use super::polyglot;
+use super::position::Position;
+
+// The openings are kept in eco/a.tsv to eco/e.tsv, one per line: the ECO code, the name and the moves from the starting
+// position. The files are split by ECO volume and have the same columns as the lichess chess-openings tables, so
+// those can be dropped in as they are.
+//
+// Openings are found by the Polyglot hash of the position they reach rather than by their moves, so a game that gets
+// there by another move order is classified the same. The Polyglot hash only counts en passant squares a pawn can
+// take on, which `Position::hash` doesn't, and without that 1. Nf3 d5 2. d4 wouldn't be 1. d4 d5 2. Nf3.
+
+const TABLES: [&str; 5] = [include_str!("eco/a.tsv"), include_str!("eco/b.tsv"), include_str!("eco/c.tsv"), include_str!("eco/d.tsv"), include_str!("eco/e.tsv")];
+
+/// The lines of every volume, without their headers.
+fn table_lines() -> impl Iterator<Item = &'static str> {
+    TABLES.iter().flat_map(|table| table.lines().skip(1))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Opening {
+    pub eco: &'static str,
+    pub name: &'static str,
+    /// How many plies the opening's moves are.
+    pub plies: usize,
+}
+
+fn openings() -> &'static HashMap<u64, Opening> {
+    static OPENINGS: OnceLock<HashMap<u64, Opening>> = OnceLock::new();
+    OPENINGS.get_or_init(|| {
+        let mut openings = HashMap::new();
+        for line in table_lines() {
+            let (position, opening) = parse_line(line).unwrap_or_else(|error| panic!("Bad line in the ECO table, {}: {}", line, error));
+            // The first line for a position names it.
+            openings.entry(polyglot::polyglot_key(&position)).or_insert(opening);
+        }
+        openings
+    })
+}
+
+fn parse_line(line: &'static str) -> Result<(Position, Opening), String> {
+    let mut columns = line.split('\t');
+    let (Some(eco), Some(name), Some(movetext)) = (columns.next(), columns.next(), columns.next()) else {
+        return Err("Expected an ECO code, a name and moves.".to_string());
+    };
+
+    let mut position = fen::position_from_fen(fen::STARTING_FEN);
+    let mut plies = 0;
+    for token in movetext.split_whitespace().filter(|token| !token.ends_with('.')) {
+        let mv = pgn::move_from_san(&position, token).ok_or_else(|| format!("Illegal move {}.", token))?;
+        moves::make_move(&mut position, &mv);
+        plies += 1;
+    }
+
+    Ok((position, Opening { eco, name, plies }))
+}
+
+/// The opening a position is known as, if it's in the table.
+pub fn classify_position(position: &Position) -> Option<Opening> {
+    openings().get(&polyglot::polyglot_key(position)).copied()
+}
+
+/// The opening of a game: the deepest line the table knows among the positions along it. A game that goes back to a
+/// shallower opening, say by moving its knights out and back, keeps the deeper one.
+pub fn classify(start: &Position, moves: &[Move]) -> Option<Opening> {
+    let mut position = start.clone();
+    let mut found = vec![classify_position(&position)];
+    for mv in moves {
+        moves::make_move(&mut position, mv);
+        found.push(classify_position(&position));
+    }
+    found.into_iter().flatten().max_by_key(|opening| opening.plies)
+}
+
+/// Adds `ECO` and `Opening` tags to a game whose opening is known, and returns the opening.
+pub fn tag_game(game: &mut PgnGame) -> Option<Opening> {
+    let opening = classify(&game.start_position(), &game.moves)?;
+    game.set_tag("ECO", opening.eco);
+    game.set_tag("Opening", opening.name);
+    Some(opening)
+}
+
+/// `eco --pgn <file> [--output <file>]` writes the games of a PGN file with `ECO` and `Opening` tags, to standard
+/// output without `--output`.
+pub fn run(flags: &Flags) -> Result<(), String> {
+    let pgn_path = flags.require("pgn")?;
+    let file = File::open(&pgn_path).map_err(|error| format!("Couldn't open {}: {}.", pgn_path, error))?;
+
+    let mut output = String::new();
+    let (mut games, mut classified, mut skipped) = (0, 0, 0);
+    for game in PgnReader::new(BufReader::new(file)) {
+        let mut game = match game {
+            Ok(game) => game,
+            Err(error) => {
+                // A game that doesn't read can't be written back either, so it's left out.
+                skipped += 1;
+                eprintln!("Skipped a game: {}", error);
+                continue;
+            }
+        };
+        games += 1;
+        if tag_game(&mut game).is_some() {
+            classified += 1;
+        }
+        output.push_str(&game.to_pgn());
+        output.push('\n');
+    }
+
+    match flags.get("output", String::new())? {
+        path if path.is_empty() => print!("{}", output),
+        path => fs::write(&path, output).map_err(|error| format!("Couldn't write {}: {}.", path, error))?,
+    }
+    eprintln!("Classified {} of {} games ({} games couldn't be read).", classified, games, skipped);
+    Ok(())
+}
+
+// Unit Tests! //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table() {
+        let mut codes = std::collections::HashSet::new();
+        for line in table_lines() {
+            let (_, opening) = parse_line(line).unwrap_or_else(|error| panic!("{} doesn't read: {}", line, error));
+            assert!(opening.eco.len() == 3 && ('A'..='E').contains(&opening.eco.chars().next().unwrap()), "{} isn't an ECO code.", opening.eco);
+            codes.insert(opening.eco);
+        }
+        for volume in ['A', 'B', 'C', 'D', 'E'] {
+            for number in 0..100 {
+                let code = format!("{}{:02}", volume, number);
+                assert!(codes.contains(code.as_str()), "{} should be in the table.", code);
+            }
+        }
+    }
+
+    #[test]
+    fn test_classify_games() {
+        let text = r#"[Event "Berlin"]
+
+1. e4 e5 2. Nf3 Nc6 3. Bb5 Nf6 4. O-O Nxe4 5. d4 *
+
+[Event "Transposed"]
+
+1. Nf3 d5 2. d4 Nf6 *
+
+[Event "Anderssen"]
+
+1. a3 e5 2. h4 *
+"#;
+        let mut games: Vec<PgnGame> = pgn::parse_games(text).into_iter().map(|game| game.expect("The games should read.")).collect();
+
+        assert_eq!(tag_game(&mut games[0]).map(|opening| opening.eco), Some("C67"), "The last known position is the deepest.");
+        assert_eq!(games[0].tag("Opening"), Some("Ruy Lopez: Berlin Defense, Rio Gambit Accepted"));
+        assert!(games[0].to_pgn().contains("[ECO \"C67\"]\n[Opening \"Ruy Lopez: Berlin Defense, Rio Gambit Accepted\"]"));
+
+        assert_eq!(tag_game(&mut games[1]).map(|opening| opening.eco), Some("D02"), "1. Nf3 d5 2. d4 is 1. d4 d5 2. Nf3.");
+        assert_eq!(tag_game(&mut games[2]).map(|opening| opening.name), Some("Anderssen's Opening"), "1. a3 is A00 however the game goes on.");
+        assert_eq!(games[2].tag("ECO"), Some("A00"));
+
+        let knights_back = pgn::parse_games("1. Nf3 Nf6 2. Ng1 Ng8 3. e4 *").remove(0).expect("The game should read.");
+        let opening = classify(&knights_back.start_position(), &knights_back.moves).expect("1. Nf3 Nf6 is in the table.");
+        assert_eq!((opening.eco, opening.plies), ("A05", 2), "1. e4 is in the table too, but it's a shallower line than 1. Nf3 Nf6.");
+
+        let french = fen::position_from_fen("rnbqkbnr/ppp2ppp/4p3/3pP3/3P4/8/PPP2PPP/RNBQKBNR b KQkq - 0 3");
+        assert_eq!(classify_position(&french).map(|opening| opening.name), Some("French Defense: Advance Variation"));
+    }
+}
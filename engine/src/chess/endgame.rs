@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use super::attacks;
+use super::dtm::Material;
+use super::eval;
+use super::piece::{self, Color, Piece};
+use super::position::{self, Position};
+
+// Endings the general evaluation gets wrong, found by their material. An evaluator replaces the evaluation with one
+// that knows how the ending is won, and a scale factor shrinks it towards a draw. Signatures are written with the
+// stronger side first, and a P stands for any number of pawns, so "KBPvK" is a bishop and pawns against a bare king.
+
+/// Scale factors are out of this, and 0 is a dead draw.
+pub const SCALE_NORMAL: i32 = 64;
+/// Added to a won ending's score, so that winning it is worth more than any material the evaluation could see.
+pub const KNOWN_WIN: i32 = 10000;
+
+/// The score for the stronger side, or `None` to leave the position to the general evaluation.
+type Evaluator = fn(&Position, Color) -> Option<i32>;
+type ScaleFactor = fn(&Position, Color) -> i32;
+
+#[derive(Clone, Copy)]
+enum Endgame {
+    Evaluate(Evaluator),
+    Scale(ScaleFactor),
+}
+
+const ENDGAMES: [(&str, Endgame); 10] = [
+    ("KPvK", Endgame::Evaluate(kpk)),
+    ("KBNvK", Endgame::Evaluate(kbnk)),
+    ("KRvK", Endgame::Evaluate(mop_up)),
+    ("KQvK", Endgame::Evaluate(mop_up)),
+    ("KQRvK", Endgame::Evaluate(mop_up)),
+    ("KRvKB", Endgame::Scale(rook_against_minor)),
+    ("KRvKN", Endgame::Scale(rook_against_minor)),
+    ("KBPvKB", Endgame::Scale(opposite_bishops)),
+    ("KBPvKBP", Endgame::Scale(opposite_bishops)),
+    ("KBPvK", Endgame::Scale(wrong_bishop)),
+];
+
+/// Every registered ending doesn't have more than this game phase, so anything above it is skipped without a lookup.
+const MAX_ENDGAME_PHASE: i32 = 6;
+
+/// The material of a position as a number: 3 bits for each piece count, capped at 7, and 1 bit for whether a side
+/// has pawns.
+fn material_key(counts: [[u32; 5]; 2]) -> u32 {
+    let mut key = 0;
+    for side in counts {
+        key = (key << 1) | side[0].min(1);
+        for count in &side[1..] {
+            key = (key << 3) | count.min(&7);
+        }
+    }
+    key
+}
+
+/// Pawns, knights, bishops, rooks and queens of each side.
+fn position_counts(position: &Position) -> [[u32; 5]; 2] {
+    [0, 6].map(|offset| std::array::from_fn(|piece| position.bitboards[offset + piece].count_ones()))
+}
+
+fn material_counts(material: &Material) -> [[u32; 5]; 2] {
+    [&material.white, &material.black].map(|pieces| std::array::from_fn(|slot| pieces.iter().filter(|piece| **piece as usize == slot).count() as u32))
+}
+
+/// The registered endings by material key, each with the color of its stronger side.
+fn endgames() -> &'static HashMap<u32, (Endgame, Color)> {
+    static ENDGAME_TABLE: OnceLock<HashMap<u32, (Endgame, Color)>> = OnceLock::new();
+
+    ENDGAME_TABLE.get_or_init(|| {
+        let mut table = HashMap::new();
+        for (signature, endgame) in ENDGAMES {
+            let [white, black] = material_counts(&Material::parse(signature).expect("Endgame signatures should parse."));
+            table.entry(material_key([black, white])).or_insert((endgame, Color::Black));
+            table.insert(material_key([white, black]), (endgame, Color::White));
+        }
+        table
+    })
+}
+
+/// Applies the endgame knowledge for the position to its evaluation, which is from the side to move's point of view.
+pub fn adjust(position: &Position, score: i32) -> i32 {
+    if position.phase > MAX_ENDGAME_PHASE {
+        return score;
+    }
+    let Some((endgame, strong)) = endgames().get(&material_key(position_counts(position))) else {
+        return score;
+    };
+
+    match endgame {
+        Endgame::Evaluate(evaluate) => match evaluate(position, *strong) {
+            Some(value) if position.side_to_move == *strong => value,
+            Some(value) => -value,
+            None => score,
+        },
+        Endgame::Scale(scale) => score * scale(position, *strong) / SCALE_NORMAL,
+    }
+}
+
+fn file(square: i32) -> i32 {
+    square % 8
+}
+
+fn rank(square: i32) -> i32 {
+    square / 8
+}
+
+/// King moves between two squares.
+fn distance(from: i32, to: i32) -> i32 {
+    (file(from) - file(to)).abs().max((rank(from) - rank(to)).abs())
+}
+
+/// 0 in the middle of the board, up to 6 in the corners.
+fn centre_distance(square: i32) -> i32 {
+    (3 - file(square).min(7 - file(square))) + (3 - rank(square).min(7 - rank(square)))
+}
+
+fn is_dark(square: i32) -> bool {
+    (file(square) + rank(square)) % 2 == 0
+}
+
+fn king(position: &Position, color: Color) -> i32 {
+    position::get_king_index(position, &color)
+}
+
+fn bitboard(position: &Position, piece: Piece, color: Color) -> i64 {
+    position.bitboards[piece::piece_id(&piece, &color) as usize]
+}
+
+/// The material of a side in endgame values, kings and pawns left out.
+fn piece_material(position: &Position, color: Color) -> i32 {
+    [Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen].iter()
+        .map(|piece| bitboard(position, *piece, color).count_ones() as i32 * eval::PIECE_VALUES[*piece as usize].eg)
+        .sum()
+}
+
+/// Mates with enough material against a bare king: drive the king to the edge and bring the other king up.
+fn mop_up(position: &Position, strong: Color) -> Option<i32> {
+    let weak_king = king(position, piece::opponent_of(&strong));
+    Some(KNOWN_WIN + piece_material(position, strong) + 20 * centre_distance(weak_king) + 10 * (7 - distance(king(position, strong), weak_king)))
+}
+
+/// Bishop and knight mate only in a corner the bishop covers, so the king is driven to one of those.
+fn kbnk(position: &Position, strong: Color) -> Option<i32> {
+    let weak_king = king(position, piece::opponent_of(&strong));
+    let corners = if is_dark(bitboard(position, Piece::Bishop, strong).trailing_zeros() as i32) { [0, 63] } else { [7, 56] };
+    let corner_distance = corners.iter().map(|corner| (file(weak_king) - file(*corner)).abs() + (rank(weak_king) - rank(*corner)).abs()).min().unwrap();
+
+    Some(KNOWN_WIN + piece_material(position, strong) + 30 * (14 - corner_distance) + 20 * (7 - distance(king(position, strong), weak_king)) + 10 * centre_distance(weak_king))
+}
+
+/// King and pawn against king, looked up in the bitbase.
+fn kpk(position: &Position, strong: Color) -> Option<i32> {
+    let pawns = bitboard(position, Piece::Pawn, strong);
+    // The bitbase only has pawns on the second to seventh ranks, and both kings on the board.
+    if pawns.count_ones() != 1 || !(1..=6).contains(&rank(pawns.trailing_zeros() as i32)) || king(position, Color::White) < 0 || king(position, Color::Black) < 0 {
+        return None;
+    }
+
+    // The bitbase is from White's side with the pawn on files a to d.
+    let orient = |square: i32| -> i32 {
+        let square = if strong == Color::Black { square ^ 56 } else { square };
+        if file(pawns.trailing_zeros() as i32) >= 4 { square ^ 7 } else { square }
+    };
+    let strong_king = orient(king(position, strong));
+    let weak_king = orient(king(position, piece::opponent_of(&strong)));
+    let pawn = orient(pawns.trailing_zeros() as i32);
+    let side = if position.side_to_move == strong { 0 } else { 1 };
+
+    if !kpk_is_win(side, strong_king, weak_king, pawn) {
+        return Some(0);
+    }
+    Some(KNOWN_WIN + eval::PIECE_VALUES[Piece::Pawn as usize].eg + 20 * rank(pawn))
+}
+
+/// A rook can rarely beat a lone minor piece.
+fn rook_against_minor(_position: &Position, _strong: Color) -> i32 {
+    SCALE_NORMAL / 8
+}
+
+/// With only bishops on opposite colors and pawns, the defending bishop holds most positions with a pawn or two
+/// down.
+fn opposite_bishops(position: &Position, strong: Color) -> i32 {
+    let weak = piece::opponent_of(&strong);
+    let (strong_bishop, weak_bishop) = (bitboard(position, Piece::Bishop, strong), bitboard(position, Piece::Bishop, weak));
+    if strong_bishop.count_ones() != 1 || weak_bishop.count_ones() != 1 || is_dark(strong_bishop.trailing_zeros() as i32) == is_dark(weak_bishop.trailing_zeros() as i32) {
+        return SCALE_NORMAL;
+    }
+
+    let pawn_difference = (bitboard(position, Piece::Pawn, strong).count_ones() as i32 - bitboard(position, Piece::Pawn, weak).count_ones() as i32).abs();
+    (SCALE_NORMAL / 4 + 8 * pawn_difference).min(SCALE_NORMAL)
+}
+
+/// Rook pawns with a bishop that doesn't cover the promotion square are a draw once the defending king gets to
+/// the corner.
+fn wrong_bishop(position: &Position, strong: Color) -> i32 {
+    const A_FILE: i64 = 0x0101_0101_0101_0101;
+    const H_FILE: i64 = A_FILE << 7;
+
+    let pawns = bitboard(position, Piece::Pawn, strong);
+    let promotion_file = if pawns & !A_FILE == 0 { 0 } else if pawns & !H_FILE == 0 { 7 } else { return SCALE_NORMAL };
+    let promotion_square = if strong == Color::White { 56 + promotion_file } else { promotion_file };
+
+    let bishops = bitboard(position, Piece::Bishop, strong);
+    let covers_promotion_square = bishops.count_ones() != 1 || is_dark(bishops.trailing_zeros() as i32) == is_dark(promotion_square);
+    if !covers_promotion_square && distance(king(position, piece::opponent_of(&strong)), promotion_square) <= 1 {
+        0
+    } else {
+        SCALE_NORMAL
+    }
+}
+
+// The KPK bitbase has a bit for every position with White's pawn on files a to d that's a win for White. It's
+// worked out the first time it's needed, by marking the positions that are settled at once, like promotions and
+// captures of the pawn, and then going over the rest until nothing changes.
+
+const KPK_SIZE: usize = 2 * 24 * 64 * 64;
+
+const INVALID: u8 = 0;
+const UNKNOWN: u8 = 1;
+const DRAW: u8 = 2;
+const WIN: u8 = 4;
+
+/// `side` is 0 with White to move and 1 with Black to move.
+fn kpk_index(side: usize, white_king: i32, black_king: i32, pawn: i32) -> usize {
+    let pawn_slot = ((rank(pawn) - 1) * 4 + file(pawn)) as usize;
+    ((side * 24 + pawn_slot) * 64 + white_king as usize) * 64 + black_king as usize
+}
+
+fn kpk_is_win(side: usize, white_king: i32, black_king: i32, pawn: i32) -> bool {
+    static KPK: OnceLock<Vec<u64>> = OnceLock::new();
+
+    let bits = KPK.get_or_init(|| {
+        let results = generate_kpk();
+        let mut bits = vec![0u64; KPK_SIZE / 64];
+        for (index, result) in results.iter().enumerate() {
+            if *result == WIN {
+                bits[index / 64] |= 1 << (index % 64);
+            }
+        }
+        bits
+    });
+
+    let index = kpk_index(side, white_king, black_king, pawn);
+    bits[index / 64] & (1 << (index % 64)) != 0
+}
+
+fn kpk_squares() -> impl Iterator<Item = (usize, i32, i32, i32)> {
+    (0..2).flat_map(|side| (0..24).flat_map(move |slot| (0..64).flat_map(move |white_king| (0..64).map(move |black_king| (side, white_king, black_king, (slot / 4 + 1) * 8 + slot % 4)))))
+}
+
+fn generate_kpk() -> Vec<u8> {
+    let mut results = vec![INVALID; KPK_SIZE];
+    for (side, white_king, black_king, pawn) in kpk_squares() {
+        results[kpk_index(side, white_king, black_king, pawn)] = initial_kpk_result(side, white_king, black_king, pawn);
+    }
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (side, white_king, black_king, pawn) in kpk_squares() {
+            let index = kpk_index(side, white_king, black_king, pawn);
+            if results[index] == UNKNOWN {
+                results[index] = classify_kpk(&results, side, white_king, black_king, pawn);
+                changed |= results[index] != UNKNOWN;
+            }
+        }
+    }
+
+    results
+}
+
+fn initial_kpk_result(side: usize, white_king: i32, black_king: i32, pawn: i32) -> u8 {
+    let pawn_attacks = attacks::PAWN_ATTACKS[0][pawn as usize];
+    let promotion_square = pawn + 8;
+
+    if distance(white_king, black_king) <= 1 || white_king == pawn || black_king == pawn || (side == 0 && pawn_attacks & (1 << black_king) != 0) {
+        return INVALID;
+    }
+
+    // The pawn promotes and the new queen can't be taken.
+    if side == 0 && rank(pawn) == 6 && white_king != promotion_square && black_king != promotion_square
+        && (distance(black_king, promotion_square) > 1 || distance(white_king, promotion_square) == 1) {
+        return WIN;
+    }
+
+    // Black is stalemated or takes the pawn.
+    let black_moves = attacks::KING_ATTACKS[black_king as usize] & !(attacks::KING_ATTACKS[white_king as usize] | pawn_attacks);
+    let takes_pawn = attacks::KING_ATTACKS[black_king as usize] & !attacks::KING_ATTACKS[white_king as usize] & (1 << pawn) != 0;
+    if side == 1 && (black_moves == 0 || takes_pawn) {
+        return DRAW;
+    }
+
+    UNKNOWN
+}
+
+/// A position with White to move is won if any move wins, and one with Black to move is drawn if any move draws.
+/// Moves into check lead to invalid positions, which count as neither.
+fn classify_kpk(results: &[u8], side: usize, white_king: i32, black_king: i32, pawn: i32) -> u8 {
+    let mut reachable = 0;
+
+    if side == 0 {
+        let mut targets = attacks::KING_ATTACKS[white_king as usize];
+        while targets != 0 {
+            reachable |= results[kpk_index(1, attacks::pop_lsb(&mut targets), black_king, pawn)];
+        }
+        if rank(pawn) < 6 {
+            reachable |= results[kpk_index(1, white_king, black_king, pawn + 8)];
+        }
+        if rank(pawn) == 1 && pawn + 8 != white_king && pawn + 8 != black_king {
+            reachable |= results[kpk_index(1, white_king, black_king, pawn + 16)];
+        }
+
+        if reachable & WIN != 0 { WIN } else if reachable & UNKNOWN != 0 { UNKNOWN } else { DRAW }
+    } else {
+        let mut targets = attacks::KING_ATTACKS[black_king as usize];
+        while targets != 0 {
+            reachable |= results[kpk_index(0, white_king, attacks::pop_lsb(&mut targets), pawn)];
+        }
+
+        if reachable & DRAW != 0 { DRAW } else if reachable & UNKNOWN != 0 { UNKNOWN } else { WIN }
+    }
+}
+
+// Unit Tests! //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::fen;
+
+    fn evaluate(fen_string: &str) -> i32 {
+        eval::evaluate(&fen::position_from_fen(fen_string))
+    }
+
+    #[test]
+    fn test_kpk() {
+        assert_eq!(evaluate("k7/8/8/8/8/8/P7/K7 w - - 0 1"), 0, "A rook pawn can't get past a king in the corner.");
+        assert_eq!(evaluate("k7/p7/8/8/8/8/8/K7 b - - 0 1"), 0, "The same goes for Black's pawn.");
+        assert!(evaluate("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1") < -KNOWN_WIN, "A king on the sixth in front of its pawn wins.");
+        assert!(evaluate("8/8/8/8/4p3/4k3/8/4K3 b - - 0 1") > KNOWN_WIN, "Black's king on the third in front of its pawn wins.");
+        assert_eq!(evaluate("4k3/8/8/8/8/8/4P3/4K3 b - - 0 1"), 0, "Black keeps the opposition.");
+        assert!(evaluate("8/8/8/8/8/4k3/3P4/3K4 w - - 0 1") < KNOWN_WIN, "The king on e3 stops the pawn.");
+    }
+
+    #[test]
+    fn test_kpk_leaves_impossible_positions_alone() {
+        let mut back_rank_pawn = fen::position_from_fen("7k/8/8/8/8/8/7P/K7 w - - 0 1");
+        position::remove_piece_at(&mut back_rank_pawn, 15);
+        position::set_piece_at(&mut back_rank_pawn, 7, Piece::Pawn, &Color::White);
+        assert_eq!(kpk(&back_rank_pawn, Color::White), None, "A pawn on the first rank isn't in the bitbase.");
+        eval::evaluate(&back_rank_pawn);
+
+        let mut no_king = fen::position_from_fen("k7/8/8/8/8/8/P7/K7 w - - 0 1");
+        position::remove_piece_at(&mut no_king, 56);
+        assert_eq!(kpk(&no_king, Color::White), None, "A position without both kings isn't in the bitbase.");
+        eval::evaluate(&no_king);
+    }
+
+    #[test]
+    fn test_mates_against_a_bare_king() {
+        // The bare king belongs in a corner the bishop covers, a1 or h8 for a dark square bishop.
+        let right_corner = evaluate("8/8/8/8/3K4/8/8/kNB5 w - - 0 1");
+        let wrong_corner = evaluate("8/8/8/8/4K3/8/8/1NB4k w - - 0 1");
+        assert!(right_corner > wrong_corner && wrong_corner > KNOWN_WIN);
+
+        assert!(evaluate("8/8/8/3k4/8/8/8/K6Q w - - 0 1") > KNOWN_WIN);
+        assert!(evaluate("8/8/8/3k4/8/8/8/K6Q b - - 0 1") < -KNOWN_WIN);
+        assert!(evaluate("7k/8/6K1/8/8/8/8/R7 w - - 0 1") > evaluate("8/8/8/3k4/8/8/8/K6R w - - 0 1"), "Mating nets should score higher.");
+    }
+
+    #[test]
+    fn test_drawish_endings_are_scaled() {
+        // The dark square bishop can't cover a8, and Black's king is already there.
+        assert_eq!(evaluate("k7/8/8/8/8/P7/P7/K1B5 w - - 0 1"), 0);
+        assert!(evaluate("8/8/8/8/8/5k2/P7/K1B5 w - - 0 1") > 0);
+
+        for fen_string in ["4k3/8/8/4b3/8/8/PP6/3BK3 w - - 0 1", "8/8/4k3/3b4/8/4K3/8/R7 w - - 0 1"] {
+            let position = fen::position_from_fen(fen_string);
+            let unscaled = eval::trace(&position).blended * if position.side_to_move == Color::Black { -1 } else { 1 };
+            assert!(eval::evaluate(&position).abs() < unscaled.abs(), "{} should be scaled towards a draw.", fen_string);
+        }
+
+        let same_colored_bishops = fen::position_from_fen("4k3/8/8/3b4/8/8/PP6/3BK3 w - - 0 1");
+        assert_eq!(eval::evaluate(&same_colored_bishops), eval::trace(&same_colored_bishops).blended);
+    }
+}
@@ -0,0 +1,625 @@
+use std::ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign};
+
+use super::attacks;
+use super::endgame;
+use super::mobility;
+use super::pawns::{self, PawnEntry, PawnTable};
+use super::piece::{self, Color};
+use super::pieces;
+use super::position::Position;
+use super::safety;
+
+/// A middlegame and an endgame value, blended by the game phase when the position is evaluated.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub struct Score {
+    pub mg: i32,
+    pub eg: i32,
+}
+
+pub const fn s(mg: i32, eg: i32) -> Score {
+    Score { mg, eg }
+}
+
+impl Add for Score {
+    type Output = Score;
+    fn add(self, other: Score) -> Score {
+        s(self.mg + other.mg, self.eg + other.eg)
+    }
+}
+
+impl Sub for Score {
+    type Output = Score;
+    fn sub(self, other: Score) -> Score {
+        s(self.mg - other.mg, self.eg - other.eg)
+    }
+}
+
+impl Neg for Score {
+    type Output = Score;
+    fn neg(self) -> Score {
+        s(-self.mg, -self.eg)
+    }
+}
+
+impl Mul<i32> for Score {
+    type Output = Score;
+    fn mul(self, factor: i32) -> Score {
+        s(self.mg * factor, self.eg * factor)
+    }
+}
+
+impl AddAssign for Score {
+    fn add_assign(&mut self, other: Score) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Score {
+    fn sub_assign(&mut self, other: Score) {
+        *self = *self - other;
+    }
+}
+
+// The material values and piece-square tables are PeSTO's, by Ronald Friederich.
+
+/// Indexed by `piece::Piece`.
+pub const PIECE_VALUES: [Score; 6] = [s(82, 94), s(337, 281), s(365, 297), s(477, 512), s(1025, 936), s(0, 0)];
+
+/// How much each piece counts towards the game phase. A full board of pieces is `MAX_PHASE`, the middlegame, and
+/// the phase falls towards 0, the endgame, as pieces come off.
+pub const PHASE_WEIGHTS: [i32; 6] = [0, 1, 1, 2, 4, 0];
+pub const MAX_PHASE: i32 = 24;
+
+// Piece-square tables from White's point of view, written with rank 8 at the top so they read like a board.
+
+const MG_PAWN: [i32; 64] = [
+      0,   0,   0,   0,   0,   0,   0,   0,
+     98, 134,  61,  95,  68, 126,  34, -11,
+     -6,   7,  26,  31,  65,  56,  25, -20,
+    -14,  13,   6,  21,  23,  12,  17, -23,
+    -27,  -2,  -5,  12,  17,   6,  10, -25,
+    -26,  -4,  -4, -10,   3,   3,  33, -12,
+    -35,  -1, -20, -23, -15,  24,  38, -22,
+      0,   0,   0,   0,   0,   0,   0,   0,
+];
+
+const EG_PAWN: [i32; 64] = [
+      0,   0,   0,   0,   0,   0,   0,   0,
+    178, 173, 158, 134, 147, 132, 165, 187,
+     94, 100,  85,  67,  56,  53,  82,  84,
+     32,  24,  13,   5,  -2,   4,  17,  17,
+     13,   9,  -3,  -7,  -7,  -8,   3,  -1,
+      4,   7,  -6,   1,   0,  -5,  -1,  -8,
+     13,   8,   8,  10,  13,   0,   2,  -7,
+      0,   0,   0,   0,   0,   0,   0,   0,
+];
+
+const MG_KNIGHT: [i32; 64] = [
+   -167, -89, -34, -49,  61, -97, -15,-107,
+    -73, -41,  72,  36,  23,  62,   7, -17,
+    -47,  60,  37,  65,  84, 129,  73,  44,
+     -9,  17,  19,  53,  37,  69,  18,  22,
+    -13,   4,  16,  13,  28,  19,  21,  -8,
+    -23,  -9,  12,  10,  19,  17,  25, -16,
+    -29, -53, -12,  -3,  -1,  18, -14, -19,
+   -105, -21, -58, -33, -17, -28, -19, -23,
+];
+
+const EG_KNIGHT: [i32; 64] = [
+    -58, -38, -13, -28, -31, -27, -63, -99,
+    -25,  -8, -25,  -2,  -9, -25, -24, -52,
+    -24, -20,  10,   9,  -1,  -9, -19, -41,
+    -17,   3,  22,  22,  22,  11,   8, -18,
+    -18,  -6,  16,  25,  16,  17,   4, -18,
+    -23,  -3,  -1,  15,  10,  -3, -20, -22,
+    -42, -20, -10,  -5,  -2, -20, -23, -44,
+    -29, -51, -23, -15, -22, -18, -50, -64,
+];
+
+const MG_BISHOP: [i32; 64] = [
+    -29,   4, -82, -37, -25, -42,   7,  -8,
+    -26,  16, -18, -13,  30,  59,  18, -47,
+    -16,  37,  43,  40,  35,  50,  37,  -2,
+     -4,   5,  19,  50,  37,  37,   7,  -2,
+     -6,  13,  13,  26,  34,  12,  10,   4,
+      0,  15,  15,  15,  14,  27,  18,  10,
+      4,  15,  16,   0,   7,  21,  33,   1,
+    -33,  -3, -14, -21, -13, -12, -39, -21,
+];
+
+const EG_BISHOP: [i32; 64] = [
+    -14, -21, -11,  -8,  -7,  -9, -17, -24,
+     -8,  -4,   7, -12,  -3, -13,  -4, -14,
+      2,  -8,   0,  -1,  -2,   6,   0,   4,
+     -3,   9,  12,   9,  14,  10,   3,   2,
+     -6,   3,  13,  19,   7,  10,  -3,  -9,
+    -12,  -3,   8,  10,  13,   3,  -7, -15,
+    -14, -18,  -7,  -1,   4,  -9, -15, -27,
+    -23,  -9, -23,  -5,  -9, -16,  -5, -17,
+];
+
+const MG_ROOK: [i32; 64] = [
+     32,  42,  32,  51,  63,   9,  31,  43,
+     27,  32,  58,  62,  80,  67,  26,  44,
+     -5,  19,  26,  36,  17,  45,  61,  16,
+    -24, -11,   7,  26,  24,  35,  -8, -20,
+    -36, -26, -12,  -1,   9,  -7,   6, -23,
+    -45, -25, -16, -17,   3,   0,  -5, -33,
+    -44, -16, -20,  -9,  -1,  11,  -6, -71,
+    -19, -13,   1,  17,  16,   7, -37, -26,
+];
+
+const EG_ROOK: [i32; 64] = [
+     13,  10,  18,  15,  12,  12,   8,   5,
+     11,  13,  13,  11,  -3,   3,   8,   3,
+      7,   7,   7,   5,   4,  -3,  -5,  -3,
+      4,   3,  13,   1,   2,   1,  -1,   2,
+      3,   5,   8,   4,  -5,  -6,  -8, -11,
+     -4,   0,  -5,  -1,  -7, -12,  -8, -16,
+     -6,  -6,   0,   2,  -9,  -9, -11,  -3,
+     -9,   2,   3,  -1,  -5, -13,   4, -20,
+];
+
+const MG_QUEEN: [i32; 64] = [
+    -28,   0,  29,  12,  59,  44,  43,  45,
+    -24, -39,  -5,   1, -16,  57,  28,  54,
+    -13, -17,   7,   8,  29,  56,  47,  57,
+    -27, -27, -16, -16,  -1,  17,  -2,   1,
+     -9, -26,  -9, -10,  -2,  -4,   3,  -3,
+    -14,   2, -11,  -2,  -5,   2,  14,   5,
+    -35,  -8,  11,   2,   8,  15,  -3,   1,
+     -1, -18,  -9,  10, -15, -25, -31, -50,
+];
+
+const EG_QUEEN: [i32; 64] = [
+     -9,  22,  22,  27,  27,  19,  10,  20,
+    -17,  20,  32,  41,  58,  25,  30,   0,
+    -20,   6,   9,  49,  47,  35,  19,   9,
+      3,  22,  24,  45,  57,  40,  57,  36,
+    -18,  28,  19,  47,  31,  34,  39,  23,
+    -16, -27,  15,   6,   9,  17,  10,   5,
+    -22, -23, -30, -16, -16, -23, -36, -32,
+    -33, -28, -22, -43,  -5, -32, -20, -41,
+];
+
+const MG_KING: [i32; 64] = [
+    -65,  23,  16, -15, -56, -34,   2,  13,
+     29,  -1, -20,  -7,  -8,  -4, -38, -29,
+     -9,  24,   2, -16, -20,   6,  22, -22,
+    -17, -20, -12, -27, -30, -25, -14, -36,
+    -49,  -1, -27, -39, -46, -44, -33, -51,
+    -14, -14, -22, -46, -44, -30, -15, -27,
+      1,   7,  -8, -64, -43, -16,   9,   8,
+    -15,  36,  12, -54,   8, -28,  24,  14,
+];
+
+const EG_KING: [i32; 64] = [
+    -74, -35, -18, -18, -11,  15,   4, -17,
+    -12,  17,  14,  17,  17,  38,  23,  11,
+     10,  17,  23,  15,  20,  45,  44,  13,
+     -8,  22,  24,  27,  26,  33,  26,   3,
+    -18,  -4,  21,  24,  27,  23,   9, -11,
+    -19,  -3,  11,  21,  23,  16,   7,  -9,
+    -27, -11,   4,  13,  14,   4,  -5, -17,
+    -53, -34, -21, -11, -28, -14, -24, -43,
+];
+
+const MG_TABLES: [[i32; 64]; 6] = [MG_PAWN, MG_KNIGHT, MG_BISHOP, MG_ROOK, MG_QUEEN, MG_KING];
+const EG_TABLES: [[i32; 64]; 6] = [EG_PAWN, EG_KNIGHT, EG_BISHOP, EG_ROOK, EG_QUEEN, EG_KING];
+
+/// Material plus piece-square value of every piece on every square, indexed by piece id and square, signed so
+/// White's pieces count up and Black's count down.
+pub const PIECE_SQUARE_VALUES: [[Score; 64]; 12] = generate_piece_square_values();
+
+const fn generate_piece_square_values() -> [[Score; 64]; 12] {
+    let mut values = [[s(0, 0); 64]; 12];
+
+    let mut piece_index = 0;
+    while piece_index < 6 {
+        let mut square = 0;
+        while square < 64 {
+            let material = PIECE_VALUES[piece_index];
+
+            // The tables are written rank 8 first, so White's square has to be flipped to read them, and Black's
+            // pieces see the board from the other side, which is the same as reading the square as written.
+            let white_index = square ^ 56;
+            values[piece_index][square] = s(material.mg + MG_TABLES[piece_index][white_index], material.eg + EG_TABLES[piece_index][white_index]);
+            values[piece_index + 6][square] = s(-material.mg - MG_TABLES[piece_index][square], -material.eg - EG_TABLES[piece_index][square]);
+
+            square += 1;
+        }
+        piece_index += 1;
+    }
+
+    values
+}
+
+/// Evaluates the position in centipawns, from the side to move's point of view.
+pub fn evaluate(position: &Position) -> i32 {
+    endgame::adjust(position, evaluate_with_pawns(position, &pawns::evaluate_pawns(position)))
+}
+
+/// Same as `evaluate`, but looks the pawn structure up in the table instead of working it out every time, and
+/// leaves the endgame knowledge to the variant, since it only holds in standard chess.
+pub fn evaluate_cached(position: &Position, pawn_table: &mut PawnTable) -> i32 {
+    evaluate_with_pawns(position, &pawn_table.probe(position))
+}
+
+fn evaluate_with_pawns(position: &Position, pawn_entry: &PawnEntry) -> i32 {
+    let score = blend(position.psqt + pawn_entry.score + piece_and_king_terms(position, pawn_entry, &mut ()), position.phase);
+
+    if position.side_to_move == Color::Black { -score } else { score }
+}
+
+/// Everything but material, piece squares and the cached pawn structure, White minus Black.
+fn piece_and_king_terms(position: &Position, pawn_entry: &PawnEntry, recorder: &mut impl Recorder) -> Score {
+    let attacks = mobility::attacks(position, recorder);
+
+    let mut score = attacks.mobility[0] - attacks.mobility[1];
+    for (color, sign) in [(Color::White, 1), (Color::Black, -1)] {
+        let terms = pawns::passed_pawn_pieces(position, pawn_entry, &color, recorder)
+            + safety::king_safety(position, &color, &attacks, recorder).total()
+            + mobility::threats(position, &color, &attacks, recorder)
+            + pieces::piece_terms(position, &color, recorder).total();
+        score += terms * sign;
+    }
+
+    score
+}
+
+/// Every weight of the evaluation, in the order they make up the parameter vector the tuner works on.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Parameter {
+    PieceValue,
+    /// Indexed by piece times 64 plus the square from White's side, a1 first.
+    PieceSquare,
+    PassedPawn,
+    PassedPawnBlocked,
+    IsolatedPawn,
+    DoubledPawn,
+    BackwardPawn,
+    ConnectedPawn,
+    PhalanxPawn,
+    CandidatePasser,
+    KnightMobility,
+    BishopMobility,
+    RookMobility,
+    QueenMobility,
+    ThreatByLesser,
+    Hanging,
+    KingDanger,
+    PawnShield,
+    PawnStorm,
+    KingOpenFile,
+    KingSemiOpenFile,
+    BishopPair,
+    BadBishop,
+    KnightOutpost,
+    BishopOutpost,
+    RookOpenFile,
+    RookSemiOpenFile,
+    RookOnSeventh,
+    EarlyQueen,
+    TrappedBishop,
+    TrappedRook,
+}
+
+pub const PARAMETERS: [Parameter; 31] = [
+    Parameter::PieceValue, Parameter::PieceSquare, Parameter::PassedPawn, Parameter::PassedPawnBlocked, Parameter::IsolatedPawn,
+    Parameter::DoubledPawn, Parameter::BackwardPawn, Parameter::ConnectedPawn, Parameter::PhalanxPawn, Parameter::CandidatePasser,
+    Parameter::KnightMobility, Parameter::BishopMobility, Parameter::RookMobility, Parameter::QueenMobility, Parameter::ThreatByLesser,
+    Parameter::Hanging, Parameter::KingDanger, Parameter::PawnShield, Parameter::PawnStorm, Parameter::KingOpenFile,
+    Parameter::KingSemiOpenFile, Parameter::BishopPair, Parameter::BadBishop, Parameter::KnightOutpost, Parameter::BishopOutpost,
+    Parameter::RookOpenFile, Parameter::RookSemiOpenFile, Parameter::RookOnSeventh, Parameter::EarlyQueen, Parameter::TrappedBishop,
+    Parameter::TrappedRook,
+];
+
+impl Parameter {
+    /// The name of the constant that holds the weight.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Parameter::PieceValue => "PIECE_VALUES",
+            Parameter::PieceSquare => "PIECE_SQUARES",
+            Parameter::PassedPawn => "PASSED_PAWN",
+            Parameter::PassedPawnBlocked => "PASSED_PAWN_BLOCKED",
+            Parameter::IsolatedPawn => "ISOLATED_PAWN",
+            Parameter::DoubledPawn => "DOUBLED_PAWN",
+            Parameter::BackwardPawn => "BACKWARD_PAWN",
+            Parameter::ConnectedPawn => "CONNECTED_PAWN",
+            Parameter::PhalanxPawn => "PHALANX_PAWN",
+            Parameter::CandidatePasser => "CANDIDATE_PASSER",
+            Parameter::KnightMobility => "KNIGHT_MOBILITY",
+            Parameter::BishopMobility => "BISHOP_MOBILITY",
+            Parameter::RookMobility => "ROOK_MOBILITY",
+            Parameter::QueenMobility => "QUEEN_MOBILITY",
+            Parameter::ThreatByLesser => "THREAT_BY_LESSER",
+            Parameter::Hanging => "HANGING",
+            Parameter::KingDanger => "KING_DANGER",
+            Parameter::PawnShield => "PAWN_SHIELD",
+            Parameter::PawnStorm => "PAWN_STORM",
+            Parameter::KingOpenFile => "KING_OPEN_FILE",
+            Parameter::KingSemiOpenFile => "KING_SEMI_OPEN_FILE",
+            Parameter::BishopPair => "BISHOP_PAIR",
+            Parameter::BadBishop => "BAD_BISHOP",
+            Parameter::KnightOutpost => "KNIGHT_OUTPOST",
+            Parameter::BishopOutpost => "BISHOP_OUTPOST",
+            Parameter::RookOpenFile => "ROOK_OPEN_FILE",
+            Parameter::RookSemiOpenFile => "ROOK_SEMI_OPEN_FILE",
+            Parameter::RookOnSeventh => "ROOK_ON_SEVENTH",
+            Parameter::EarlyQueen => "EARLY_QUEEN",
+            Parameter::TrappedBishop => "TRAPPED_BISHOP",
+            Parameter::TrappedRook => "TRAPPED_ROOK",
+        }
+    }
+
+    /// The weights the evaluation uses now.
+    pub fn values(&self) -> Vec<Score> {
+        match self {
+            Parameter::PieceValue => PIECE_VALUES.to_vec(),
+            Parameter::PieceSquare => (0..6 * 64).map(|index| s(MG_TABLES[index / 64][(index % 64) ^ 56], EG_TABLES[index / 64][(index % 64) ^ 56])).collect(),
+            Parameter::PassedPawn => pawns::PASSED_PAWN.to_vec(),
+            Parameter::PassedPawnBlocked => pawns::PASSED_PAWN_BLOCKED.to_vec(),
+            Parameter::IsolatedPawn => vec![pawns::ISOLATED_PAWN],
+            Parameter::DoubledPawn => vec![pawns::DOUBLED_PAWN],
+            Parameter::BackwardPawn => vec![pawns::BACKWARD_PAWN],
+            Parameter::ConnectedPawn => pawns::CONNECTED_PAWN.to_vec(),
+            Parameter::PhalanxPawn => pawns::PHALANX_PAWN.to_vec(),
+            Parameter::CandidatePasser => pawns::CANDIDATE_PASSER.to_vec(),
+            Parameter::KnightMobility => mobility::KNIGHT_MOBILITY.to_vec(),
+            Parameter::BishopMobility => mobility::BISHOP_MOBILITY.to_vec(),
+            Parameter::RookMobility => mobility::ROOK_MOBILITY.to_vec(),
+            Parameter::QueenMobility => mobility::QUEEN_MOBILITY.to_vec(),
+            Parameter::ThreatByLesser => mobility::THREAT_BY_LESSER.to_vec(),
+            Parameter::Hanging => vec![mobility::HANGING],
+            Parameter::KingDanger => safety::KING_DANGER.to_vec(),
+            Parameter::PawnShield => safety::PAWN_SHIELD.to_vec(),
+            Parameter::PawnStorm => safety::PAWN_STORM.to_vec(),
+            Parameter::KingOpenFile => vec![safety::KING_OPEN_FILE],
+            Parameter::KingSemiOpenFile => vec![safety::KING_SEMI_OPEN_FILE],
+            Parameter::BishopPair => vec![pieces::BISHOP_PAIR],
+            Parameter::BadBishop => vec![pieces::BAD_BISHOP],
+            Parameter::KnightOutpost => vec![pieces::KNIGHT_OUTPOST],
+            Parameter::BishopOutpost => vec![pieces::BISHOP_OUTPOST],
+            Parameter::RookOpenFile => vec![pieces::ROOK_OPEN_FILE],
+            Parameter::RookSemiOpenFile => vec![pieces::ROOK_SEMI_OPEN_FILE],
+            Parameter::RookOnSeventh => vec![pieces::ROOK_ON_SEVENTH],
+            Parameter::EarlyQueen => vec![pieces::EARLY_QUEEN],
+            Parameter::TrappedBishop => vec![pieces::TRAPPED_BISHOP],
+            Parameter::TrappedRook => vec![pieces::TRAPPED_ROOK],
+        }
+    }
+}
+
+/// Told about every weight the evaluation adds, with how many times it's added for which side. The evaluation is a
+/// sum of weights, so this is enough to write it as a dot product of the parameter vector and a vector of counts.
+/// `()` records nothing, which is what everything but the tuner uses.
+pub trait Recorder {
+    fn record(&mut self, parameter: Parameter, index: usize, color: &Color, count: i32);
+}
+
+impl Recorder for () {
+    #[inline(always)]
+    fn record(&mut self, _parameter: Parameter, _index: usize, _color: &Color, _count: i32) {}
+}
+
+/// Evaluates the position from scratch, recording every weight on the way. Returns White's score minus Black's
+/// before it's blended by the phase, which is the sum of the recorded weights.
+pub fn record(position: &Position, recorder: &mut impl Recorder) -> Score {
+    let mut score = Score::default();
+
+    for (piece_id, bitboard) in position.bitboards.iter().enumerate() {
+        let color = piece::color_from_id(piece_id as i32);
+        let piece_index = piece_id % 6;
+
+        let mut bitboard = *bitboard;
+        while bitboard != 0 {
+            let square = attacks::pop_lsb(&mut bitboard) as usize;
+            let relative_square = if color == Color::Black { square ^ 56 } else { square };
+
+            score += PIECE_SQUARE_VALUES[piece_id][square];
+            recorder.record(Parameter::PieceValue, piece_index, &color, 1);
+            recorder.record(Parameter::PieceSquare, piece_index * 64 + relative_square, &color, 1);
+        }
+    }
+
+    let (white_pawns, white_passed) = pawns::pawn_terms(position, &Color::White, recorder);
+    let (black_pawns, black_passed) = pawns::pawn_terms(position, &Color::Black, recorder);
+    let pawn_entry = PawnEntry { key: position.pawn_hash, score: white_pawns.total() - black_pawns.total(), passed: [white_passed, black_passed] };
+
+    score + pawn_entry.score + piece_and_king_terms(position, &pawn_entry, recorder)
+}
+
+/// One line of an evaluation trace.
+#[derive(Clone, Copy, Debug)]
+pub struct TraceTerm {
+    pub name: &'static str,
+    /// Each side's own score for the term, both counted as good for that side.
+    pub white: Score,
+    pub black: Score,
+}
+
+/// The evaluation taken apart, to see why a position gets the score it does.
+#[derive(Clone, Debug)]
+pub struct Trace {
+    pub terms: Vec<TraceTerm>,
+    pub phase: i32,
+    /// Every term added up, White minus Black.
+    pub total: Score,
+    /// The total blended by the phase, from White's point of view.
+    pub blended: i32,
+    /// The final score from the side to move's point of view after any endgame knowledge, the same as `evaluate`
+    /// gives.
+    pub score: i32,
+}
+
+/// Evaluates the position term by term.
+pub fn trace(position: &Position) -> Trace {
+    let pawn_entry = pawns::evaluate_pawns(position);
+    let attacks = mobility::attacks(position, &mut ());
+
+    let mut material = [Score::default(); 2];
+    let mut piece_squares = [Score::default(); 2];
+    for (piece_id, bitboard) in position.bitboards.iter().enumerate() {
+        let color_index = piece_id / 6;
+        let sign = if color_index == 0 { 1 } else { -1 };
+        let piece_value = PIECE_VALUES[piece_id % 6];
+
+        let mut bitboard = *bitboard;
+        while bitboard != 0 {
+            let square = attacks::pop_lsb(&mut bitboard) as usize;
+            material[color_index] += piece_value;
+            piece_squares[color_index] += PIECE_SQUARE_VALUES[piece_id][square] * sign - piece_value;
+        }
+    }
+
+    let sides = [Color::White, Color::Black];
+    let pawn_terms = sides.map(|color| pawns::pawn_terms(position, &color, &mut ()).0);
+    let blocked_passers = sides.map(|color| pawns::passed_pawn_pieces(position, &pawn_entry, &color, &mut ()));
+    let safety_terms = sides.map(|color| safety::king_safety(position, &color, &attacks, &mut ()));
+    let threats = sides.map(|color| mobility::threats(position, &color, &attacks, &mut ()));
+    let piece_terms = sides.map(|color| pieces::piece_terms(position, &color, &mut ()));
+
+    let term = |name: &'static str, scores: [Score; 2]| TraceTerm { name, white: scores[0], black: scores[1] };
+    let terms = vec![
+        term("Material", material),
+        term("Piece squares", piece_squares),
+        term("Passed pawns", [pawn_terms[0].passed + blocked_passers[0], pawn_terms[1].passed + blocked_passers[1]]),
+        term("Isolated pawns", pawn_terms.map(|terms| terms.isolated)),
+        term("Doubled pawns", pawn_terms.map(|terms| terms.doubled)),
+        term("Backward pawns", pawn_terms.map(|terms| terms.backward)),
+        term("Connected pawns", pawn_terms.map(|terms| terms.connected)),
+        term("Phalanx pawns", pawn_terms.map(|terms| terms.phalanx)),
+        term("Candidate passers", pawn_terms.map(|terms| terms.candidate)),
+        term("Mobility", attacks.mobility),
+        term("King attacks", safety_terms.map(|terms| terms.attacks)),
+        term("Pawn shield", safety_terms.map(|terms| terms.shield)),
+        term("Pawn storm", safety_terms.map(|terms| terms.storm)),
+        term("King files", safety_terms.map(|terms| terms.open_files)),
+        term("Threats", threats),
+        term("Bishop pair", piece_terms.map(|terms| terms.bishop_pair)),
+        term("Bad bishops", piece_terms.map(|terms| terms.bad_bishop)),
+        term("Outposts", piece_terms.map(|terms| terms.outposts)),
+        term("Rook files", piece_terms.map(|terms| terms.rook_files)),
+        term("Rooks on seventh", piece_terms.map(|terms| terms.rook_on_seventh)),
+        term("Early queen", piece_terms.map(|terms| terms.early_queen)),
+        term("Trapped pieces", piece_terms.map(|terms| terms.trapped)),
+    ];
+
+    let total = terms.iter().fold(Score::default(), |total, term| total + term.white - term.black);
+    let blended = blend(total, position.phase);
+
+    Trace {
+        terms,
+        phase: position.phase.min(MAX_PHASE),
+        total,
+        blended,
+        score: endgame::adjust(position, if position.side_to_move == Color::Black { -blended } else { blended }),
+    }
+}
+
+/// Interpolates between the middlegame and endgame values by the game phase.
+pub fn blend(score: Score, phase: i32) -> i32 {
+    let phase = phase.min(MAX_PHASE);
+    (score.mg * phase + score.eg * (MAX_PHASE - phase)) / MAX_PHASE
+}
+
+/// Computes the incrementally updated parts of the evaluation from scratch, as `(psqt, phase)`. `Position::psqt`
+/// and `Position::phase` always hold the same values, this is for checking them.
+pub fn material_and_psqt(position: &Position) -> (Score, i32) {
+    let mut psqt = Score::default();
+    let mut phase = 0;
+
+    for (piece_id, bitboard) in position.bitboards.iter().enumerate() {
+        let mut bitboard = *bitboard;
+        while bitboard != 0 {
+            let square = attacks::pop_lsb(&mut bitboard) as usize;
+            psqt += PIECE_SQUARE_VALUES[piece_id][square];
+            phase += PHASE_WEIGHTS[piece::piece_from_id(piece_id as i32) as usize];
+        }
+    }
+
+    (psqt, phase)
+}
+
+// Unit Tests! //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{fen, moves, position};
+
+    /// Mirrors the board top to bottom and swaps the colours, which should give the same position for the other side.
+    fn flip(position: &Position) -> Position {
+        let mut flipped = Position::new();
+
+        for square in 0..64 {
+            let (piece, color) = position::get_piece_at(position, square);
+            if piece != piece::Piece::Empty {
+                position::set_piece_at(&mut flipped, square ^ 56, piece, &piece::opponent_of(&color));
+            }
+        }
+
+        flipped.side_to_move = piece::opponent_of(&position.side_to_move);
+        flipped.castling_rooks = [position.castling_rooks[2], position.castling_rooks[3], position.castling_rooks[0], position.castling_rooks[1]];
+        flipped.en_passant_index = if position.en_passant_index >= 0 { position.en_passant_index ^ 56 } else { -1 };
+        flipped
+    }
+
+    const TEST_FENS: [&str; 5] = [
+        fen::STARTING_FEN,
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1",
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+    ];
+
+    #[test]
+    fn test_evaluation_is_symmetric() {
+        for fen_string in TEST_FENS {
+            let position = fen::position_from_fen(fen_string);
+            let flipped = flip(&position);
+
+            assert_eq!(evaluate(&position), evaluate(&flipped), "{} and its colour-flipped copy should evaluate the same.", fen_string);
+        }
+
+        assert_eq!(evaluate(&fen::position_from_fen(fen::STARTING_FEN)), 0, "The starting position is equal.");
+    }
+
+    #[test]
+    fn test_trace_adds_up() {
+        for fen_string in TEST_FENS {
+            let position = fen::position_from_fen(fen_string);
+            let trace = trace(&position);
+
+            assert_eq!(trace.score, evaluate(&position), "The trace of {} should come to the same score as the evaluation.", fen_string);
+            assert_eq!(trace.terms[0].white + trace.terms[1].white - trace.terms[0].black - trace.terms[1].black, position.psqt);
+        }
+    }
+
+    #[test]
+    fn test_incremental_update() {
+        fn walk(position: &Position, depth: i32) {
+            assert_eq!((position.psqt, position.phase), material_and_psqt(position), "The incremental evaluation drifted.");
+            if depth == 0 {
+                return;
+            }
+
+            for mv in moves::get_legal_moves(position) {
+                let mut child = position.clone();
+                moves::make_move(&mut child, &mv);
+                walk(&child, depth - 1);
+            }
+        }
+
+        for fen_string in TEST_FENS {
+            walk(&fen::position_from_fen(fen_string), 2);
+        }
+    }
+
+    #[test]
+    fn test_game_phase() {
+        assert_eq!(fen::position_from_fen(fen::STARTING_FEN).phase, MAX_PHASE);
+        assert_eq!(fen::position_from_fen("4k3/pppppppp/8/8/8/8/PPPPPPPP/4K3 w - - 0 1").phase, 0);
+        assert_eq!(fen::position_from_fen("4k3/8/8/8/8/8/8/3QK3 w - - 0 1").phase, PHASE_WEIGHTS[4]);
+    }
+}
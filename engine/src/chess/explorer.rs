@@ -0,0 +1,382 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::BufReader;
+
+use super::cli::Flags;
+use super::datagen::GameResult;
+use super::fen;
+use super::moves::{self, Move, PromotionOption};
+use super::pgn::{PgnGame, PgnReader};
+use super::piece::Color;
+use super::polyglot::{self, MoveStats};
+use super::position::Position;
+
+// The explorer index maps the Zobrist hash of every position in a game collection to the moves played from it and
+// the games that reached it. Games are numbered by their order in the PGN file, from 0.
+//
+// Positions are keyed by their Polyglot hash rather than `Position::hash`, which counts en passant squares even
+// when no pawn can take there, so 1. e4 e5 2. Nf3 and 1. Nf3 e5 2. e4 would be different positions.
+//
+// The file is little endian:
+//
+//   magic "MBEX", version u32, games u32, plies indexed u32, positions u32
+//   for every position:
+//     key u64, games u32, then the game numbers as u32s
+//     moves u16, then for every move:
+//       move u16, wins u32, draws u32, losses u32, elo total u64, rated u32, last played u32
+
+const MAGIC: &[u8; 4] = b"MBEX";
+const VERSION: u32 = 1;
+
+/// How a move did in the games of the index.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ExplorerStats {
+    /// Wins, draws and losses for the side that played the move.
+    pub results: MoveStats,
+    /// The sum of the ratings of the players who played the move, when they had one.
+    pub elo_total: u64,
+    pub rated: u32,
+    /// The latest date the move was played, as YYYYMMDD, with unknown parts 0. 0 when no game had a date.
+    pub last_played: u32,
+}
+
+impl ExplorerStats {
+    pub fn count(&self) -> u32 {
+        self.results.games()
+    }
+
+    pub fn average_elo(&self) -> Option<u32> {
+        (self.rated > 0).then(|| (self.elo_total / self.rated as u64) as u32)
+    }
+}
+
+/// What the index knows about a position.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct PositionEntry {
+    games: Vec<u32>,
+    moves: Vec<(u16, ExplorerStats)>,
+}
+
+/// The moves played from a position, most played first, and the games that reached it.
+#[derive(Clone, Debug)]
+pub struct PositionReport {
+    pub moves: Vec<(Move, ExplorerStats)>,
+    pub games: Vec<u32>,
+}
+
+/// An index of a game collection, from positions to the moves played in them.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Explorer {
+    positions: HashMap<u64, PositionEntry>,
+    pub games: u32,
+    /// Only the first this many plies of every game are indexed. 0 indexes whole games.
+    pub max_plies: usize,
+}
+
+impl Explorer {
+    pub fn new(max_plies: usize) -> Self {
+        Explorer { max_plies, ..Default::default() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// Indexes the next game of the collection, which gets the next game number whether it was finished or not.
+    pub fn add_game(&mut self, game: &PgnGame) {
+        let id = self.games;
+        self.games += 1;
+        let date = parse_date(game.tag("Date").unwrap_or(""));
+
+        let mut position = game.start_position();
+        let plies = if self.max_plies == 0 { game.moves.len() } else { self.max_plies };
+        for (ply, mv) in game.moves.iter().enumerate() {
+            if ply >= plies {
+                break;
+            }
+
+            let entry = self.positions.entry(polyglot::polyglot_key(&position)).or_default();
+            // A repetition reaches the position again in the same game.
+            if entry.games.last() != Some(&id) {
+                entry.games.push(id);
+            }
+
+            let encoded = encode_move(mv);
+            let index = match entry.moves.iter().position(|(known, _)| *known == encoded) {
+                Some(index) => index,
+                None => {
+                    entry.moves.push((encoded, ExplorerStats::default()));
+                    entry.moves.len() - 1
+                }
+            };
+            let stats = &mut entry.moves[index].1;
+
+            let white = position.side_to_move == Color::White;
+            match (game.result, white) {
+                (Some(GameResult::Draw), _) => stats.results.draws += 1,
+                (Some(GameResult::WhiteWin), true) | (Some(GameResult::BlackWin), false) => stats.results.wins += 1,
+                (Some(_), _) => stats.results.losses += 1,
+                // Unfinished games reach positions but don't score their moves.
+                (None, _) => {}
+            }
+            if let Some(elo) = game.elo(white).filter(|_| game.result.is_some()) {
+                stats.elo_total += elo as u64;
+                stats.rated += 1;
+            }
+            stats.last_played = stats.last_played.max(date);
+
+            moves::make_move(&mut position, mv);
+        }
+
+        // The position the game ended in was reached too, even though nothing was played from it.
+        if game.moves.len() <= plies {
+            let entry = self.positions.entry(polyglot::polyglot_key(&position)).or_default();
+            if entry.games.last() != Some(&id) {
+                entry.games.push(id);
+            }
+        }
+    }
+
+    /// The moves played from the position and the games that reached it, if any did.
+    pub fn query(&self, position: &Position) -> Option<PositionReport> {
+        let entry = self.positions.get(&polyglot::polyglot_key(position))?;
+        let mut moves: Vec<(Move, ExplorerStats)> = entry.moves.iter()
+            .filter(|(_, stats)| stats.count() > 0)
+            .filter_map(|(encoded, stats)| decode_move(position, *encoded).map(|mv| (mv, *stats)))
+            .collect();
+        moves.sort_by_key(|(mv, stats)| (std::cmp::Reverse(stats.count()), *mv));
+        Some(PositionReport { moves, games: entry.games.clone() })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend(MAGIC);
+        bytes.extend(VERSION.to_le_bytes());
+        bytes.extend(self.games.to_le_bytes());
+        bytes.extend((self.max_plies as u32).to_le_bytes());
+        bytes.extend((self.positions.len() as u32).to_le_bytes());
+
+        // Sorted, so the same games always make the same file.
+        let mut keys: Vec<&u64> = self.positions.keys().collect();
+        keys.sort();
+        for key in keys {
+            let entry = &self.positions[key];
+            bytes.extend(key.to_le_bytes());
+            bytes.extend((entry.games.len() as u32).to_le_bytes());
+            entry.games.iter().for_each(|id| bytes.extend(id.to_le_bytes()));
+            bytes.extend((entry.moves.len() as u16).to_le_bytes());
+            for (mv, stats) in &entry.moves {
+                bytes.extend(mv.to_le_bytes());
+                bytes.extend(stats.results.wins.to_le_bytes());
+                bytes.extend(stats.results.draws.to_le_bytes());
+                bytes.extend(stats.results.losses.to_le_bytes());
+                bytes.extend(stats.elo_total.to_le_bytes());
+                bytes.extend(stats.rated.to_le_bytes());
+                bytes.extend(stats.last_played.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Explorer, String> {
+        let mut reader = Reader { bytes, offset: 0 };
+
+        if reader.take(4)? != MAGIC {
+            return Err("Not an explorer index.".to_string());
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(format!("Unsupported explorer index version {}.", version));
+        }
+
+        let mut explorer = Explorer::new(0);
+        explorer.games = reader.u32()?;
+        explorer.max_plies = reader.u32()? as usize;
+        let positions = reader.u32()?;
+        for _ in 0..positions {
+            let key = reader.u64()?;
+            let mut entry = PositionEntry::default();
+            let games = reader.u32()?;
+            for _ in 0..games {
+                entry.games.push(reader.u32()?);
+            }
+            let moves = reader.u16()?;
+            for _ in 0..moves {
+                let mv = reader.u16()?;
+                let results = MoveStats { wins: reader.u32()?, draws: reader.u32()?, losses: reader.u32()? };
+                let stats = ExplorerStats { results, elo_total: reader.u64()?, rated: reader.u32()?, last_played: reader.u32()? };
+                entry.moves.push((mv, stats));
+            }
+            explorer.positions.insert(key, entry);
+        }
+
+        if reader.offset != bytes.len() {
+            return Err("The explorer index has data after its last position.".to_string());
+        }
+        Ok(explorer)
+    }
+
+    pub fn load(path: &str) -> Result<Explorer, String> {
+        let bytes = fs::read(path).map_err(|error| format!("Couldn't read {}: {}.", path, error))?;
+        Explorer::from_bytes(&bytes)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.to_bytes()).map_err(|error| format!("Couldn't write {}: {}.", path, error))
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, count: usize) -> Result<&[u8], String> {
+        let end = self.offset.checked_add(count).filter(|end| *end <= self.bytes.len()).ok_or("The explorer index is truncated.")?;
+        let taken = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(taken)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+fn encode_move(mv: &Move) -> u16 {
+    let promotion = match mv.promotion {
+        PromotionOption::None => 0,
+        PromotionOption::Knight => 1,
+        PromotionOption::Bishop => 2,
+        PromotionOption::Rook => 3,
+        PromotionOption::Queen => 4,
+    };
+    (promotion << 12) | ((mv.to as u16) << 6) | mv.from as u16
+}
+
+fn decode_move(position: &Position, encoded: u16) -> Option<Move> {
+    moves::get_legal_moves(position).into_iter().find(|mv| encode_move(mv) == encoded)
+}
+
+/// A PGN date like `2024.03.??` as 20240300.
+fn parse_date(date: &str) -> u32 {
+    let mut parts = date.split('.').map(|part| part.parse::<u32>().unwrap_or(0));
+    let (year, month, day) = (parts.next().unwrap_or(0), parts.next().unwrap_or(0), parts.next().unwrap_or(0));
+    year.min(9999) * 10000 + month.min(99) * 100 + day.min(99)
+}
+
+fn format_date(date: u32) -> String {
+    let part = |value: u32, width: usize| if value == 0 { "?".repeat(width) } else { format!("{:0width$}", value, width = width) };
+    format!("{}.{}.{}", part(date / 10000, 4), part(date / 100 % 100, 2), part(date % 100, 2))
+}
+
+/// `index --pgn <file> --output <file> [--plies N]` indexes a PGN database for the explorer.
+pub fn run_index(flags: &Flags) -> Result<(), String> {
+    let pgn_path = flags.require("pgn")?;
+    let output = flags.require("output")?;
+    let mut explorer = Explorer::new(flags.get("plies", 40)?);
+
+    let file = File::open(&pgn_path).map_err(|error| format!("Couldn't open {}: {}.", pgn_path, error))?;
+    for game in PgnReader::new(BufReader::new(file)) {
+        match game {
+            Ok(game) => explorer.add_game(&game),
+            Err(error) => {
+                // The game keeps its number, so numbers still match the order of the file.
+                explorer.games += 1;
+                eprintln!("Skipped game {}: {}", explorer.games - 1, error);
+            }
+        }
+    }
+
+    explorer.save(&output)?;
+    eprintln!("Indexed {} positions from {} games to {}.", explorer.len(), explorer.games, output);
+    Ok(())
+}
+
+/// `explore --index <file> [fen]` shows what was played in a position, the starting position if no FEN is given.
+pub fn run_explore(flags: &Flags) -> Result<(), String> {
+    let explorer = Explorer::load(&flags.require("index")?)?;
+    let fen_string = if flags.positional.is_empty() { fen::STARTING_FEN.to_string() } else { flags.positional.join(" ") };
+    let position = fen::try_position_from_fen(&fen_string)?;
+
+    let Some(report) = explorer.query(&position) else {
+        println!("No games reached {}.", fen_string);
+        return Ok(());
+    };
+
+    println!("{:<6} {:>7} {:>6} {:>6} {:>6} {:>5} {:>10}", "move", "games", "win", "draw", "loss", "elo", "last");
+    for (mv, stats) in &report.moves {
+        let percent = |count: u32| 100.0 * count as f64 / stats.count() as f64;
+        let elo = stats.average_elo().map_or("-".to_string(), |elo| elo.to_string());
+        println!("{:<6} {:>7} {:>5.1}% {:>5.1}% {:>5.1}% {:>5} {:>10}", mv.to_string(), stats.count(), percent(stats.results.wins), percent(stats.results.draws), percent(stats.results.losses), elo, format_date(stats.last_played));
+    }
+
+    let shown: Vec<String> = report.games.iter().take(20).map(|id| id.to_string()).collect();
+    let more = if report.games.len() > shown.len() { format!(" and {} more", report.games.len() - shown.len()) } else { String::new() };
+    println!("Reached in {} games: {}{}", report.games.len(), shown.join(" "), more);
+    Ok(())
+}
+
+// Unit Tests! //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::pgn;
+
+    #[test]
+    fn test_explorer() {
+        let text = r#"[Date "2020.01.05"] [WhiteElo "2400"] [BlackElo "2200"] [Result "1-0"]
+1. e4 e5 2. Nf3 1-0
+
+[Date "2023.??.??"] [WhiteElo "2600"] [Result "1/2-1/2"]
+1. Nf3 e5 2. e4 1/2-1/2
+
+[Date "2021.06.01"] [Result "0-1"]
+1. d4 d5 0-1
+
+1. e4 *
+"#;
+        let mut explorer = Explorer::new(0);
+        for game in pgn::parse_games(text) {
+            explorer.add_game(&game.expect("The games should read."));
+        }
+
+        let report = explorer.query(&fen::position_from_fen(fen::STARTING_FEN)).expect("Every game reached the start.");
+        assert_eq!(report.games, vec![0, 1, 2, 3]);
+        let e4 = report.moves.iter().find(|(mv, _)| mv.to_string() == "e2e4").expect("e4 was played.").1;
+        assert_eq!(e4.results, MoveStats { wins: 1, draws: 0, losses: 0 }, "The unfinished game doesn't score.");
+        assert_eq!(e4.average_elo(), Some(2400));
+        assert_eq!(format_date(e4.last_played), "2020.01.05");
+        assert_eq!(report.moves.len(), 3);
+
+        // 1. e4 e5 2. Nf3 and 1. Nf3 e5 2. e4 reach the same position.
+        let transposed = fen::position_from_fen("rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2");
+        let report = explorer.query(&transposed).expect("The position was reached twice.");
+        assert_eq!(report.games, vec![0, 1]);
+        assert!(report.moves.is_empty(), "Both games ended there.");
+
+        let after_nf3 = fen::position_from_fen("rnbqkbnr/pppppppp/8/8/8/5N2/PPPPPPPP/RNBQKB1R b KQkq - 1 1");
+        let e5 = explorer.query(&after_nf3).expect("Nf3 was played.").moves[0].1;
+        assert_eq!((e5.average_elo(), format_date(e5.last_played)), (None, "2023.??.??".to_string()), "Black had no rating.");
+
+        assert!(explorer.query(&fen::position_from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1")).is_none());
+
+        let bytes = explorer.to_bytes();
+        assert_eq!(Explorer::from_bytes(&bytes), Ok(explorer), "The index should read back as it was written.");
+        assert!(Explorer::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
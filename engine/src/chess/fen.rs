@@ -1,46 +1,281 @@
-use position::Position;
-
 use super::*;
 
+pub const STARTING_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+const BACK_RANKS: i64 = 0xff | 0xff << 56;
+
+/// The FEN of a Chess960 start position from its Scharnagl number, 0 to 959. 518 is the standard start position.
+pub fn chess960_fen(index: u32) -> String {
+    const KNIGHTS: [(usize, usize); 10] = [(0, 1), (0, 2), (0, 3), (0, 4), (1, 2), (1, 3), (1, 4), (2, 3), (2, 4), (3, 4)];
+    assert!(index < 960, "There are 960 start positions.");
+
+    let mut back_rank = [' '; 8];
+    // The light squared bishop goes on b, d, f or h, the dark squared one on a, c, e or g.
+    back_rank[(index % 4 * 2 + 1) as usize] = 'B';
+    back_rank[(index / 4 % 4 * 2) as usize] = 'B';
+
+    let empty = |back_rank: &[char; 8]| (0..8).filter(|file| back_rank[*file] == ' ').collect::<Vec<usize>>();
+    let queen = empty(&back_rank)[(index / 16 % 6) as usize];
+    back_rank[queen] = 'Q';
+
+    let (first, second) = KNIGHTS[(index / 96) as usize];
+    let squares = empty(&back_rank);
+    back_rank[squares[first]] = 'N';
+    back_rank[squares[second]] = 'N';
+
+    // The king goes between the rooks on the three squares left.
+    for (file, piece) in empty(&back_rank).into_iter().zip(['R', 'K', 'R']) {
+        back_rank[file] = piece;
+    }
+
+    let white: String = back_rank.iter().collect();
+    format!("{}/pppppppp/8/8/8/8/PPPPPPPP/{} w KQkq - 0 1", white.to_ascii_lowercase(), white)
+}
+
+/// Reads a FEN that's known to be good, like the ones written into the engine. Anything that came from outside goes
+/// through `try_position_from_fen`.
 pub fn position_from_fen(fen: &str) -> position::Position {
+    try_position_from_fen(fen).unwrap_or_else(|error| panic!("{} ({})", error, fen))
+}
+
+pub fn try_position_from_fen(fen: &str) -> Result<position::Position, String> {
     let parts: Vec<&str> = fen.split_whitespace().collect();
-    assert_eq!(parts.len(), 6, "FEN must have 6 parts.");
+    if parts.len() != 6 {
+        return Err("A FEN must have 6 parts.".to_string());
+    }
 
     let mut position = position::Position::new();
 
     let ranks: Vec<&str> = parts[0].split("/").collect();
-    for rank_index in 0..8 {
+    if ranks.len() != 8 {
+        return Err(format!("The placement {} doesn't have 8 ranks.", parts[0]));
+    }
+    for (rank_index, rank) in ranks.iter().enumerate() {
         let mut file = 0;
-        for char in ranks[rank_index].chars() {
-            if char::is_numeric(char) {
-                file += char.to_digit(10).unwrap();
+        for char in rank.chars() {
+            if let Some(empty_squares @ 1..=8) = char.to_digit(10) {
+                file += empty_squares;
+                continue;
             }
-            else {
-                let piece = display::piece_from_notation(char);
+
+            let piece = display::piece_from_notation(char);
+            if piece.0 == piece::Piece::Empty {
+                return Err(format!("Invalid piece {} in FEN.", char));
+            }
+            if file < 8 {
                 position::set_piece_at(&mut position, (7 - rank_index as i32) * 8 + (file as i32), piece.0, &piece.1);
-                file += 1;
             }
-        }   
+            file += 1;
+        }
+        if file != 8 {
+            return Err(format!("The rank {} doesn't have 8 files.", rank));
+        }
+    }
+
+    // Move generation and evaluation take a king of each color for granted, and pawns on the first or eighth rank
+    // for ones that have moved.
+    for king in [5, 11] {
+        if position.bitboards[king].count_ones() != 1 {
+            return Err(format!("The placement {} doesn't have one king of each color.", parts[0]));
+        }
+    }
+    if (position.bitboards[0] | position.bitboards[6]) & BACK_RANKS != 0 {
+        return Err(format!("The placement {} has a pawn on the first or eighth rank.", parts[0]));
     }
 
     position.side_to_move = match parts[1] {
         "w" => piece::Color::White,
         "b" => piece::Color::Black,
-        _ => panic!("Invalid color in FEN!")
+        _ => return Err(format!("Invalid side to move {} in FEN.", parts[1])),
     };
 
-    if parts[2] != "-" {
-        if parts[2].contains("K") { position::set_castling_rights(&mut position, piece::Color::White, position::CastlingSide::Short, true); }
-        if parts[2].contains("Q") { position::set_castling_rights(&mut position, piece::Color::White, position::CastlingSide::Long, true); }
-        if parts[2].contains("k") { position::set_castling_rights(&mut position, piece::Color::Black, position::CastlingSide::Short, true); }
-        if parts[2].contains("q") { position::set_castling_rights(&mut position, piece::Color::Black, position::CastlingSide::Long, true); }
+    // `KQkq` is the rook furthest from the king on that side, as in X-FEN, and a file is that file's rook, as in
+    // Shredder-FEN, which X-FEN also uses when the outer rook isn't the one that can castle.
+    for symbol in parts[2].chars().filter(|_| parts[2] != "-") {
+        let color = if symbol.is_ascii_uppercase() { piece::Color::White } else { piece::Color::Black };
+        let back_rank = if color == piece::Color::White { 0 } else { 56 };
+        let king_file = position::get_king_index(&position, &color) - back_rank;
+        if !(0..8).contains(&king_file) {
+            return Err(format!("Invalid castling rights {} in FEN, the king isn't on its back rank.", parts[2]));
+        }
+        let (side, file) = match symbol.to_ascii_lowercase() {
+            'k' => (position::CastlingSide::Short, position::outer_rook_file(&position, color, position::CastlingSide::Short)),
+            'q' => (position::CastlingSide::Long, position::outer_rook_file(&position, color, position::CastlingSide::Long)),
+            file @ 'a'..='h' => {
+                let file = file as i32 - 'a' as i32;
+                (if file > king_file { position::CastlingSide::Short } else { position::CastlingSide::Long }, Some(file))
+            }
+            _ => return Err(format!("Invalid castling rights {} in FEN.", parts[2])),
+        };
+        if let Some(file) = file {
+            position::set_castling_rook(&mut position, color, side, file);
+        }
     }
 
-    let en_passant_option = display::coordinate_name_to_board_index(parts[3].to_string());
-    position.en_passant_index = en_passant_option.unwrap_or(-1);
+    // Castling that standard chess can't have makes this a Chess960 position.
+    position.chess960 = moves::CASTLING_RIGHTS.iter().any(|(color, side)| {
+        let file = position.castling_rooks[position::castling_index(*color, *side)];
+        let standard_file = if *side == position::CastlingSide::Short { 7 } else { 0 };
+        file != position::NO_CASTLING && (file != standard_file || position::get_king_index(&position, color) % 8 != 4)
+    });
 
-    position.half_move_clock = parts[4].parse::<i32>().unwrap();
-    position.full_move_clock = parts[5].parse::<i32>().unwrap();
+    // The en passant square is behind a pawn that just moved two squares, so it's on the sixth rank of the side to
+    // move.
+    let en_passant_rank = if position.side_to_move == piece::Color::White { 5 } else { 2 };
+    position.en_passant_index = match parts[3] {
+        "-" => -1,
+        square => display::coordinate_name_to_board_index(square.to_string())
+            .filter(|index| index / 8 == en_passant_rank)
+            .ok_or(format!("Invalid en passant square {} in FEN.", square))?,
+    };
+
+    let counter = |part: &str| part.parse::<i32>().ok().filter(|count| *count >= 0).ok_or(format!("Invalid move counter {} in FEN.", part));
+    position.half_move_clock = counter(parts[4])?;
+    position.full_move_clock = counter(parts[5])?;
+
+    position.hash = zobrist::hash_position(&position);
+
+    Ok(position)
+}
+
+pub fn fen_from_position(position: &position::Position) -> String {
+    let mut placement = String::new();
+    for rank in (0..8).rev() {
+        let mut empty_squares = 0;
+        for file in 0..8 {
+            let (piece, color) = position::get_piece_at(position, rank * 8 + file);
+            if piece == piece::Piece::Empty {
+                empty_squares += 1;
+                continue;
+            }
 
-    position
-}
\ No newline at end of file
+            if empty_squares > 0 {
+                placement.push_str(&empty_squares.to_string());
+                empty_squares = 0;
+            }
+            placement.push(display::get_piece_ascii(piece, color));
+        }
+
+        if empty_squares > 0 {
+            placement.push_str(&empty_squares.to_string());
+        }
+        if rank > 0 {
+            placement.push('/');
+        }
+    }
+
+    let side_to_move = if position.side_to_move == piece::Color::White { "w" } else { "b" };
+
+    let castling = castling_field(position, false);
+    let en_passant = if position.en_passant_index >= 0 { display::board_index_to_coordinate_name(position.en_passant_index) } else { "-".to_string() };
+
+    format!("{} {} {} {} {} {}", placement, side_to_move, castling, en_passant, position.half_move_clock, position.full_move_clock)
+}
+
+/// A FEN with the castling rights written as the files of the rooks, like `HAha`, as Shredder-FEN does.
+pub fn shredder_fen_from_position(position: &position::Position) -> String {
+    let fen = fen_from_position(position);
+    let mut parts: Vec<&str> = fen.split(' ').collect();
+    let castling = castling_field(position, true);
+    parts[2] = &castling;
+    parts.join(" ")
+}
+
+/// Standard chess castling is `KQkq`. Chess960 castling is written as X-FEN, with `KQkq` for the outer rooks and
+/// files for others, or as Shredder-FEN, with files for every rook.
+fn castling_field(position: &position::Position, shredder: bool) -> String {
+    let castling: String = ['K', 'Q', 'k', 'q'].iter().zip(moves::CASTLING_RIGHTS).zip(position.castling_rooks)
+        .filter(|(_, file)| *file != position::NO_CASTLING)
+        .map(|((symbol, (color, side)), file)| {
+            let is_outer = position::outer_rook_file(position, color, side) == Some(file);
+            if (!position.chess960 || !shredder) && is_outer {
+                return *symbol;
+            }
+            let file = (b'a' + file as u8) as char;
+            if color == piece::Color::White { file.to_ascii_uppercase() } else { file }
+        })
+        .collect();
+
+    if castling.is_empty() { "-".to_string() } else { castling }
+}
+
+// Unit Tests! //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chess960_fens() {
+        assert_eq!(chess960_fen(518), STARTING_FEN);
+        assert_eq!(chess960_fen(0), "bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w KQkq - 0 1");
+        assert_eq!(chess960_fen(959), "rkrnnqbb/pppppppp/8/8/8/8/PPPPPPPP/RKRNNQBB w KQkq - 0 1");
+
+        assert!(position_from_fen(&chess960_fen(0)).chess960);
+        assert!(!position_from_fen(&chess960_fen(518)).chess960);
+
+        let mut seen = std::collections::HashSet::new();
+        for index in 0..960 {
+            let fen = chess960_fen(index);
+            let back_rank = fen.split('/').next_back().unwrap().split(' ').next().unwrap().to_string();
+            let bishops: Vec<usize> = back_rank.match_indices('B').map(|(file, _)| file).collect();
+            let (king, rooks) = (back_rank.find('K').unwrap(), back_rank.match_indices('R').map(|(file, _)| file).collect::<Vec<_>>());
+            assert!(bishops[0] % 2 != bishops[1] % 2, "{} has both bishops on the same colour.", fen);
+            assert!(rooks[0] < king && king < rooks[1], "{} doesn't have the king between the rooks.", fen);
+            assert!(seen.insert(back_rank), "{} is there twice.", fen);
+
+            let position = position_from_fen(&fen);
+            assert_eq!(fen_from_position(&position), fen);
+        }
+    }
+
+    #[test]
+    fn test_castling_fields() {
+        // X-FEN uses a file when the castling rook isn't the outer one, Shredder-FEN always does.
+        let fen = "rn2k1r1/ppp1pppp/8/8/8/8/PPPPPPPP/R1R1K2R w KCq - 0 1";
+        let position = position_from_fen(fen);
+        assert!(position.chess960);
+        assert_eq!(position.castling_rooks, [7, 2, position::NO_CASTLING, 0]);
+        assert_eq!(fen_from_position(&position), fen);
+        assert_eq!(shredder_fen_from_position(&position), "rn2k1r1/ppp1pppp/8/8/8/8/PPPPPPPP/R1R1K2R w HCa - 0 1");
+        assert_eq!(fen_from_position(&position_from_fen("rn2k1r1/ppp1pppp/8/8/8/8/PPPPPPPP/R1R1K2R w HCa - 0 1")), fen);
+
+        let standard = position_from_fen("r3k2r/8/8/8/8/8/8/R3K2R w HAha - 0 1");
+        assert!(!standard.chess960, "Shredder-FEN doesn't make a standard position Chess960.");
+        assert_eq!(fen_from_position(&standard), "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+    }
+
+    #[test]
+    fn test_fen_round_trip() {
+        for fen_string in [STARTING_FEN, "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w Kq f6 0 3", "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 b - - 12 40"] {
+            assert_eq!(fen_from_position(&position_from_fen(fen_string)), fen_string);
+        }
+    }
+
+    #[test]
+    fn test_rejects_malformed_fens() {
+        let malformed = [
+            "",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNRR w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNX w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR x KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkx - 0 1",
+            "rnbq1bnr/ppppkppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq e9 0 1",
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e6 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - x 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 -1",
+            "7k/8/8/8/8/8/8/K6P w - - 0 1",
+            "kP6/8/8/8/8/8/8/K7 w - - 0 1",
+            "8/8/8/8/8/8/8/K7 w - - 0 1",
+            "kk6/8/8/8/8/8/8/K7 w - - 0 1",
+        ];
+        for fen_string in malformed {
+            assert!(try_position_from_fen(fen_string).is_err(), "{} should be rejected.", fen_string);
+        }
+    }
+}
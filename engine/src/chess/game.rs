@@ -0,0 +1,109 @@
+use super::fen;
+use super::moves::{self, Move};
+use super::position::Position;
+use super::variant::{self, Variant};
+
+/// A game in progress: the current position, the moves that led to it, and the hashes of every earlier position,
+/// which the search needs to recognise repetitions.
+#[derive(Clone)]
+pub struct Game {
+    pub position: Position,
+    pub moves: Vec<Move>,
+    pub history: Vec<u64>,
+    /// The rules the game is played by.
+    pub variant: &'static dyn Variant,
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Game {
+    pub fn new() -> Self {
+        Self::from_fen(fen::STARTING_FEN)
+    }
+
+    pub fn from_fen(fen_string: &str) -> Self {
+        Game {
+            position: fen::position_from_fen(fen_string),
+            moves: Vec::new(),
+            history: Vec::new(),
+            variant: variant::standard(),
+        }
+    }
+
+    /// A game of a variant from its start position.
+    pub fn for_variant(variant: &'static dyn Variant) -> Self {
+        Self::from_variant_fen(variant, variant.starting_fen()).expect("A variant's start position should read.")
+    }
+
+    /// A game of a variant from a FEN, which can have the fields the variant adds to it.
+    pub fn from_variant_fen(variant: &'static dyn Variant, fen_string: &str) -> Result<Self, String> {
+        Ok(Game {
+            position: variant.position_from_fen(fen_string)?,
+            moves: Vec::new(),
+            history: Vec::new(),
+            variant,
+        })
+    }
+
+    /// Plays a move, which should be legal in the current position.
+    pub fn play_move(&mut self, mv: Move) {
+        self.history.push(self.position.hash);
+        self.moves.push(mv);
+        self.variant.make_move(&mut self.position, &mv);
+    }
+
+    /// Plays a move given in long algebraic notation, like `e2e4` or `e7e8q`.
+    pub fn play_move_name(&mut self, name: &str) -> Result<(), String> {
+        let mv = self.variant.legal_moves(&self.position).into_iter()
+            .find(|mv| mv.to_string() == name)
+            .ok_or_else(|| format!("Illegal move {}.", name))?;
+        self.play_move(mv);
+        Ok(())
+    }
+}
+
+/// Finds the legal move with the given name in long algebraic notation.
+pub fn find_move(position: &Position, name: &str) -> Option<Move> {
+    moves::get_legal_moves(position).into_iter().find(|mv| mv.to_string() == name)
+}
+
+// Unit Tests! //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_play_moves() {
+        let mut game = Game::new();
+        for name in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+            game.play_move_name(name).expect("The move should be legal.");
+        }
+
+        assert_eq!(game.position.hash, fen::position_from_fen(fen::STARTING_FEN).hash, "Moving the knights out and back should repeat the start.");
+        assert_eq!(game.history.len(), 4);
+        assert_eq!(game.history[0], game.position.hash);
+
+        assert!(game.play_move_name("e2e5").is_err(), "A pawn can't move three squares.");
+        assert_eq!(game.moves.len(), 4, "An illegal move shouldn't be played.");
+    }
+
+    #[test]
+    fn test_variant_games() {
+        let mut game = Game::for_variant(variant::from_name("3check").unwrap());
+        for name in ["e2e4", "e7e5", "f1b5", "g8f6", "b5d7"] {
+            game.play_move_name(name).expect("The move should be legal.");
+        }
+        assert_eq!(game.position.checks_given, [1, 0], "Bxd7+ is the first check.");
+        assert_eq!(game.variant.fen(&game.position), "rnbqkb1r/pppB1ppp/5n2/4p3/4P3/8/PPPP1PPP/RNBQK1NR b KQkq - 2+3 0 3");
+
+        let mut race = Game::for_variant(variant::from_name("racingkings").unwrap());
+        assert!(race.play_move_name("h2h3").is_ok());
+        assert!(race.play_move_name("b2b1").is_err(), "A rook can't take its own rook.");
+        assert!(Game::from_variant_fen(variant::standard(), "8/8/8/8/8/8/8/k6K w - -").is_err());
+    }
+}
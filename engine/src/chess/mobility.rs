@@ -0,0 +1,203 @@
+use super::attacks;
+use super::eval::{s, Parameter, Recorder, Score};
+use super::piece::{self, Color, Piece};
+use super::position::{self, Position};
+use super::safety;
+
+// Mobility weights, indexed by the number of safe squares the piece attacks.
+
+pub const KNIGHT_MOBILITY: [Score; 9] = [
+    s(-31, -40), s(-26, -28), s(-6, -15), s(-2, -7), s(2, 4), s(6, 8), s(11, 12), s(14, 14), s(16, 16),
+];
+
+pub const BISHOP_MOBILITY: [Score; 14] = [
+    s(-24, -30), s(-10, -12), s(8, -2), s(13, 6), s(19, 12), s(25, 21), s(27, 27),
+    s(31, 28), s(31, 32), s(34, 36), s(40, 39), s(40, 43), s(45, 44), s(49, 48),
+];
+
+pub const ROOK_MOBILITY: [Score; 15] = [
+    s(-30, -39), s(-10, -9), s(1, 12), s(2, 20), s(2, 35), s(6, 50), s(11, 52), s(16, 60),
+    s(20, 67), s(20, 70), s(21, 79), s(24, 82), s(29, 84), s(29, 85), s(31, 86),
+];
+
+pub const QUEEN_MOBILITY: [Score; 28] = [
+    s(-20, -18), s(-10, -8), s(2, 4), s(2, 9), s(7, 17), s(11, 27), s(14, 30), s(20, 36), s(22, 40), s(24, 46),
+    s(28, 47), s(30, 52), s(30, 56), s(33, 60), s(34, 62), s(35, 63), s(36, 66), s(37, 68), s(40, 70), s(44, 72),
+    s(44, 74), s(50, 83), s(51, 85), s(51, 88), s(53, 92), s(55, 96), s(56, 103), s(58, 106),
+];
+
+// Threat weights.
+
+/// Indexed by the attacked piece, for a piece attacked by something worth less than itself.
+pub const THREAT_BY_LESSER: [Score; 6] = [s(0, 0), s(32, 26), s(32, 26), s(45, 25), s(50, 40), s(0, 0)];
+/// For every enemy piece or pawn that is attacked and not defended at all.
+pub const HANGING: Score = s(30, 18);
+
+/// What every piece attacks, worked out once per evaluation and shared by the terms built on it.
+pub struct Attacks {
+    /// Squares attacked by each piece id.
+    pub by_piece: [i64; 12],
+    /// Squares attacked by each color.
+    pub by_color: [i64; 2],
+    /// Mobility of each color's pieces, added up.
+    pub mobility: [Score; 2],
+    /// How many enemy pieces attack each color's king zone.
+    pub king_attackers: [i32; 2],
+    /// The weighted attacks on each color's king zone, see `safety::KING_ATTACK_WEIGHTS`.
+    pub king_attack_units: [i32; 2],
+}
+
+/// Generates the attacks of every piece from the attack tables, scoring mobility and king attacks on the way.
+pub fn attacks(position: &Position, recorder: &mut impl Recorder) -> Attacks {
+    let mut result = Attacks {
+        by_piece: [0; 12],
+        by_color: [0; 2],
+        mobility: [Score::default(); 2],
+        king_attackers: [0; 2],
+        king_attack_units: [0; 2],
+    };
+
+    for color in [Color::White, Color::Black] {
+        let color_index = color as usize;
+        let pawn_id = piece::piece_id(&Piece::Pawn, &color) as usize;
+
+        let mut pawns = position.bitboards[pawn_id];
+        while pawns != 0 {
+            result.by_piece[pawn_id] |= attacks::PAWN_ATTACKS[color_index][attacks::pop_lsb(&mut pawns) as usize];
+        }
+
+        let king_index = position::get_king_index(position, &color);
+        if king_index >= 0 {
+            result.by_piece[pawn_id + 5] = attacks::KING_ATTACKS[king_index as usize];
+        }
+    }
+
+    for color in [Color::White, Color::Black] {
+        let color_index = color as usize;
+        let enemy = piece::opponent_of(&color);
+        let enemy_pawn_attacks = result.by_piece[piece::piece_id(&Piece::Pawn, &enemy) as usize];
+        let mobility_area = !position::get_color_bitboard(position, &color) & !enemy_pawn_attacks;
+
+        let enemy_king = position::get_king_index(position, &enemy);
+        let enemy_king_zone = if enemy_king >= 0 { safety::king_zone(enemy_king) } else { 0 };
+
+        for piece in [Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen] {
+            let piece_id = piece::piece_id(&piece, &color) as usize;
+
+            let mut pieces = position.bitboards[piece_id];
+            while pieces != 0 {
+                let index = attacks::pop_lsb(&mut pieces);
+                let piece_attacks = piece_attacks(piece, index, position.occupation_bitboard);
+                result.by_piece[piece_id] |= piece_attacks;
+
+                let safe_squares = (piece_attacks & mobility_area).count_ones() as usize;
+                let (mobility, parameter) = match piece {
+                    Piece::Knight => (KNIGHT_MOBILITY[safe_squares], Parameter::KnightMobility),
+                    Piece::Bishop => (BISHOP_MOBILITY[safe_squares], Parameter::BishopMobility),
+                    Piece::Rook => (ROOK_MOBILITY[safe_squares], Parameter::RookMobility),
+                    _ => (QUEEN_MOBILITY[safe_squares], Parameter::QueenMobility),
+                };
+                result.mobility[color_index] += mobility;
+                recorder.record(parameter, safe_squares, &color, 1);
+
+                let king_zone_hits = (piece_attacks & enemy_king_zone).count_ones() as i32;
+                if king_zone_hits > 0 {
+                    let enemy_index = enemy as usize;
+                    result.king_attackers[enemy_index] += 1;
+                    result.king_attack_units[enemy_index] += safety::KING_ATTACK_WEIGHTS[piece as usize] * king_zone_hits;
+                }
+            }
+        }
+    }
+
+    for color_index in 0..2 {
+        result.by_color[color_index] = result.by_piece[color_index * 6..color_index * 6 + 6].iter().fold(0, |all, bitboard| all | bitboard);
+    }
+
+    result
+}
+
+pub fn piece_attacks(piece: Piece, index: i32, occupation: i64) -> i64 {
+    match piece {
+        Piece::Pawn => 0,
+        Piece::Knight => attacks::KNIGHT_ATTACKS[index as usize],
+        Piece::Bishop => attacks::bishop_attacks(index, occupation),
+        Piece::Rook => attacks::rook_attacks(index, occupation),
+        Piece::Queen => attacks::queen_attacks(index, occupation),
+        Piece::King => attacks::KING_ATTACKS[index as usize],
+        Piece::Empty => 0,
+    }
+}
+
+/// Threats the color makes against the other side's pieces.
+pub fn threats(position: &Position, color: &Color, attacks: &Attacks, recorder: &mut impl Recorder) -> Score {
+    let enemy = piece::opponent_of(color);
+    let offset = if *color == Color::Black { 6 } else { 0 };
+    let by_piece = &attacks.by_piece[offset..offset + 6];
+
+    let by_pawns = by_piece[Piece::Pawn as usize];
+    let by_minors = by_pawns | by_piece[Piece::Knight as usize] | by_piece[Piece::Bishop as usize];
+    let by_rooks = by_minors | by_piece[Piece::Rook as usize];
+
+    let mut score = Score::default();
+
+    for (victim, attacked_by_lesser) in [(Piece::Knight, by_pawns), (Piece::Bishop, by_pawns), (Piece::Rook, by_minors), (Piece::Queen, by_rooks)] {
+        let victims = position.bitboards[piece::piece_id(&victim, &enemy) as usize];
+        let threatened = (victims & attacked_by_lesser).count_ones() as i32;
+        score += THREAT_BY_LESSER[victim as usize] * threatened;
+        recorder.record(Parameter::ThreatByLesser, victim as usize, color, threatened);
+    }
+
+    let enemy_king = position.bitboards[piece::piece_id(&Piece::King, &enemy) as usize];
+    let hanging = position::get_color_bitboard(position, &enemy) & !enemy_king & attacks.by_color[*color as usize] & !attacks.by_color[enemy as usize];
+    score += HANGING * hanging.count_ones() as i32;
+    recorder.record(Parameter::Hanging, 0, color, hanging.count_ones() as i32);
+
+    score
+}
+
+// Unit Tests! //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::fen;
+
+    #[test]
+    fn test_attack_maps_match_the_position() {
+        let position = fen::position_from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        let attacks = attacks(&position, &mut ());
+
+        for index in 0..64 {
+            let attackers = position::get_pieces_attacking(&position, index);
+            for color in [Color::White, Color::Black] {
+                let expected = attackers & position::get_color_bitboard(&position, &color) != 0;
+                assert_eq!(attacks.by_color[color as usize] & (1 << index) != 0, expected, "Square {} attacked by {:?} disagrees with the position.", index, color);
+            }
+        }
+    }
+
+    #[test]
+    fn test_mobility() {
+        // A knight in the corner has two squares, in the centre eight.
+        let corner = attacks(&fen::position_from_fen("4k3/8/8/8/8/8/8/N3K3 w - - 0 1"), &mut ());
+        let centre = attacks(&fen::position_from_fen("4k3/8/8/8/3N4/8/8/4K3 w - - 0 1"), &mut ());
+
+        assert_eq!(corner.mobility[0], KNIGHT_MOBILITY[2]);
+        assert_eq!(centre.mobility[0], KNIGHT_MOBILITY[8]);
+
+        // Squares guarded by enemy pawns don't count.
+        let guarded = attacks(&fen::position_from_fen("4k3/8/2p1p3/8/3N4/8/8/4K3 w - - 0 1"), &mut ());
+        assert_eq!(guarded.mobility[0], KNIGHT_MOBILITY[6]);
+    }
+
+    #[test]
+    fn test_threats() {
+        // The pawn on e4 forks the knight and bishop, and neither is defended, nor is the rook on a8.
+        let position = fen::position_from_fen("r3k3/8/8/3n1b2/4P3/8/8/R3K3 w - - 0 1");
+        let attacks = attacks(&position, &mut ());
+        let expected = THREAT_BY_LESSER[Piece::Knight as usize] + THREAT_BY_LESSER[Piece::Bishop as usize] + HANGING * 3;
+
+        assert_eq!(threats(&position, &Color::White, &attacks, &mut ()), expected);
+    }
+}
@@ -2,4 +2,30 @@ pub mod position;
 pub mod moves;
 pub mod piece;
 pub mod fen;
-pub mod display;
\ No newline at end of file
+pub mod display;
+pub mod attacks;
+pub mod ordering;
+pub mod zobrist;
+pub mod transposition;
+pub mod search;
+pub mod timing;
+pub mod game;
+pub mod uci;
+pub mod eval;
+pub mod pawns;
+pub mod mobility;
+pub mod safety;
+pub mod pieces;
+pub mod nnue;
+pub mod cli;
+pub mod datagen;
+pub mod tune;
+pub mod tablebase;
+pub mod syzygy;
+pub mod dtm;
+pub mod endgame;
+pub mod polyglot;
+pub mod pgn;
+pub mod explorer;
+pub mod eco;
+pub mod variant;
@@ -3,6 +3,7 @@ use std::fmt;
 use super::display;
 use super::piece;
 use super::position;
+use super::zobrist;
 
 const KNIGHT_MOVES: &[i32] = &[-17, -15, -10, -6, 6, 10, 15, 17];
 const BISHOP_DIRECTIONS: &[i32] = &[-9, -7, 7, 9];
@@ -10,7 +11,15 @@ const ROOK_DIRECTIONS: &[i32] = &[-8, -1, 1, 8];
 const QUEEN_DIRECTIONS: &[i32] = &[-9, -8, -7, -1, 1, 7, 8, 9];
 const KING_DIRECTIONS: &[i32] = &[-9, -8, -7, -1, 1, 7, 8, 9];
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+/// The castling rights in the order of `Position::castling_rooks`.
+pub const CASTLING_RIGHTS: [(piece::Color, position::CastlingSide); 4] = [
+    (piece::Color::White, position::CastlingSide::Short),
+    (piece::Color::White, position::CastlingSide::Long),
+    (piece::Color::Black, position::CastlingSide::Short),
+    (piece::Color::Black, position::CastlingSide::Long),
+];
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
 pub enum PromotionOption {
     None,
     Knight,
@@ -19,11 +28,11 @@ pub enum PromotionOption {
     Queen
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
 pub struct Move {
-    from: i32,
-    to: i32,
-    promotion: PromotionOption
+    pub from: i32,
+    pub to: i32,
+    pub promotion: PromotionOption
 }
 
 impl fmt::Display for Move {
@@ -86,7 +95,7 @@ pub fn get_pawn_unchecked_moves(position: &position::Position, index: i32, color
     let opponent_color = if *color == piece::Color::White { piece::Color::Black } else { piece::Color::White };
 
     if !position::is_square_occupied(position, index + 8 * direction) {
-        add_pawn_move(&mut moves, index, index + 8 * direction, &color);
+        add_pawn_move(&mut moves, index, index + 8 * direction, color);
 
         if rank == pawn_home_rank && !position::is_square_occupied(position, index + 16 * direction) {
             moves.push(Move {from: index, to: index + 16 * direction, promotion: PromotionOption::None});
@@ -95,14 +104,23 @@ pub fn get_pawn_unchecked_moves(position: &position::Position, index: i32, color
     
     if position::is_square_occupied_by_color(position, index + 7 * direction, &opponent_color) {
         let opponent_rank = (index + 7 * direction) / 8;
-        if opponent_rank == rank + 1 * direction {
-            add_pawn_move(&mut moves, index, index + 7 * direction, &color);
+        if opponent_rank == rank + direction {
+            add_pawn_move(&mut moves, index, index + 7 * direction, color);
         }
     }
     if index + 9 * direction < 64 && index + 9 * direction >= 0 && position::is_square_occupied_by_color(position, index + 9 * direction, &opponent_color) {
         let opponent_rank = (index + 9 * direction) / 8;
-        if opponent_rank == rank + 1 * direction {
-            add_pawn_move(&mut moves, index, index + 9 * direction, &color);
+        if opponent_rank == rank + direction {
+            add_pawn_move(&mut moves, index, index + 9 * direction, color);
+        }
+    }
+
+    if position.en_passant_index >= 0 {
+        for capture_offset in [7, 9] {
+            let target = index + capture_offset * direction;
+            if target == position.en_passant_index && target / 8 == rank + direction && (target % 8 - index % 8).abs() == 1 {
+                moves.push(Move {from: index, to: target, promotion: PromotionOption::None});
+            }
         }
     }
 
@@ -114,7 +132,7 @@ fn get_knight_unchecked_moves(position: &position::Position, index: i32, color:
 
     for &move_offset in KNIGHT_MOVES {
         if let Some(next_index) = index.checked_add(move_offset) {
-            if next_index < 0 || next_index >= 64 { // Out of bounds check
+            if !(0..64).contains(&next_index) { // Out of bounds check
                 continue;
             }
 
@@ -126,7 +144,7 @@ fn get_knight_unchecked_moves(position: &position::Position, index: i32, color:
             }
 
             if position::is_square_occupied(position, next_index) &&
-               position::is_square_occupied_by_color(position, next_index, &color) {
+               position::is_square_occupied_by_color(position, next_index, color) {
                 continue;
             }
 
@@ -150,7 +168,78 @@ fn get_queen_unchecked_moves(position: &position::Position, index: i32, color: &
 }
 
 fn get_king_unchecked_moves(position: &position::Position, index: i32, color: &piece::Color) -> Vec<Move> {
-    generate_unchecked_sliding_moves(position, index, color, KING_DIRECTIONS, true)
+    let mut moves = generate_unchecked_sliding_moves(position, index, color, KING_DIRECTIONS, true);
+    moves.append(&mut get_castling_unchecked_moves(position, index, color));
+
+    moves
+}
+
+/// Castling is only generated if the king isn't in check and doesn't pass through an attacked square.
+/// Whether the destination is attacked is left to the legality check, like any other king move, which also catches
+/// a Chess960 king left in check by its own rook moving out of the way.
+fn get_castling_unchecked_moves(position: &position::Position, index: i32, color: &piece::Color) -> Vec<Move> {
+    let mut moves: Vec<Move> = Vec::new();
+
+    let back_rank = if *color == piece::Color::White { 0 } else { 56 };
+    let opponent_color = piece::opponent_of(color);
+
+    if index / 8 != back_rank / 8 || !(position::can_castle(position, *color, position::CastlingSide::Short) || position::can_castle(position, *color, position::CastlingSide::Long)) {
+        return moves;
+    }
+    if position::is_square_attacked_by(position, index, &opponent_color) {
+        return moves;
+    }
+
+    for side in [position::CastlingSide::Short, position::CastlingSide::Long] {
+        let rook_file = position.castling_rooks[position::castling_index(*color, side)];
+        if rook_file == position::NO_CASTLING {
+            continue;
+        }
+
+        let rook_from = back_rank + rook_file;
+        let (king_to, rook_to) = castling_destinations(back_rank, side);
+        if position::get_piece_at(position, rook_from) != (piece::Piece::Rook, *color) {
+            continue;
+        }
+
+        // Everything the king and rook cross has to be empty, apart from the two of them.
+        let lowest = index.min(rook_from).min(king_to).min(rook_to);
+        let highest = index.max(rook_from).max(king_to).max(rook_to);
+        let is_blocked = (lowest..=highest).any(|square| square != index && square != rook_from && position::is_square_occupied(position, square));
+
+        let step = if king_to > index { 1 } else { -1 };
+        let mut king_path = (1..(king_to - index).abs()).map(|distance| index + distance * step);
+        let is_attacked = king_path.any(|square| position::is_square_attacked_by(position, square, &opponent_color));
+
+        if !is_blocked && !is_attacked {
+            let to = if position.chess960 { rook_from } else { king_to };
+            moves.push(Move {from: index, to, promotion: PromotionOption::None});
+        }
+    }
+
+    moves
+}
+
+/// Where the king and the rook end up when castling, given the back rank's first square.
+fn castling_destinations(back_rank: i32, side: position::CastlingSide) -> (i32, i32) {
+    if side == position::CastlingSide::Short { (back_rank + 6, back_rank + 5) } else { (back_rank + 2, back_rank + 3) }
+}
+
+/// Whether a move is castling, written either way: the king moving two squares or taking its own rook.
+pub fn is_castling(position: &position::Position, mv: &Move) -> bool {
+    let (moving, color) = position::get_piece_at(position, mv.from);
+    moving == piece::Piece::King && ((mv.to - mv.from).abs() == 2 || position::get_piece_at(position, mv.to) == (piece::Piece::Rook, color))
+}
+
+/// The square of the rook a castling move castles with.
+pub fn castling_rook_square(position: &position::Position, mv: &Move) -> i32 {
+    let color = position::get_piece_at(position, mv.from).1;
+    if position::get_piece_at(position, mv.to) == (piece::Piece::Rook, color) {
+        return mv.to;
+    }
+
+    let side = if mv.to > mv.from { position::CastlingSide::Short } else { position::CastlingSide::Long };
+    mv.from / 8 * 8 + position.castling_rooks[position::castling_index(color, side)]
 }
 
 fn generate_unchecked_sliding_moves(position: &position::Position, index: i32, color: &piece::Color, directions: &[i32], move_only_once: bool) -> Vec<Move> {
@@ -169,7 +258,7 @@ fn generate_unchecked_sliding_moves(position: &position::Position, index: i32, c
         let mut last_file = file;
 
         while let Some(next_index) = index.checked_add(direction * step) {
-            if next_index < 0 || next_index >= 64 {
+            if !(0..64).contains(&next_index) {
                 break;
             }
 
@@ -177,22 +266,20 @@ fn generate_unchecked_sliding_moves(position: &position::Position, index: i32, c
             let next_file = next_index % 8;
 
             // Wraparound Checks
-            if orthogonal_moves.iter().any(|&dir| dir == *direction) {
-                let is_different_rank = rank != next_rank;
-                let is_different_file = file != next_file;
+            if orthogonal_moves.contains(direction) {
+                let is_different_rank = last_rank != next_rank;
+                let is_different_file = last_file != next_file;
 
-                if !((is_different_file && !is_different_rank) || (is_different_rank && !is_different_file)) {
+                if is_different_file == is_different_rank {
                     break; // Any rook move will change either rank or file but cannot change both.
                 }
             }
-            if diagonal_moves.iter().any(|&dir| dir == *direction) {
-                if (last_rank - next_rank).abs() > 1 || (last_file - next_file).abs() > 1 {
-                    break;
-                }
+            if diagonal_moves.contains(direction) && ((last_rank - next_rank).abs() > 1 || (last_file - next_file).abs() > 1) {
+                break;
             }
 
             if position::is_square_occupied(position, next_index) {
-                if position::is_square_occupied_by_color(position, next_index, &color) {
+                if position::is_square_occupied_by_color(position, next_index, color) {
                     break;
                 }
 
@@ -213,6 +300,127 @@ fn generate_unchecked_sliding_moves(position: &position::Position, index: i32, c
     moves
 }
 
+/// Gets every legal move in the position.
+pub fn get_legal_moves(position: &position::Position) -> Vec<Move> {
+    get_unchecked_moves(position).into_iter()
+        .filter(|mv| is_legal(position, mv))
+        .collect()
+}
+
+/// Checks that an unchecked move doesn't leave the mover's own king in check.
+pub fn is_legal(position: &position::Position, mv: &Move) -> bool {
+    let mut next_position = position.clone();
+    make_move(&mut next_position, mv);
+
+    !position::is_in_check(&next_position, &position.side_to_move)
+}
+
+/// Plays a move on the position, updating castling rights, en passant, clocks and the hash.
+/// The move isn't checked for legality, so it should come from the move generator.
+pub fn make_move(position: &mut position::Position, mv: &Move) {
+    let (moving_piece, color) = position::get_piece_at(position, mv.from);
+    let direction = if color == piece::Color::White { 1 } else { -1 };
+    let is_en_passant = moving_piece == piece::Piece::Pawn && mv.to == position.en_passant_index && (mv.to - mv.from) % 8 != 0;
+
+    if position.en_passant_index >= 0 {
+        position.hash ^= zobrist::EN_PASSANT_KEYS[(position.en_passant_index % 8) as usize];
+        position.en_passant_index = -1;
+    }
+
+    let mut captured = (piece::Piece::Empty, piece::Color::None);
+    if moving_piece == piece::Piece::King && is_castling(position, mv) {
+        let rook_from = castling_rook_square(position, mv);
+        let side = if rook_from > mv.from { position::CastlingSide::Short } else { position::CastlingSide::Long };
+        let (king_to, rook_to) = castling_destinations(mv.from / 8 * 8, side);
+
+        // Both come off the board before either goes back, since in Chess960 they can land on each other's square.
+        position::remove_piece_at(position, mv.from);
+        position::remove_piece_at(position, rook_from);
+        position::set_piece_at(position, king_to, piece::Piece::King, &color);
+        position::set_piece_at(position, rook_to, piece::Piece::Rook, &color);
+    } else {
+        position::remove_piece_at(position, mv.from);
+        captured = position::remove_piece_at(position, mv.to);
+        if is_en_passant {
+            position::remove_piece_at(position, mv.to - 8 * direction);
+        }
+
+        let placed_piece = match mv.promotion {
+            PromotionOption::None => moving_piece,
+            PromotionOption::Knight => piece::Piece::Knight,
+            PromotionOption::Bishop => piece::Piece::Bishop,
+            PromotionOption::Rook => piece::Piece::Rook,
+            PromotionOption::Queen => piece::Piece::Queen,
+        };
+        position::set_piece_at(position, mv.to, placed_piece, &color);
+    }
+
+    if moving_piece == piece::Piece::King {
+        position::set_castling_rook(position, color, position::CastlingSide::Short, position::NO_CASTLING);
+        position::set_castling_rook(position, color, position::CastlingSide::Long, position::NO_CASTLING);
+    }
+
+    // Moving from or capturing on a castling rook's square takes away that castling right.
+    for (castling_index, (rights_color, side)) in CASTLING_RIGHTS.into_iter().enumerate() {
+        let rook_file = position.castling_rooks[castling_index];
+        let back_rank = if rights_color == piece::Color::White { 0 } else { 56 };
+        if rook_file != position::NO_CASTLING && (mv.from == back_rank + rook_file || mv.to == back_rank + rook_file) {
+            position::set_castling_rook(position, rights_color, side, position::NO_CASTLING);
+        }
+    }
+
+    if moving_piece == piece::Piece::Pawn && (mv.to - mv.from).abs() == 16 {
+        position.en_passant_index = mv.from + 8 * direction;
+        position.hash ^= zobrist::EN_PASSANT_KEYS[(position.en_passant_index % 8) as usize];
+    }
+
+    if moving_piece == piece::Piece::Pawn || captured.0 != piece::Piece::Empty || is_en_passant {
+        position.half_move_clock = 0;
+    } else {
+        position.half_move_clock += 1;
+    }
+
+    if color == piece::Color::Black {
+        position.full_move_clock += 1;
+    }
+
+    position.side_to_move = piece::opponent_of(&color);
+    position.hash ^= zobrist::SIDE_KEY;
+}
+
+/// Passes the turn without moving, for null move pruning.
+pub fn make_null_move(position: &mut position::Position) {
+    if position.en_passant_index >= 0 {
+        position.hash ^= zobrist::EN_PASSANT_KEYS[(position.en_passant_index % 8) as usize];
+        position.en_passant_index = -1;
+    }
+
+    position.half_move_clock += 1;
+    position.side_to_move = piece::opponent_of(&position.side_to_move);
+    position.hash ^= zobrist::SIDE_KEY;
+}
+
+/// Counts the leaf nodes of the legal move tree, for validating the move generator.
+pub fn perft(position: &position::Position, depth: i32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+
+    let mut nodes = 0;
+    for mv in get_unchecked_moves(position) {
+        let mut next_position = position.clone();
+        make_move(&mut next_position, &mv);
+
+        if position::is_in_check(&next_position, &position.side_to_move) {
+            continue;
+        }
+
+        nodes += if depth == 1 { 1 } else { perft(&next_position, depth - 1) };
+    }
+
+    nodes
+}
+
 fn add_pawn_move(moves: &mut Vec<Move>, from: i32, to: i32, color: &piece::Color) {
     let promotion_rank = if *color == piece::Color::White { 7 } else { 0 };
     let direction = if *color == piece::Color::White { 1 } else { -1 };
@@ -222,25 +430,26 @@ fn add_pawn_move(moves: &mut Vec<Move>, from: i32, to: i32, color: &piece::Color
         moves.append(&mut create_promotion_options(from, to));
     }
     else {
-        moves.push(Move {from: from, to: to, promotion: PromotionOption::None});
+        moves.push(Move {from, to, promotion: PromotionOption::None});
     }
 }
 
 fn create_promotion_options(from: i32, to: i32) -> Vec<Move> {
-    let mut promotion_options: Vec<Move> = Vec::new();
-
-    promotion_options.push(Move {from: from, to: to, promotion: PromotionOption::Knight});
-    promotion_options.push(Move {from: from, to: to, promotion: PromotionOption::Bishop});
-    promotion_options.push(Move {from: from, to: to, promotion: PromotionOption::Rook});
-    promotion_options.push(Move {from: from, to: to, promotion: PromotionOption::Queen});
-
-    promotion_options
+    vec![
+        Move {from, to, promotion: PromotionOption::Knight},
+        Move {from, to, promotion: PromotionOption::Bishop},
+        Move {from, to, promotion: PromotionOption::Rook},
+        Move {from, to, promotion: PromotionOption::Queen},
+    ]
 }
 
 // Unit Tests! //
 
+#[cfg(test)]
 mod tests {
     use super::*;
+    use super::super::fen;
+    use super::super::game;
 
     #[test]
     fn test_pawn_move_generation() {
@@ -323,4 +532,84 @@ mod tests {
             }
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_perft() {
+        // Reference counts from https://www.chessprogramming.org/Perft_Results
+        let positions = [
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", 3, 8902),
+            ("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", 2, 2039),
+            ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 4, 43238),
+            ("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", 3, 9467),
+            ("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8", 2, 1486),
+        ];
+
+        for (fen_string, depth, expected_nodes) in positions {
+            let position = fen::position_from_fen(fen_string);
+            let nodes = perft(&position, depth);
+            assert!(nodes == expected_nodes, "Perft {} of {} should be {} but was {}.", depth, fen_string, expected_nodes, nodes);
+        }
+    }
+
+    #[test]
+    fn test_chess960_perft() {
+        // The first three are from https://www.chessprogramming.org/Chess960_Perft_Results, the others have the king
+        // staying put, the rook on the king's destination and the king stepping towards its rook.
+        let positions = [
+            ("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9", 3, 12189),
+            ("2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9", 3, 18002),
+            ("b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9", 3, 10471),
+            ("1r2k1r1/pppppppp/8/8/8/8/PPPPPPPP/1R2K1R1 w GBgb - 0 1", 3, 15131),
+            ("rk2r3/pp3ppp/8/8/8/8/PP3PPP/RK2R3 w EAea - 0 1", 3, 13343),
+            ("5rkr/8/8/8/8/8/8/5RKR w HFhf - 0 1", 3, 6961),
+        ];
+
+        for (fen_string, depth, expected_nodes) in positions {
+            let position = fen::position_from_fen(fen_string);
+            let nodes = perft(&position, depth);
+            assert!(nodes == expected_nodes, "Perft {} of {} should be {} but was {}.", depth, fen_string, expected_nodes, nodes);
+        }
+    }
+
+    #[test]
+    fn test_chess960_castling() {
+        // The king steps towards its rook and the rook jumps over it.
+        let mut position = fen::position_from_fen("rk2r3/pp3ppp/8/8/8/8/PP3PPP/RK2R3 w EAea - 0 1");
+        let castling = get_legal_moves(&position).into_iter().find(|mv| mv.to_string() == "b1a1").expect("Castling should be written as the king taking its rook.");
+        assert!(is_castling(&position, &castling));
+        make_move(&mut position, &castling);
+        assert_eq!(fen::fen_from_position(&position), "rk2r3/pp3ppp/8/8/8/8/PP3PPP/2KRR3 b kq - 1 1");
+        assert_eq!(position.hash, zobrist::hash_position(&position));
+
+        // Taking a castling rook on its own file takes away that right only.
+        let mut position = fen::position_from_fen("rk2r3/pp3ppp/8/8/8/8/PP3PPP/RK2R3 w EAea - 0 1");
+        for name in ["e1e7", "e8e7"] {
+            let mv = game::find_move(&position, name).unwrap_or_else(|| panic!("{} should be legal here.", name));
+            make_move(&mut position, &mv);
+        }
+        assert_eq!(position.castling_rooks, [position::NO_CASTLING, 0, position::NO_CASTLING, 0]);
+
+        // The standard start position played as Chess960 castles the same way, written differently.
+        let mut start = fen::position_from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        start.chess960 = true;
+        let names: Vec<String> = get_legal_moves(&start).iter().filter(|mv| is_castling(&start, mv)).map(|mv| mv.to_string()).collect();
+        assert_eq!(names, vec!["e1h1", "e1a1"]);
+    }
+
+    #[test]
+    fn test_make_move_keeps_hash() {
+        let mut position = fen::position_from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+
+        // Castle, then push a pawn two squares and take it en passant.
+        for name in ["e1g1", "c7c5", "d5c6", "e8c8"] {
+            let mv = *get_legal_moves(&position).iter()
+                .find(|mv| mv.to_string() == name)
+                .unwrap_or_else(|| panic!("{} should be legal here.", name));
+            make_move(&mut position, &mv);
+
+            assert!(position.hash == zobrist::hash_position(&position), "The incremental hash drifted after {}.", name);
+        }
+
+        assert!(!position::has_castling_rights(&position), "Both sides castled, so no rights should be left.");
+    }
+}
@@ -0,0 +1,601 @@
+use std::fs;
+
+use super::attacks;
+use super::piece::{self, Color};
+use super::position::{self, Position};
+
+// Network file format, all numbers little endian:
+//
+//   magic          4 bytes, "MBNN"
+//   version        u32, 1
+//   king buckets   u32, 1, 4 or 64
+//   hidden size    u32, accumulator neurons per perspective
+//   layer 1 size   u32
+//   feature weights     i16 x king buckets * 768 * hidden size, one row of hidden size per feature
+//   feature biases      i16 x hidden size
+//   layer 1 weights     i8  x layer 1 size * 2 * hidden size, one row per layer 1 neuron
+//   layer 1 biases      i32 x layer 1 size
+//   output weights      i8  x layer 1 size
+//   output bias         i32
+//
+// Features are HalfKA: every piece, kings included, on every square, relative to the king bucket of the
+// perspective's own king. Each perspective sees the board from its own side, so Black's squares are flipped and
+// "own" and "enemy" pieces take the place of White and Black. With 64 buckets every king square is its own bucket;
+// with 4 they're the a-d and e-h halves of ranks 1-4, then of ranks 5-8.
+
+const MAGIC: &[u8; 4] = b"MBNN";
+const VERSION: u32 = 1;
+
+/// Features per king bucket: two colors of six pieces on 64 squares.
+const FEATURES_PER_BUCKET: usize = 768;
+/// Activations are clipped to 0..=`ACTIVATION_MAX` after the feature transformer and after layer 1.
+const ACTIVATION_MAX: i32 = 127;
+/// Layer 1 weights are scaled up by 2^`LAYER_1_SHIFT`, which is taken back off its outputs.
+const LAYER_1_SHIFT: i32 = 6;
+/// The raw network output divided by this is centipawns.
+const OUTPUT_DIVISOR: i32 = 16;
+
+/// A quantized network, loaded from a file.
+pub struct Network {
+    pub king_buckets: usize,
+    pub hidden_size: usize,
+    pub layer_1_size: usize,
+    feature_weights: Vec<i16>,
+    feature_biases: Vec<i16>,
+    /// Stored widened to i16, so the dot products can be done with exact 16 bit multiplies.
+    layer_1_weights: Vec<i16>,
+    layer_1_biases: Vec<i32>,
+    output_weights: Vec<i32>,
+    output_bias: i32,
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, count: usize) -> Result<&[u8], String> {
+        let end = self.offset.checked_add(count).filter(|end| *end <= self.bytes.len()).ok_or("The network file is truncated.")?;
+        let taken = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i32s(&mut self, count: usize) -> Result<Vec<i32>, String> {
+        Ok(self.take(count * 4)?.chunks_exact(4).map(|chunk| i32::from_le_bytes(chunk.try_into().unwrap())).collect())
+    }
+
+    fn i16s(&mut self, count: usize) -> Result<Vec<i16>, String> {
+        Ok(self.take(count * 2)?.chunks_exact(2).map(|chunk| i16::from_le_bytes(chunk.try_into().unwrap())).collect())
+    }
+
+    fn i8s(&mut self, count: usize) -> Result<Vec<i8>, String> {
+        Ok(self.take(count)?.iter().map(|byte| *byte as i8).collect())
+    }
+}
+
+impl Network {
+    pub fn load(path: &str) -> Result<Network, String> {
+        let bytes = fs::read(path).map_err(|error| format!("Couldn't read {}: {}.", path, error))?;
+        Network::from_bytes(&bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Network, String> {
+        let mut reader = Reader { bytes, offset: 0 };
+
+        if reader.take(4)? != MAGIC {
+            return Err("Not a network file.".to_string());
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(format!("Unsupported network version {}.", version));
+        }
+
+        let king_buckets = reader.u32()? as usize;
+        let hidden_size = reader.u32()? as usize;
+        let layer_1_size = reader.u32()? as usize;
+        if ![1, 4, 64].contains(&king_buckets) {
+            return Err(format!("Unsupported king bucket count {}.", king_buckets));
+        }
+        if hidden_size == 0 || hidden_size > 4096 || layer_1_size == 0 || layer_1_size > 256 {
+            return Err(format!("Unsupported layer sizes {} and {}.", hidden_size, layer_1_size));
+        }
+
+        let network = Network {
+            king_buckets,
+            hidden_size,
+            layer_1_size,
+            feature_weights: reader.i16s(king_buckets * FEATURES_PER_BUCKET * hidden_size)?,
+            feature_biases: reader.i16s(hidden_size)?,
+            layer_1_weights: reader.i8s(layer_1_size * 2 * hidden_size)?.into_iter().map(i16::from).collect(),
+            layer_1_biases: reader.i32s(layer_1_size)?,
+            output_weights: reader.i8s(layer_1_size)?.into_iter().map(i32::from).collect(),
+            output_bias: reader.i32s(1)?[0],
+        };
+
+        if reader.offset != bytes.len() {
+            return Err("The network file has trailing data.".to_string());
+        }
+
+        Ok(network)
+    }
+
+    fn king_bucket(&self, king_square: usize) -> usize {
+        match self.king_buckets {
+            64 => king_square,
+            4 => (king_square / 32) * 2 + (king_square % 8) / 4,
+            _ => 0,
+        }
+    }
+
+    /// The feature row of a piece on a square, seen by `perspective` with its king in `bucket`.
+    fn feature(&self, perspective: Color, bucket: usize, piece_id: usize, square: usize) -> &[i16] {
+        let relative_color = if piece::color_from_id(piece_id as i32) == perspective { 0 } else { 1 };
+        let index = bucket * FEATURES_PER_BUCKET + (relative_color * 6 + piece_id % 6) * 64 + orient(square, perspective);
+        &self.feature_weights[index * self.hidden_size..(index + 1) * self.hidden_size]
+    }
+
+    /// The bucket of the perspective's own king in the position.
+    fn bucket_of(&self, position: &Position, perspective: Color) -> usize {
+        let king_index = position::get_king_index(position, &perspective);
+        if king_index < 0 { 0 } else { self.king_bucket(orient(king_index as usize, perspective)) }
+    }
+}
+
+/// Flips squares for Black, so every perspective sees its own pieces start on the first ranks.
+fn orient(square: usize, perspective: Color) -> usize {
+    if perspective == Color::Black { square ^ 56 } else { square }
+}
+
+/// The sum of the active feature rows for both perspectives, the expensive part of the network kept up to date
+/// move by move.
+#[derive(Clone)]
+pub struct Accumulator {
+    /// Indexed by color.
+    pub values: [Vec<i16>; 2],
+    buckets: [usize; 2],
+    /// The clipped activations of both perspectives, side to move first, kept here so evaluating doesn't allocate.
+    inputs: Vec<i16>,
+}
+
+impl Accumulator {
+    pub fn new(network: &Network) -> Self {
+        Accumulator {
+            values: [network.feature_biases.clone(), network.feature_biases.clone()],
+            buckets: [0; 2],
+            inputs: vec![0; 2 * network.hidden_size],
+        }
+    }
+
+    /// Computes both perspectives from scratch.
+    pub fn refresh(&mut self, network: &Network, position: &Position) {
+        for perspective in [Color::White, Color::Black] {
+            self.refresh_perspective(network, position, perspective);
+        }
+    }
+
+    fn refresh_perspective(&mut self, network: &Network, position: &Position, perspective: Color) {
+        let bucket = network.bucket_of(position, perspective);
+        let values = &mut self.values[perspective as usize];
+
+        values.copy_from_slice(&network.feature_biases);
+        for (piece_id, bitboard) in position.bitboards.iter().enumerate() {
+            let mut bitboard = *bitboard;
+            while bitboard != 0 {
+                let square = attacks::pop_lsb(&mut bitboard) as usize;
+                simd::add(values, network.feature(perspective, bucket, piece_id, square));
+            }
+        }
+
+        self.buckets[perspective as usize] = bucket;
+    }
+
+    /// Makes this the accumulator of `child`, given `parent` and its accumulator, by adding and removing only the
+    /// features of the pieces that moved. A perspective whose king changed bucket is refreshed instead.
+    pub fn update(&mut self, network: &Network, parent_accumulator: &Accumulator, parent: &Position, child: &Position) {
+        for perspective in [Color::White, Color::Black] {
+            let color_index = perspective as usize;
+            let bucket = network.bucket_of(child, perspective);
+
+            if bucket != parent_accumulator.buckets[color_index] {
+                self.refresh_perspective(network, child, perspective);
+                continue;
+            }
+
+            let values = &mut self.values[color_index];
+            values.copy_from_slice(&parent_accumulator.values[color_index]);
+
+            for piece_id in 0..12 {
+                let mut removed = parent.bitboards[piece_id] & !child.bitboards[piece_id];
+                while removed != 0 {
+                    simd::sub(values, network.feature(perspective, bucket, piece_id, attacks::pop_lsb(&mut removed) as usize));
+                }
+
+                let mut added = child.bitboards[piece_id] & !parent.bitboards[piece_id];
+                while added != 0 {
+                    simd::add(values, network.feature(perspective, bucket, piece_id, attacks::pop_lsb(&mut added) as usize));
+                }
+            }
+
+            self.buckets[color_index] = bucket;
+        }
+    }
+}
+
+/// Runs the layers after the feature transformer. Returns centipawns from the side to move's point of view.
+pub fn evaluate(network: &Network, accumulator: &mut Accumulator, side_to_move: &Color) -> i32 {
+    let hidden_size = network.hidden_size;
+    let (own, enemy) = if *side_to_move == Color::Black { (1, 0) } else { (0, 1) };
+
+    let inputs = &mut accumulator.inputs;
+    simd::clipped_relu(&accumulator.values[own], &mut inputs[..hidden_size]);
+    simd::clipped_relu(&accumulator.values[enemy], &mut inputs[hidden_size..]);
+
+    let mut output = network.output_bias;
+    for neuron in 0..network.layer_1_size {
+        let weights = &network.layer_1_weights[neuron * 2 * hidden_size..(neuron + 1) * 2 * hidden_size];
+        let sum = network.layer_1_biases[neuron] + simd::dot(inputs, weights);
+        let activation = (sum >> LAYER_1_SHIFT).clamp(0, ACTIVATION_MAX);
+
+        output += activation * network.output_weights[neuron];
+    }
+
+    output / OUTPUT_DIVISOR
+}
+
+/// Evaluates a position from scratch, without an accumulator kept up to date.
+pub fn evaluate_position(network: &Network, position: &Position) -> i32 {
+    let mut accumulator = Accumulator::new(network);
+    accumulator.refresh(network, position);
+    evaluate(network, &mut accumulator, &position.side_to_move)
+}
+
+/// The vector operations the network needs, with AVX2 versions picked at runtime and plain loops everywhere else.
+/// Everything is integer arithmetic with the same wrapping, so both give exactly the same results.
+pub mod simd {
+    pub fn add(values: &mut [i16], row: &[i16]) {
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("avx2") {
+            unsafe { avx2::add(values, row) };
+            return;
+        }
+
+        scalar::add(values, row);
+    }
+
+    pub fn sub(values: &mut [i16], row: &[i16]) {
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("avx2") {
+            unsafe { avx2::sub(values, row) };
+            return;
+        }
+
+        scalar::sub(values, row);
+    }
+
+    pub fn clipped_relu(values: &[i16], output: &mut [i16]) {
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("avx2") {
+            unsafe { avx2::clipped_relu(values, output) };
+            return;
+        }
+
+        scalar::clipped_relu(values, output);
+    }
+
+    pub fn dot(inputs: &[i16], weights: &[i16]) -> i32 {
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("avx2") {
+            return unsafe { avx2::dot(inputs, weights) };
+        }
+
+        scalar::dot(inputs, weights)
+    }
+
+    pub mod scalar {
+        use super::super::ACTIVATION_MAX;
+
+        pub fn add(values: &mut [i16], row: &[i16]) {
+            for (value, weight) in values.iter_mut().zip(row) {
+                *value = value.wrapping_add(*weight);
+            }
+        }
+
+        pub fn sub(values: &mut [i16], row: &[i16]) {
+            for (value, weight) in values.iter_mut().zip(row) {
+                *value = value.wrapping_sub(*weight);
+            }
+        }
+
+        pub fn clipped_relu(values: &[i16], output: &mut [i16]) {
+            for (activation, value) in output.iter_mut().zip(values) {
+                *activation = (*value).clamp(0, ACTIVATION_MAX as i16);
+            }
+        }
+
+        pub fn dot(inputs: &[i16], weights: &[i16]) -> i32 {
+            inputs.iter().zip(weights).fold(0i32, |sum, (input, weight)| sum.wrapping_add(*input as i32 * *weight as i32))
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    mod avx2 {
+        use std::arch::x86_64::*;
+
+        use super::scalar;
+        use super::super::ACTIVATION_MAX;
+
+        const LANES: usize = 16;
+
+        #[target_feature(enable = "avx2")]
+        pub unsafe fn add(values: &mut [i16], row: &[i16]) {
+            let length = values.len().min(row.len());
+            let chunks = length / LANES;
+
+            for chunk in 0..chunks {
+                let value_pointer = values.as_mut_ptr().add(chunk * LANES) as *mut __m256i;
+                let row_pointer = row.as_ptr().add(chunk * LANES) as *const __m256i;
+                _mm256_storeu_si256(value_pointer, _mm256_add_epi16(_mm256_loadu_si256(value_pointer), _mm256_loadu_si256(row_pointer)));
+            }
+
+            scalar::add(&mut values[chunks * LANES..length], &row[chunks * LANES..length]);
+        }
+
+        #[target_feature(enable = "avx2")]
+        pub unsafe fn sub(values: &mut [i16], row: &[i16]) {
+            let length = values.len().min(row.len());
+            let chunks = length / LANES;
+
+            for chunk in 0..chunks {
+                let value_pointer = values.as_mut_ptr().add(chunk * LANES) as *mut __m256i;
+                let row_pointer = row.as_ptr().add(chunk * LANES) as *const __m256i;
+                _mm256_storeu_si256(value_pointer, _mm256_sub_epi16(_mm256_loadu_si256(value_pointer), _mm256_loadu_si256(row_pointer)));
+            }
+
+            scalar::sub(&mut values[chunks * LANES..length], &row[chunks * LANES..length]);
+        }
+
+        #[target_feature(enable = "avx2")]
+        pub unsafe fn clipped_relu(values: &[i16], output: &mut [i16]) {
+            let length = values.len().min(output.len());
+            let chunks = length / LANES;
+            let zero = _mm256_setzero_si256();
+            let max = _mm256_set1_epi16(ACTIVATION_MAX as i16);
+
+            for chunk in 0..chunks {
+                let value = _mm256_loadu_si256(values.as_ptr().add(chunk * LANES) as *const __m256i);
+                let clipped = _mm256_min_epi16(_mm256_max_epi16(value, zero), max);
+                _mm256_storeu_si256(output.as_mut_ptr().add(chunk * LANES) as *mut __m256i, clipped);
+            }
+
+            scalar::clipped_relu(&values[chunks * LANES..length], &mut output[chunks * LANES..length]);
+        }
+
+        /// Pairs of 16 bit products are summed into 32 bits by `madd`, which is exact for activations and weights
+        /// that fit in a byte.
+        #[target_feature(enable = "avx2")]
+        pub unsafe fn dot(inputs: &[i16], weights: &[i16]) -> i32 {
+            let length = inputs.len().min(weights.len());
+            let chunks = length / LANES;
+            let mut sums = _mm256_setzero_si256();
+
+            for chunk in 0..chunks {
+                let input = _mm256_loadu_si256(inputs.as_ptr().add(chunk * LANES) as *const __m256i);
+                let weight = _mm256_loadu_si256(weights.as_ptr().add(chunk * LANES) as *const __m256i);
+                sums = _mm256_add_epi32(sums, _mm256_madd_epi16(input, weight));
+            }
+
+            let mut lanes = [0i32; 8];
+            _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, sums);
+            let sum = lanes.iter().fold(0i32, |sum, lane| sum.wrapping_add(*lane));
+
+            sum.wrapping_add(scalar::dot(&inputs[chunks * LANES..length], &weights[chunks * LANES..length]))
+        }
+    }
+}
+
+// Unit Tests! //
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use super::super::{fen, moves};
+    use super::super::piece::Piece;
+
+    /// A network file of random weights, small enough that the accumulator never leaves the range of an i16.
+    pub fn test_network_bytes(king_buckets: u32, hidden_size: u32, layer_1_size: u32) -> Vec<u8> {
+        let mut state = 0x9e37_79b9_7f4a_7c15u64 ^ (king_buckets as u64) << 32 ^ (hidden_size as u64) << 16 ^ layer_1_size as u64;
+        let mut next = |range: i64| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % (2 * range as u64 + 1)) as i64 - range
+        };
+
+        let mut bytes = MAGIC.to_vec();
+        for value in [VERSION, king_buckets, hidden_size, layer_1_size] {
+            bytes.extend(value.to_le_bytes());
+        }
+
+        let (king_buckets, hidden_size, layer_1_size) = (king_buckets as usize, hidden_size as usize, layer_1_size as usize);
+        for _ in 0..king_buckets * FEATURES_PER_BUCKET * hidden_size {
+            bytes.extend((next(96) as i16).to_le_bytes());
+        }
+        for _ in 0..hidden_size {
+            bytes.extend((next(200) as i16).to_le_bytes());
+        }
+        for _ in 0..layer_1_size * 2 * hidden_size {
+            bytes.push(next(127) as i8 as u8);
+        }
+        for _ in 0..layer_1_size {
+            bytes.extend((next(4000) as i32).to_le_bytes());
+        }
+        for _ in 0..layer_1_size {
+            bytes.push(next(127) as i8 as u8);
+        }
+        bytes.extend((next(2000) as i32).to_le_bytes());
+        bytes
+    }
+
+    pub fn test_network() -> Network {
+        Network::from_bytes(&test_network_bytes(4, 16, 8)).expect("The test network should load.")
+    }
+
+    /// The network worked out one neuron at a time straight from the bytes of the file, with wide integers and
+    /// without accumulators, buckets tables or vector code.
+    fn reference_evaluate(bytes: &[u8], position: &Position) -> i32 {
+        let number = |offset: usize, size: usize| {
+            let mut little_endian = [0u8; 8];
+            little_endian[..size].copy_from_slice(&bytes[offset..offset + size]);
+            let shift = 64 - 8 * size as u32;
+            (i64::from_le_bytes(little_endian) << shift) >> shift
+        };
+
+        let (king_buckets, hidden_size, layer_1_size) = (number(8, 4) as usize, number(12, 4) as usize, number(16, 4) as usize);
+        let feature_weights = 20;
+        let feature_biases = feature_weights + king_buckets * 768 * hidden_size * 2;
+        let layer_1_weights = feature_biases + hidden_size * 2;
+        let layer_1_biases = layer_1_weights + layer_1_size * 2 * hidden_size;
+        let output_weights = layer_1_biases + layer_1_size * 4;
+        let output_bias = output_weights + layer_1_size;
+        assert_eq!(output_bias + 4, bytes.len());
+
+        let activations = |perspective: Color| {
+            let flip = if perspective == Color::Black { 56 } else { 0 };
+            let king = (0..64).find(|square| position::get_piece_at(position, *square) == (Piece::King, perspective)).unwrap() as usize ^ flip;
+            let bucket = match king_buckets {
+                64 => king,
+                4 => 2 * (king / 32) + king % 8 / 4,
+                _ => 0,
+            };
+
+            (0..hidden_size).map(|neuron| {
+                let mut sum = number(feature_biases + neuron * 2, 2);
+                for square in 0..64 {
+                    let (piece, color) = position::get_piece_at(position, square);
+                    if piece != Piece::Empty {
+                        let relative_color = if color == perspective { 0 } else { 1 };
+                        let feature = bucket * 768 + (relative_color * 6 + piece as usize) * 64 + (square as usize ^ flip);
+                        sum += number(feature_weights + (feature * hidden_size + neuron) * 2, 2);
+                    }
+                }
+                sum.clamp(0, 127)
+            }).collect::<Vec<i64>>()
+        };
+
+        let side_to_move = position.side_to_move;
+        let other_side = if side_to_move == Color::White { Color::Black } else { Color::White };
+        let inputs = [activations(side_to_move), activations(other_side)].concat();
+
+        let mut output = number(output_bias, 4);
+        for neuron in 0..layer_1_size {
+            let mut sum = number(layer_1_biases + neuron * 4, 4);
+            for (index, input) in inputs.iter().enumerate() {
+                sum += input * number(layer_1_weights + neuron * 2 * hidden_size + index, 1);
+            }
+            output += (sum >> 6).clamp(0, 127) * number(output_weights + neuron, 1);
+        }
+        (output / 16) as i32
+    }
+
+    #[test]
+    fn test_matches_reference_outputs() {
+        let fens = [
+            fen::STARTING_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        ];
+
+        // Every bucket layout, and a hidden layer that isn't a whole number of vector lanes.
+        for (king_buckets, hidden_size, layer_1_size) in [(1, 16, 8), (4, 16, 8), (64, 37, 5)] {
+            let bytes = test_network_bytes(king_buckets, hidden_size, layer_1_size);
+            let network = Network::from_bytes(&bytes).expect("The test network should load.");
+
+            let outputs: Vec<i32> = fens.iter().map(|fen_string| evaluate_position(&network, &fen::position_from_fen(fen_string))).collect();
+            for (fen_string, output) in fens.iter().zip(&outputs) {
+                assert_eq!(*output, reference_evaluate(&bytes, &fen::position_from_fen(fen_string)), "Wrong output for {} with {} buckets.", fen_string, king_buckets);
+            }
+            assert!(outputs.iter().any(|output| *output != outputs[0]), "The test network should tell positions apart.");
+        }
+    }
+
+    #[test]
+    fn test_incremental_updates_match_refresh() {
+        fn walk(network: &Network, position: &Position, accumulator: &Accumulator, depth: i32) {
+            let mut fresh = Accumulator::new(network);
+            fresh.refresh(network, position);
+            assert!(fresh.values == accumulator.values, "The incrementally updated accumulator drifted.");
+
+            if depth == 0 {
+                return;
+            }
+
+            for mv in moves::get_legal_moves(position) {
+                let mut child = position.clone();
+                moves::make_move(&mut child, &mv);
+
+                let mut child_accumulator = Accumulator::new(network);
+                child_accumulator.update(network, accumulator, position, &child);
+                walk(network, &child, &child_accumulator, depth - 1);
+            }
+        }
+
+        let network = test_network();
+        // Castling, promotions and king moves across buckets all show up within two plies of these.
+        for fen_string in ["r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8", "8/8/8/3k4/4K3/8/8/8 w - - 0 1"] {
+            let position = fen::position_from_fen(fen_string);
+            let mut accumulator = Accumulator::new(&network);
+            accumulator.refresh(&network, &position);
+            walk(&network, &position, &accumulator, 2);
+        }
+    }
+
+    #[test]
+    fn test_simd_matches_scalar() {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as i16
+        };
+
+        for length in [16, 37, 64] {
+            let values: Vec<i16> = (0..length).map(|_| next()).collect();
+            let row: Vec<i16> = (0..length).map(|_| next()).collect();
+            let weights: Vec<i16> = (0..length).map(|_| next() % 128).collect();
+
+            let (mut simd_sum, mut scalar_sum) = (values.clone(), values.clone());
+            simd::add(&mut simd_sum, &row);
+            simd::scalar::add(&mut scalar_sum, &row);
+            assert_eq!(simd_sum, scalar_sum);
+
+            simd::sub(&mut simd_sum, &row);
+            simd::scalar::sub(&mut scalar_sum, &row);
+            assert_eq!(simd_sum, values);
+            assert_eq!(scalar_sum, values);
+
+            let (mut simd_activations, mut scalar_activations) = (vec![0; length], vec![0; length]);
+            simd::clipped_relu(&values, &mut simd_activations);
+            simd::scalar::clipped_relu(&values, &mut scalar_activations);
+            assert_eq!(simd_activations, scalar_activations);
+
+            assert_eq!(simd::dot(&simd_activations, &weights), simd::scalar::dot(&scalar_activations, &weights));
+        }
+    }
+
+    #[test]
+    fn test_rejects_bad_files() {
+        let bytes = test_network_bytes(4, 16, 8);
+        assert!(Network::from_bytes(b"nope").is_err());
+        assert!(Network::from_bytes(&bytes[..bytes.len() - 1]).is_err(), "A truncated network should be rejected.");
+        assert!(Network::from_bytes(&test_network_bytes(2, 16, 8)).is_err(), "Two king buckets aren't supported.");
+    }
+}
@@ -0,0 +1,480 @@
+use super::attacks;
+use super::moves::{self, Move, PromotionOption};
+use super::piece::{self, Piece};
+use super::position;
+
+/// Deepest ply the killer table keeps track of.
+pub const MAX_PLY: usize = 128;
+
+/// Piece values used for MVV-LVA and static exchange evaluation, indexed by `Piece`.
+pub const SEE_VALUES: [i32; 6] = [100, 320, 330, 500, 900, 20000];
+
+const HISTORY_MAX: i32 = 16384;
+
+/// Everything the search learns about good quiet moves while it runs.
+#[derive(Clone)]
+pub struct OrderingTables {
+    /// Two quiet moves per ply that recently caused a beta cutoff.
+    pub killers: [[Option<Move>; 2]; MAX_PLY],
+    /// Indexed by piece id and destination square.
+    pub history: [[i32; 64]; 12],
+    /// The quiet reply that refuted a move, indexed by the piece id and destination square of that move.
+    pub countermoves: [[Option<Move>; 64]; 12],
+}
+
+impl Default for OrderingTables {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OrderingTables {
+    pub fn new() -> Self {
+        OrderingTables {
+            killers: [[None; 2]; MAX_PLY],
+            history: [[0; 64]; 12],
+            countermoves: [[None; 64]; 12],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    pub fn store_killer(&mut self, ply: usize, mv: Move) {
+        if ply >= MAX_PLY || self.killers[ply][0] == Some(mv) {
+            return;
+        }
+
+        self.killers[ply][1] = self.killers[ply][0];
+        self.killers[ply][0] = Some(mv);
+    }
+
+    pub fn get_killers(&self, ply: usize) -> [Option<Move>; 2] {
+        if ply >= MAX_PLY { [None; 2] } else { self.killers[ply] }
+    }
+
+    /// Adds `bonus` (negative for a malus) to the history of a quiet move. Scores decay towards zero as they
+    /// approach `HISTORY_MAX`, so old information is gradually replaced.
+    pub fn update_history(&mut self, position: &position::Position, mv: &Move, bonus: i32) {
+        let moving = position::get_piece_at(position, mv.from);
+        let piece_id = piece::piece_id(&moving.0, &moving.1);
+        if piece_id < 0 {
+            return;
+        }
+
+        let bonus = bonus.clamp(-HISTORY_MAX, HISTORY_MAX);
+        let entry = &mut self.history[piece_id as usize][mv.to as usize];
+        *entry += bonus - *entry * bonus.abs() / HISTORY_MAX;
+    }
+
+    pub fn get_history(&self, position: &position::Position, mv: &Move) -> i32 {
+        let moving = position::get_piece_at(position, mv.from);
+        let piece_id = piece::piece_id(&moving.0, &moving.1);
+        if piece_id < 0 { 0 } else { self.history[piece_id as usize][mv.to as usize] }
+    }
+
+    /// Remembers `mv` as the refutation of `previous_move`. Must be called on the position after `previous_move`
+    /// was played, so the piece that moved can be found on its destination square.
+    pub fn store_countermove(&mut self, position: &position::Position, previous_move: &Move, mv: Move) {
+        let moved = position::get_piece_at(position, previous_move.to);
+        let piece_id = piece::piece_id(&moved.0, &moved.1);
+        if piece_id >= 0 {
+            self.countermoves[piece_id as usize][previous_move.to as usize] = Some(mv);
+        }
+    }
+
+    pub fn get_countermove(&self, position: &position::Position, previous_move: Option<&Move>) -> Option<Move> {
+        let previous_move = previous_move?;
+        let moved = position::get_piece_at(position, previous_move.to);
+        let piece_id = piece::piece_id(&moved.0, &moved.1);
+        if piece_id < 0 { None } else { self.countermoves[piece_id as usize][previous_move.to as usize] }
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+enum Stage {
+    TtMove,
+    GenerateCaptures,
+    GoodCaptures,
+    Promotions,
+    Killers,
+    Countermove,
+    GenerateQuiets,
+    Quiets,
+    BadCaptures,
+    Done,
+}
+
+struct ScoredMove {
+    mv: Move,
+    score: i32,
+}
+
+/// Hands out moves one at a time in the order most likely to produce an early cutoff:
+/// the TT move, winning captures, promotions, killers, the countermove, quiets by history and finally losing captures.
+///
+/// Moves are only scored once their stage is reached, and each call selects the best remaining move instead of
+/// sorting the whole list, so a cutoff on the first few moves doesn't pay for ordering the rest.
+pub struct MovePicker {
+    stage: Stage,
+    captures_only: bool,
+    tt_move: Option<Move>,
+    killers: [Option<Move>; 2],
+    countermove: Option<Move>,
+    captures: Vec<Move>,
+    quiets: Vec<Move>,
+    promotions: Vec<Move>,
+    scored: Vec<ScoredMove>,
+    bad_captures: Vec<ScoredMove>,
+    special_index: usize,
+}
+
+impl MovePicker {
+    /// Creates a picker over every move in the position. `previous_move` is the move that led to this position,
+    /// used to look up the countermove.
+    pub fn new(position: &position::Position, moves: Vec<Move>, tt_move: Option<Move>, ply: usize, previous_move: Option<&Move>, tables: &OrderingTables) -> Self {
+        let mut picker = Self::empty(moves, tt_move, false);
+
+        picker.killers = tables.get_killers(ply);
+        picker.countermove = tables.get_countermove(position, previous_move);
+        picker.split_moves(position);
+
+        picker
+    }
+
+    /// Creates a picker that only hands out captures and capturing or queen promotions, for quiescence search.
+    pub fn new_captures(position: &position::Position, moves: Vec<Move>, tt_move: Option<Move>) -> Self {
+        let mut picker = Self::empty(moves, tt_move, true);
+        picker.split_moves(position);
+
+        picker
+    }
+
+    fn empty(moves: Vec<Move>, tt_move: Option<Move>, captures_only: bool) -> Self {
+        MovePicker {
+            stage: Stage::TtMove,
+            captures_only,
+            tt_move,
+            killers: [None; 2],
+            countermove: None,
+            captures: moves,
+            quiets: Vec::new(),
+            promotions: Vec::new(),
+            scored: Vec::new(),
+            bad_captures: Vec::new(),
+            special_index: 0,
+        }
+    }
+
+    fn split_moves(&mut self, position: &position::Position) {
+        let all_moves = std::mem::take(&mut self.captures);
+
+        for mv in all_moves {
+            if is_capture(position, &mv) {
+                self.captures.push(mv);
+            } else if mv.promotion != PromotionOption::None {
+                if !self.captures_only || mv.promotion == PromotionOption::Queen {
+                    self.promotions.push(mv);
+                }
+            } else if !self.captures_only {
+                self.quiets.push(mv);
+            }
+        }
+
+        // The TT move is only trusted if the generator agrees it's playable here, since hash collisions happen.
+        if let Some(tt_move) = self.tt_move {
+            if !self.captures.contains(&tt_move) && !self.promotions.contains(&tt_move) && !self.quiets.contains(&tt_move) {
+                self.tt_move = None;
+            }
+        }
+
+        // Killers and countermoves are only worth a special slot if they are quiet moves that exist in this position.
+        for killer in self.killers.iter_mut() {
+            if killer.is_some_and(|mv| !self.quiets.contains(&mv) || Some(mv) == self.tt_move) {
+                *killer = None;
+            }
+        }
+        if self.countermove.is_some_and(|mv| !self.quiets.contains(&mv) || Some(mv) == self.tt_move || self.killers.contains(&Some(mv))) {
+            self.countermove = None;
+        }
+    }
+
+    pub fn next(&mut self, position: &position::Position, tables: &OrderingTables) -> Option<Move> {
+        loop {
+            match self.stage {
+                Stage::TtMove => {
+                    self.stage = Stage::GenerateCaptures;
+                    if self.tt_move.is_some() {
+                        return self.tt_move;
+                    }
+                }
+                Stage::GenerateCaptures => {
+                    self.scored = self.captures.drain(..)
+                        .filter(|mv| Some(*mv) != self.tt_move)
+                        .map(|mv| ScoredMove { mv, score: mvv_lva(position, &mv) })
+                        .collect();
+                    self.stage = Stage::GoodCaptures;
+                }
+                Stage::GoodCaptures => {
+                    match pick_best(&mut self.scored) {
+                        Some(scored) => {
+                            // Only pay for the exchange evaluation once the capture is actually about to be tried.
+                            if see(position, &scored.mv) >= 0 {
+                                return Some(scored.mv);
+                            }
+                            self.bad_captures.push(scored);
+                        }
+                        None => {
+                            self.scored = self.promotions.drain(..)
+                                .filter(|mv| Some(*mv) != self.tt_move)
+                                .map(|mv| ScoredMove { mv, score: promotion_value(&mv.promotion) })
+                                .collect();
+                            self.stage = Stage::Promotions;
+                        }
+                    }
+                }
+                Stage::Promotions => {
+                    match pick_best(&mut self.scored) {
+                        Some(scored) => return Some(scored.mv),
+                        None => self.stage = if self.captures_only { Stage::BadCaptures } else { Stage::Killers },
+                    }
+                }
+                Stage::Killers => {
+                    if self.special_index < 2 {
+                        self.special_index += 1;
+                        if self.killers[self.special_index - 1].is_some() {
+                            return self.killers[self.special_index - 1];
+                        }
+                    } else {
+                        self.stage = Stage::Countermove;
+                    }
+                }
+                Stage::Countermove => {
+                    self.stage = Stage::GenerateQuiets;
+                    if self.countermove.is_some() {
+                        return self.countermove;
+                    }
+                }
+                Stage::GenerateQuiets => {
+                    let already_tried = [self.tt_move, self.killers[0], self.killers[1], self.countermove];
+                    self.scored = self.quiets.drain(..)
+                        .filter(|mv| !already_tried.contains(&Some(*mv)))
+                        .map(|mv| ScoredMove { mv, score: tables.get_history(position, &mv) })
+                        .collect();
+                    self.stage = Stage::Quiets;
+                }
+                Stage::Quiets => {
+                    match pick_best(&mut self.scored) {
+                        Some(scored) => return Some(scored.mv),
+                        None => self.stage = Stage::BadCaptures,
+                    }
+                }
+                Stage::BadCaptures => {
+                    match pick_best(&mut self.bad_captures) {
+                        Some(scored) => return Some(scored.mv),
+                        None => self.stage = Stage::Done,
+                    }
+                }
+                Stage::Done => return None,
+            }
+        }
+    }
+}
+
+/// Removes and returns the highest scoring move. A single selection pass, since most nodes cut off early.
+fn pick_best(scored: &mut Vec<ScoredMove>) -> Option<ScoredMove> {
+    let best_index = scored.iter().enumerate().max_by_key(|(index, scored)| (scored.score, -(*index as i32)))?.0;
+    Some(scored.swap_remove(best_index))
+}
+
+fn promotion_value(promotion: &PromotionOption) -> i32 {
+    match promotion {
+        PromotionOption::None => 0,
+        PromotionOption::Knight => SEE_VALUES[1],
+        PromotionOption::Bishop => SEE_VALUES[2],
+        PromotionOption::Rook => SEE_VALUES[3],
+        PromotionOption::Queen => SEE_VALUES[4],
+    }
+}
+
+fn piece_value(piece: &Piece) -> i32 {
+    if *piece == Piece::Empty { 0 } else { SEE_VALUES[*piece as usize] }
+}
+
+/// Gets the piece a move captures, including pawns taken en passant.
+pub fn captured_piece(position: &position::Position, mv: &Move) -> Piece {
+    let (target, target_color) = position::get_piece_at(position, mv.to);
+    let (moving, moving_color) = position::get_piece_at(position, mv.from);
+    // Chess960 castling is written as the king taking its own rook.
+    if target != Piece::Empty && target_color != moving_color {
+        return target;
+    }
+
+    if moving == Piece::Pawn && mv.to == position.en_passant_index && (mv.to - mv.from) % 8 != 0 {
+        return Piece::Pawn;
+    }
+
+    Piece::Empty
+}
+
+#[inline]
+pub fn is_capture(position: &position::Position, mv: &Move) -> bool {
+    captured_piece(position, mv) != Piece::Empty
+}
+
+/// Most valuable victim, least valuable attacker.
+pub fn mvv_lva(position: &position::Position, mv: &Move) -> i32 {
+    let victim = piece_value(&captured_piece(position, mv));
+    let attacker = piece_value(&position::get_piece_at(position, mv.from).0);
+
+    victim * 16 - attacker / 100 + promotion_value(&mv.promotion)
+}
+
+/// Static exchange evaluation: the material balance, from the mover's side, of the sequence of captures on the
+/// destination square when both sides always recapture with their least valuable piece.
+pub fn see(position: &position::Position, mv: &Move) -> i32 {
+    let mut gain = [0i32; 32];
+    let mut depth = 0;
+
+    let moving = position::get_piece_at(position, mv.from);
+    let mut side = piece::opponent_of(&moving.1);
+    let mut occupation = position.occupation_bitboard & !(1 << mv.from);
+
+    gain[0] = piece_value(&captured_piece(position, mv));
+    let mut attacker_value = piece_value(&moving.0);
+    if mv.promotion != PromotionOption::None {
+        gain[0] += promotion_value(&mv.promotion) - SEE_VALUES[0];
+        attacker_value = promotion_value(&mv.promotion);
+    }
+
+    if moving.0 == Piece::Pawn && mv.to == position.en_passant_index && position::get_piece_at(position, mv.to).0 == Piece::Empty {
+        let captured_index = if moving.1 == piece::Color::White { mv.to - 8 } else { mv.to + 8 };
+        occupation &= !(1 << captured_index);
+    }
+
+    loop {
+        let attackers = position::get_pieces_attacking_with_occupation(position, mv.to, occupation) & occupation;
+        let Some((index, piece)) = least_valuable_attacker(position, attackers, &side) else { break };
+
+        depth += 1;
+        gain[depth] = attacker_value - gain[depth - 1];
+        if (-gain[depth - 1]).max(gain[depth]) < 0 || depth == gain.len() - 1 {
+            break;
+        }
+
+        attacker_value = piece_value(&piece);
+        occupation &= !(1 << index);
+        side = piece::opponent_of(&side);
+    }
+
+    while depth > 0 {
+        gain[depth - 1] = -(-gain[depth - 1]).max(gain[depth]);
+        depth -= 1;
+    }
+
+    gain[0]
+}
+
+fn least_valuable_attacker(position: &position::Position, attackers: i64, color: &piece::Color) -> Option<(i32, Piece)> {
+    for piece_index in 0..6 {
+        let piece = piece::piece_from_id(piece_index);
+        let bitboard = position.bitboards[piece::piece_id(&piece, color) as usize] & attackers;
+
+        if bitboard != 0 {
+            let mut bitboard = bitboard;
+            return Some((attacks::pop_lsb(&mut bitboard), piece));
+        }
+    }
+
+    None
+}
+
+/// Convenience for tests and tools: every move the picker would hand out, in order.
+pub fn ordered_moves(position: &position::Position, tt_move: Option<Move>, tables: &OrderingTables) -> Vec<Move> {
+    let mut picker = MovePicker::new(position, moves::get_unchecked_moves(position), tt_move, 0, None, tables);
+    let mut ordered = Vec::new();
+
+    while let Some(mv) = picker.next(position, tables) {
+        ordered.push(mv);
+    }
+
+    ordered
+}
+
+// Unit Tests! //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{display, fen};
+
+    fn find_move(position: &position::Position, name: &str) -> Move {
+        *moves::get_unchecked_moves(position).iter()
+            .find(|mv| mv.to_string() == name)
+            .unwrap_or_else(|| panic!("{} should be a move in this position.", name))
+    }
+
+    #[test]
+    fn test_every_move_is_picked_once() {
+        let position = fen::position_from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        let mut tables = OrderingTables::new();
+        let tt_move = find_move(&position, "a2a3");
+        tables.store_killer(0, find_move(&position, "g2g3"));
+
+        let mut generated = moves::get_unchecked_moves(&position);
+        let mut ordered = ordered_moves(&position, Some(tt_move), &tables);
+        assert_eq!(ordered[0], tt_move, "The TT move should always be tried first.");
+
+        generated.sort();
+        ordered.sort();
+        assert!(generated == ordered, "Picked moves don't match the generated ones.\nExpected: {}\nGot: {}", display::list_moves(&generated), display::list_moves(&ordered));
+    }
+
+    #[test]
+    fn test_stage_order() {
+        // Qxd5 wins a queen for free, Rxa7 loses the rook to the king and knight, and h7h8 is a promotion.
+        let position = fen::position_from_fen("1k6/p6P/8/1n1q4/8/8/8/R2Q2K1 w - - 0 1");
+        let mut tables = OrderingTables::new();
+        let killer = find_move(&position, "g1f2");
+        tables.store_killer(0, killer);
+
+        let ordered = ordered_moves(&position, None, &tables);
+        let position_of = |name: &str| ordered.iter().position(|mv| mv.to_string() == name).unwrap();
+
+        assert_eq!(ordered[0].to_string(), "d1d5", "The winning capture should come first.");
+        assert!(position_of("h7h8q") < position_of("g1f2"), "Promotions should come before killers.");
+        assert_eq!(position_of("g1f2"), position_of("h7h8n") + 1, "The killer should follow the promotions.");
+        assert_eq!(ordered.last().unwrap().to_string(), "a1a7", "The losing capture should come last.");
+    }
+
+    #[test]
+    fn test_history_orders_quiets() {
+        let position = fen::position_from_fen("4k3/8/8/8/8/8/8/4K2R w - - 0 1");
+        let mut tables = OrderingTables::new();
+        let favourite = find_move(&position, "h1h5");
+        tables.update_history(&position, &favourite, 400);
+
+        let ordered = ordered_moves(&position, None, &tables);
+        assert_eq!(ordered[0], favourite, "The quiet move with the best history should be tried first.");
+    }
+
+    #[test]
+    fn test_static_exchange_evaluation() {
+        // Pawn takes a knight defended by a pawn: wins a knight for a pawn.
+        let position = fen::position_from_fen("4k3/8/2p5/3n4/4P3/8/8/4K3 w - - 0 1");
+        assert_eq!(see(&position, &find_move(&position, "e4d5")), SEE_VALUES[1] - SEE_VALUES[0]);
+
+        // Rook takes a pawn defended by a pawn: loses the exchange.
+        let position = fen::position_from_fen("4k3/2p5/3p4/8/8/8/8/3RK3 w - - 0 1");
+        assert_eq!(see(&position, &find_move(&position, "d1d6")), SEE_VALUES[0] - SEE_VALUES[3]);
+
+        // Undefended queen.
+        let position = fen::position_from_fen("4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1");
+        assert_eq!(see(&position, &find_move(&position, "d1d5")), SEE_VALUES[4]);
+
+        // Doubled rooks battery through the front rook: RxR, RxR wins a rook for nothing.
+        let position = fen::position_from_fen("3rk3/8/8/3r4/8/8/3R4/3RK3 w - - 0 1");
+        assert_eq!(see(&position, &find_move(&position, "d2d5")), SEE_VALUES[3]);
+    }
+}
@@ -0,0 +1,326 @@
+use super::attacks;
+use super::eval::{s, Parameter, Recorder, Score};
+use super::piece::{self, Color, Piece};
+use super::position::Position;
+
+// Pawn structure weights. Arrays are indexed by the pawn's rank counted from its own side, 0 to 7.
+
+pub const PASSED_PAWN: [Score; 8] = [s(0, 0), s(2, 8), s(6, 14), s(12, 28), s(30, 55), s(55, 105), s(95, 165), s(0, 0)];
+/// Taken off a passed pawn when something stands on the square in front of it.
+pub const PASSED_PAWN_BLOCKED: [Score; 8] = [s(0, 0), s(-1, -3), s(-3, -6), s(-5, -12), s(-10, -25), s(-18, -45), s(-28, -70), s(0, 0)];
+/// A pawn with no own pawns on the files next to it, which no pawn will ever protect.
+pub const ISOLATED_PAWN: Score = s(-8, -14);
+/// For every pawn with another own pawn in front of it on the same file.
+pub const DOUBLED_PAWN: Score = s(-10, -24);
+/// A pawn that can't be protected by its neighbours and can't safely advance.
+pub const BACKWARD_PAWN: Score = s(-7, -10);
+/// A pawn protected by another pawn.
+pub const CONNECTED_PAWN: [Score; 8] = [s(0, 0), s(0, 0), s(7, 4), s(9, 7), s(14, 13), s(26, 28), s(45, 55), s(0, 0)];
+/// A pawn with an own pawn right beside it.
+pub const PHALANX_PAWN: [Score; 8] = [s(0, 0), s(2, 1), s(4, 3), s(7, 5), s(14, 12), s(28, 28), s(45, 55), s(0, 0)];
+/// A pawn on an open file with at least as many pawns to help it through as there are enemy pawns in the way.
+pub const CANDIDATE_PASSER: [Score; 8] = [s(0, 0), s(2, 4), s(4, 7), s(7, 13), s(13, 24), s(22, 42), s(0, 0), s(0, 0)];
+
+const PAWN_TABLE_SIZE: usize = 1 << 14;
+
+pub const FILE_MASKS: [i64; 8] = generate_file_masks();
+/// The files on either side of a file, not including the file itself.
+pub const ADJACENT_FILE_MASKS: [i64; 8] = generate_adjacent_file_masks();
+/// Indexed by color, then square. Every square in front of the square on the same file, from that color's side.
+pub const FORWARD_FILE_MASKS: [[i64; 64]; 2] = generate_forward_masks(false);
+/// Indexed by color, then square. Every square in front of the square on the same and adjacent files, which is where
+/// an enemy pawn would have to be to stop a pawn on the square.
+pub const PASSED_PAWN_MASKS: [[i64; 64]; 2] = generate_forward_masks(true);
+
+const fn generate_file_masks() -> [i64; 8] {
+    let mut masks = [0i64; 8];
+
+    let mut file = 0;
+    while file < 8 {
+        masks[file] = (0x0101_0101_0101_0101u64 as i64) << file;
+        file += 1;
+    }
+
+    masks
+}
+
+const fn generate_adjacent_file_masks() -> [i64; 8] {
+    let files = generate_file_masks();
+    let mut masks = [0i64; 8];
+
+    let mut file = 0;
+    while file < 8 {
+        if file > 0 {
+            masks[file] |= files[file - 1];
+        }
+        if file < 7 {
+            masks[file] |= files[file + 1];
+        }
+        file += 1;
+    }
+
+    masks
+}
+
+const fn generate_forward_masks(include_adjacent_files: bool) -> [[i64; 64]; 2] {
+    let mut masks = [[0i64; 64]; 2];
+
+    let mut index = 0;
+    while index < 64 {
+        let file = index % 8;
+        let rank = index / 8;
+
+        let mut target_rank = 0;
+        while target_rank < 8 {
+            let mut target_file = if include_adjacent_files && file > 0 { file - 1 } else { file };
+            let last_file = if include_adjacent_files && file < 7 { file + 1 } else { file };
+
+            while target_file <= last_file {
+                let target = 1i64 << (target_rank * 8 + target_file);
+                if target_rank > rank {
+                    masks[0][index] |= target;
+                } else if target_rank < rank {
+                    masks[1][index] |= target;
+                }
+                target_file += 1;
+            }
+            target_rank += 1;
+        }
+
+        index += 1;
+    }
+
+    masks
+}
+
+/// The pawn structure terms of one side, before they're added up.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PawnTerms {
+    pub passed: Score,
+    pub isolated: Score,
+    pub doubled: Score,
+    pub backward: Score,
+    pub connected: Score,
+    pub phalanx: Score,
+    pub candidate: Score,
+}
+
+impl PawnTerms {
+    pub fn total(&self) -> Score {
+        self.passed + self.isolated + self.doubled + self.backward + self.connected + self.phalanx + self.candidate
+    }
+}
+
+/// Everything about the pawn structure that depends only on where the pawns are, so it can be cached by the pawn hash.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PawnEntry {
+    pub key: u64,
+    /// White's structure minus Black's.
+    pub score: Score,
+    /// Passed pawns of each color, for the terms that also depend on the other pieces.
+    pub passed: [i64; 2],
+}
+
+/// A cache of pawn structure evaluations, indexed by `Position::pawn_hash`. Pawn structures change rarely during a
+/// search, so nearly every lookup hits.
+pub struct PawnTable {
+    entries: Vec<Option<PawnEntry>>,
+}
+
+impl Default for PawnTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PawnTable {
+    pub fn new() -> Self {
+        PawnTable { entries: vec![None; PAWN_TABLE_SIZE] }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.fill(None);
+    }
+
+    /// Gets the pawn structure evaluation of the position, computing and storing it if it's not in the table.
+    pub fn probe(&mut self, position: &Position) -> PawnEntry {
+        let slot = &mut self.entries[position.pawn_hash as usize % PAWN_TABLE_SIZE];
+
+        match slot {
+            Some(entry) if entry.key == position.pawn_hash => *entry,
+            _ => {
+                let entry = evaluate_pawns(position);
+                *slot = Some(entry);
+                entry
+            }
+        }
+    }
+}
+
+/// Evaluates the pawn structure from scratch.
+pub fn evaluate_pawns(position: &Position) -> PawnEntry {
+    let (white_terms, white_passed) = pawn_terms(position, &Color::White, &mut ());
+    let (black_terms, black_passed) = pawn_terms(position, &Color::Black, &mut ());
+
+    PawnEntry {
+        key: position.pawn_hash,
+        score: white_terms.total() - black_terms.total(),
+        passed: [white_passed, black_passed],
+    }
+}
+
+/// Scores the pawn structure of one side, returning the terms and a bitboard of its passed pawns.
+pub fn pawn_terms(position: &Position, color: &Color, recorder: &mut impl Recorder) -> (PawnTerms, i64) {
+    let color_index = *color as usize;
+    let own_pawns = position.bitboards[piece::piece_id(&Piece::Pawn, color) as usize];
+    let enemy_pawns = position.bitboards[piece::piece_id(&Piece::Pawn, &piece::opponent_of(color)) as usize];
+    let forward = if *color == Color::White { 8 } else { -8 };
+
+    let mut terms = PawnTerms::default();
+    let mut passed = 0;
+
+    let mut pawns = own_pawns;
+    while pawns != 0 {
+        let index = attacks::pop_lsb(&mut pawns);
+        let file = (index % 8) as usize;
+        let relative_rank = relative_rank(index, color);
+        let stop_square = index + forward;
+
+        let neighbours = own_pawns & ADJACENT_FILE_MASKS[file];
+        let supporters = own_pawns & attacks::PAWN_ATTACKS[1 - color_index][index as usize];
+        let phalanx = own_pawns & ADJACENT_FILE_MASKS[file] & (0xffi64 << (index / 8 * 8));
+        let is_passed = enemy_pawns & PASSED_PAWN_MASKS[color_index][index as usize] == 0;
+        let is_open_file = enemy_pawns & FORWARD_FILE_MASKS[color_index][index as usize] == 0;
+
+        if is_passed {
+            passed |= 1 << index;
+            terms.passed += PASSED_PAWN[relative_rank];
+            recorder.record(Parameter::PassedPawn, relative_rank, color, 1);
+        }
+
+        if neighbours == 0 {
+            terms.isolated += ISOLATED_PAWN;
+            recorder.record(Parameter::IsolatedPawn, 0, color, 1);
+        }
+
+        if own_pawns & FORWARD_FILE_MASKS[color_index][index as usize] != 0 {
+            terms.doubled += DOUBLED_PAWN;
+            recorder.record(Parameter::DoubledPawn, 0, color, 1);
+        }
+
+        if supporters != 0 {
+            terms.connected += CONNECTED_PAWN[relative_rank];
+            recorder.record(Parameter::ConnectedPawn, relative_rank, color, 1);
+        }
+        if phalanx != 0 {
+            terms.phalanx += PHALANX_PAWN[relative_rank];
+            recorder.record(Parameter::PhalanxPawn, relative_rank, color, 1);
+        }
+
+        // Backward: every neighbour has already gone past it, and an enemy pawn guards the square it would move to.
+        let neighbours_level_or_behind = neighbours & !PASSED_PAWN_MASKS[color_index][index as usize];
+        let stop_square_guarded = (0..64).contains(&stop_square)
+            && attacks::PAWN_ATTACKS[color_index][stop_square as usize] & enemy_pawns != 0;
+        if neighbours != 0 && neighbours_level_or_behind == 0 && !is_passed && stop_square_guarded {
+            terms.backward += BACKWARD_PAWN;
+            recorder.record(Parameter::BackwardPawn, 0, color, 1);
+        }
+
+        // Candidate passer: nothing on its own file stops it, and its neighbours can trade off the pawns that could.
+        if !is_passed && is_open_file {
+            let sentries = (enemy_pawns & PASSED_PAWN_MASKS[color_index][index as usize]).count_ones();
+            let helpers = neighbours_level_or_behind.count_ones();
+            if helpers >= sentries {
+                terms.candidate += CANDIDATE_PASSER[relative_rank];
+                recorder.record(Parameter::CandidatePasser, relative_rank, color, 1);
+            }
+        }
+    }
+
+    (terms, passed)
+}
+
+/// The part of the color's passed pawn evaluation that depends on more than pawns.
+pub fn passed_pawn_pieces(position: &Position, entry: &PawnEntry, color: &Color, recorder: &mut impl Recorder) -> Score {
+    let forward = if *color == Color::White { 8 } else { -8 };
+    let mut score = Score::default();
+
+    let mut passed = entry.passed[*color as usize];
+    while passed != 0 {
+        let index = attacks::pop_lsb(&mut passed);
+        let stop_square = index + forward;
+
+        if (0..64).contains(&stop_square) && position.occupation_bitboard & (1 << stop_square) != 0 {
+            score += PASSED_PAWN_BLOCKED[relative_rank(index, color)];
+            recorder.record(Parameter::PassedPawnBlocked, relative_rank(index, color), color, 1);
+        }
+    }
+
+    score
+}
+
+/// The rank of a square counted from the given color's side of the board.
+pub fn relative_rank(index: i32, color: &Color) -> usize {
+    let rank = (index / 8) as usize;
+    if *color == Color::Black { 7 - rank } else { rank }
+}
+
+// Unit Tests! //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{fen, moves, zobrist};
+
+    fn white_terms(fen_string: &str) -> (PawnTerms, i64) {
+        pawn_terms(&fen::position_from_fen(fen_string), &Color::White, &mut ())
+    }
+
+    #[test]
+    fn test_pawn_masks() {
+        // d4 is 27. A white pawn there is stopped by black pawns from c5 to e7.
+        assert_eq!(PASSED_PAWN_MASKS[0][27].count_ones(), 12);
+        assert_eq!(FORWARD_FILE_MASKS[0][27], (1 << 35) | (1 << 43) | (1 << 51) | (1 << 59));
+        assert_eq!(FORWARD_FILE_MASKS[1][27], (1 << 19) | (1 << 11) | (1 << 3));
+        assert_eq!(ADJACENT_FILE_MASKS[0], FILE_MASKS[1]);
+    }
+
+    #[test]
+    fn test_pawn_terms() {
+        // An isolated passed pawn on d5, far from the black pawns.
+        let (terms, passed) = white_terms("4k3/p7/8/3P4/8/8/8/4K3 w - - 0 1");
+        assert_eq!(passed, 1 << 35);
+        assert_eq!(terms.passed, PASSED_PAWN[4]);
+        assert_eq!(terms.isolated, ISOLATED_PAWN);
+
+        // Doubled pawns on the c-file, protected by the b-pawn.
+        let (terms, _) = white_terms("4k3/8/8/8/2P5/1PP5/8/4K3 w - - 0 1");
+        assert_eq!(terms.doubled, DOUBLED_PAWN);
+        assert_eq!(terms.connected, CONNECTED_PAWN[3]);
+        assert_eq!(terms.phalanx, PHALANX_PAWN[2] * 2);
+
+        // The d3 pawn is left behind by the pawns on c4 and e4, and e5 stops it from catching up. It's no candidate,
+        // since its own file isn't open.
+        let (terms, _) = white_terms("4k3/8/3p4/4p3/2P1P3/3P4/8/4K3 w - - 0 1");
+        assert_eq!(terms.backward, BACKWARD_PAWN);
+
+        // The a-pawn is already passed, and the b-pawn can get past c7 with its help.
+        let (terms, _) = white_terms("4k3/2p5/3p4/8/PP6/8/8/4K3 w - - 0 1");
+        assert_eq!(terms.candidate, CANDIDATE_PASSER[3]);
+    }
+
+    #[test]
+    fn test_pawn_hash() {
+        let mut position = fen::position_from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        assert_eq!(position.pawn_hash, zobrist::hash_pawns(&position));
+
+        // Moving a piece doesn't change the pawn hash, and the table gives back the same evaluation it computed.
+        let mut table = PawnTable::new();
+        let before = table.probe(&position);
+        moves::make_move(&mut position, &moves::Move { from: 21, to: 29, promotion: moves::PromotionOption::None });
+        assert_eq!(position.pawn_hash, before.key);
+
+        let after = table.probe(&position);
+        assert_eq!(after.score, evaluate_pawns(&position).score);
+    }
+}
@@ -0,0 +1,477 @@
+use std::io::BufRead;
+
+use super::datagen::GameResult;
+use super::display;
+use super::fen;
+use super::moves::{self, Move, PromotionOption};
+use super::piece::{Color, Piece};
+use super::position::{self, Position};
+
+const RESULTS: &[&str] = &["1-0", "0-1", "1/2-1/2", "*"];
+
+/// A game read from PGN: its tags in the order they were given, and its main line.
+#[derive(Clone)]
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    pub moves: Vec<Move>,
+    /// `None` when the game wasn't finished, `*`.
+    pub result: Option<GameResult>,
+    /// Read from the `FEN` tag once, when the game is parsed.
+    start: Position,
+}
+
+impl PgnGame {
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(tag, _)| tag == name).map(|(_, value)| value.as_str())
+    }
+
+    /// The position the game started from, the starting position unless there's a `FEN` tag.
+    pub fn start_position(&self) -> Position {
+        self.start.clone()
+    }
+
+    /// A player's rating from the `WhiteElo` or `BlackElo` tag, if it has one.
+    pub fn elo(&self, white: bool) -> Option<u32> {
+        self.tag(if white { "WhiteElo" } else { "BlackElo" })?.parse().ok()
+    }
+
+    /// Sets a tag, replacing its value if the game has it already and adding it at the end if not.
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(tag, _)| tag == name) {
+            Some((_, old)) => *old = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    /// The game as PGN, with its moves in standard algebraic notation.
+    pub fn to_pgn(&self) -> String {
+        let mut pgn = String::new();
+        for (name, value) in &self.tags {
+            pgn.push_str(&format!("[{} \"{}\"]\n", name, value.replace('"', "\\\"")));
+        }
+        pgn.push('\n');
+
+        let mut position = self.start_position();
+        let mut tokens = Vec::new();
+        for (ply, mv) in self.moves.iter().enumerate() {
+            if position.side_to_move == Color::White {
+                tokens.push(format!("{}.", position.full_move_clock));
+            } else if ply == 0 {
+                tokens.push(format!("{}...", position.full_move_clock));
+            }
+            tokens.push(san(&position, mv));
+            moves::make_move(&mut position, mv);
+        }
+        tokens.push(match self.result {
+            Some(GameResult::WhiteWin) => "1-0",
+            Some(GameResult::BlackWin) => "0-1",
+            Some(GameResult::Draw) => "1/2-1/2",
+            None => "*",
+        }.to_string());
+
+        // Movetext lines are kept under 80 characters.
+        let mut line = String::new();
+        for token in tokens {
+            if !line.is_empty() && line.len() + 1 + token.len() >= 80 {
+                pgn.push_str(&line);
+                pgn.push('\n');
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&token);
+        }
+        pgn.push_str(&line);
+        pgn.push('\n');
+        pgn
+    }
+}
+
+/// Reads the games of a PGN file one at a time, so a large database never has to fit in memory.
+pub struct PgnReader<R: BufRead> {
+    lines: std::io::Lines<R>,
+    /// The tag line that ended the previous game, which belongs to the next one.
+    pending: Option<String>,
+}
+
+impl<R: BufRead> PgnReader<R> {
+    pub fn new(reader: R) -> Self {
+        PgnReader { lines: reader.lines(), pending: None }
+    }
+}
+
+impl<R: BufRead> Iterator for PgnReader<R> {
+    type Item = Result<PgnGame, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut tags = Vec::new();
+        let mut movetext = String::new();
+        let mut error = None;
+
+        for line in self.pending.take().map(Ok).into_iter().chain(&mut self.lines) {
+            let line = match line {
+                Ok(line) => line,
+                Err(error) => return Some(Err(format!("Couldn't read the PGN: {}.", error))),
+            };
+            let line = line.trim();
+
+            if line.starts_with('[') {
+                // A tag after the moves starts the next game.
+                if !movetext.trim().is_empty() {
+                    self.pending = Some(line.to_string());
+                    break;
+                }
+                // Some databases put several tags on a line.
+                for tag in split_tags(line) {
+                    match parse_tag(tag) {
+                        Ok(tag) => tags.push(tag),
+                        // The game is still read to its end, or its moves would be taken for the next game.
+                        Err(tag_error) => {
+                            error.get_or_insert(tag_error);
+                        }
+                    }
+                }
+            } else if !line.starts_with('%') {
+                movetext.push_str(line);
+                movetext.push('\n');
+
+                // Games without tags are only told apart by their results.
+                if line.split_whitespace().last().is_some_and(|token| RESULTS.contains(&token)) {
+                    break;
+                }
+            }
+        }
+
+        if let Some(error) = error {
+            return Some(Err(error));
+        }
+        if tags.is_empty() && movetext.trim().is_empty() {
+            return None;
+        }
+        Some(parse_game(tags, &movetext))
+    }
+}
+
+/// Reads every game of a PGN text.
+pub fn parse_games(text: &str) -> Vec<Result<PgnGame, String>> {
+    PgnReader::new(text.as_bytes()).collect()
+}
+
+/// The tags of a tag line. A `]` only ends a tag outside its quoted value, so `[Event "Rated [Blitz] game"]` is one tag.
+fn split_tags(line: &str) -> Vec<&str> {
+    let mut tags = Vec::new();
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+    for (index, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ']' if !quoted => {
+                tags.push(line[start..=index].trim());
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    tags.push(line[start..].trim());
+    tags.retain(|tag| !tag.is_empty());
+    tags
+}
+
+fn parse_tag(line: &str) -> Result<(String, String), String> {
+    let inner = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')).ok_or_else(|| format!("Invalid tag {}.", line))?;
+    let (name, value) = inner.trim().split_once(' ').ok_or_else(|| format!("Invalid tag {}.", line))?;
+    Ok((name.to_string(), value.trim().trim_matches('"').replace("\\\"", "\"")))
+}
+
+fn parse_game(tags: Vec<(String, String)>, movetext: &str) -> Result<PgnGame, String> {
+    let fen_string = tags.iter().find(|(tag, _)| tag == "FEN").map_or(fen::STARTING_FEN, |(_, value)| value.as_str());
+    let start = fen::try_position_from_fen(fen_string).map_err(|error| format!("Invalid FEN tag {}: {}", fen_string, error))?;
+
+    let mut game = PgnGame { tags, moves: Vec::new(), result: None, start };
+    let mut position = game.start_position();
+    let mut result_token = None;
+
+    for token in movetext_tokens(movetext) {
+        match token {
+            _ if RESULTS.contains(&token) => result_token = Some(token),
+            _ => {
+                let mv = move_from_san(&position, token).ok_or_else(|| format!("Illegal move {} in {}.", token, fen::fen_from_position(&position)))?;
+                moves::make_move(&mut position, &mv);
+                game.moves.push(mv);
+            }
+        }
+    }
+
+    game.result = match game.tag("Result").or(result_token) {
+        Some("1-0") => Some(GameResult::WhiteWin),
+        Some("0-1") => Some(GameResult::BlackWin),
+        Some("1/2-1/2") => Some(GameResult::Draw),
+        _ => None,
+    };
+    Ok(game)
+}
+
+/// The moves and result of movetext, without move numbers, comments, variations and annotations.
+fn movetext_tokens(movetext: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut variation_depth = 0;
+    let mut rest = movetext;
+
+    while let Some(c) = rest.chars().next() {
+        let skip = match c {
+            '{' => rest.find('}').map_or(rest.len(), |end| end + 1),
+            ';' => rest.find('\n').unwrap_or(rest.len()),
+            '(' => { variation_depth += 1; 1 }
+            ')' => { variation_depth -= 1; 1 }
+            _ if c.is_whitespace() => c.len_utf8(),
+            _ => {
+                let end = rest.find(|c: char| c.is_whitespace() || "{}();".contains(c)).unwrap_or(rest.len());
+                let token = rest[..end].trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+                // `12.e4` is a number and a move together; `12.` and `12...` are only numbers.
+                let token = if rest[..end].contains('.') { token } else { &rest[..end] };
+                if variation_depth == 0 && !token.is_empty() && !token.starts_with('$') {
+                    tokens.push(token);
+                }
+                end
+            }
+        };
+        rest = &rest[skip..];
+    }
+
+    tokens
+}
+
+/// Finds the legal move written in standard algebraic notation, like `Nbd7`, `exd8=Q+` or `O-O`.
+pub fn move_from_san(position: &Position, san: &str) -> Option<Move> {
+    let san = san.trim_end_matches(['+', '#', '!', '?']);
+    let legal_moves = moves::get_legal_moves(position);
+
+    let castling = |short: bool| legal_moves.iter().find(|mv| moves::is_castling(position, mv) && (mv.to > mv.from) == short).copied();
+    match san {
+        "O-O" | "0-0" => return castling(true),
+        "O-O-O" | "0-0-0" => return castling(false),
+        _ => {}
+    }
+
+    let (san, promotion) = match san.rsplit_once('=').or_else(|| {
+        // Promotions are sometimes written without the `=`, like `e8Q`.
+        san.char_indices().last().filter(|(_, c)| "NBRQ".contains(*c) && san.len() > 2).map(|(index, _)| (&san[..index], &san[index..]))
+    }) {
+        Some((rest, piece)) => (rest, match piece {
+            "N" => PromotionOption::Knight,
+            "B" => PromotionOption::Bishop,
+            "R" => PromotionOption::Rook,
+            "Q" => PromotionOption::Queen,
+            _ => return None,
+        }),
+        None => (san, PromotionOption::None),
+    };
+
+    let piece = match san.chars().next()? {
+        'N' => Piece::Knight,
+        'B' => Piece::Bishop,
+        'R' => Piece::Rook,
+        'Q' => Piece::Queen,
+        'K' => Piece::King,
+        _ => Piece::Pawn,
+    };
+    let squares = if piece == Piece::Pawn { san } else { &san[1..] };
+    let squares: Vec<char> = squares.chars().filter(|c| *c != 'x' && *c != '-').collect();
+    if squares.len() < 2 {
+        return None;
+    }
+
+    let (to_file, to_rank) = (squares[squares.len() - 2], squares[squares.len() - 1]);
+    if !('a'..='h').contains(&to_file) || !('1'..='8').contains(&to_rank) {
+        return None;
+    }
+    let to = (to_rank as i32 - '1' as i32) * 8 + (to_file as i32 - 'a' as i32);
+
+    // What's left is the disambiguation: a file, a rank or both.
+    let mut from_file = None;
+    let mut from_rank = None;
+    for c in &squares[..squares.len() - 2] {
+        match c {
+            'a'..='h' => from_file = Some(*c as i32 - 'a' as i32),
+            '1'..='8' => from_rank = Some(*c as i32 - '1' as i32),
+            _ => return None,
+        }
+    }
+
+    let mut candidates = legal_moves.into_iter().filter(|mv| {
+        !moves::is_castling(position, mv) && mv.to == to && mv.promotion == promotion
+            && position::get_piece_at(position, mv.from).0 == piece
+            && from_file.is_none_or(|file| mv.from % 8 == file)
+            && from_rank.is_none_or(|rank| mv.from / 8 == rank)
+    });
+
+    let mv = candidates.next()?;
+    if candidates.next().is_some() {
+        return None;
+    }
+    Some(mv)
+}
+
+/// A legal move in standard algebraic notation, with `+` for check and `#` for mate.
+pub fn san(position: &Position, mv: &Move) -> String {
+    let piece = position::get_piece_at(position, mv.from).0;
+    let mut name = String::new();
+
+    if moves::is_castling(position, mv) {
+        name.push_str(if mv.to > mv.from { "O-O" } else { "O-O-O" });
+    } else {
+        let to_name = display::board_index_to_coordinate_name(mv.to);
+        let is_capture = position::get_piece_at(position, mv.to).0 != Piece::Empty || (piece == Piece::Pawn && mv.to == position.en_passant_index);
+
+        if piece == Piece::Pawn {
+            if is_capture {
+                name.push(display::board_index_to_coordinate_name(mv.from).chars().next().unwrap());
+            }
+        } else {
+            name.push(display::get_piece_ascii(piece, Color::White));
+
+            // Other pieces of the same kind that could go to the same square.
+            let others: Vec<Move> = moves::get_legal_moves(position).into_iter()
+                .filter(|other| other.to == mv.to && other.from != mv.from && position::get_piece_at(position, other.from).0 == piece)
+                .collect();
+            let from_name = display::board_index_to_coordinate_name(mv.from);
+            if !others.is_empty() {
+                if others.iter().all(|other| other.from % 8 != mv.from % 8) {
+                    name.push_str(&from_name[..1]);
+                } else if others.iter().all(|other| other.from / 8 != mv.from / 8) {
+                    name.push_str(&from_name[1..]);
+                } else {
+                    name.push_str(&from_name);
+                }
+            }
+        }
+
+        if is_capture {
+            name.push('x');
+        }
+        name.push_str(&to_name);
+
+        match mv.promotion {
+            PromotionOption::None => {}
+            PromotionOption::Knight => name.push_str("=N"),
+            PromotionOption::Bishop => name.push_str("=B"),
+            PromotionOption::Rook => name.push_str("=R"),
+            PromotionOption::Queen => name.push_str("=Q"),
+        }
+    }
+
+    let mut after = position.clone();
+    moves::make_move(&mut after, mv);
+    if position::is_in_check(&after, &after.side_to_move) {
+        name.push(if moves::get_legal_moves(&after).is_empty() { '#' } else { '+' });
+    }
+    name
+}
+
+// Unit Tests! //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::game;
+
+    #[test]
+    fn test_move_from_san() {
+        let start = fen::position_from_fen(fen::STARTING_FEN);
+        assert_eq!(move_from_san(&start, "e4").map(|mv| mv.to_string()), Some("e2e4".to_string()));
+        assert_eq!(move_from_san(&start, "Nf3").map(|mv| mv.to_string()), Some("g1f3".to_string()));
+        assert_eq!(move_from_san(&start, "e5"), None, "A pawn can't move three squares.");
+
+        let middlegame = fen::position_from_fen("r3k2r/1P6/8/8/8/5N2/8/R3KN1R w KQkq - 0 1");
+        let names = ["O-O-O", "N1h2", "N3h2", "bxa8=Q+", "b8N", "Ra2"];
+        let expected = ["e1c1", "f1h2", "f3h2", "b7a8q", "b7b8n", "a1a2"];
+        for (name, expected) in names.iter().zip(expected) {
+            assert_eq!(move_from_san(&middlegame, name).map(|mv| mv.to_string()), Some(expected.to_string()), "{} should be {}.", name, expected);
+        }
+        assert_eq!(move_from_san(&middlegame, "Nh2"), None, "Two knights can go to h2.");
+        assert_eq!(move_from_san(&middlegame, "O-O"), None, "The knight on f1 is in the way.");
+    }
+
+    #[test]
+    fn test_san() {
+        let middlegame = fen::position_from_fen("r3k2r/1P6/8/8/8/5N2/8/R3KN1R w KQkq - 0 1");
+        let names = ["O-O-O", "N1h2", "N3h2", "bxa8=Q+", "b8=N", "Ra2", "Nd4"];
+        for name in names {
+            let mv = move_from_san(&middlegame, name).expect("The move should be legal.");
+            assert_eq!(san(&middlegame, &mv), name);
+        }
+
+        let mate = fen::position_from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+        assert_eq!(san(&mate, &game::find_move(&mate, "a1a8").unwrap()), "Ra8#");
+        let en_passant = fen::position_from_fen("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2");
+        assert_eq!(san(&en_passant, &game::find_move(&en_passant, "e5d6").unwrap()), "exd6");
+    }
+
+    #[test]
+    fn test_write_games() {
+        let text = "[Event \"Test\"]\n[FEN \"rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1\"]\n\n1... e5 2. Nf3 Nc6 3. Bb5 a6 0-1\n";
+        let mut game = parse_games(text).remove(0).expect("The game should read.");
+        assert_eq!(game.to_pgn(), text, "A game should be written the way it was read.");
+
+        game.set_tag("Event", "Changed");
+        game.set_tag("ECO", "C70");
+        assert_eq!(game.tags.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), vec!["Event", "FEN", "ECO"]);
+        let again = parse_games(&game.to_pgn()).remove(0).expect("The written game should read.");
+        assert_eq!((again.tag("Event"), &again.moves, again.result), (Some("Changed"), &game.moves, game.result));
+    }
+
+    #[test]
+    fn test_read_games() {
+        let text = r#"[Event "One"]
+[White "A"]
+[WhiteElo "2400"]
+[Result "1-0"]
+
+1. e4 {best by test} e5 2. Nf3 (2. f4 exf4) 2... Nc6 $1 3.Bb5 a6 ; the Morphy defence
+1-0
+
+[Event "Two"]
+[FEN "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1"]
+
+1. e4 Kd7 *
+"#;
+        let games = parse_games(text);
+        assert_eq!(games.len(), 2);
+
+        let first = games[0].as_ref().expect("The first game should read.");
+        assert_eq!(first.tag("White"), Some("A"));
+        assert_eq!(first.elo(true), Some(2400));
+        assert_eq!(first.elo(false), None);
+        assert_eq!(first.result, Some(GameResult::WhiteWin));
+        assert_eq!(first.moves.iter().map(|mv| mv.to_string()).collect::<Vec<_>>(), vec!["e2e4", "e7e5", "g1f3", "b8c6", "f1b5", "a7a6"], "Comments and variations aren't moves.");
+
+        let second = games[1].as_ref().expect("The second game should read.");
+        assert_eq!(second.moves.len(), 2, "The moves should be read from the FEN.");
+        assert_eq!(second.result, None);
+
+        assert!(parse_games("1. e4 e4").first().is_some_and(|game| game.is_err()), "An illegal move should fail the game.");
+
+        let bad_fen = "[FEN \"4k3/8/8/8/8/8/4P3/4K3 w - - x 1\"]\n\n1. e4 *\n\n[Event \"Next\"]\n\n1. d4 *\n";
+        let games = parse_games(bad_fen);
+        assert!(games[0].is_err(), "A game with a broken FEN tag should fail.");
+        assert!(games[1].as_ref().is_ok_and(|game| game.moves.len() == 1), "The games after it should still read.");
+    }
+
+    #[test]
+    fn test_read_tags() {
+        let text = "[Event \"Rated [Blitz] game\"] [Site \"A \\\"quoted]\\\" site\"]\n[White \"A\"]\n\n1. e4 *\n";
+        let game = parse_games(text).remove(0).expect("The game should read.");
+        assert_eq!(game.tag("Event"), Some("Rated [Blitz] game"), "A ] inside a value doesn't end the tag.");
+        assert_eq!(game.tag("Site"), Some("A \"quoted]\" site"));
+        assert_eq!(game.tag("White"), Some("A"));
+
+        let text = "[Event \"Broken\"]\n[Round]\n\n1. e4 e5\n2. Nf3 *\n\n[Event \"Next\"]\n\n1. d4 *\n\n[Event \"Unclosed\"\n\n1. c4 *\n";
+        let games = parse_games(text);
+        assert_eq!(games.len(), 3, "A game with a broken tag should be skipped whole.");
+        assert!(games[0].is_err(), "A tag without a value should fail its game.");
+        assert!(games[1].as_ref().is_ok_and(|game| game.tag("Event") == Some("Next") && game.moves.len() == 1), "The game after it should read.");
+        assert!(games[2].is_err(), "A tag without its ] should fail its game.");
+    }
+}
@@ -1,4 +1,4 @@
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Hash)]
 pub enum Piece {
     Empty = -1,
     Pawn = 0,
@@ -9,7 +9,7 @@ pub enum Piece {
     King = 5,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub enum Color {
     None = -1,
     White = 0,
@@ -43,5 +43,24 @@ pub fn color_from_id(id: i32) -> Color {
         return Color::Black;
     }
 
-    return Color::White;
-}
\ No newline at end of file
+    Color::White
+}
+
+/// Gets the bitboard index used for a piece of the given color, the inverse of `piece_from_id` and `color_from_id`.
+#[inline]
+pub fn piece_id(piece: &Piece, color: &Color) -> i32 {
+    if *piece == Piece::Empty || *color == Color::None {
+        return -1;
+    }
+
+    *piece as i32 + if *color == Color::Black { 6 } else { 0 }
+}
+
+#[inline]
+pub fn opponent_of(color: &Color) -> Color {
+    match color {
+        Color::White => Color::Black,
+        Color::Black => Color::White,
+        Color::None => Color::None,
+    }
+}
@@ -0,0 +1,231 @@
+use super::attacks;
+use super::eval::{s, Parameter, Recorder, Score};
+use super::mobility;
+use super::pawns;
+use super::piece::{self, Color, Piece};
+use super::position::{self, Position};
+
+pub const BISHOP_PAIR: Score = s(28, 52);
+/// For every own pawn on the same colour of square as the bishop, where it blocks the bishop's diagonals.
+pub const BAD_BISHOP: Score = s(-3, -6);
+/// A piece in the enemy half, protected by a pawn, that no enemy pawn can ever chase away.
+pub const KNIGHT_OUTPOST: Score = s(28, 18);
+pub const BISHOP_OUTPOST: Score = s(16, 6);
+pub const ROOK_OPEN_FILE: Score = s(38, 14);
+pub const ROOK_SEMI_OPEN_FILE: Score = s(17, 8);
+/// A rook on the seventh rank, where it attacks pawns that haven't moved or cuts off the enemy king.
+pub const ROOK_ON_SEVENTH: Score = s(10, 28);
+/// For every own minor piece still at home while the queen is already out.
+pub const EARLY_QUEEN: Score = s(-6, 0);
+/// A bishop on a7 or h7 shut in by a pawn on b6 or g6.
+pub const TRAPPED_BISHOP: Score = s(-110, -110);
+/// A rook in the corner with little room, hemmed in by its own king that can't castle any more.
+pub const TRAPPED_ROOK: Score = s(-45, -5);
+
+/// The piece terms of one side.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PieceTerms {
+    pub bishop_pair: Score,
+    pub bad_bishop: Score,
+    pub outposts: Score,
+    pub rook_files: Score,
+    pub rook_on_seventh: Score,
+    pub early_queen: Score,
+    pub trapped: Score,
+}
+
+impl PieceTerms {
+    pub fn total(&self) -> Score {
+        self.bishop_pair + self.bad_bishop + self.outposts + self.rook_files + self.rook_on_seventh + self.early_queen + self.trapped
+    }
+}
+
+const LIGHT_SQUARES: i64 = 0x55aa_55aa_55aa_55aa;
+
+/// Squares are given from White's side. `relative_square(a1, Black)` is a8.
+fn relative_square(index: i32, color: &Color) -> i32 {
+    if *color == Color::Black { index ^ 56 } else { index }
+}
+
+fn bitboard_of(position: &Position, piece: Piece, color: &Color) -> i64 {
+    position.bitboards[piece::piece_id(&piece, color) as usize]
+}
+
+pub fn piece_terms(position: &Position, color: &Color, recorder: &mut impl Recorder) -> PieceTerms {
+    let color_index = *color as usize;
+    let enemy = piece::opponent_of(color);
+    let own_pawns = bitboard_of(position, Piece::Pawn, color);
+    let enemy_pawns = bitboard_of(position, Piece::Pawn, &enemy);
+    let own_pawn_attacks = pawn_attacks(own_pawns, color_index);
+
+    let mut terms = PieceTerms::default();
+
+    let bishops = bitboard_of(position, Piece::Bishop, color);
+    if bishops.count_ones() >= 2 {
+        terms.bishop_pair = BISHOP_PAIR;
+        recorder.record(Parameter::BishopPair, 0, color, 1);
+    }
+
+    let mut remaining = bishops;
+    while remaining != 0 {
+        let index = attacks::pop_lsb(&mut remaining);
+        let same_colour_squares = if LIGHT_SQUARES & (1 << index) != 0 { LIGHT_SQUARES } else { !LIGHT_SQUARES };
+        let blocking_pawns = (own_pawns & same_colour_squares).count_ones() as i32;
+        terms.bad_bishop += BAD_BISHOP * blocking_pawns;
+        recorder.record(Parameter::BadBishop, 0, color, blocking_pawns);
+    }
+
+    for (piece, bonus, parameter) in [(Piece::Knight, KNIGHT_OUTPOST, Parameter::KnightOutpost), (Piece::Bishop, BISHOP_OUTPOST, Parameter::BishopOutpost)] {
+        let mut pieces = bitboard_of(position, piece, color);
+        while pieces != 0 {
+            let index = attacks::pop_lsb(&mut pieces);
+            let relative_rank = pawns::relative_rank(index, color);
+            let can_be_chased = enemy_pawns & pawns::PASSED_PAWN_MASKS[color_index][index as usize] & pawns::ADJACENT_FILE_MASKS[(index % 8) as usize] != 0;
+
+            if (3..=5).contains(&relative_rank) && own_pawn_attacks & (1 << index) != 0 && !can_be_chased {
+                terms.outposts += bonus;
+                recorder.record(parameter, 0, color, 1);
+            }
+        }
+    }
+
+    let enemy_king = position::get_king_index(position, &enemy);
+    let mut rooks = bitboard_of(position, Piece::Rook, color);
+    while rooks != 0 {
+        let index = attacks::pop_lsb(&mut rooks);
+        let file_mask = pawns::FILE_MASKS[(index % 8) as usize];
+
+        if own_pawns & file_mask == 0 {
+            let (bonus, parameter) = if enemy_pawns & file_mask == 0 { (ROOK_OPEN_FILE, Parameter::RookOpenFile) } else { (ROOK_SEMI_OPEN_FILE, Parameter::RookSemiOpenFile) };
+            terms.rook_files += bonus;
+            recorder.record(parameter, 0, color, 1);
+        }
+
+        let seventh_rank: i64 = 0xff << (relative_square(48, color) & 56);
+        let enemy_king_on_eighth = enemy_king >= 0 && pawns::relative_rank(enemy_king, color) == 7;
+        if pawns::relative_rank(index, color) == 6 && (enemy_pawns & seventh_rank != 0 || enemy_king_on_eighth) {
+            terms.rook_on_seventh += ROOK_ON_SEVENTH;
+            recorder.record(Parameter::RookOnSeventh, 0, color, 1);
+        }
+    }
+
+    let queen = bitboard_of(position, Piece::Queen, color);
+    if queen != 0 && queen & (1 << relative_square(3, color)) == 0 {
+        let home_minors = [1, 2, 5, 6].iter().filter(|file| {
+            let index = relative_square(**file, color);
+            let (piece, piece_color) = position::get_piece_at(position, index);
+            piece_color == *color && (piece == Piece::Knight || piece == Piece::Bishop)
+        }).count();
+        terms.early_queen = EARLY_QUEEN * home_minors as i32;
+        recorder.record(Parameter::EarlyQueen, 0, color, home_minors as i32);
+    }
+
+    terms.trapped = trapped_pieces(position, color, recorder);
+
+    terms
+}
+
+fn pawn_attacks(pawns: i64, color_index: usize) -> i64 {
+    let mut attacked = 0;
+    let mut remaining = pawns;
+    while remaining != 0 {
+        attacked |= attacks::PAWN_ATTACKS[color_index][attacks::pop_lsb(&mut remaining) as usize];
+    }
+    attacked
+}
+
+fn trapped_pieces(position: &Position, color: &Color, recorder: &mut impl Recorder) -> Score {
+    let enemy = piece::opponent_of(color);
+    let mut score = Score::default();
+
+    // A bishop that took a pawn on a7 or h7 and got shut in by the b- or g-pawn moving up.
+    for (bishop_square, pawn_square) in [(48, 41), (55, 46)] {
+        let (bishop, bishop_color) = position::get_piece_at(position, relative_square(bishop_square, color));
+        let (pawn, pawn_color) = position::get_piece_at(position, relative_square(pawn_square, color));
+        if bishop == Piece::Bishop && bishop_color == *color && pawn == Piece::Pawn && pawn_color == enemy {
+            score += TRAPPED_BISHOP;
+            recorder.record(Parameter::TrappedBishop, 0, color, 1);
+        }
+    }
+
+    // A rook boxed in the corner by its own king, which has walked without castling.
+    let king_index = position::get_king_index(position, color);
+    if king_index >= 0 && pawns::relative_rank(king_index, color) == 0 {
+        let king_file = king_index % 8;
+        let own_pieces = position::get_color_bitboard(position, color);
+
+        let mut rooks = bitboard_of(position, Piece::Rook, color);
+        while rooks != 0 {
+            let index = attacks::pop_lsb(&mut rooks);
+            let rook_file = index % 8;
+            let boxed_in = (king_file >= 5 && rook_file > king_file) || (king_file <= 3 && rook_file < king_file);
+
+            if pawns::relative_rank(index, color) == 0 && boxed_in {
+                let room = mobility::piece_attacks(Piece::Rook, index, position.occupation_bitboard) & !own_pieces;
+                if room.count_ones() <= 3 {
+                    score += TRAPPED_ROOK;
+                    recorder.record(Parameter::TrappedRook, 0, color, 1);
+                }
+            }
+        }
+    }
+
+    score
+}
+
+// Unit Tests! //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::fen;
+
+    fn white_terms(fen_string: &str) -> PieceTerms {
+        piece_terms(&fen::position_from_fen(fen_string), &Color::White, &mut ())
+    }
+
+    #[test]
+    fn test_bishops() {
+        let terms = white_terms("4k3/8/8/8/8/8/1P1P4/2B1KB2 w - - 0 1");
+        assert_eq!(terms.bishop_pair, BISHOP_PAIR);
+        // b2 and d2 are both dark squares, like c1. f1 is light.
+        assert_eq!(terms.bad_bishop, BAD_BISHOP * 2);
+
+        let terms = white_terms("1k6/B7/1p6/8/8/8/8/4K3 w - - 0 1");
+        assert_eq!(terms.bishop_pair, Score::default());
+        assert_eq!(terms.trapped, TRAPPED_BISHOP);
+    }
+
+    #[test]
+    fn test_outposts() {
+        // The knight on d5 is protected by e4 and no black pawn on the c- or e-file can reach it.
+        let terms = white_terms("4k3/pp3ppp/8/3N4/4P3/8/8/4K3 w - - 0 1");
+        assert_eq!(terms.outposts, KNIGHT_OUTPOST);
+
+        // A black pawn on c7 could still come to c6 and chase it.
+        let terms = white_terms("4k3/ppp2ppp/8/3N4/4P3/8/8/4K3 w - - 0 1");
+        assert_eq!(terms.outposts, Score::default());
+    }
+
+    #[test]
+    fn test_rooks() {
+        let terms = white_terms("4k3/p7/8/8/8/8/1P6/R2RK3 w - - 0 1");
+        assert_eq!(terms.rook_files, ROOK_SEMI_OPEN_FILE + ROOK_OPEN_FILE);
+
+        let terms = white_terms("4k3/R7/8/8/8/8/8/4K3 w - - 0 1");
+        assert_eq!(terms.rook_on_seventh, ROOK_ON_SEVENTH);
+
+        // The king walked to f1 and the rook on h1 can't get out.
+        let terms = white_terms("4k3/8/8/8/8/8/5PPP/5K1R w - - 0 1");
+        assert_eq!(terms.trapped, TRAPPED_ROOK);
+    }
+
+    #[test]
+    fn test_early_queen() {
+        let terms = white_terms("rnbqkbnr/pppppppp/8/8/8/5Q2/PPPPPPPP/RNB1KBNR w KQkq - 0 1");
+        assert_eq!(terms.early_queen, EARLY_QUEEN * 4);
+
+        let terms = white_terms(fen::STARTING_FEN);
+        assert_eq!(terms.early_queen, Score::default());
+    }
+}
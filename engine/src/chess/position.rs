@@ -1,13 +1,39 @@
+use super::attacks;
+use super::eval::{self, Score};
 use super::piece::{self, Color};
+use super::zobrist;
 
+#[derive(Clone)]
 pub struct Position {
     pub bitboards: [i64; 12],
     pub occupation_bitboard: i64,
-    pub castling_rights: [bool; 4],
+    /// The file of the rook each castling right is for, short then long for White, then the same for Black.
+    /// `NO_CASTLING` where the right is gone. Keeping the file rather than a flag lets the rooks start anywhere.
+    pub castling_rooks: [i32; 4],
+    /// Whether castling moves are written as the king taking its own rook, as Chess960 needs, rather than as the
+    /// king moving two squares.
+    pub chess960: bool,
+    /// The checks each side has given, White then Black. Only Three-check counts them, in `variant::ThreeCheck`.
+    pub checks_given: [i32; 2],
     pub en_passant_index: i32,
     pub side_to_move: piece::Color,
     pub half_move_clock: i32,
-    pub full_move_clock: i32
+    pub full_move_clock: i32,
+    /// Zobrist hash, kept up to date by `set_piece_at`, `remove_piece_at` and `moves::make_move`.
+    pub hash: u64,
+    /// Zobrist hash of the pawns alone, which keys the pawn structure cache.
+    pub pawn_hash: u64,
+    /// Material and piece-square values, White minus Black, kept up to date the same way as the hash.
+    /// Unmaking a move is restoring the copy made before it, which restores these too.
+    pub psqt: Score,
+    /// The game phase, from `eval::MAX_PHASE` with every piece on the board down to 0 with none.
+    pub phase: i32,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Position {
@@ -15,16 +41,25 @@ impl Position {
         Position {
             bitboards: [0; 12],
             occupation_bitboard: 0,
-            castling_rights: [false; 4],
+            castling_rooks: [NO_CASTLING; 4],
+            chess960: false,
+            checks_given: [0; 2],
             en_passant_index: -1,
             side_to_move: piece::Color::White,
             half_move_clock: 0,
-            full_move_clock: 1
+            full_move_clock: 1,
+            hash: 0,
+            pawn_hash: 0,
+            psqt: Score::default(),
+            phase: 0,
         }
     }
 }
 
-#[derive(PartialEq, Debug)]
+/// A castling right that's gone, in `Position::castling_rooks`.
+pub const NO_CASTLING: i32 = -1;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub enum CastlingSide {
     Short = 0,
     Long = 1,
@@ -38,13 +73,66 @@ pub fn is_square_occupied_by_color(position: &Position, index: i32, color: &piec
     get_piece_at(position, index).1 == *color
 }
 
-pub fn set_castling_rights(position: &mut Position, color: Color, side: CastlingSide, is_available: bool) {
-    let castling_index = (if side == CastlingSide::Short {0} else {1}) + (if color == Color::White { 0 } else { 2 } );
-    position.castling_rights[castling_index] = is_available;
+/// The index of a castling right in `Position::castling_rooks`.
+pub fn castling_index(color: Color, side: CastlingSide) -> usize {
+    (if side == CastlingSide::Short {0} else {1}) + (if color == Color::White { 0 } else { 2 } )
+}
+
+/// Gives a castling right with the rook on the given file, or takes it away with `NO_CASTLING`.
+pub fn set_castling_rook(position: &mut Position, color: Color, side: CastlingSide, file: i32) {
+    let castling_index = castling_index(color, side);
+    if (position.castling_rooks[castling_index] != NO_CASTLING) != (file != NO_CASTLING) {
+        position.hash ^= zobrist::CASTLING_KEYS[castling_index];
+    }
+    position.castling_rooks[castling_index] = file;
+}
+
+pub fn can_castle(position: &Position, color: Color, side: CastlingSide) -> bool {
+    position.castling_rooks[castling_index(color, side)] != NO_CASTLING
+}
+
+pub fn has_castling_rights(position: &Position) -> bool {
+    position.castling_rooks.iter().any(|file| *file != NO_CASTLING)
+}
+
+/// The file of the rook furthest from the king on one side of it, on the back rank. This is the rook `K` and `Q`
+/// mean in a FEN, standard or X-FEN.
+pub fn outer_rook_file(position: &Position, color: Color, side: CastlingSide) -> Option<i32> {
+    let back_rank = if color == Color::White { 0 } else { 56 };
+    let king_file = get_king_index(position, &color) - back_rank;
+    if !(0..8).contains(&king_file) {
+        return None;
+    }
+
+    let files: Vec<i32> = if side == CastlingSide::Short { (king_file + 1..8).rev().collect() } else { (0..king_file).collect() };
+    files.into_iter().find(|file| get_piece_at(position, back_rank + file) == (piece::Piece::Rook, color))
+}
+
+/// Gets a bitboard of every piece, of either color, attacking the given square.
+pub fn get_pieces_attacking(position: &Position, index: i32) -> i64 {
+    get_pieces_attacking_with_occupation(position, index, position.occupation_bitboard)
+}
+
+/// Same as `get_pieces_attacking`, but sliding pieces are blocked by `occupation` instead of the real board.
+/// Used to look through pieces that have already been traded off, like in static exchange evaluation.
+pub fn get_pieces_attacking_with_occupation(position: &Position, index: i32, occupation: i64) -> i64 {
+    let bitboards = &position.bitboards;
+
+    let diagonal_sliders = bitboards[2] | bitboards[4] | bitboards[8] | bitboards[10];
+    let orthogonal_sliders = bitboards[3] | bitboards[4] | bitboards[9] | bitboards[10];
+
+    (attacks::PAWN_ATTACKS[1][index as usize] & bitboards[0])
+        | (attacks::PAWN_ATTACKS[0][index as usize] & bitboards[6])
+        | (attacks::KNIGHT_ATTACKS[index as usize] & (bitboards[1] | bitboards[7]))
+        | (attacks::KING_ATTACKS[index as usize] & (bitboards[5] | bitboards[11]))
+        | (attacks::bishop_attacks(index, occupation) & diagonal_sliders)
+        | (attacks::rook_attacks(index, occupation) & orthogonal_sliders)
 }
 
-pub fn get_pieces_attacking(position: &Position, index: i32) {
-    
+/// Gets a bitboard of every piece belonging to `color`.
+pub fn get_color_bitboard(position: &Position, color: &Color) -> i64 {
+    let offset = if *color == Color::Black { 6 } else { 0 };
+    position.bitboards[offset..offset + 6].iter().fold(0, |all, bitboard| all | bitboard)
 }
 
 pub fn get_piece_at(position: &Position, index: i32) -> (piece::Piece, piece::Color) {
@@ -59,7 +147,7 @@ pub fn get_piece_at(position: &Position, index: i32) -> (piece::Piece, piece::Co
         }
     }
 
-    return (piece::Piece::Empty, piece::Color::None); // shouldn't happen
+    (piece::Piece::Empty, piece::Color::None) // shouldn't happen
 }
 
 pub fn set_piece_at(position: &mut Position, index: i32, piece: piece::Piece, color: &piece::Color) {
@@ -72,17 +160,70 @@ pub fn set_piece_at(position: &mut Position, index: i32, piece: piece::Piece, co
         target_bitboard += 6;
     }
 
-    position.occupation_bitboard = position.occupation_bitboard | (1 << index);
-    position.bitboards[target_bitboard as usize] = position.bitboards[target_bitboard as usize] | (1 << index);
+    position.occupation_bitboard |= 1 << index;
+    position.bitboards[target_bitboard as usize] |= 1 << index;
+    position.hash ^= zobrist::PIECE_KEYS[target_bitboard as usize][index as usize];
+    position.psqt += eval::PIECE_SQUARE_VALUES[target_bitboard as usize][index as usize];
+    if piece == piece::Piece::Pawn {
+        position.pawn_hash ^= zobrist::PIECE_KEYS[target_bitboard as usize][index as usize];
+    }
+    position.phase += eval::PHASE_WEIGHTS[piece as usize];
+}
+
+/// Removes whatever piece is on the square, returning it.
+pub fn remove_piece_at(position: &mut Position, index: i32) -> (piece::Piece, piece::Color) {
+    let removed = get_piece_at(position, index);
+    if removed.0 == piece::Piece::Empty {
+        return removed;
+    }
+
+    let target_bitboard = piece::piece_id(&removed.0, &removed.1) as usize;
+    position.occupation_bitboard &= !(1 << index);
+    position.bitboards[target_bitboard] &= !(1 << index);
+    position.hash ^= zobrist::PIECE_KEYS[target_bitboard][index as usize];
+    position.psqt -= eval::PIECE_SQUARE_VALUES[target_bitboard][index as usize];
+    if removed.0 == piece::Piece::Pawn {
+        position.pawn_hash ^= zobrist::PIECE_KEYS[target_bitboard][index as usize];
+    }
+    position.phase -= eval::PHASE_WEIGHTS[removed.0 as usize];
+
+    removed
+}
+
+/// Gets the square of the king of the given color, or -1 if there is none.
+pub fn get_king_index(position: &Position, color: &Color) -> i32 {
+    let kings = position.bitboards[piece::piece_id(&piece::Piece::King, color) as usize];
+    if kings == 0 { -1 } else { kings.trailing_zeros() as i32 }
+}
+
+pub fn is_square_attacked_by(position: &Position, index: i32, color: &Color) -> bool {
+    get_pieces_attacking(position, index) & get_color_bitboard(position, color) != 0
+}
+
+pub fn is_in_check(position: &Position, color: &Color) -> bool {
+    let king_index = get_king_index(position, color);
+    king_index >= 0 && is_square_attacked_by(position, king_index, &piece::opponent_of(color))
+}
+
+/// Whether the side has anything besides its king and pawns. Null move pruning is unsound without it, because
+/// king and pawn endings are full of zugzwang.
+pub fn has_non_pawn_material(position: &Position, color: &Color) -> bool {
+    let offset = if *color == Color::Black { 6 } else { 0 };
+    position.bitboards[offset + 1..offset + 5].iter().any(|bitboard| *bitboard != 0)
 }
 
 pub fn massacre(position: &mut Position) {
     position.occupation_bitboard = 0;
     position.bitboards = [0; 12];
+    position.hash = zobrist::hash_state(position);
+    position.pawn_hash = 0;
+    position.psqt = Score::default();
+    position.phase = 0;
 }
 
 // Unit Tests! //
 
+#[cfg(test)]
 mod tests {
     use super::*;
 
@@ -0,0 +1,145 @@
+use super::attacks;
+use super::eval::{s, Parameter, Recorder, Score};
+use super::mobility::Attacks;
+use super::pawns;
+use super::piece::{self, Color, Piece};
+use super::position::{self, Position};
+
+/// Indexed by `piece::Piece`, how much a piece adds to the attack units for every king zone square it attacks.
+pub const KING_ATTACK_WEIGHTS: [i32; 6] = [0, 2, 2, 3, 5, 0];
+
+/// Indexed by attack units. Danger grows much faster than the number of attackers, since attacks that can be
+/// parried one at a time often can't be parried together.
+pub const KING_DANGER: [Score; 32] = [
+    s(0, 0), s(-1, -3), s(-2, -6), s(-5, -9), s(-9, -12), s(-14, -15), s(-20, -18), s(-27, -21),
+    s(-35, -24), s(-45, -27), s(-55, -30), s(-67, -33), s(-79, -36), s(-93, -39), s(-108, -42), s(-124, -45),
+    s(-141, -48), s(-159, -51), s(-178, -54), s(-199, -57), s(-220, -60), s(-243, -63), s(-266, -66), s(-291, -69),
+    s(-317, -72), s(-344, -75), s(-372, -78), s(-401, -81), s(-431, -84), s(-463, -87), s(-495, -90), s(-529, -93),
+];
+
+/// Indexed by how many ranks in front of the king the closest own pawn on a file is, 0 when there is none.
+pub const PAWN_SHIELD: [Score; 4] = [s(-18, 0), s(22, 0), s(10, 0), s(2, 0)];
+/// Indexed by how many ranks in front of the king the closest enemy pawn on a file is, 0 when there is none.
+/// A pawn right in front of the king is usually stuck there, so it's less of a threat than one a rank further.
+pub const PAWN_STORM: [Score; 5] = [s(0, 0), s(-4, 0), s(-24, 0), s(-12, 0), s(-5, 0)];
+/// A file next to the king, or the king's own file, without any pawns.
+pub const KING_OPEN_FILE: Score = s(-22, 0);
+/// A file near the king with enemy pawns but no own pawns.
+pub const KING_SEMI_OPEN_FILE: Score = s(-12, 0);
+
+/// The squares around the king, and the king's own square.
+pub fn king_zone(king_index: i32) -> i64 {
+    attacks::KING_ATTACKS[king_index as usize] | (1 << king_index)
+}
+
+/// The king safety terms of one side.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SafetyTerms {
+    pub attacks: Score,
+    pub shield: Score,
+    pub storm: Score,
+    pub open_files: Score,
+}
+
+impl SafetyTerms {
+    pub fn total(&self) -> Score {
+        self.attacks + self.shield + self.storm + self.open_files
+    }
+}
+
+/// How safe the color's king is.
+pub fn king_safety(position: &Position, color: &Color, attacks: &Attacks, recorder: &mut impl Recorder) -> SafetyTerms {
+    let mut terms = SafetyTerms::default();
+    let king_index = position::get_king_index(position, color);
+    if king_index < 0 {
+        return terms;
+    }
+
+    // A lone attacker is rarely dangerous on its own.
+    let color_index = *color as usize;
+    if attacks.king_attackers[color_index] >= 2 {
+        let units = (attacks.king_attack_units[color_index] as usize).min(KING_DANGER.len() - 1);
+        terms.attacks = KING_DANGER[units];
+        recorder.record(Parameter::KingDanger, units, color, 1);
+    }
+
+    let own_pawns = position.bitboards[piece::piece_id(&Piece::Pawn, color) as usize];
+    let enemy_pawns = position.bitboards[piece::piece_id(&Piece::Pawn, &piece::opponent_of(color)) as usize];
+    let king_file = king_index % 8;
+    let king_rank = pawns::relative_rank(king_index, color) as i32;
+
+    for file in (king_file - 1).max(0)..=(king_file + 1).min(7) {
+        let in_front = pawns::FORWARD_FILE_MASKS[color_index][(king_rank_square(king_rank, color) + file) as usize];
+        let file_mask = pawns::FILE_MASKS[file as usize];
+
+        let shield_distance = closest_distance(own_pawns & in_front, king_rank, color).filter(|distance| *distance < PAWN_SHIELD.len() as i32);
+        terms.shield += PAWN_SHIELD[shield_distance.unwrap_or(0) as usize];
+        recorder.record(Parameter::PawnShield, shield_distance.unwrap_or(0) as usize, color, 1);
+
+        let storm_distance = closest_distance(enemy_pawns & in_front, king_rank, color).filter(|distance| *distance < PAWN_STORM.len() as i32);
+        terms.storm += PAWN_STORM[storm_distance.unwrap_or(0) as usize];
+        recorder.record(Parameter::PawnStorm, storm_distance.unwrap_or(0) as usize, color, 1);
+
+        if own_pawns & file_mask == 0 {
+            let (penalty, parameter) = if enemy_pawns & file_mask == 0 { (KING_OPEN_FILE, Parameter::KingOpenFile) } else { (KING_SEMI_OPEN_FILE, Parameter::KingSemiOpenFile) };
+            terms.open_files += penalty;
+            recorder.record(parameter, 0, color, 1);
+        }
+    }
+
+    terms
+}
+
+/// The first square of the king's rank, as a square index.
+fn king_rank_square(relative_rank: i32, color: &Color) -> i32 {
+    if *color == Color::Black { (7 - relative_rank) * 8 } else { relative_rank * 8 }
+}
+
+/// How many ranks in front of the king the closest of the pawns is.
+fn closest_distance(pawns: i64, king_rank: i32, color: &Color) -> Option<i32> {
+    if pawns == 0 {
+        return None;
+    }
+
+    // From the color's side, the closest pawn in front is the lowest one for White and the highest one for Black.
+    let closest = if *color == Color::Black { 63 - pawns.leading_zeros() as i32 } else { pawns.trailing_zeros() as i32 };
+    Some(pawns::relative_rank(closest, color) as i32 - king_rank)
+}
+
+// Unit Tests! //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{fen, mobility};
+
+    fn white_king_safety(fen_string: &str) -> SafetyTerms {
+        let position = fen::position_from_fen(fen_string);
+        king_safety(&position, &Color::White, &mobility::attacks(&position, &mut ()), &mut ())
+    }
+
+    #[test]
+    fn test_pawn_shield_and_storm() {
+        // A castled king behind untouched pawns.
+        let terms = white_king_safety("4k3/8/8/8/8/8/5PPP/6K1 w - - 0 1");
+        assert_eq!(terms.shield, PAWN_SHIELD[1] * 3);
+        assert_eq!(terms.storm, Score::default());
+        assert_eq!(terms.open_files, Score::default());
+
+        // The g-pawn has gone, the h-pawn has moved up, and a black pawn is coming down the g-file.
+        let terms = white_king_safety("4k3/8/8/8/6p1/7P/5P2/6K1 w - - 0 1");
+        assert_eq!(terms.shield, PAWN_SHIELD[1] + PAWN_SHIELD[0] + PAWN_SHIELD[2]);
+        assert_eq!(terms.storm, PAWN_STORM[3]);
+        assert_eq!(terms.open_files, KING_SEMI_OPEN_FILE);
+    }
+
+    #[test]
+    fn test_king_attacks() {
+        // The queen and rook both bear down on the king's zone, a knight alone doesn't count.
+        let attacked = white_king_safety("4k3/8/8/8/8/6q1/5PPr/6K1 w - - 0 1");
+        assert!(attacked.attacks.mg < 0, "Two attackers on the king zone should be dangerous.");
+
+        let lone_knight = white_king_safety("4k3/8/8/8/8/5n2/5PPP/6K1 w - - 0 1");
+        assert_eq!(lone_knight.attacks, Score::default());
+    }
+}
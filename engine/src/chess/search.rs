@@ -0,0 +1,1385 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+use super::datagen::GameResult;
+use super::eval;
+use super::moves::{self, Move, PromotionOption};
+use super::nnue::{self, Accumulator, Network};
+use super::ordering::{self, MovePicker, OrderingTables, MAX_PLY};
+use super::pawns::PawnTable;
+use super::piece::{self, Piece};
+use super::position::{self, Position};
+use super::tablebase::{self, Tablebase, Wdl};
+use super::timing::{SystemClock, TimeControl, TimeManager};
+use super::transposition::{Bound, Entry, TranspositionTable};
+use super::variant::{self, Variant};
+
+pub const INFINITY: i32 = 32000;
+pub const MATE: i32 = 31000;
+/// Scores beyond this are mates, with the distance to mate encoded in the difference from `MATE`.
+pub const MATE_BOUND: i32 = MATE - MAX_PLY as i32;
+/// A win the tablebases found, less the plies to the position that was probed. Below every mate, and above anything
+/// the evaluation would give.
+pub const TB_WIN: i32 = MATE_BOUND - MAX_PLY as i32;
+
+/// Helper threads skip some iterations so they don't all search the same depth at the same time. A helper skips the
+/// depths where `(depth + phase) / size` is odd, with its size and phase picked from these by its index.
+const SKIP_SIZE: [i32; 20] = [1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 4, 4, 4, 4, 4, 4, 4, 4];
+const SKIP_PHASE: [i32; 20] = [0, 1, 0, 1, 2, 3, 0, 1, 2, 3, 4, 5, 0, 1, 2, 3, 4, 5, 6, 7];
+
+/// Switches and margins for the selective parts of the search. Every technique can be turned off on its own,
+/// so its strength can be measured by playing the engine against itself with and without it.
+#[derive(Clone, Debug)]
+pub struct SearchParams {
+    pub null_move_pruning: bool,
+    pub null_move_min_depth: i32,
+
+    pub late_move_reductions: bool,
+
+    /// Prune a node whose static evaluation beats beta by a depth dependent margin.
+    pub reverse_futility_pruning: bool,
+    pub reverse_futility_margin: i32,
+    pub reverse_futility_max_depth: i32,
+
+    /// Skip quiet moves when the static evaluation is too far below alpha for them to matter.
+    pub futility_pruning: bool,
+    pub futility_margin: i32,
+    pub futility_max_depth: i32,
+
+    /// Drop into quiescence search when the static evaluation is hopelessly below alpha.
+    pub razoring: bool,
+    pub razoring_margin: i32,
+    pub razoring_max_depth: i32,
+
+    /// Stop trying quiet moves after a depth dependent number of them.
+    pub late_move_pruning: bool,
+    pub late_move_pruning_max_depth: i32,
+
+    /// Search every move after the first with a zero window, and only re-search the ones that beat alpha.
+    pub principal_variation_search: bool,
+
+    /// Start each iteration with a window around the previous score, widening it when the score falls outside.
+    pub aspiration_windows: bool,
+    pub aspiration_window: i32,
+    pub aspiration_min_depth: i32,
+
+    /// Search one ply deeper after a move that gives check.
+    pub check_extensions: bool,
+    /// Search one ply deeper after recapturing on the square the opponent just captured on.
+    pub recapture_extensions: bool,
+
+    /// Extend the TT move when a reduced search without it shows every other move is clearly worse. The TT move is
+    /// extended twice when the others are far worse, and reduced instead when it isn't singular but still fails high.
+    pub singular_extensions: bool,
+    pub singular_min_depth: i32,
+    pub singular_margin: i32,
+    pub double_extension_margin: i32,
+
+    /// The most plies a single line can be extended by, so extensions can't make the search explode.
+    pub max_line_extensions: i32,
+}
+
+impl Default for SearchParams {
+    fn default() -> Self {
+        SearchParams {
+            null_move_pruning: true,
+            null_move_min_depth: 3,
+            late_move_reductions: true,
+            reverse_futility_pruning: true,
+            reverse_futility_margin: 90,
+            reverse_futility_max_depth: 6,
+            futility_pruning: true,
+            futility_margin: 120,
+            futility_max_depth: 4,
+            razoring: true,
+            razoring_margin: 250,
+            razoring_max_depth: 2,
+            late_move_pruning: true,
+            late_move_pruning_max_depth: 4,
+            principal_variation_search: true,
+            aspiration_windows: true,
+            aspiration_window: 25,
+            aspiration_min_depth: 4,
+            check_extensions: true,
+            recapture_extensions: true,
+            singular_extensions: true,
+            singular_min_depth: 6,
+            singular_margin: 2,
+            double_extension_margin: 20,
+            max_line_extensions: 16,
+        }
+    }
+}
+
+impl SearchParams {
+    /// Plain alpha-beta, with every selective technique turned off.
+    pub fn exhaustive() -> Self {
+        SearchParams {
+            null_move_pruning: false,
+            late_move_reductions: false,
+            reverse_futility_pruning: false,
+            futility_pruning: false,
+            razoring: false,
+            late_move_pruning: false,
+            principal_variation_search: false,
+            aspiration_windows: false,
+            check_extensions: false,
+            recapture_extensions: false,
+            singular_extensions: false,
+            ..Self::default()
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SearchLimits {
+    pub depth: Option<i32>,
+    pub nodes: Option<u64>,
+    pub time: Option<TimeControl>,
+    /// Only these root moves are searched, or every move when empty.
+    pub search_moves: Vec<Move>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchResult {
+    pub best_move: Option<Move>,
+    pub score: i32,
+    pub depth: i32,
+    pub pv: Vec<Move>,
+    pub nodes: u64,
+    /// The best lines found, one per `multi_pv`, best first. The first line is the same as `score` and `pv`.
+    pub lines: Vec<PvLine>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PvLine {
+    pub score: i32,
+    pub pv: Vec<Move>,
+}
+
+#[derive(Clone, Copy, Default)]
+struct StackEntry {
+    current_move: Option<Move>,
+    is_null_move: bool,
+    is_capture: bool,
+    /// Plies of extension taken along the line, up to and including `current_move`.
+    extensions: i32,
+    /// A move to leave out while checking whether the TT move is singular.
+    excluded_move: Option<Move>,
+}
+
+pub struct Searcher {
+    pub params: SearchParams,
+    pub tables: OrderingTables,
+    /// Shared with the helper threads, which is how they help: they fill it with results the main thread can use.
+    pub transposition_table: Arc<TranspositionTable>,
+    pub pawn_table: PawnTable,
+    /// Evaluates with this network instead of the hand written evaluation when set, see `set_network`.
+    network: Option<Arc<Network>>,
+    /// The network's accumulator for the position at every ply of the current line.
+    accumulators: Vec<Accumulator>,
+    /// Endgame tables, probed for positions with few enough pieces.
+    pub tablebase: Option<Arc<dyn Tablebase>>,
+    /// The rules to search by. Tablebases are only probed for standard chess.
+    pub variant: &'static dyn Variant,
+    /// Set from another thread to end the search as soon as possible.
+    pub stop: Arc<AtomicBool>,
+    /// Set while the engine thinks on the opponent's time. The clock is ignored until it's cleared by `ponderhit`,
+    /// and from then on the time already spent counts against the move.
+    pub pondering: Arc<AtomicBool>,
+    /// Print a UCI `info` line after every iteration.
+    pub print_info: bool,
+    /// Milliseconds kept in reserve on every move for communication lag.
+    pub move_overhead: u64,
+    /// How many of the best root moves to rank, each with its own PV.
+    pub multi_pv: usize,
+    /// Threads to search with. Anything above one starts Lazy SMP helpers for the length of each search.
+    pub threads: usize,
+    /// 0 for the main thread, counting up for helpers.
+    thread_index: usize,
+    /// This thread's node count, published every few thousand nodes so the main thread can report the total.
+    node_counter: Arc<AtomicU64>,
+    /// The node counters of the helper threads while they run.
+    helper_counters: Vec<Arc<AtomicU64>>,
+    time_manager: Option<TimeManager>,
+    /// Nodes spent on each root move in the current iteration.
+    root_move_nodes: Vec<(Move, u64)>,
+    /// The root moves the search may play, after `searchmoves`.
+    root_moves: Vec<Move>,
+    /// Root moves already ranked in this iteration, which the search for the next line leaves out.
+    root_excluded: Vec<Move>,
+    nodes: u64,
+    /// Positions found in the tablebases in this search.
+    tb_hits: u64,
+    node_limit: u64,
+    stopped: bool,
+    start_time: Instant,
+    /// Hashes of every position before the one being searched, for repetition detection.
+    history: Vec<u64>,
+    stack: Vec<StackEntry>,
+    pv_table: Vec<Vec<Move>>,
+    reductions: [[i32; 64]; 64],
+}
+
+impl Searcher {
+    pub fn new(hash_size_mb: usize) -> Self {
+        Self::with_transposition_table(Arc::new(TranspositionTable::new(hash_size_mb)))
+    }
+
+    pub fn with_transposition_table(transposition_table: Arc<TranspositionTable>) -> Self {
+        let mut reductions = [[0; 64]; 64];
+        for (depth, row) in reductions.iter_mut().enumerate().skip(1) {
+            for (move_number, reduction) in row.iter_mut().enumerate().skip(1) {
+                *reduction = (0.75 + (depth as f64).ln() * (move_number as f64).ln() / 2.25) as i32;
+            }
+        }
+
+        Searcher {
+            params: SearchParams::default(),
+            tables: OrderingTables::new(),
+            transposition_table,
+            pawn_table: PawnTable::new(),
+            network: None,
+            accumulators: Vec::new(),
+            tablebase: None,
+            variant: variant::standard(),
+            stop: Arc::new(AtomicBool::new(false)),
+            pondering: Arc::new(AtomicBool::new(false)),
+            print_info: false,
+            move_overhead: 30,
+            multi_pv: 1,
+            threads: 1,
+            thread_index: 0,
+            node_counter: Arc::new(AtomicU64::new(0)),
+            helper_counters: Vec::new(),
+            time_manager: None,
+            root_move_nodes: Vec::new(),
+            root_moves: Vec::new(),
+            root_excluded: Vec::new(),
+            nodes: 0,
+            tb_hits: 0,
+            node_limit: u64::MAX,
+            stopped: false,
+            start_time: Instant::now(),
+            history: Vec::new(),
+            stack: vec![StackEntry::default(); MAX_PLY + 1],
+            pv_table: vec![Vec::new(); MAX_PLY + 1],
+            reductions,
+        }
+    }
+
+    /// Sets the hashes of the positions played before the one that will be searched.
+    pub fn set_game_history(&mut self, hashes: Vec<u64>) {
+        self.history = hashes;
+    }
+
+    /// Switches to evaluating with the network, or back to the hand written evaluation with `None`.
+    pub fn set_network(&mut self, network: Option<Arc<Network>>) {
+        self.accumulators = match &network {
+            Some(network) => vec![Accumulator::new(network); MAX_PLY + 1],
+            None => Vec::new(),
+        };
+        self.network = network;
+    }
+
+    pub fn network(&self) -> Option<&Arc<Network>> {
+        self.network.as_ref()
+    }
+
+    /// Forgets everything learned in previous searches, for a new game.
+    pub fn clear(&mut self) {
+        self.tables.clear();
+        self.transposition_table.clear();
+        self.pawn_table.clear();
+    }
+
+    /// Searches the position with iterative deepening until a limit is reached or the search is stopped.
+    pub fn search(&mut self, position: &Position, limits: &SearchLimits) -> SearchResult {
+        self.transposition_table.new_search();
+
+        if self.threads > 1 {
+            self.search_with_helpers(position, limits)
+        } else {
+            self.iterative_deepening(position, limits)
+        }
+    }
+
+    /// Lazy SMP: helper threads run their own iterative deepening on the same position, sharing only the
+    /// transposition table. Limits, time management and output stay with the main thread, which stops the helpers
+    /// once it's done, and the best move comes from whichever thread got furthest.
+    fn search_with_helpers(&mut self, position: &Position, limits: &SearchLimits) -> SearchResult {
+        let helper_stop = Arc::new(AtomicBool::new(false));
+        let helper_limits = SearchLimits { depth: limits.depth, search_moves: limits.search_moves.clone(), ..Default::default() };
+
+        let helpers: Vec<Searcher> = (1..self.threads).map(|thread_index| self.helper(thread_index, &helper_stop)).collect();
+        self.helper_counters = helpers.iter().map(|helper| helper.node_counter.clone()).collect();
+
+        let (mut result, helper_results) = thread::scope(|scope| {
+            let helper_threads: Vec<_> = helpers.into_iter()
+                .map(|mut helper| {
+                    let helper_limits = &helper_limits;
+                    scope.spawn(move || helper.iterative_deepening(position, helper_limits))
+                })
+                .collect();
+
+            let result = self.iterative_deepening(position, limits);
+            helper_stop.store(true, Ordering::Relaxed);
+
+            let helper_results: Vec<SearchResult> = helper_threads.into_iter()
+                .map(|helper_thread| helper_thread.join().expect("A helper thread panicked."))
+                .collect();
+            (result, helper_results)
+        });
+
+        let best_helper = helper_results.iter()
+            .fold(None, |best: Option<&SearchResult>, helper_result| {
+                if is_better_thread(helper_result, best.unwrap_or(&result)) { Some(helper_result) } else { best }
+            })
+            .filter(|_| self.multi_pv == 1);
+
+        if let Some(best_helper) = best_helper {
+            result.best_move = best_helper.best_move;
+            result.score = best_helper.score;
+            result.depth = best_helper.depth;
+            result.pv = best_helper.pv.clone();
+            result.lines = vec![PvLine { score: result.score, pv: result.pv.clone() }];
+
+            if self.print_info {
+                self.print_iteration(result.depth, 0, result.score, Bound::Exact, &result.pv);
+            }
+        }
+
+        self.helper_counters.clear();
+        result.nodes += helper_results.iter().map(|helper_result| helper_result.nodes).sum::<u64>();
+        result
+    }
+
+    fn helper(&self, thread_index: usize, helper_stop: &Arc<AtomicBool>) -> Searcher {
+        let mut helper = Searcher::with_transposition_table(self.transposition_table.clone());
+        helper.params = self.params.clone();
+        helper.tables = self.tables.clone();
+        helper.history = self.history.clone();
+        helper.set_network(self.network.clone());
+        helper.tablebase = self.tablebase.clone();
+        helper.variant = self.variant;
+        helper.stop = helper_stop.clone();
+        helper.thread_index = thread_index;
+        helper
+    }
+
+    fn skips_depth(&self, depth: i32) -> bool {
+        if self.thread_index == 0 {
+            return false;
+        }
+
+        let index = (self.thread_index - 1) % SKIP_SIZE.len();
+        ((depth + SKIP_PHASE[index]) / SKIP_SIZE[index]) % 2 != 0
+    }
+
+    /// The nodes searched by every thread so far.
+    fn total_nodes(&self) -> u64 {
+        self.nodes + self.helper_counters.iter().map(|counter| counter.load(Ordering::Relaxed)).sum::<u64>()
+    }
+
+    fn iterative_deepening(&mut self, position: &Position, limits: &SearchLimits) -> SearchResult {
+        self.nodes = 0;
+        self.tb_hits = 0;
+        self.node_counter.store(0, Ordering::Relaxed);
+        self.node_limit = limits.nodes.unwrap_or(u64::MAX);
+        self.stopped = false;
+        self.start_time = Instant::now();
+        if let Some(network) = &self.network {
+            self.accumulators[0].refresh(network, position);
+        }
+        self.time_manager = limits.time.as_ref()
+            .and_then(|control| TimeManager::new(control, &position.side_to_move, self.move_overhead, Box::new(SystemClock::new())));
+
+        self.root_moves = self.variant.legal_moves(position).into_iter()
+            .filter(|mv| limits.search_moves.is_empty() || limits.search_moves.contains(mv))
+            .collect();
+        if self.root_moves.is_empty() {
+            // None of the requested moves are legal, so search them all rather than give no move at all.
+            self.root_moves = self.variant.legal_moves(position);
+        }
+        if let Some((moves, _)) = self.tablebase.as_deref().filter(|_| self.variant.is_standard()).and_then(|tablebase| tablebase::filter_root_moves(tablebase, position, &self.root_moves)) {
+            self.tb_hits += self.root_moves.len() as u64;
+            self.root_moves = moves;
+        }
+        let line_count = self.multi_pv.clamp(1, self.root_moves.len().max(1));
+
+        let max_depth = limits.depth.unwrap_or(MAX_PLY as i32 - 1).clamp(1, MAX_PLY as i32 - 1);
+        let mut result = SearchResult {
+            best_move: self.root_moves.first().copied(),
+            score: 0,
+            depth: 0,
+            pv: Vec::new(),
+            nodes: 0,
+            lines: Vec::new(),
+        };
+
+        for depth in 1..=max_depth {
+            if self.skips_depth(depth) && depth < max_depth {
+                continue;
+            }
+
+            self.root_move_nodes.clear();
+            self.root_excluded.clear();
+            let mut lines: Vec<PvLine> = Vec::with_capacity(line_count);
+
+            // Each line searches the root without the moves of the lines before it, so it finds the next best move.
+            for line_index in 0..line_count {
+                let previous_score = result.lines.get(line_index).map_or(result.score, |line| line.score);
+                let score = self.aspiration_search(position, depth, previous_score, line_index);
+                if self.stopped {
+                    break;
+                }
+
+                let pv = self.pv_table[0].clone();
+                let Some(first_move) = pv.first().copied() else { break };
+                self.root_excluded.push(first_move);
+                lines.push(PvLine { score, pv });
+            }
+
+            if self.stopped || lines.is_empty() {
+                break;
+            }
+
+            // A later line can come out better than an earlier one, since each is searched with less to compare against.
+            lines.sort_by_key(|line| -line.score);
+
+            let score = lines[0].score;
+            result.depth = depth;
+            result.score = score;
+            result.pv = lines[0].pv.clone();
+            result.best_move = result.pv.first().copied().or(result.best_move);
+            result.lines = lines;
+
+            if self.print_info {
+                for (line_index, line) in result.lines.iter().enumerate() {
+                    self.print_iteration(depth, line_index, line.score, Bound::Exact, &line.pv);
+                }
+            }
+
+            let best_move_effort = self.best_move_effort(result.best_move);
+            if let Some(time_manager) = self.time_manager.as_mut() {
+                let has_time = time_manager.should_continue(result.best_move, score, best_move_effort);
+                if !has_time && !self.pondering.load(Ordering::Relaxed) {
+                    break;
+                }
+            }
+        }
+
+        self.time_manager = None;
+        self.node_counter.store(self.nodes, Ordering::Relaxed);
+        result.nodes = self.nodes;
+        result
+    }
+
+    /// The reply the search expects to the best move, to think about on the opponent's time. Taken from the PV, or
+    /// from the transposition table when the PV was cut short.
+    pub fn ponder_move(&self, position: &Position, result: &SearchResult) -> Option<Move> {
+        let best_move = result.best_move?;
+        if let Some(mv) = result.pv.get(1).filter(|_| result.pv[0] == best_move) {
+            return Some(*mv);
+        }
+
+        let mut child = position.clone();
+        self.variant.make_move(&mut child, &best_move);
+        self.transposition_table.probe(child.hash)
+            .and_then(|entry| entry.best_move)
+            .filter(|mv| self.variant.legal_moves(&child).contains(mv))
+    }
+
+    /// Searches the root with a narrow window around the previous iteration's score. When the score lands outside
+    /// the window, the failing side is widened a little more each time until the score fits.
+    fn aspiration_search(&mut self, position: &Position, depth: i32, previous_score: i32, line_index: usize) -> i32 {
+        if !self.params.aspiration_windows || depth < self.params.aspiration_min_depth || previous_score.abs() >= MATE_BOUND {
+            return self.negamax(position, depth, -INFINITY, INFINITY, 0);
+        }
+
+        let mut delta = self.params.aspiration_window;
+        let mut alpha = (previous_score - delta).max(-INFINITY);
+        let mut beta = (previous_score + delta).min(INFINITY);
+
+        loop {
+            let score = self.negamax(position, depth, alpha, beta, 0);
+            if self.stopped {
+                return score;
+            }
+
+            if score <= alpha {
+                if self.print_info {
+                    self.print_iteration(depth, line_index, score, Bound::Upper, &self.pv_table[0]);
+                }
+                beta = (alpha + beta) / 2;
+                alpha = (score - delta).max(-INFINITY);
+            } else if score >= beta {
+                if self.print_info {
+                    self.print_iteration(depth, line_index, score, Bound::Lower, &self.pv_table[0]);
+                }
+                beta = (score + delta).min(INFINITY);
+            } else {
+                return score;
+            }
+
+            delta += delta / 2;
+        }
+    }
+
+    fn print_iteration(&self, depth: i32, line_index: usize, score: i32, bound: Bound, pv: &[Move]) {
+        let elapsed = self.start_time.elapsed().as_millis().max(1);
+        let nodes = self.total_nodes();
+        let pv_names: Vec<String> = pv.iter().map(|mv| mv.to_string()).collect();
+        // A fail low at the root has no move that beat alpha, so there's no PV to show.
+        let pv_text = if pv.is_empty() { String::new() } else { format!(" pv {}", pv_names.join(" ")) };
+        let bound_name = match bound {
+            Bound::Lower => " lowerbound",
+            Bound::Upper => " upperbound",
+            _ => "",
+        };
+
+        println!("info depth {} multipv {} score {}{} nodes {} nps {} time {} hashfull {} tbhits {}{}",
+            depth,
+            line_index + 1,
+            format_score(score),
+            bound_name,
+            nodes,
+            nodes as u128 * 1000 / elapsed,
+            elapsed,
+            self.transposition_table.hashfull(),
+            self.tb_hits,
+            pv_text);
+    }
+
+    /// The fraction of this iteration's root nodes that went into the best move.
+    fn best_move_effort(&self, best_move: Option<Move>) -> f64 {
+        let total: u64 = self.root_move_nodes.iter().map(|(_, nodes)| nodes).sum();
+        let best: u64 = self.root_move_nodes.iter().filter(|(mv, _)| Some(*mv) == best_move).map(|(_, nodes)| nodes).sum();
+
+        if total == 0 { 0.0 } else { best as f64 / total as f64 }
+    }
+
+    fn should_stop(&mut self) -> bool {
+        if self.nodes >= self.node_limit {
+            self.stopped = true;
+        } else if self.nodes.is_multiple_of(1024) {
+            self.node_counter.store(self.nodes, Ordering::Relaxed);
+            let out_of_time = !self.pondering.load(Ordering::Relaxed)
+                && self.time_manager.as_ref().is_some_and(|time_manager| time_manager.hard_limit_reached());
+            if out_of_time || self.stop.load(Ordering::Relaxed) {
+                self.stopped = true;
+            }
+        }
+
+        self.stopped
+    }
+
+    fn negamax(&mut self, position: &Position, depth: i32, mut alpha: i32, mut beta: i32, ply: usize) -> i32 {
+        self.pv_table[ply].clear();
+
+        if depth <= 0 {
+            return self.quiescence(position, alpha, beta, ply);
+        }
+
+        self.nodes += 1;
+        if self.should_stop() {
+            return 0;
+        }
+
+        let is_root = ply == 0;
+        let pv_node = beta - alpha > 1;
+        let excluded_move = self.stack[ply].excluded_move;
+        let line_extensions = if is_root { 0 } else { self.stack[ply - 1].extensions };
+
+        if !is_root {
+            if let Some(result) = self.variant.variant_end(position) {
+                return variant_end_score(result, position, ply);
+            }
+            if self.is_draw(position) {
+                return 0;
+            }
+            if ply >= MAX_PLY - 1 {
+                return self.evaluate(position, ply);
+            }
+
+            // Mate distance pruning: no line from here can beat a mate that was already found closer to the root.
+            alpha = alpha.max(-MATE + ply as i32);
+            beta = beta.min(MATE - ply as i32 - 1);
+            if alpha >= beta {
+                return alpha;
+            }
+        }
+
+        let tt_entry = self.transposition_table.probe(position.hash);
+        let tt_move = tt_entry.and_then(|entry| entry.best_move);
+        if let Some(entry) = tt_entry.filter(|_| excluded_move.is_none()) {
+            let score = score_from_tt(entry.score, ply);
+            if !pv_node && entry.depth >= depth && match entry.bound {
+                Bound::Exact => true,
+                Bound::Lower => score >= beta,
+                Bound::Upper => score <= alpha,
+                Bound::None => false,
+            } {
+                return score;
+            }
+        }
+
+        // The tables only know about positions where the fifty move count starts again, since that's all they count.
+        if !is_root && excluded_move.is_none() && position.half_move_clock == 0 {
+            let tablebase = self.tablebase.as_deref().filter(|tablebase| self.variant.is_standard() && tablebase::can_probe(*tablebase, position));
+            if let Some((tablebase, wdl)) = tablebase.and_then(|tablebase| Some((tablebase, tablebase.probe_wdl(position)?))) {
+                self.tb_hits += 1;
+                // Tables that know the distance to mate give real mate scores, as long as they fit under the mate bound.
+                let mate_ply = tablebase.probe_dtm(position).map(|plies| ply as i32 + plies).filter(|mate_ply| *mate_ply < MAX_PLY as i32);
+                let score = match wdl {
+                    Wdl::Win => mate_ply.map_or(TB_WIN - ply as i32, |mate_ply| MATE - mate_ply),
+                    Wdl::CursedWin => 1,
+                    Wdl::Draw => 0,
+                    Wdl::BlessedLoss => -1,
+                    Wdl::Loss => mate_ply.map_or(-TB_WIN + ply as i32, |mate_ply| -MATE + mate_ply),
+                };
+
+                self.transposition_table.store(position.hash, None, score_to_tt(score, ply), 0, (depth + 6).min(MAX_PLY as i32 - 1), Bound::Exact);
+                return score;
+            }
+        }
+
+        let in_check = position::is_in_check(position, &position.side_to_move);
+        let static_eval = if in_check { -INFINITY } else { self.evaluate(position, ply) };
+
+        if !in_check && !pv_node && excluded_move.is_none() {
+            if self.params.razoring && depth <= self.params.razoring_max_depth && static_eval + self.params.razoring_margin * depth <= alpha {
+                let score = self.quiescence(position, alpha, alpha + 1, ply);
+                if score <= alpha {
+                    return score;
+                }
+            }
+
+            if self.params.reverse_futility_pruning
+                && depth <= self.params.reverse_futility_max_depth
+                && static_eval - self.params.reverse_futility_margin * depth >= beta
+                && beta.abs() < MATE_BOUND {
+                return static_eval;
+            }
+
+            let previous_was_null = ply > 0 && self.stack[ply - 1].is_null_move;
+            if self.params.null_move_pruning
+                && depth >= self.params.null_move_min_depth
+                && static_eval >= beta
+                && !previous_was_null
+                && beta.abs() < MATE_BOUND
+                && position::has_non_pawn_material(position, &position.side_to_move) {
+                let reduction = 3 + depth / 4 + ((static_eval - beta) / 200).min(3);
+
+                let mut null_position = position.clone();
+                moves::make_null_move(&mut null_position);
+
+                self.stack[ply].current_move = None;
+                self.stack[ply].is_null_move = true;
+                self.stack[ply].extensions = line_extensions;
+                self.update_accumulator(position, &null_position, ply);
+                self.history.push(position.hash);
+                let score = -self.negamax(&null_position, depth - 1 - reduction, -beta, -beta + 1, ply + 1);
+                self.history.pop();
+                self.stack[ply].is_null_move = false;
+
+                if self.stopped {
+                    return 0;
+                }
+                if score >= beta {
+                    // Don't trust a mate found after passing.
+                    return if score >= MATE_BOUND { beta } else { score };
+                }
+            }
+        }
+
+        let futility_pruning = self.params.futility_pruning
+            && !pv_node
+            && !in_check
+            && depth <= self.params.futility_max_depth
+            && static_eval + self.params.futility_margin * depth <= alpha;
+
+        let previous_move = if ply > 0 { self.stack[ply - 1].current_move } else { None };
+        let mut picker = MovePicker::new(position, self.variant.unchecked_moves(position), tt_move, ply, previous_move.as_ref(), &self.tables);
+
+        let original_alpha = alpha;
+        let mut best_score = -INFINITY;
+        let mut best_move: Option<Move> = None;
+        let mut legal_moves = 0;
+        let mut moves_searched = 0;
+        let mut quiets_tried: Vec<Move> = Vec::new();
+
+        while let Some(mv) = picker.next(position, &self.tables) {
+            if Some(mv) == excluded_move {
+                continue;
+            }
+            if is_root && (!self.root_moves.contains(&mv) || self.root_excluded.contains(&mv)) {
+                continue;
+            }
+
+            let is_capture = ordering::is_capture(position, &mv);
+            let is_quiet = mv.promotion == PromotionOption::None && !is_capture;
+
+            let mut child = position.clone();
+            self.variant.make_move(&mut child, &mv);
+            if !self.variant.is_legal(position, &child) {
+                continue;
+            }
+            legal_moves += 1;
+
+            let gives_check = position::is_in_check(&child, &child.side_to_move);
+
+            // Once a move has kept us out of a forced mate, quiet moves late in the list are unlikely to matter.
+            if !is_root && is_quiet && !in_check && !gives_check && best_score > -MATE_BOUND {
+                if self.params.late_move_pruning
+                    && depth <= self.params.late_move_pruning_max_depth
+                    && quiets_tried.len() as i32 >= 3 + depth * depth {
+                    continue;
+                }
+                if futility_pruning {
+                    continue;
+                }
+            }
+
+            let mut extension = 0;
+            if line_extensions < self.params.max_line_extensions {
+                if !is_root && Some(mv) == tt_move && excluded_move.is_none() {
+                    match self.singular_extension(position, depth, beta, ply, pv_node, tt_entry) {
+                        SingularResult::Extension(singular_extension) => extension = singular_extension,
+                        SingularResult::MultiCut(score) => return score,
+                    }
+                }
+
+                let is_recapture = !is_root && self.stack[ply - 1].is_capture && self.stack[ply - 1].current_move.is_some_and(|previous_move| previous_move.to == mv.to);
+                if extension == 0 && self.params.recapture_extensions && is_capture && is_recapture {
+                    extension = 1;
+                }
+                if extension == 0 && self.params.check_extensions && gives_check {
+                    extension = 1;
+                }
+
+                extension = extension.min(self.params.max_line_extensions - line_extensions);
+            }
+
+            self.stack[ply].current_move = Some(mv);
+            self.stack[ply].is_null_move = false;
+            self.stack[ply].is_capture = is_capture;
+            self.stack[ply].extensions = line_extensions + extension.max(0);
+            self.update_accumulator(position, &child, ply);
+            self.history.push(position.hash);
+
+            let new_depth = depth - 1 + extension;
+            let nodes_before_move = self.nodes;
+            let reduction = if self.params.late_move_reductions && depth >= 3 && legal_moves > 1 && is_quiet && !in_check && !gives_check {
+                let reduction = self.reductions[depth.min(63) as usize][legal_moves.min(63)] - pv_node as i32;
+                reduction.clamp(0, (new_depth - 1).max(0))
+            } else {
+                0
+            };
+
+            let mut score;
+            if moves_searched == 0 {
+                score = -self.negamax(&child, new_depth, -beta, -alpha, ply + 1);
+            } else if self.params.principal_variation_search {
+                // Try to prove the move is no better than alpha with a zero window, and only search it properly if that fails.
+                score = -self.negamax(&child, new_depth - reduction, -alpha - 1, -alpha, ply + 1);
+                if reduction > 0 && score > alpha {
+                    score = -self.negamax(&child, new_depth, -alpha - 1, -alpha, ply + 1);
+                }
+                if pv_node && score > alpha && score < beta {
+                    score = -self.negamax(&child, new_depth, -beta, -alpha, ply + 1);
+                }
+            } else {
+                score = -self.negamax(&child, new_depth - reduction, -beta, -alpha, ply + 1);
+                if reduction > 0 && score > alpha {
+                    score = -self.negamax(&child, new_depth, -beta, -alpha, ply + 1);
+                }
+            }
+            moves_searched += 1;
+
+            self.history.pop();
+
+            if is_root {
+                let move_nodes = self.nodes - nodes_before_move;
+                match self.root_move_nodes.iter_mut().find(|(root_move, _)| *root_move == mv) {
+                    Some((_, nodes)) => *nodes += move_nodes,
+                    None => self.root_move_nodes.push((mv, move_nodes)),
+                }
+            }
+
+            if self.stopped {
+                return 0;
+            }
+
+            if is_quiet {
+                quiets_tried.push(mv);
+            }
+
+            if score > best_score {
+                best_score = score;
+
+                if score > alpha {
+                    alpha = score;
+                    best_move = Some(mv);
+                    self.update_pv(ply, mv);
+
+                    if score >= beta {
+                        if is_quiet {
+                            self.reward_quiet(position, mv, &quiets_tried, depth, ply, previous_move);
+                        }
+                        break;
+                    }
+                }
+            }
+        }
+
+        if legal_moves == 0 {
+            // With the only legal move excluded, this isn't mate, there's just nothing else to compare against.
+            if excluded_move.is_some() {
+                return alpha;
+            }
+            return if in_check { -MATE + ply as i32 } else { 0 };
+        }
+
+        // Leaving out root moves also leaves the best one out, so these results don't belong in the table.
+        if excluded_move.is_some() || (is_root && !self.root_excluded.is_empty()) {
+            return best_score;
+        }
+
+        let bound = if best_score >= beta {
+            Bound::Lower
+        } else if alpha > original_alpha {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+        self.transposition_table.store(position.hash, best_move, score_to_tt(best_score, ply), static_eval, depth, bound);
+
+        best_score
+    }
+
+    /// Checks whether the TT move is singular, meaning a search without it at reduced depth fails low against a
+    /// bound just below the TT score. Only worth doing when the TT entry is deep enough to trust.
+    fn singular_extension(&mut self, position: &Position, depth: i32, beta: i32, ply: usize, pv_node: bool, tt_entry: Option<Entry>) -> SingularResult {
+        let Some(entry) = tt_entry else { return SingularResult::Extension(0) };
+        let tt_score = score_from_tt(entry.score, ply);
+
+        if !self.params.singular_extensions
+            || depth < self.params.singular_min_depth
+            || entry.depth < depth - 3
+            || entry.bound == Bound::Upper
+            || tt_score.abs() >= MATE_BOUND {
+            return SingularResult::Extension(0);
+        }
+
+        let singular_beta = tt_score - self.params.singular_margin * depth;
+        let saved_entry = self.stack[ply];
+
+        self.stack[ply].excluded_move = entry.best_move;
+        let score = self.negamax(position, (depth - 1) / 2, singular_beta - 1, singular_beta, ply);
+        self.stack[ply] = saved_entry;
+        self.stack[ply].excluded_move = None;
+
+        if score < singular_beta {
+            let is_far_worse = !pv_node && score < singular_beta - self.params.double_extension_margin;
+            SingularResult::Extension(if is_far_worse { 2 } else { 1 })
+        } else if singular_beta >= beta {
+            // Several moves beat beta, so this node will fail high whichever one is tried.
+            SingularResult::MultiCut(singular_beta)
+        } else if tt_score >= beta {
+            SingularResult::Extension(-1)
+        } else {
+            SingularResult::Extension(0)
+        }
+    }
+
+    fn quiescence(&mut self, position: &Position, mut alpha: i32, beta: i32, ply: usize) -> i32 {
+        self.pv_table[ply].clear();
+
+        self.nodes += 1;
+        if self.should_stop() {
+            return 0;
+        }
+        if let Some(result) = self.variant.variant_end(position) {
+            return variant_end_score(result, position, ply);
+        }
+        if ply >= MAX_PLY - 1 {
+            return self.evaluate(position, ply);
+        }
+
+        let in_check = position::is_in_check(position, &position.side_to_move);
+        let mut best_score = if in_check { -MATE + ply as i32 } else { self.evaluate(position, ply) };
+
+        if !in_check {
+            if best_score >= beta {
+                return best_score;
+            }
+            alpha = alpha.max(best_score);
+        }
+
+        // When in check every evasion has to be tried, otherwise only captures and queen promotions.
+        let unchecked_moves = self.variant.unchecked_moves(position);
+        let mut picker = if in_check {
+            MovePicker::new(position, unchecked_moves, None, ply, None, &self.tables)
+        } else {
+            MovePicker::new_captures(position, unchecked_moves, None)
+        };
+
+        while let Some(mv) = picker.next(position, &self.tables) {
+            if !in_check && ordering::see(position, &mv) < 0 {
+                continue;
+            }
+
+            let mut child = position.clone();
+            self.variant.make_move(&mut child, &mv);
+            if !self.variant.is_legal(position, &child) {
+                continue;
+            }
+
+            self.update_accumulator(position, &child, ply);
+            let score = -self.quiescence(&child, -beta, -alpha, ply + 1);
+            if self.stopped {
+                return 0;
+            }
+
+            if score > best_score {
+                best_score = score;
+
+                if score > alpha {
+                    alpha = score;
+                    self.update_pv(ply, mv);
+
+                    if score >= beta {
+                        break;
+                    }
+                }
+            }
+        }
+
+        best_score
+    }
+
+    /// The static evaluation of the position at `ply`, from the network when there is one, with the variant's
+    /// adjustments, which in standard chess are the endgame evaluators.
+    fn evaluate(&mut self, position: &Position, ply: usize) -> i32 {
+        let score = match &self.network {
+            Some(network) => nnue::evaluate(network, &mut self.accumulators[ply], &position.side_to_move),
+            None => eval::evaluate_cached(position, &mut self.pawn_table),
+        };
+        self.variant.evaluate(position, score)
+    }
+
+    /// Brings the accumulator of the child at `ply + 1` up to date from its parent's.
+    fn update_accumulator(&mut self, parent: &Position, child: &Position, ply: usize) {
+        if let Some(network) = &self.network {
+            let (parents, children) = self.accumulators.split_at_mut(ply + 1);
+            children[0].update(network, &parents[ply], parent, child);
+        }
+    }
+
+    fn update_pv(&mut self, ply: usize, mv: Move) {
+        let (current, deeper) = self.pv_table.split_at_mut(ply + 1);
+
+        current[ply].clear();
+        current[ply].push(mv);
+        current[ply].extend_from_slice(&deeper[0]);
+    }
+
+    /// A quiet move caused a cutoff: remember it as a killer and countermove, and shift history towards it and
+    /// away from the quiet moves that were tried before it.
+    fn reward_quiet(&mut self, position: &Position, mv: Move, quiets_tried: &[Move], depth: i32, ply: usize, previous_move: Option<Move>) {
+        let bonus = depth * depth;
+
+        self.tables.store_killer(ply, mv);
+        self.tables.update_history(position, &mv, bonus);
+        for tried in quiets_tried.iter().filter(|tried| **tried != mv) {
+            self.tables.update_history(position, tried, -bonus);
+        }
+
+        if let Some(previous_move) = previous_move {
+            self.tables.store_countermove(position, &previous_move, mv);
+        }
+    }
+
+    fn is_draw(&self, position: &Position) -> bool {
+        // A lone king can still win some variants, so only standard chess is drawn for lack of material.
+        if position.half_move_clock >= 100 || (self.variant.is_standard() && is_insufficient_material(position)) {
+            return true;
+        }
+
+        // Only positions with the same side to move, since the last irreversible move, can repeat.
+        self.history.iter().rev()
+            .skip(1)
+            .step_by(2)
+            .take(position.half_move_clock as usize / 2)
+            .any(|hash| *hash == position.hash)
+    }
+}
+
+enum SingularResult {
+    Extension(i32),
+    MultiCut(i32),
+}
+
+/// Whether a helper's result should be played over the best one so far: a deeper search wins, then a better score,
+/// except that a shorter mate always wins.
+fn is_better_thread(candidate: &SearchResult, current: &SearchResult) -> bool {
+    if candidate.pv.is_empty() {
+        return false;
+    }
+    if candidate.score >= MATE_BOUND || current.score >= MATE_BOUND {
+        return candidate.score > current.score;
+    }
+
+    candidate.depth > current.depth || (candidate.depth == current.depth && candidate.score > current.score)
+}
+
+/// The score of a game the variant's rules have ended, for the side to move, as a mate at `ply`.
+fn variant_end_score(result: GameResult, position: &Position, ply: usize) -> i32 {
+    match (result, position.side_to_move) {
+        (GameResult::Draw, _) => 0,
+        (GameResult::WhiteWin, piece::Color::White) | (GameResult::BlackWin, piece::Color::Black) => MATE - ply as i32,
+        _ => -MATE + ply as i32,
+    }
+}
+
+/// Bare kings, or a single minor piece against a bare king.
+pub fn is_insufficient_material(position: &Position) -> bool {
+    let heavy_material = [Piece::Pawn, Piece::Rook, Piece::Queen].iter()
+        .any(|piece| {
+            position.bitboards[piece::piece_id(piece, &piece::Color::White) as usize] != 0
+                || position.bitboards[piece::piece_id(piece, &piece::Color::Black) as usize] != 0
+        });
+
+    let minor_pieces = position.bitboards[1] | position.bitboards[2] | position.bitboards[7] | position.bitboards[8];
+
+    !heavy_material && minor_pieces.count_ones() <= 1
+}
+
+/// Mate and tablebase scores are stored relative to the node rather than the root, so they stay correct wherever the
+/// entry is hit.
+fn score_to_tt(score: i32, ply: usize) -> i32 {
+    if score >= TB_WIN - MAX_PLY as i32 {
+        score + ply as i32
+    } else if score <= -TB_WIN + MAX_PLY as i32 {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+fn score_from_tt(score: i32, ply: usize) -> i32 {
+    if score >= TB_WIN - MAX_PLY as i32 {
+        score - ply as i32
+    } else if score <= -TB_WIN + MAX_PLY as i32 {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+/// Formats a score the way UCI expects it, `cp 35` or `mate -3`.
+pub fn format_score(score: i32) -> String {
+    if score >= MATE_BOUND {
+        format!("mate {}", (MATE - score + 1) / 2)
+    } else if score <= -MATE_BOUND {
+        format!("mate -{}", (MATE + score) / 2)
+    } else {
+        format!("cp {}", score)
+    }
+}
+
+// Unit Tests! //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{display, fen};
+    use super::super::dtm::{DtmTablebase, Material};
+    use super::super::endgame;
+    use super::super::moves;
+    use super::super::tablebase::tests::MaterialTablebase;
+
+    fn search_position(fen_string: &str, depth: i32, params: SearchParams) -> SearchResult {
+        let mut searcher = Searcher::new(4);
+        searcher.params = params;
+        searcher.search(&fen::position_from_fen(fen_string), &SearchLimits { depth: Some(depth), ..Default::default() })
+    }
+
+    #[test]
+    fn test_finds_mate_in_one() {
+        let result = search_position("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", 3, SearchParams::default());
+
+        assert_eq!(result.best_move.map(|mv| mv.to_string()), Some("a1a8".to_string()));
+        assert_eq!(result.score, MATE - 1);
+    }
+
+    #[test]
+    fn test_selectivity_keeps_mates() {
+        // Ra7 forces Kg8, then Rb8 mates.
+        for params in [SearchParams::exhaustive(), SearchParams::default()] {
+            let result = search_position("7k/8/8/8/8/8/R7/1R4K1 w - - 0 1", 5, params.clone());
+            assert!(result.score == MATE - 3, "Expected mate in two with {:?}, got {} ({}).", params, format_score(result.score), display::list_moves(&result.pv));
+        }
+    }
+
+    #[test]
+    fn test_selectivity_saves_nodes() {
+        let fen_string = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+
+        let exhaustive = search_position(fen_string, 4, SearchParams::exhaustive());
+        let selective = search_position(fen_string, 4, SearchParams::default());
+
+        assert!(selective.nodes < exhaustive.nodes, "Selective search used {} nodes, plain alpha-beta {}.", selective.nodes, exhaustive.nodes);
+    }
+
+    #[test]
+    fn test_principal_variation_search_and_aspiration() {
+        let windowed = SearchParams { principal_variation_search: true, aspiration_windows: true, aspiration_min_depth: 2, ..SearchParams::exhaustive() };
+
+        // Narrow windows must not change the result, only how much work it takes to find it.
+        let plain = search_position("7k/8/8/8/8/8/R7/1R4K1 w - - 0 1", 5, SearchParams::exhaustive());
+        let zero_window = search_position("7k/8/8/8/8/8/R7/1R4K1 w - - 0 1", 5, windowed.clone());
+        assert_eq!(plain.score, zero_window.score);
+
+        let fen_string = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let plain = search_position(fen_string, 4, SearchParams::exhaustive());
+        let zero_window = search_position(fen_string, 4, windowed);
+        assert_eq!(plain.score, zero_window.score);
+        assert!(zero_window.nodes < plain.nodes, "PVS used {} nodes, plain alpha-beta {}.", zero_window.nodes, plain.nodes);
+    }
+
+    #[test]
+    fn test_no_null_move_in_pawn_endings() {
+        // Without non-pawn material null move pruning stays off, so it can't change the result of a pawn ending.
+        let fen_string = "8/8/3k4/8/3PK3/8/8/8 w - - 0 1";
+        assert!(!position::has_non_pawn_material(&fen::position_from_fen(fen_string), &piece::Color::White));
+
+        let with_null_move = search_position(fen_string, 6, SearchParams::default());
+        let without_null_move = search_position(fen_string, 6, SearchParams { null_move_pruning: false, ..SearchParams::default() });
+        assert_eq!(with_null_move.score, without_null_move.score);
+    }
+
+    #[test]
+    fn test_repetition_is_a_draw() {
+        let mut searcher = Searcher::new(4);
+        let position = fen::position_from_fen("6k1/8/8/8/8/8/q7/6K1 b - - 10 40");
+
+        searcher.set_game_history(vec![position.hash, 0]);
+        assert!(searcher.is_draw(&position), "The position was already on the board with the same side to move.");
+
+        searcher.set_game_history(vec![0, position.hash]);
+        assert!(!searcher.is_draw(&position), "A position can't repeat with the other side to move.");
+    }
+
+    #[test]
+    fn test_check_extensions_see_further() {
+        // A quiet check and then a quiet mate, which plain alpha-beta can't see at depth two.
+        let fen_string = "8/8/5K2/7k/1R2B3/8/8/8 w - - 0 1";
+
+        let plain = search_position(fen_string, 2, SearchParams::exhaustive());
+        let extended = search_position(fen_string, 2, SearchParams { check_extensions: true, ..SearchParams::exhaustive() });
+
+        assert!(plain.score < MATE_BOUND, "Plain alpha-beta shouldn't see the mate at depth two.");
+        assert!(extended.score == MATE - 3, "The check extension should reveal mate in two, got {}.", format_score(extended.score));
+    }
+
+    #[test]
+    fn test_extensions_are_capped() {
+        // Endless checks are available to both sides, so uncapped check extensions would never reach the horizon.
+        let fen_string = "6k1/5q2/8/8/8/8/5Q2/6K1 w - - 0 1";
+        let mut searcher = Searcher::new(4);
+        searcher.params.max_line_extensions = 4;
+
+        let result = searcher.search(&fen::position_from_fen(fen_string), &SearchLimits { depth: Some(5), nodes: Some(2_000_000), ..Default::default() });
+        assert_eq!(result.depth, 5, "The search should finish depth 5 well within its node budget.");
+    }
+
+    #[test]
+    fn test_respects_move_time() {
+        let mut searcher = Searcher::new(4);
+        let limits = SearchLimits { time: Some(TimeControl { move_time: Some(200), ..Default::default() }), ..Default::default() };
+
+        let start = Instant::now();
+        let result = searcher.search(&fen::position_from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1"), &limits);
+
+        assert!(result.best_move.is_some());
+        assert!(start.elapsed().as_millis() < 1000, "The search ran for {}ms on a 200ms budget.", start.elapsed().as_millis());
+    }
+
+    #[test]
+    fn test_multi_pv_ranks_root_moves() {
+        let fen_string = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let position = fen::position_from_fen(fen_string);
+        let params = SearchParams { aspiration_windows: true, aspiration_min_depth: 2, ..SearchParams::exhaustive() };
+
+        let mut searcher = Searcher::new(4);
+        searcher.params = params.clone();
+        searcher.multi_pv = 3;
+        let result = searcher.search(&position, &SearchLimits { depth: Some(3), ..Default::default() });
+
+        assert_eq!(result.lines.len(), 3);
+        assert_eq!(result.pv, result.lines[0].pv);
+        assert!(result.lines.windows(2).all(|pair| pair[0].score >= pair[1].score), "The lines should be ranked best first.");
+
+        // Every line should have the score its first move gets when it's the only move searched.
+        for line in &result.lines {
+            let mut single = Searcher::new(4);
+            single.params = params.clone();
+            let limits = SearchLimits { depth: Some(3), search_moves: vec![line.pv[0]], ..Default::default() };
+            let single_result = single.search(&position, &limits);
+
+            assert_eq!(single_result.best_move, Some(line.pv[0]));
+            assert_eq!(single_result.score, line.score, "The line starting with {} got a different score on its own.", line.pv[0]);
+        }
+    }
+
+    #[test]
+    fn test_search_moves_restrict_the_root() {
+        // Taking the queen is by far the best move, but it isn't one of the allowed moves.
+        let position = fen::position_from_fen("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1");
+        let allowed: Vec<Move> = moves::get_legal_moves(&position).into_iter().filter(|mv| mv.from == 4).collect();
+
+        let mut searcher = Searcher::new(4);
+        searcher.multi_pv = 10;
+        let result = searcher.search(&position, &SearchLimits { depth: Some(4), search_moves: allowed.clone(), ..Default::default() });
+
+        assert_eq!(result.lines.len(), allowed.len(), "There should be a line for every allowed move and no more.");
+        assert!(result.lines.iter().all(|line| allowed.contains(&line.pv[0])), "Only the allowed moves should be searched.");
+    }
+
+    #[test]
+    fn test_single_thread_is_deterministic() {
+        let fen_string = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let first = search_position(fen_string, 5, SearchParams::default());
+        let second = search_position(fen_string, 5, SearchParams::default());
+
+        assert_eq!((first.nodes, first.score, &first.pv), (second.nodes, second.score, &second.pv));
+    }
+
+    #[test]
+    fn test_lazy_smp() {
+        let mut searcher = Searcher::new(16);
+        searcher.threads = 4;
+
+        let result = searcher.search(&fen::position_from_fen("7k/8/8/8/8/8/R7/1R4K1 w - - 0 1"), &SearchLimits { depth: Some(6), ..Default::default() });
+        assert_eq!(result.score, MATE - 3, "The threads together should still find mate in two.");
+        assert!(result.nodes > searcher.nodes, "The helpers' nodes should be counted too.");
+
+        // Stopping the main thread has to stop the helpers as well, or the search would never return.
+        let stop = searcher.stop.clone();
+        let position = fen::position_from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        let search_thread = std::thread::spawn(move || searcher.search(&position, &SearchLimits::default()));
+
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        let stopped_at = Instant::now();
+        stop.store(true, Ordering::Relaxed);
+        let result = search_thread.join().expect("The search thread panicked.");
+
+        assert!(result.best_move.is_some());
+        assert!(stopped_at.elapsed().as_millis() < 500, "The search took {}ms to stop.", stopped_at.elapsed().as_millis());
+    }
+
+    #[test]
+    fn test_searches_with_a_network() {
+        let network = nnue::tests::test_network();
+        let mut searcher = Searcher::new(4);
+        searcher.set_network(Some(Arc::new(network)));
+
+        // The test network plays nonsense, but mates are found by the search whatever the evaluation says.
+        let result = searcher.search(&fen::position_from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1"), &SearchLimits { depth: Some(3), ..Default::default() });
+        assert_eq!(result.score, MATE - 1, "The search should find mate in one with a network too.");
+    }
+
+    #[test]
+    fn test_endgame_knowledge_is_for_standard_chess() {
+        let mut searcher = Searcher::new(1);
+        for variant in variant::VARIANTS {
+            searcher.variant = variant;
+            let mut evaluate = |fen_string: &str| searcher.evaluate(&variant.position_from_fen(fen_string).unwrap(), 0);
+
+            // KRvK is a known win, and Black keeps the opposition against the pawn, but only by the rules of chess.
+            let rook = evaluate("8/8/6k1/8/8/8/8/K6R b - - 0 1");
+            let pawn = evaluate("4k3/8/8/8/8/8/4P3/4K3 b - - 0 1");
+            if variant.is_standard() {
+                assert!(rook < -endgame::KNOWN_WIN && pawn == 0, "Standard chess should use the endgame evaluators.");
+            } else {
+                assert!(rook > -endgame::KNOWN_WIN && pawn != 0, "{} shouldn't use the standard endgame evaluators.", variant.name());
+            }
+        }
+
+        searcher.variant = variant::from_name("racingkings").unwrap();
+        let result = searcher.search(&fen::position_from_fen("8/8/6k1/8/8/8/8/K6R b - - 0 1"), &SearchLimits { depth: Some(6), ..Default::default() });
+        assert!(result.score > -endgame::KNOWN_WIN, "Black's king is the closer one to the eighth rank in Racing Kings, so it isn't lost.");
+    }
+
+    #[test]
+    fn test_tablebase_probes() {
+        let mut searcher = Searcher::new(4);
+        searcher.tablebase = Some(Arc::new(MaterialTablebase));
+
+        // Taking the rook is the only move the tables keep, and the position after it is a tablebase win.
+        let result = searcher.search(&fen::position_from_fen("3r3k/8/8/8/8/8/8/3Q2K1 w - - 0 1"), &SearchLimits { depth: Some(4), ..Default::default() });
+        assert_eq!(result.best_move.map(|mv| mv.to_string()), Some("d1d8".to_string()));
+        assert_eq!(result.score, TB_WIN - 1);
+        assert!(searcher.tb_hits > 0);
+
+        // With the fifty move count running, nothing after a quiet move can be probed.
+        searcher.clear();
+        let result = searcher.search(&fen::position_from_fen("7k/8/8/8/8/8/8/1Q4K1 w - - 0 1"), &SearchLimits { depth: Some(2), ..Default::default() });
+        assert!(result.score < TB_WIN - MAX_PLY as i32, "Positions after quiet moves shouldn't be probed.");
+    }
+
+    #[test]
+    fn test_distance_to_mate_tables_give_mate_scores() {
+        let mut tablebase = DtmTablebase::default();
+        tablebase.generate(&Material::parse("KQvK").unwrap()).expect("KQvK needs no other tables.");
+        let tablebase = Arc::new(tablebase);
+
+        // Taking the rook leaves a KQvK position the tables know the mate of.
+        let position = fen::position_from_fen("3r3k/8/8/8/8/8/8/3Q2K1 w - - 0 1");
+        let mut child = position.clone();
+        moves::make_move(&mut child, &Move { from: 3, to: 59, promotion: moves::PromotionOption::None });
+        let plies_to_mate = tablebase.probe_dtm(&child).expect("KQvK is won after the capture.");
+
+        let mut searcher = Searcher::new(4);
+        searcher.tablebase = Some(tablebase);
+        let result = searcher.search(&position, &SearchLimits { depth: Some(4), ..Default::default() });
+        assert_eq!(result.best_move.map(|mv| mv.to_string()), Some("d1d8".to_string()));
+        assert_eq!(result.score, MATE - 1 - plies_to_mate, "The score should be the mate the tables found.");
+    }
+
+    #[test]
+    fn test_pondering_ignores_the_clock() {
+        let mut searcher = Searcher::new(4);
+        let pondering = searcher.pondering.clone();
+        pondering.store(true, Ordering::Relaxed);
+
+        let limits = SearchLimits { time: Some(TimeControl { move_time: Some(50), ..Default::default() }), ..Default::default() };
+        let position = fen::position_from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        let search_thread = std::thread::spawn(move || searcher.search(&position, &limits));
+
+        std::thread::sleep(std::time::Duration::from_millis(300));
+        assert!(!search_thread.is_finished(), "The search stopped on its own while pondering.");
+
+        // After the ponderhit the time already spent is far over the budget, so the search stops right away.
+        let ponderhit = Instant::now();
+        pondering.store(false, Ordering::Relaxed);
+        let result = search_thread.join().expect("The search thread panicked.");
+
+        assert!(result.best_move.is_some());
+        assert!(ponderhit.elapsed().as_millis() < 500, "The search ran {}ms after the ponderhit.", ponderhit.elapsed().as_millis());
+    }
+
+    #[test]
+    fn test_ponder_move_follows_the_pv() {
+        let mut searcher = Searcher::new(4);
+        let position = fen::position_from_fen("7k/8/8/8/8/8/R7/1R4K1 w - - 0 1");
+        let result = searcher.search(&position, &SearchLimits { depth: Some(5), ..Default::default() });
+
+        assert_eq!(searcher.ponder_move(&position, &result), result.pv.get(1).copied());
+        assert_eq!(searcher.ponder_move(&position, &result).map(|mv| mv.to_string()), Some("h8g8".to_string()));
+    }
+}
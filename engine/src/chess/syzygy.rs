@@ -0,0 +1,1423 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use super::attacks;
+use super::dtm::Material;
+use super::fen;
+use super::moves;
+use super::piece::{self, Color, Piece};
+use super::position::{self, Position};
+use super::tablebase::{Tablebase, Wdl};
+
+// Syzygy tables come as one `.rtbw` file (win/draw/loss) and one `.rtbz` file (distance to zeroing) per material
+// signature, named like `KQvKR.rtbw` with the pieces before the `v` as White. A file is made of subtables: a WDL file
+// has one per side to move, or only White's when both sides have the same pieces, and a DTZ file only one side's,
+// with the other worked out by a search of a ply. With pawns there's one of those per file a to d of the leading pawn.
+//
+// A position's index in a subtable comes from the squares of its pieces, in the order the file lists them, once the
+// board is mirrored to put the leading pawn on files a to d, or without pawns the leading piece in the a1-d1-d4
+// triangle. Identical pieces form a group whose squares are numbered as a set, and each group's number is scaled
+// by the sizes of the groups before it. The values are Huffman coded in blocks of a fixed size, with a sparse index
+// every `span` values into the block and position where its value is. A symbol is either a value or a pair of
+// symbols, so a run of values can take one code.
+//
+// The tables don't care about en passant or positions where a capture is best, so probes search the captures first,
+// and DTZ probes the pawn moves too. All of this follows Stockfish's tbprobe.cpp, which is the reference for the
+// format, down to the numbering tables.
+//
+// `write_table` writes tables in the same format from values worked out here, which is how the tests get tables to
+// read. It gives each value its own symbol, so the files are bigger than the real generator's, but they're read the
+// same way.
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xe8, 0x23, 0x5d];
+const DTZ_MAGIC: [u8; 4] = [0xd7, 0x66, 0x0c, 0xa5];
+
+// File flags.
+const SPLIT: u8 = 1;
+const HAS_PAWNS: u8 = 2;
+
+// Subtable flags. DTZ values are in moves rather than plies unless a `_PLIES` flag says so, and mapped ones go through
+// a table for each result.
+const STM: u8 = 1;
+const MAPPED: u8 = 2;
+const WIN_PLIES: u8 = 4;
+const LOSS_PLIES: u8 = 8;
+const WIDE: u8 = 16;
+const SINGLE_VALUE: u8 = 128;
+
+/// A symbol's right half when it stands for the value in its left half.
+const LEAF: u16 = 0xfff;
+
+// How the writer splits values into blocks. A probe decodes its block from the start, so blocks are short: 1 << 5
+// bytes hold at most 256 values.
+const BLOCK_SIZE_LOG: u8 = 5;
+const SPAN_LOG: u8 = 10;
+const MAX_CODE_LENGTH: usize = 24;
+
+/// The Syzygy files found on a `SyzygyPath`.
+#[derive(Debug, Default)]
+pub struct SyzygyFiles {
+    pub wdl: Vec<PathBuf>,
+    pub dtz: Vec<PathBuf>,
+    /// The most pieces of any table, kings included.
+    pub max_pieces: usize,
+}
+
+/// Looks through the directories of a `SyzygyPath`, separated like `PATH` is on this system, for table files with the
+/// right magic number.
+pub fn find_tables(syzygy_path: &str) -> Result<SyzygyFiles, String> {
+    let mut files = SyzygyFiles::default();
+
+    for directory in env::split_paths(syzygy_path) {
+        let entries = fs::read_dir(&directory).map_err(|error| format!("Couldn't read {}: {}.", directory.display(), error))?;
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let (Some(stem), Some(extension)) = (path.file_stem().and_then(|stem| stem.to_str()), path.extension().and_then(|extension| extension.to_str())) else { continue };
+            let Some(piece_count) = piece_count(stem) else { continue };
+
+            let (list, magic) = match extension {
+                "rtbw" => (&mut files.wdl, WDL_MAGIC),
+                "rtbz" => (&mut files.dtz, DTZ_MAGIC),
+                _ => continue,
+            };
+            if has_magic(&path, magic) {
+                list.push(path);
+                files.max_pieces = files.max_pieces.max(piece_count);
+            }
+        }
+    }
+
+    files.wdl.sort();
+    files.dtz.sort();
+    Ok(files)
+}
+
+/// The number of pieces in a material signature like `KRPvKR`, or `None` if it isn't one.
+fn piece_count(signature: &str) -> Option<usize> {
+    let (white, black) = signature.split_once('v')?;
+    let is_side = |side: &str| side.starts_with('K') && side.chars().all(|piece| "KQRBNP".contains(piece));
+
+    if is_side(white) && is_side(black) { Some(white.len() + black.len()) } else { None }
+}
+
+fn has_magic(path: &PathBuf, magic: [u8; 4]) -> bool {
+    let mut header = [0u8; 4];
+    File::open(path).and_then(|mut file| file.read_exact(&mut header)).is_ok() && header == magic
+}
+
+/// The numbering tables every index is built from.
+struct Indexing {
+    /// The squares below the a1-h8 diagonal, 0 to 27.
+    b1h1h7: [u64; 64],
+    /// The a1-d1-d4 triangle, 0 to 5 off the diagonal and 6 to 9 on it.
+    a1d1d4: [u64; 64],
+    /// The 462 ways to place the kings, by the triangle number of the first and the square of the second.
+    kings: [[u64; 64]; 10],
+    /// Pawn squares, 47 on the a and h files' second rank down to 0 in the middle of the seventh.
+    pawns: [u64; 64],
+    /// `binomial[k][n]` is n choose k.
+    binomial: [[u64; 64]; 6],
+    /// Where the indexes of each number of leading pawns with the first on a square start, and how many each file has.
+    lead_pawn_index: [[u64; 64]; 6],
+    lead_pawns_size: [[u64; 4]; 6],
+}
+
+fn indexing() -> &'static Indexing {
+    static INDEXING: OnceLock<Indexing> = OnceLock::new();
+    INDEXING.get_or_init(Indexing::new)
+}
+
+/// Rank less file: 0 on the a1-h8 diagonal and negative below it.
+fn off_diagonal(square: usize) -> i32 {
+    (square / 8) as i32 - (square % 8) as i32
+}
+
+impl Indexing {
+    fn new() -> Indexing {
+        let mut b1h1h7 = [0; 64];
+        for (code, square) in (0..64).filter(|square| off_diagonal(*square) < 0).enumerate() {
+            b1h1h7[square] = code as u64;
+        }
+
+        let mut a1d1d4 = [0; 64];
+        let triangle: Vec<usize> = (0..64).filter(|square| square % 8 <= 3 && off_diagonal(*square) <= 0 && square / 8 <= 3).collect();
+        let off_first = triangle.iter().filter(|square| off_diagonal(**square) < 0).chain(triangle.iter().filter(|square| off_diagonal(**square) == 0));
+        for (code, square) in off_first.enumerate() {
+            a1d1d4[*square] = code as u64;
+        }
+
+        // Placements with both kings on the diagonal come last, so they can be left out when a third piece decides.
+        let mut kings = [[0; 64]; 10];
+        let mut both_on_diagonal = Vec::new();
+        let mut code = 0;
+        for first_code in 0..10 {
+            for first in triangle.iter().copied().filter(|square| a1d1d4[*square] == first_code) {
+                for (second, king_code) in kings[first_code as usize].iter_mut().enumerate() {
+                    if (attacks::KING_ATTACKS[first] | 1 << first) & (1 << second) != 0 || (off_diagonal(first) == 0 && off_diagonal(second) > 0) {
+                        continue;
+                    }
+                    if off_diagonal(first) == 0 && off_diagonal(second) == 0 {
+                        both_on_diagonal.push((first_code as usize, second));
+                    } else {
+                        *king_code = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (first_code, second) in both_on_diagonal {
+            kings[first_code][second] = code;
+            code += 1;
+        }
+
+        let mut binomial = [[0; 64]; 6];
+        for n in 0..64 {
+            for k in 0..6.min(n + 1) {
+                binomial[k][n] = if n == 0 { 1 } else { (if k > 0 { binomial[k - 1][n - 1] } else { 0 }) + if k < n { binomial[k][n - 1] } else { 0 } };
+            }
+        }
+
+        let mut pawns = [0; 64];
+        let mut available = 48;
+        let mut lead_pawn_index = [[0; 64]; 6];
+        let mut lead_pawns_size = [[0; 4]; 6];
+        for lead_pawns in 1..6 {
+            for (file, size) in lead_pawns_size[lead_pawns].iter_mut().enumerate() {
+                let mut index = 0;
+                for rank in 1..7 {
+                    let square = rank * 8 + file;
+                    if lead_pawns == 1 {
+                        pawns[square] = available - 1;
+                        pawns[square ^ 7] = available - 2;
+                        available -= 2;
+                    }
+                    lead_pawn_index[lead_pawns][square] = index;
+                    index += binomial[lead_pawns - 1][pawns[square] as usize];
+                }
+                *size = index;
+            }
+        }
+
+        Indexing { b1h1h7, a1d1d4, kings, pawns, binomial, lead_pawn_index, lead_pawns_size }
+    }
+}
+
+/// The Syzygy code of a piece: 1 to 6 for White's pawn to king, 9 to 14 for Black's.
+fn piece_code(piece: Piece, color: Color) -> u8 {
+    piece as u8 + 1 + if color == Color::Black { 8 } else { 0 }
+}
+
+/// What a table's signature says about it.
+struct Info {
+    material: Material,
+    piece_count: usize,
+    has_pawns: bool,
+    /// Some side has exactly one piece of a kind other than the king, so three pieces lead instead of the kings.
+    has_unique_pieces: bool,
+    /// Both sides have the same pieces, so positions with Black to move are looked up with the colors swapped.
+    symmetric: bool,
+    /// The side whose pawns lead, which is the one with fewer, and the pawns of each side, leading side first.
+    lead_color: Color,
+    pawn_counts: [usize; 2],
+}
+
+impl Info {
+    fn new(material: &Material) -> Info {
+        let count = |pieces: &[Piece], kind: Piece| pieces.iter().filter(|piece| **piece == kind).count();
+        let (white_pawns, black_pawns) = (count(&material.white, Piece::Pawn), count(&material.black, Piece::Pawn));
+        let lead_color = if black_pawns == 0 || (white_pawns > 0 && black_pawns >= white_pawns) { Color::White } else { Color::Black };
+
+        Info {
+            material: material.clone(),
+            piece_count: material.piece_count(),
+            has_pawns: white_pawns + black_pawns > 0,
+            has_unique_pieces: [&material.white, &material.black].iter().any(|side| side.iter().any(|piece| count(side, *piece) == 1)),
+            symmetric: material.white == material.black,
+            lead_color,
+            pawn_counts: if lead_color == Color::White { [white_pawns, black_pawns] } else { [black_pawns, white_pawns] },
+        }
+    }
+
+    fn sides(&self, is_dtz: bool) -> usize {
+        if is_dtz || self.symmetric { 1 } else { 2 }
+    }
+
+    fn files(&self) -> usize {
+        if self.has_pawns { 4 } else { 1 }
+    }
+}
+
+/// How the squares of a subtable's pieces make its indexes.
+#[derive(Clone)]
+struct Encoding {
+    /// Piece codes in the order the file lists them.
+    pieces: Vec<u8>,
+    group_lengths: Vec<usize>,
+    /// What each group's number is scaled by, then the size of the subtable.
+    factors: Vec<u64>,
+}
+
+impl Encoding {
+    /// Groups the pieces and works out the factors. `order` says where the leading group, and the other side's pawns
+    /// when both sides have some, come among the groups in the scaling.
+    fn new(info: &Info, pieces: Vec<u8>, order: [usize; 2], file: usize) -> Result<Encoding, String> {
+        let indexing = indexing();
+
+        let mut group_lengths = vec![1];
+        let lead_length = if info.has_pawns { info.pawn_counts[0] } else if info.has_unique_pieces { 3 } else { 2 };
+        let mut first_length = if info.has_pawns { 0 } else { lead_length as i32 };
+        for i in 1..pieces.len() {
+            first_length -= 1;
+            if first_length > 0 || pieces[i] == pieces[i - 1] {
+                *group_lengths.last_mut().unwrap() += 1;
+            } else {
+                group_lengths.push(1);
+            }
+        }
+        if pieces.len() != info.piece_count || group_lengths[0] != lead_length || group_lengths.iter().any(|length| *length > 5) {
+            return Err("The table's pieces don't group the way its signature says.".to_string());
+        }
+
+        let groups = group_lengths.len();
+        let both_pawns = info.pawn_counts[1] > 0;
+        let mut next = if both_pawns { 2 } else { 1 };
+        let mut free_squares = 64 - group_lengths[0] - if both_pawns { group_lengths[1] } else { 0 };
+        let mut factors = vec![0; groups + 1];
+        let mut factor = 1u64;
+
+        let mut k = 0;
+        while next < groups || k == order[0] || k == order[1] {
+            if k == order[0] {
+                factors[0] = factor;
+                factor *= if info.has_pawns { indexing.lead_pawns_size[group_lengths[0]][file] } else if info.has_unique_pieces { 31332 } else { 462 };
+            } else if k == order[1] {
+                factors[1] = factor;
+                factor *= indexing.binomial[group_lengths[1]][48 - group_lengths[0]];
+            } else {
+                factors[next] = factor;
+                factor *= indexing.binomial[group_lengths[next]][free_squares];
+                free_squares -= group_lengths[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        factors[groups] = factor;
+
+        if factors.contains(&0) {
+            return Err("The table's group order is broken.".to_string());
+        }
+        Ok(Encoding { pieces, group_lengths, factors })
+    }
+
+    fn size(&self) -> u64 {
+        *self.factors.last().unwrap()
+    }
+
+    /// The index of a located position in the subtable.
+    fn index(&self, info: &Info, location: Location) -> u64 {
+        let indexing = indexing();
+        let Location { mut squares, mut pieces, lead_pawns, .. } = location;
+        let size = squares.len();
+
+        // Identical pieces are interchangeable, so any order that matches the file's will do.
+        for i in lead_pawns..size.saturating_sub(1) {
+            if let Some(j) = (i + 1..size).find(|j| self.pieces[i] == pieces[*j]) {
+                squares.swap(i, j);
+                pieces.swap(i, j);
+            }
+        }
+
+        if squares[0] % 8 > 3 {
+            squares.iter_mut().for_each(|square| *square ^= 7);
+        }
+
+        let mut index;
+        if info.has_pawns {
+            index = indexing.lead_pawn_index[lead_pawns][squares[0]];
+            squares[1..lead_pawns].sort_by_key(|square| indexing.pawns[*square]);
+            for (i, square) in squares.iter().enumerate().take(lead_pawns).skip(1) {
+                index += indexing.binomial[i][indexing.pawns[*square] as usize];
+            }
+        } else {
+            if squares[0] / 8 > 3 {
+                squares.iter_mut().for_each(|square| *square ^= 56);
+            }
+
+            // The first of the leading pieces off the diagonal has to be below it.
+            for i in 0..self.group_lengths[0] {
+                match off_diagonal(squares[i]) {
+                    0 => continue,
+                    off if off > 0 => squares[i..].iter_mut().for_each(|square| *square = ((*square >> 3) | (*square << 3)) & 63),
+                    _ => {}
+                }
+                break;
+            }
+
+            index = if info.has_unique_pieces {
+                let [first, second, third] = [squares[0], squares[1], squares[2]].map(|square| square as u64);
+                let adjust_1 = (second > first) as u64;
+                let adjust_2 = (third > first) as u64 + (third > second) as u64;
+                let rank = |square: u64| square / 8;
+
+                if off_diagonal(squares[0]) != 0 {
+                    (indexing.a1d1d4[squares[0]] * 63 + second - adjust_1) * 62 + third - adjust_2
+                } else if off_diagonal(squares[1]) != 0 {
+                    (6 * 63 + rank(first) * 28 + indexing.b1h1h7[squares[1]]) * 62 + third - adjust_2
+                } else if off_diagonal(squares[2]) != 0 {
+                    6 * 63 * 62 + 4 * 28 * 62 + rank(first) * 7 * 28 + (rank(second) - adjust_1) * 28 + indexing.b1h1h7[squares[2]]
+                } else {
+                    6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + rank(first) * 7 * 6 + (rank(second) - adjust_1) * 6 + (rank(third) - adjust_2)
+                }
+            } else {
+                indexing.kings[indexing.a1d1d4[squares[0]] as usize][squares[1]]
+            };
+        }
+        index *= self.factors[0];
+
+        // Each later group is a set of squares, skipping the ones earlier groups are on, and the other side's pawns
+        // skip the first rank too.
+        let mut start = self.group_lengths[0];
+        let mut remaining_pawns = info.pawn_counts[1] > 0;
+        for (group, length) in self.group_lengths.iter().enumerate().skip(1) {
+            squares[start..start + length].sort_unstable();
+
+            let mut number = 0;
+            for i in 0..*length {
+                let square = squares[start + i];
+                let adjust = squares[..start].iter().filter(|earlier| square > **earlier).count();
+                number += indexing.binomial[i + 1][square - adjust - if remaining_pawns { 8 } else { 0 }];
+            }
+            remaining_pawns = false;
+
+            index += number * self.factors[group];
+            start += length;
+        }
+
+        index
+    }
+}
+
+/// A position as a table sees it: the side to move and file of the leading pawn that pick the subtable, and the
+/// squares and codes of the pieces, leading pawns first, with the colors swapped if the table has them the other way.
+struct Location {
+    side: usize,
+    file: usize,
+    squares: Vec<usize>,
+    pieces: Vec<u8>,
+    lead_pawns: usize,
+}
+
+/// Locates a position in a table whose leading pawn, if it has pawns, has the code `lead_pawn`.
+fn locate(info: &Info, lead_pawn: u8, position: &Position) -> Location {
+    let black_to_move = position.side_to_move == Color::Black;
+    let flip = if info.symmetric { black_to_move } else { Material::of(position) != info.material };
+    let (flip_color, flip_squares) = if flip { (8, 56) } else { (0, 0) };
+
+    let mut squares = Vec::with_capacity(info.piece_count);
+    let mut pieces = Vec::with_capacity(info.piece_count);
+    let mut lead_pawns = 0;
+    let mut file = 0;
+
+    if info.has_pawns {
+        let color = if (lead_pawn ^ flip_color) & 8 != 0 { Color::Black } else { Color::White };
+        lead_pawns = position.bitboards[piece::piece_id(&Piece::Pawn, &color) as usize];
+        let mut pawns = lead_pawns;
+        while pawns != 0 {
+            squares.push(attacks::pop_lsb(&mut pawns) as usize ^ flip_squares);
+            pieces.push(lead_pawn);
+        }
+
+        let pawn_squares = &indexing().pawns;
+        let first = (0..squares.len()).max_by_key(|i| pawn_squares[squares[*i]]).unwrap();
+        squares.swap(0, first);
+        file = if squares[0] % 8 > 3 { (squares[0] ^ 7) % 8 } else { squares[0] % 8 };
+    }
+    let lead_pawn_count = squares.len();
+
+    let mut others = position.occupation_bitboard ^ lead_pawns;
+    while others != 0 {
+        let square = attacks::pop_lsb(&mut others);
+        let (piece, color) = position::get_piece_at(position, square);
+        squares.push(square as usize ^ flip_squares);
+        pieces.push(piece_code(piece, color) ^ flip_color);
+    }
+
+    Location { side: flip as usize ^ black_to_move as usize, file, squares, pieces, lead_pawns: lead_pawn_count }
+}
+
+/// One subtable's compressed values.
+struct Pairs {
+    encoding: Encoding,
+    flags: u8,
+    single_value: Option<u16>,
+    block_size: usize,
+    span: u64,
+    sparse_entries: u64,
+    block_count: usize,
+    block_length_count: usize,
+    min_length: usize,
+    /// The first symbol of each code length, shortest first, and the smallest code of that length shifted to the top
+    /// of a u64.
+    lowest_symbols: Vec<u16>,
+    bases: Vec<u64>,
+    /// The left and right halves of each symbol, with `LEAF` on the right of one that stands for a value.
+    symbols: Vec<(u16, u16)>,
+    /// How many values each symbol stands for, less one.
+    symbol_lengths: Vec<u32>,
+    // Where the sparse index, block lengths and blocks start in the file.
+    sparse_index: usize,
+    block_lengths: usize,
+    data: usize,
+    /// Where the DTZ maps for each result start, when the values are mapped.
+    maps: [usize; 4],
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, count: usize) -> Result<&[u8], String> {
+        let bytes = self.bytes.get(self.offset..self.offset + count).ok_or("The table is cut short.")?;
+        self.offset += count;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn align(&mut self, alignment: usize) {
+        self.offset = self.offset.next_multiple_of(alignment);
+    }
+}
+
+/// Big endian bytes at an offset, reading zeros past the end of the file: the decoder reads a little ahead of the
+/// last code it needs.
+fn bytes_at<const N: usize>(bytes: &[u8], offset: usize) -> [u8; N] {
+    let mut read = [0; N];
+    for (i, byte) in read.iter_mut().enumerate() {
+        *byte = bytes.get(offset + i).copied().unwrap_or(0);
+    }
+    read
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(offset..offset + 2)?.try_into().unwrap()))
+}
+
+/// A table file read into memory.
+struct Contents {
+    bytes: Vec<u8>,
+    sides: usize,
+    /// In the order of the file: by the leading pawn's file, then by side to move.
+    subtables: Vec<Pairs>,
+}
+
+impl Contents {
+    fn read(bytes: Vec<u8>, info: &Info, is_dtz: bool) -> Result<Contents, String> {
+        let mut reader = Reader { bytes: &bytes, offset: 0 };
+        if reader.take(4)? != if is_dtz { DTZ_MAGIC } else { WDL_MAGIC } {
+            return Err("Not a Syzygy table.".to_string());
+        }
+        let flags = reader.u8()?;
+        if (flags & HAS_PAWNS != 0) != info.has_pawns || (flags & SPLIT != 0) == info.symmetric {
+            return Err("The table doesn't have the pieces its name says.".to_string());
+        }
+
+        let sides = info.sides(is_dtz);
+        let both_pawns = info.pawn_counts[1] > 0;
+        let mut encodings = Vec::new();
+        for file in 0..info.files() {
+            let orders = reader.take(if both_pawns { 2 } else { 1 })?.to_vec();
+            let pieces = reader.take(info.piece_count)?.to_vec();
+            for side in 0..sides {
+                let nibble = |byte: u8| if side == 0 { byte & 0xf } else { byte >> 4 };
+                let order = [nibble(orders[0]) as usize, if both_pawns { nibble(orders[1]) as usize } else { 0xf }];
+                encodings.push(Encoding::new(info, pieces.iter().map(|piece| nibble(*piece)).collect(), order, file)?);
+            }
+        }
+        reader.align(2);
+
+        let mut subtables = encodings.into_iter().map(|encoding| read_sizes(&mut reader, encoding)).collect::<Result<Vec<Pairs>, String>>()?;
+
+        if is_dtz {
+            for pairs in subtables.iter_mut().filter(|pairs| pairs.flags & MAPPED != 0) {
+                if pairs.flags & WIDE != 0 {
+                    reader.align(2);
+                    for map in &mut pairs.maps {
+                        *map = reader.offset + 2;
+                        let length = reader.u16()? as usize;
+                        reader.take(2 * length)?;
+                    }
+                } else {
+                    for map in &mut pairs.maps {
+                        *map = reader.offset + 1;
+                        let length = reader.u8()? as usize;
+                        reader.take(length)?;
+                    }
+                }
+            }
+            reader.align(2);
+        }
+
+        for pairs in &mut subtables {
+            pairs.sparse_index = reader.offset;
+            reader.take(pairs.sparse_entries as usize * 6)?;
+        }
+        for pairs in &mut subtables {
+            pairs.block_lengths = reader.offset;
+            reader.take(pairs.block_length_count * 2)?;
+        }
+        for pairs in &mut subtables {
+            reader.align(64);
+            pairs.data = reader.offset;
+            reader.offset += pairs.block_count * pairs.block_size;
+        }
+        if subtables.iter().any(|pairs| pairs.block_count > 0 && pairs.data >= bytes.len()) {
+            return Err("The table is cut short.".to_string());
+        }
+
+        Ok(Contents { bytes, sides, subtables })
+    }
+
+    fn subtable(&self, side: usize, file: usize) -> Option<&Pairs> {
+        self.subtables.get(file * self.sides + side)
+    }
+
+    /// The code of the leading pawn, or of whatever leads in a table without pawns.
+    fn lead_pawn(&self) -> u8 {
+        self.subtables[0].encoding.pieces[0]
+    }
+
+    /// The value at an index, or `None` if the file is broken.
+    fn value(&self, pairs: &Pairs, index: u64) -> Option<u16> {
+        if let Some(value) = pairs.single_value {
+            return Some(value);
+        }
+
+        let bytes = &self.bytes;
+        let sparse = index / pairs.span;
+        if sparse >= pairs.sparse_entries {
+            return None;
+        }
+        let entry = pairs.sparse_index + 6 * sparse as usize;
+        let mut block = u32::from_le_bytes(bytes.get(entry..entry + 4)?.try_into().unwrap()) as usize;
+        let mut offset = u16_at(bytes, entry + 4)? as i64 + (index % pairs.span) as i64 - (pairs.span / 2) as i64;
+
+        let block_length = |block: usize| -> Option<i64> {
+            Some(u16_at(bytes, pairs.block_lengths + 2 * block).filter(|_| block < pairs.block_length_count)? as i64)
+        };
+        while offset < 0 {
+            block = block.checked_sub(1)?;
+            offset += block_length(block)? + 1;
+        }
+        while offset > block_length(block)? {
+            offset -= block_length(block)? + 1;
+            block += 1;
+        }
+        if block >= pairs.block_count {
+            return None;
+        }
+
+        let mut pointer = pairs.data + block * pairs.block_size;
+        let mut buffer = u64::from_be_bytes(bytes_at(bytes, pointer));
+        pointer += 8;
+        let mut buffer_bits = 64;
+        let mut symbol;
+        loop {
+            let length = pairs.bases.iter().position(|base| buffer >= *base)?;
+            symbol = ((buffer - pairs.bases[length]) >> (64 - length - pairs.min_length)) as usize + pairs.lowest_symbols[length] as usize;
+            let symbol_length = *pairs.symbol_lengths.get(symbol)? as i64;
+            if offset < symbol_length + 1 {
+                break;
+            }
+            offset -= symbol_length + 1;
+
+            let bits = length + pairs.min_length;
+            buffer <<= bits;
+            buffer_bits -= bits;
+            if buffer_bits <= 32 {
+                buffer_bits += 32;
+                buffer |= (u32::from_be_bytes(bytes_at(bytes, pointer)) as u64) << (64 - buffer_bits);
+                pointer += 4;
+            }
+        }
+
+        while pairs.symbol_lengths[symbol] != 0 {
+            let (left, right) = pairs.symbols[symbol];
+            let left_length = pairs.symbol_lengths[left as usize] as i64;
+            if offset < left_length + 1 {
+                symbol = left as usize;
+            } else {
+                offset -= left_length + 1;
+                symbol = right as usize;
+            }
+        }
+        Some(pairs.symbols[symbol].0)
+    }
+}
+
+/// Reads a subtable's flags and Huffman code.
+fn read_sizes(reader: &mut Reader, encoding: Encoding) -> Result<Pairs, String> {
+    let flags = reader.u8()?;
+    let mut pairs = Pairs {
+        encoding,
+        flags,
+        single_value: None,
+        block_size: 0,
+        span: 1,
+        sparse_entries: 0,
+        block_count: 0,
+        block_length_count: 0,
+        min_length: 0,
+        lowest_symbols: Vec::new(),
+        bases: Vec::new(),
+        symbols: Vec::new(),
+        symbol_lengths: Vec::new(),
+        sparse_index: 0,
+        block_lengths: 0,
+        data: 0,
+        maps: [0; 4],
+    };
+    if flags & SINGLE_VALUE != 0 {
+        pairs.single_value = Some(reader.u8()? as u16);
+        return Ok(pairs);
+    }
+
+    let (block_size_log, span_log) = (reader.u8()?, reader.u8()?);
+    if block_size_log > 24 || span_log > 32 {
+        return Err("The table's block sizes are broken.".to_string());
+    }
+    pairs.block_size = 1 << block_size_log;
+    pairs.span = 1 << span_log;
+    pairs.sparse_entries = pairs.encoding.size().div_ceil(pairs.span);
+    let padding = reader.u8()? as usize;
+    pairs.block_count = reader.u32()? as usize;
+    pairs.block_length_count = pairs.block_count + padding;
+
+    let (max_length, min_length) = (reader.u8()? as usize, reader.u8()? as usize);
+    if min_length == 0 || max_length < min_length || max_length > 32 {
+        return Err("The table's code lengths are broken.".to_string());
+    }
+    pairs.min_length = min_length;
+    pairs.lowest_symbols = (min_length..=max_length).map(|_| reader.u16()).collect::<Result<Vec<u16>, String>>()?;
+
+    // The smallest code of each length follows from the one after it and how many symbols that one has.
+    let lengths = pairs.lowest_symbols.len();
+    pairs.bases = vec![0; lengths];
+    for i in (0..lengths - 1).rev() {
+        pairs.bases[i] = pairs.bases[i + 1].wrapping_add(pairs.lowest_symbols[i] as u64).wrapping_sub(pairs.lowest_symbols[i + 1] as u64) / 2;
+    }
+    for (i, base) in pairs.bases.iter_mut().enumerate() {
+        *base <<= 64 - i - min_length;
+    }
+
+    let symbol_count = reader.u16()? as usize;
+    pairs.symbols = (0..symbol_count).map(|_| {
+        let bytes = reader.take(3)?;
+        Ok((((bytes[1] as u16 & 0xf) << 8) | bytes[0] as u16, ((bytes[2] as u16) << 4) | (bytes[1] as u16 >> 4)))
+    }).collect::<Result<Vec<(u16, u16)>, String>>()?;
+    reader.take(symbol_count & 1)?;
+    pairs.symbol_lengths = symbol_lengths(&pairs.symbols)?;
+
+    Ok(pairs)
+}
+
+/// How many values each symbol stands for, less one, worked out without recursing since symbols can nest deeply.
+fn symbol_lengths(symbols: &[(u16, u16)]) -> Result<Vec<u32>, String> {
+    let mut lengths: Vec<Option<u32>> = vec![None; symbols.len()];
+
+    for start in 0..symbols.len() {
+        let mut stack = vec![start];
+        while let Some(&symbol) = stack.last() {
+            if stack.len() > symbols.len() {
+                return Err("The table's symbols go round in a circle.".to_string());
+            }
+            let (left, right) = symbols[symbol];
+            if lengths[symbol].is_some() {
+                stack.pop();
+                continue;
+            }
+            if right == LEAF {
+                lengths[symbol] = Some(0);
+                stack.pop();
+                continue;
+            }
+
+            let (left, right) = (left as usize, right as usize);
+            if left >= symbols.len() || right >= symbols.len() {
+                return Err("A symbol of the table is out of range.".to_string());
+            }
+            match (lengths[left], lengths[right]) {
+                (Some(left), Some(right)) => {
+                    lengths[symbol] = Some(left + right + 1);
+                    stack.pop();
+                }
+                (None, _) => stack.push(left),
+                _ => stack.push(right),
+            }
+        }
+    }
+
+    Ok(lengths.into_iter().map(|length| length.unwrap()).collect())
+}
+
+/// A table file, read the first time a probe needs it.
+struct Table {
+    info: Info,
+    path: PathBuf,
+    is_dtz: bool,
+    /// `None` when the file couldn't be read or is broken, which makes probes of it fail.
+    contents: OnceLock<Option<Contents>>,
+}
+
+impl Table {
+    fn contents(&self) -> Option<&Contents> {
+        self.contents.get_or_init(|| fs::read(&self.path).ok().and_then(|bytes| Contents::read(bytes, &self.info, self.is_dtz).ok())).as_ref()
+    }
+}
+
+/// What a DTZ table has for a position.
+enum Stored {
+    Plies(i32),
+    /// The table keeps the other side to move.
+    OtherSide,
+}
+
+/// Syzygy tables read from a `SyzygyPath`, probed in the search through `Tablebase`.
+#[derive(Default)]
+pub struct SyzygyTablebase {
+    wdl: HashMap<Material, Table>,
+    dtz: HashMap<Material, Table>,
+    max_pieces: usize,
+}
+
+impl SyzygyTablebase {
+    /// Finds the tables on a `SyzygyPath`. Files are only read once a position needs them.
+    pub fn load(syzygy_path: &str) -> Result<SyzygyTablebase, String> {
+        let files = find_tables(syzygy_path)?;
+        let mut tablebase = SyzygyTablebase::default();
+
+        for (paths, is_dtz) in [(files.wdl, false), (files.dtz, true)] {
+            for path in paths {
+                let Some(material) = path.file_stem().and_then(|stem| stem.to_str()).and_then(Material::parse) else { continue };
+                if !is_dtz {
+                    tablebase.max_pieces = tablebase.max_pieces.max(material.piece_count());
+                }
+
+                let tables = if is_dtz { &mut tablebase.dtz } else { &mut tablebase.wdl };
+                tables.insert(material.clone(), Table { info: Info::new(&material), path, is_dtz, contents: OnceLock::new() });
+            }
+        }
+
+        Ok(tablebase)
+    }
+
+    /// The number of WDL and DTZ tables.
+    pub fn table_counts(&self) -> (usize, usize) {
+        (self.wdl.len(), self.dtz.len())
+    }
+
+    /// The table of a position's ending, whichever way around the file has it.
+    fn table<'a>(tables: &'a HashMap<Material, Table>, position: &Position) -> Option<&'a Table> {
+        let material = Material::of(position);
+        tables.get(&material).or_else(|| tables.get(&material.flipped()))
+    }
+
+    /// The result the WDL table has, which can be wrong when a capture is best or en passant is possible.
+    fn table_wdl(&self, position: &Position) -> Option<Wdl> {
+        if position.occupation_bitboard.count_ones() == 2 {
+            return Some(Wdl::Draw);
+        }
+
+        let table = Self::table(&self.wdl, position)?;
+        let contents = table.contents()?;
+        let location = locate(&table.info, contents.lead_pawn(), position);
+        let pairs = contents.subtable(location.side, location.file)?;
+        let index = pairs.encoding.index(&table.info, location);
+
+        match contents.value(pairs, index)? {
+            0 => Some(Wdl::Loss),
+            1 => Some(Wdl::BlessedLoss),
+            2 => Some(Wdl::Draw),
+            3 => Some(Wdl::CursedWin),
+            4 => Some(Wdl::Win),
+            _ => None,
+        }
+    }
+
+    /// The plies to zeroing the DTZ table has for a position with the result `wdl`, always positive.
+    fn table_dtz(&self, position: &Position, wdl: Wdl) -> Option<Stored> {
+        let table = Self::table(&self.dtz, position)?;
+        let contents = table.contents()?;
+        let location = locate(&table.info, contents.lead_pawn(), position);
+        let pairs = contents.subtable(0, location.file)?;
+        if (pairs.flags & STM) as usize != location.side && (table.info.has_pawns || !table.info.symmetric) {
+            return Some(Stored::OtherSide);
+        }
+
+        let index = pairs.encoding.index(&table.info, location);
+        let mut value = contents.value(pairs, index)? as usize;
+        if pairs.flags & MAPPED != 0 {
+            let map = pairs.maps[match wdl { Wdl::Loss => 1, Wdl::BlessedLoss => 3, Wdl::CursedWin => 2, _ => 0 }];
+            value = if pairs.flags & WIDE != 0 { u16_at(&contents.bytes, map + 2 * value)? as usize } else { *contents.bytes.get(map + value)? as usize };
+        }
+
+        // Values are in moves unless the flags say plies, and cursed ones always are.
+        let in_moves = match wdl {
+            Wdl::Win => pairs.flags & WIN_PLIES == 0,
+            Wdl::Loss => pairs.flags & LOSS_PLIES == 0,
+            _ => true,
+        };
+        Some(Stored::Plies(if in_moves { 2 * value as i32 } else { value as i32 } + 1))
+    }
+
+    /// The result of a position from its captures, and with `zeroing` its pawn moves too, along with the table,
+    /// which only needs to be right when none of those are best. Also whether one of them is the best move.
+    fn search(&self, position: &Position, zeroing: bool) -> Option<(Wdl, bool)> {
+        let legal_moves = moves::get_legal_moves(position);
+        let mut best = Wdl::Loss;
+        let mut searched = 0;
+
+        for mv in &legal_moves {
+            let mut child = position.clone();
+            moves::make_move(&mut child, mv);
+            let is_capture = child.occupation_bitboard.count_ones() < position.occupation_bitboard.count_ones();
+            let is_pawn_move = position::get_piece_at(position, mv.from).0 == Piece::Pawn;
+            if !(is_capture || zeroing && is_pawn_move) {
+                continue;
+            }
+            searched += 1;
+
+            let result = self.search(&child, false)?.0.flip();
+            if result > best {
+                best = result;
+                if result == Wdl::Win {
+                    return Some((result, true));
+                }
+            }
+        }
+
+        let no_more_moves = searched > 0 && searched == legal_moves.len();
+        let result = if no_more_moves { best } else { self.table_wdl(position)? };
+        if best >= result {
+            return Some((best, best > Wdl::Draw || no_more_moves));
+        }
+        Some((result, false))
+    }
+}
+
+/// Plies to zeroing right before a zeroing move with the result `wdl`.
+fn before_zeroing(wdl: Wdl) -> i32 {
+    match wdl {
+        Wdl::Loss => -1,
+        Wdl::BlessedLoss => -101,
+        Wdl::Draw => 0,
+        Wdl::CursedWin => 101,
+        Wdl::Win => 1,
+    }
+}
+
+fn sign(wdl: Wdl) -> i32 {
+    match wdl {
+        Wdl::Loss | Wdl::BlessedLoss => -1,
+        Wdl::Draw => 0,
+        _ => 1,
+    }
+}
+
+impl Tablebase for SyzygyTablebase {
+    fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    fn probe_wdl(&self, position: &Position) -> Option<Wdl> {
+        self.search(position, false).map(|(wdl, _)| wdl)
+    }
+
+    /// Cursed wins and blessed losses are 100 plies further than the table's value, so they sort after the wins
+    /// and losses the fifty move rule allows.
+    fn probe_dtz(&self, position: &Position) -> Option<i32> {
+        let (wdl, zeroing_is_best) = self.search(position, true)?;
+        if wdl == Wdl::Draw {
+            return Some(0);
+        }
+        if zeroing_is_best {
+            return Some(before_zeroing(wdl));
+        }
+
+        if let Stored::Plies(plies) = self.table_dtz(position, wdl)? {
+            let cursed = if matches!(wdl, Wdl::CursedWin | Wdl::BlessedLoss) { 100 } else { 0 };
+            return Some((plies + cursed) * sign(wdl));
+        }
+
+        // The table has the other side to move, so this side's value is a ply more than its best move's.
+        let mut best: Option<i32> = None;
+        for mv in moves::get_legal_moves(position) {
+            let mut child = position.clone();
+            moves::make_move(&mut child, &mv);
+            let is_zeroing = child.occupation_bitboard.count_ones() < position.occupation_bitboard.count_ones() || position::get_piece_at(position, mv.from).0 == Piece::Pawn;
+
+            let mut plies = if is_zeroing { -before_zeroing(self.search(&child, false)?.0) } else { -self.probe_dtz(&child)? };
+            if plies == 1 && position::is_in_check(&child, &child.side_to_move) && moves::get_legal_moves(&child).is_empty() {
+                best = Some(1);
+            }
+            if !is_zeroing {
+                plies += plies.signum();
+            }
+            if plies.signum() == sign(wdl) && best.is_none_or(|best| plies < best) {
+                best = Some(plies);
+            }
+        }
+        Some(best.unwrap_or(-1))
+    }
+}
+
+/// The file of a Syzygy table for an ending, from the value `value` gives each of its positions: the result plus 2
+/// in a WDL table, from 0 for a loss to 4 for a win, and in a DTZ table the plies to zeroing less one, which is only
+/// asked for White to move, or with the same pieces on both sides for either. `None` is for values that don't
+/// matter, like the DTZ of a draw. Positions that share an index have to share their value too.
+pub fn write_table(material: &Material, is_dtz: bool, value: impl Fn(&Position) -> Option<u16>) -> Result<Vec<u8>, String> {
+    let info = Info::new(material);
+    let pieces = writing_order(&info);
+    let both_pawns = info.pawn_counts[1] > 0;
+    let order = [0, if both_pawns { 1 } else { 0xf }];
+    let sides = info.sides(is_dtz);
+
+    let mut encodings = Vec::new();
+    for file in 0..info.files() {
+        for _ in 0..sides {
+            encodings.push(Encoding::new(&info, pieces.clone(), order, file)?);
+        }
+    }
+
+    let mut values: Vec<Vec<Option<u16>>> = encodings.iter().map(|encoding| vec![None; encoding.size() as usize]).collect();
+    for_each_position(material, &mut |position| {
+        let Some(value) = value(position) else { return Ok(()) };
+        let location = locate(&info, pieces[0], position);
+        if location.side >= sides {
+            return Ok(());
+        }
+
+        let subtable = location.file * sides + location.side;
+        let index = encodings[subtable].index(&info, location) as usize;
+        let slot = values[subtable].get_mut(index).ok_or_else(|| format!("{} is indexed past the end of its table.", fen::fen_from_position(position)))?;
+        if slot.is_some_and(|existing| existing != value) {
+            return Err(format!("{} shares its index with a position whose value isn't the same.", fen::fen_from_position(position)));
+        }
+        *slot = Some(value);
+        Ok(())
+    })?;
+
+    let flags = if is_dtz { WIN_PLIES | LOSS_PLIES } else { 0 };
+    let compressed = values.iter().map(|values| compress(values, flags)).collect::<Result<Vec<Compressed>, String>>()?;
+
+    let mut bytes = Vec::new();
+    bytes.extend(if is_dtz { DTZ_MAGIC } else { WDL_MAGIC });
+    bytes.push(if info.symmetric { 0 } else { SPLIT } | if info.has_pawns { HAS_PAWNS } else { 0 });
+    for _ in 0..info.files() {
+        bytes.push((order[0] | order[0] << 4) as u8);
+        if both_pawns {
+            bytes.push((order[1] | order[1] << 4) as u8);
+        }
+        bytes.extend(pieces.iter().map(|piece| piece | piece << 4));
+    }
+    pad(&mut bytes, 2);
+
+    for subtable in &compressed {
+        bytes.extend(&subtable.sizes);
+    }
+    if is_dtz {
+        pad(&mut bytes, 2);
+    }
+    for subtable in &compressed {
+        bytes.extend(&subtable.sparse_index);
+    }
+    for subtable in &compressed {
+        bytes.extend(&subtable.block_lengths);
+    }
+    for subtable in &compressed {
+        pad(&mut bytes, 64);
+        bytes.extend(&subtable.data);
+    }
+    Ok(bytes)
+}
+
+fn pad(bytes: &mut Vec<u8>, alignment: usize) {
+    bytes.resize(bytes.len().next_multiple_of(alignment), 0);
+}
+
+/// The order the writer lists the pieces in: the leading pawns, then the other side's pawns, then the rest with the
+/// kings first. Without pawns the kings lead, along with a unique piece if there is one.
+fn writing_order(info: &Info) -> Vec<u8> {
+    let material = &info.material;
+    let mut rest: Vec<u8> = material.white.iter().map(|piece| piece_code(*piece, Color::White))
+        .chain(material.black.iter().map(|piece| piece_code(*piece, Color::Black)))
+        .collect();
+    rest.sort_unstable();
+
+    let mut pieces = Vec::new();
+    if info.has_pawns {
+        let lead_pawn = piece_code(Piece::Pawn, info.lead_color);
+        let other_pawn = piece_code(Piece::Pawn, piece::opponent_of(&info.lead_color));
+        pieces.extend(rest.iter().filter(|piece| **piece == lead_pawn));
+        pieces.extend(rest.iter().filter(|piece| **piece == other_pawn));
+        rest.retain(|piece| *piece != lead_pawn && *piece != other_pawn);
+        pieces.extend([piece_code(Piece::King, Color::White), piece_code(Piece::King, Color::Black)]);
+    } else {
+        pieces.extend([piece_code(Piece::King, Color::White), piece_code(Piece::King, Color::Black)]);
+        if info.has_unique_pieces {
+            let unique = rest.iter().position(|piece| rest.iter().filter(|other| *other == piece).count() == 1).unwrap();
+            pieces.push(rest.remove(unique));
+        }
+    }
+    pieces.extend(rest);
+    pieces
+}
+
+/// Calls `visit` with every legal position of an ending, with either side to move.
+fn for_each_position(material: &Material, visit: &mut impl FnMut(&Position) -> Result<(), String>) -> Result<(), String> {
+    let pieces: Vec<(Piece, Color)> = [(Piece::King, Color::White), (Piece::King, Color::Black)].into_iter()
+        .chain(material.white.iter().map(|piece| (*piece, Color::White)))
+        .chain(material.black.iter().map(|piece| (*piece, Color::Black)))
+        .collect();
+    place(&pieces, &mut Position::new(), visit)
+}
+
+fn place(pieces: &[(Piece, Color)], position: &mut Position, visit: &mut impl FnMut(&Position) -> Result<(), String>) -> Result<(), String> {
+    let Some(((piece, color), rest)) = pieces.split_first() else {
+        for side in [Color::White, Color::Black] {
+            position.side_to_move = side;
+            if !position::is_in_check(position, &piece::opponent_of(&side)) {
+                visit(position)?;
+            }
+        }
+        return Ok(());
+    };
+
+    for square in 0..64 {
+        if position::is_square_occupied(position, square) || (*piece == Piece::Pawn && !(8..56).contains(&square)) {
+            continue;
+        }
+        position::set_piece_at(position, square, *piece, color);
+        place(rest, position, visit)?;
+        position::remove_piece_at(position, square);
+    }
+    Ok(())
+}
+
+/// A subtable's parts of the file, which go in different sections.
+struct Compressed {
+    sizes: Vec<u8>,
+    sparse_index: Vec<u8>,
+    block_lengths: Vec<u8>,
+    data: Vec<u8>,
+}
+
+/// Huffman codes a subtable's values, with the values that don't matter set to the most common one.
+fn compress(values: &[Option<u16>], flags: u8) -> Result<Compressed, String> {
+    let mut counts: BTreeMap<u16, u64> = BTreeMap::new();
+    for value in values.iter().flatten() {
+        *counts.entry(*value).or_default() += 1;
+    }
+    let common = counts.iter().max_by_key(|(value, count)| (**count, Reverse(**value))).map_or(0, |(value, _)| *value);
+
+    if counts.len() <= 1 {
+        let value = u8::try_from(common).map_err(|_| format!("The value {} is too big for a table.", common))?;
+        return Ok(Compressed { sizes: vec![flags | SINGLE_VALUE, value], sparse_index: Vec::new(), block_lengths: Vec::new(), data: Vec::new() });
+    }
+    if let Some(value) = counts.keys().find(|value| **value >= LEAF) {
+        return Err(format!("The value {} is too big for a table.", value));
+    }
+
+    // Symbols are numbered from the longest code to the shortest, and the codes of each length count up with them
+    // from the smallest the codes after them leave free.
+    let lengths = code_lengths(&counts);
+    let mut symbols: Vec<u16> = counts.keys().copied().collect();
+    symbols.sort_by_key(|value| (Reverse(lengths[value]), *value));
+    let max_length = lengths[&symbols[0]];
+    let min_length = lengths[symbols.last().unwrap()];
+
+    let mut codes: HashMap<u16, (u64, usize)> = HashMap::new();
+    let mut lowest_symbols = vec![0u16; max_length - min_length + 1];
+    let mut base = 0u64;
+    let mut next_symbol = 0;
+    for length in (min_length..=max_length).rev() {
+        if length < max_length {
+            base = (base + symbols.iter().filter(|value| lengths[value] == length + 1).count() as u64) / 2;
+        }
+        lowest_symbols[length - min_length] = next_symbol as u16;
+        for (offset, value) in symbols.iter().filter(|value| lengths[value] == length).enumerate() {
+            codes.insert(*value, (base + offset as u64, length));
+            next_symbol += 1;
+        }
+    }
+
+    let block_size = 1usize << BLOCK_SIZE_LOG;
+    let mut data: Vec<u8> = Vec::new();
+    let mut block_starts: Vec<usize> = Vec::new();
+    let mut bits = 0;
+    for (index, value) in values.iter().enumerate() {
+        let (code, length) = codes[&value.unwrap_or(common)];
+        if block_starts.is_empty() || bits + length > block_size * 8 {
+            block_starts.push(index);
+            data.resize(block_starts.len() * block_size, 0);
+            bits = 0;
+        }
+
+        let block = (block_starts.len() - 1) * block_size;
+        for bit in (0..length).rev() {
+            if code >> bit & 1 != 0 {
+                data[block + bits / 8] |= 0x80 >> (bits % 8);
+            }
+            bits += 1;
+        }
+    }
+
+    let mut block_lengths = Vec::new();
+    for (block, start) in block_starts.iter().enumerate() {
+        let end = block_starts.get(block + 1).copied().unwrap_or(values.len());
+        block_lengths.extend(((end - start - 1) as u16).to_le_bytes());
+    }
+
+    // Each entry points at the value in the middle of its span, or past the last block's end for the last span.
+    let span = 1usize << SPAN_LOG;
+    let mut sparse_index = Vec::new();
+    for entry in 0..values.len().div_ceil(span) {
+        let target = entry * span + span / 2;
+        let block = block_starts.partition_point(|start| *start <= target) - 1;
+        let offset = u16::try_from(target - block_starts[block]).map_err(|_| "A block is too long for the sparse index.".to_string())?;
+        sparse_index.extend((block as u32).to_le_bytes());
+        sparse_index.extend(offset.to_le_bytes());
+    }
+
+    let mut sizes = vec![flags, BLOCK_SIZE_LOG, SPAN_LOG, 0];
+    sizes.extend((block_starts.len() as u32).to_le_bytes());
+    sizes.extend([max_length as u8, min_length as u8]);
+    for lowest in lowest_symbols {
+        sizes.extend(lowest.to_le_bytes());
+    }
+    sizes.extend((symbols.len() as u16).to_le_bytes());
+    for value in &symbols {
+        sizes.extend([*value as u8, (value >> 8) as u8 | 0xf0, 0xff]);
+    }
+    if symbols.len() % 2 == 1 {
+        sizes.push(0);
+    }
+
+    Ok(Compressed { sizes, sparse_index, block_lengths, data })
+}
+
+/// Huffman code lengths for values with these counts, flattened until none is longer than `MAX_CODE_LENGTH`.
+fn code_lengths(counts: &BTreeMap<u16, u64>) -> HashMap<u16, usize> {
+    let mut weights: Vec<u64> = counts.values().copied().collect();
+    loop {
+        // The leaves come first, then each pair merged from the two lightest nodes.
+        let mut heap: BinaryHeap<Reverse<(u64, usize)>> = weights.iter().enumerate().map(|(node, weight)| Reverse((*weight, node))).collect();
+        let mut parents = vec![usize::MAX; weights.len()];
+        while heap.len() > 1 {
+            let Reverse((first_weight, first)) = heap.pop().unwrap();
+            let Reverse((second_weight, second)) = heap.pop().unwrap();
+            parents[first] = parents.len();
+            parents[second] = parents.len();
+            heap.push(Reverse((first_weight + second_weight, parents.len())));
+            parents.push(usize::MAX);
+        }
+
+        let lengths: Vec<usize> = (0..weights.len()).map(|leaf| {
+            let (mut node, mut length) = (leaf, 0);
+            while parents[node] != usize::MAX {
+                node = parents[node];
+                length += 1;
+            }
+            length
+        }).collect();
+
+        if lengths.iter().all(|length| *length <= MAX_CODE_LENGTH) {
+            return counts.keys().copied().zip(lengths).collect();
+        }
+        weights.iter_mut().for_each(|weight| *weight = *weight / 2 + 1);
+    }
+}
+
+// Unit Tests! //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::dtm::{self, DtmTablebase};
+
+    #[test]
+    fn test_find_tables() {
+        let directory = env::temp_dir().join(format!("macbeth-syzygy-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("KQvK.rtbw"), WDL_MAGIC).unwrap();
+        fs::write(directory.join("KRPvKR.rtbz"), DTZ_MAGIC).unwrap();
+        fs::write(directory.join("KBNvK.rtbw"), b"not a table").unwrap();
+        fs::write(directory.join("notes.txt"), WDL_MAGIC).unwrap();
+
+        let files = find_tables(directory.to_str().unwrap()).expect("The directory should be readable.");
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(files.wdl, vec![directory.join("KQvK.rtbw")]);
+        assert_eq!(files.dtz, vec![directory.join("KRPvKR.rtbz")]);
+        assert_eq!(files.max_pieces, 5);
+
+        assert!(find_tables("/no/such/directory").is_err());
+        assert_eq!(piece_count("KvK"), Some(2));
+        assert_eq!(piece_count("KQK"), None);
+    }
+
+    #[test]
+    fn test_indexing() {
+        let indexing = indexing();
+        assert_eq!(indexing.kings.iter().flatten().max(), Some(&461), "There should be 462 ways to place the kings.");
+        assert_eq!(indexing.binomial[2][5], 10);
+        assert_eq!(indexing.lead_pawns_size[1], [6; 4], "A single leading pawn should have 6 squares on each file.");
+
+        let mut pawns: Vec<u64> = (8..56).map(|square| indexing.pawns[square]).collect();
+        pawns.sort_unstable();
+        assert_eq!(pawns, (0..48).collect::<Vec<u64>>(), "Pawn squares should be numbered 0 to 47.");
+
+        let size = |signature: &str| {
+            let info = Info::new(&Material::parse(signature).unwrap());
+            Encoding::new(&info, writing_order(&info), [0, if info.pawn_counts[1] > 0 { 1 } else { 0xf }], 0).unwrap().size()
+        };
+        assert_eq!(size("KQvK"), 31332);
+        assert_eq!(size("KRvKN"), 31332 * 61);
+        assert_eq!(size("KNNvK"), 462 * 1891);
+        assert_eq!(size("KPvK"), 6 * 63 * 62);
+        assert_eq!(size("KPvKP"), 6 * 47 * 62 * 61);
+    }
+
+    /// The plies to zeroing a won or lost position should have, from the tables' values of the positions after it.
+    fn dtz_from_moves(tablebase: &SyzygyTablebase, position: &Position, wdl: Wdl) -> i32 {
+        let mut best: Option<i32> = None;
+        for mv in moves::get_legal_moves(position) {
+            let mut child = position.clone();
+            moves::make_move(&mut child, &mv);
+
+            let plies = if child.occupation_bitboard.count_ones() < position.occupation_bitboard.count_ones() || position::get_piece_at(position, mv.from).0 == Piece::Pawn {
+                sign(tablebase.probe_wdl(&child).unwrap().flip())
+            } else if moves::get_legal_moves(&child).is_empty() && position::is_in_check(&child, &child.side_to_move) {
+                1
+            } else {
+                let plies = -tablebase.probe_dtz(&child).unwrap();
+                plies + plies.signum()
+            };
+            if plies.signum() == sign(wdl) && best.is_none_or(|best| plies < best) {
+                best = Some(plies);
+            }
+        }
+        best.unwrap_or(-1)
+    }
+
+    /// Generates the DTM tables of some endings, in an order where captures and promotions lead to earlier ones,
+    /// and writes Syzygy tables for them into a new directory.
+    fn generate_tables(name: &str, signatures: &[&str]) -> (PathBuf, DtmTablebase) {
+        let directory = env::temp_dir().join(format!("macbeth-syzygy-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+
+        let mut dtm_tables = DtmTablebase::default();
+        for signature in signatures {
+            let material = Material::parse(signature).unwrap();
+            dtm_tables.generate(&material).expect("The endings should come after the ones they lead to.");
+            dtm::write_syzygy(&material, &dtm_tables, &directory).expect("The Syzygy tables should be written.");
+        }
+        (directory, dtm_tables)
+    }
+
+    /// Probes one position in `stride` of each ending, and checks the results against the DTM tables and the
+    /// distances to zeroing against the positions after them. With a single piece besides the kings and no pawns those
+    /// are the distances to mate too, since neither side can capture in a won or lost position.
+    fn check_probes(tablebase: &SyzygyTablebase, dtm_tables: &DtmTablebase, signatures: &[&str], stride: usize) {
+        for signature in signatures {
+            let material = Material::parse(signature).unwrap();
+            let mut positions = 0;
+            for_each_position(&material, &mut |position| {
+                positions += 1;
+                if positions % stride != 0 {
+                    return Ok(());
+                }
+
+                let fen = fen::fen_from_position(position);
+                let wdl = tablebase.probe_wdl(position).expect("Every position should be in the tables.");
+                assert_eq!(Some(wdl), dtm_tables.probe_wdl(position), "{} should have the result the DTM tables have.", fen);
+                if wdl == Wdl::Draw {
+                    assert_eq!(tablebase.probe_dtz(position), Some(0), "{} is a draw.", fen);
+                    return Ok(());
+                }
+
+                let dtz = tablebase.probe_dtz(position).expect("Every position should be in the tables.");
+                if material.piece_count() == 3 && !signature.contains('P') {
+                    assert_eq!(dtz.abs(), dtm_tables.probe_dtm(position).unwrap().max(1), "{} should be as far from zeroing as from mate.", fen);
+                }
+                if positions % (stride * 8) == 0 {
+                    assert_eq!(dtz, dtz_from_moves(tablebase, position, wdl), "{} should be one ply further from zeroing than its best move.", fen);
+                }
+                Ok(())
+            }).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_reads_generated_tables() {
+        let (directory, dtm_tables) = generate_tables("kqk", &["KQvK"]);
+        let tablebase = SyzygyTablebase::load(directory.to_str().unwrap()).expect("The tables should be found.");
+        assert_eq!(tablebase.table_counts(), (1, 1));
+        assert_eq!(tablebase.max_pieces(), 3);
+
+        check_probes(&tablebase, &dtm_tables, &["KQvK", "KvKQ"], 127);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_reads_generated_tables_with_pawns() {
+        let (directory, dtm_tables) = generate_tables("kpk", &["KQvK", "KRvK", "KBvK", "KNvK", "KPvK"]);
+        let tablebase = SyzygyTablebase::load(directory.to_str().unwrap()).expect("The tables should be found.");
+        assert_eq!(tablebase.max_pieces(), 3);
+
+        check_probes(&tablebase, &dtm_tables, &["KPvK", "KvKP"], 13);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    #[ignore = "generates endings of four pieces, which takes minutes; run it with cargo test --release -- --ignored"]
+    fn test_reads_generated_tables_with_groups() {
+        let signatures = ["KQvK", "KRvK", "KBvK", "KNvK", "KRvKN", "KNNvK"];
+        let (directory, dtm_tables) = generate_tables("groups", &signatures);
+        let tablebase = SyzygyTablebase::load(directory.to_str().unwrap()).expect("The tables should be found.");
+        assert_eq!(tablebase.max_pieces(), 4);
+
+        check_probes(&tablebase, &dtm_tables, &["KRvKN", "KNvKR", "KNNvK", "KvKNN"], 101);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_broken_tables_fail_probes() {
+        let directory = env::temp_dir().join(format!("macbeth-syzygy-broken-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("KQvK.rtbw"), [&WDL_MAGIC[..], &[SPLIT, 0x00, 0x16, 0x9e]].concat()).unwrap();
+
+        let tablebase = SyzygyTablebase::load(directory.to_str().unwrap()).expect("The table should be found.");
+        assert_eq!(tablebase.probe_wdl(&fen::position_from_fen("8/8/8/8/8/8/8/KQ5k w - - 0 1")), None, "A table that's cut short shouldn't be probed.");
+        assert_eq!(tablebase.probe_wdl(&fen::position_from_fen("8/8/8/8/8/8/8/K6k w - - 0 1")), Some(Wdl::Draw), "Bare kings don't need a table.");
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
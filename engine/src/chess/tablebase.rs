@@ -0,0 +1,194 @@
+use std::sync::Arc;
+
+use super::moves::{self, Move};
+use super::position::{self, Position};
+
+/// Win, draw or loss for the side to move with perfect play. A cursed win is a win that takes too long to get to a
+/// capture or pawn move and so is only a draw by the fifty move rule, and a blessed loss is the same from the other
+/// side.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss,
+    BlessedLoss,
+    Draw,
+    CursedWin,
+    Win,
+}
+
+impl Wdl {
+    /// The same result from the other side.
+    pub fn flip(self) -> Wdl {
+        match self {
+            Wdl::Loss => Wdl::Win,
+            Wdl::BlessedLoss => Wdl::CursedWin,
+            Wdl::Draw => Wdl::Draw,
+            Wdl::CursedWin => Wdl::BlessedLoss,
+            Wdl::Win => Wdl::Loss,
+        }
+    }
+}
+
+/// Endgame tables the search can ask about positions with few pieces.
+pub trait Tablebase: Send + Sync {
+    /// The most pieces, kings included, of any table.
+    fn max_pieces(&self) -> usize;
+
+    fn probe_wdl(&self, position: &Position) -> Option<Wdl>;
+
+    /// Plies to the next capture or pawn move with best play, positive when the side to move wins, negative when it
+    /// loses and 0 for a draw.
+    fn probe_dtz(&self, position: &Position) -> Option<i32>;
+
+    /// Plies to mate with best play, for a position that's won or lost. Tables that know it report mates in the
+    /// search instead of bare tablebase wins.
+    fn probe_dtm(&self, _position: &Position) -> Option<i32> {
+        None
+    }
+}
+
+/// The tables from `DtmPath` and `SyzygyPath` together. Distance to mate tables are asked first, so the search
+/// reports mates where they have the ending, and the Syzygy tables answer for the endings they don't.
+#[derive(Clone, Default)]
+pub struct Tablebases {
+    pub dtm: Option<Arc<dyn Tablebase>>,
+    pub syzygy: Option<Arc<dyn Tablebase>>,
+}
+
+impl Tablebases {
+    /// The tables for the search, or `None` when neither option has any.
+    pub fn combined(&self) -> Option<Arc<dyn Tablebase>> {
+        match (&self.dtm, &self.syzygy) {
+            (None, None) => None,
+            (Some(tablebase), None) | (None, Some(tablebase)) => Some(tablebase.clone()),
+            _ => Some(Arc::new(self.clone())),
+        }
+    }
+
+    fn probe(&self, probe: impl Fn(&dyn Tablebase) -> Option<i32>) -> Option<i32> {
+        self.dtm.as_deref().and_then(&probe).or_else(|| self.syzygy.as_deref().and_then(&probe))
+    }
+}
+
+impl Tablebase for Tablebases {
+    fn max_pieces(&self) -> usize {
+        self.dtm.iter().chain(&self.syzygy).map(|tablebase| tablebase.max_pieces()).max().unwrap_or(0)
+    }
+
+    fn probe_wdl(&self, position: &Position) -> Option<Wdl> {
+        self.dtm.as_deref().and_then(|tablebase| tablebase.probe_wdl(position)).or_else(|| self.syzygy.as_deref()?.probe_wdl(position))
+    }
+
+    fn probe_dtz(&self, position: &Position) -> Option<i32> {
+        self.probe(|tablebase| tablebase.probe_dtz(position))
+    }
+
+    fn probe_dtm(&self, position: &Position) -> Option<i32> {
+        self.probe(|tablebase| tablebase.probe_dtm(position))
+    }
+}
+
+/// Whether the tables can have the position: few enough pieces, and no castling rights, which no table has.
+pub fn can_probe(tablebase: &dyn Tablebase, position: &Position) -> bool {
+    position.occupation_bitboard.count_ones() as usize <= tablebase.max_pieces() && !position::has_castling_rights(position)
+}
+
+/// The root moves that keep the best result the tables allow, and that result. When winning only the moves that
+/// mate soonest, or failing that get to the next capture or pawn move soonest, are kept, so the engine makes
+/// progress instead of drifting into a fifty move draw, and when losing the ones that put it off longest. `None`
+/// when the tables don't have every move.
+pub fn filter_root_moves(tablebase: &dyn Tablebase, position: &Position, root_moves: &[Move]) -> Option<(Vec<Move>, Wdl)> {
+    if !can_probe(tablebase, position) {
+        return None;
+    }
+
+    let mut ranked: Vec<(Move, Position, Wdl)> = Vec::with_capacity(root_moves.len());
+    for mv in root_moves {
+        let mut child = position.clone();
+        moves::make_move(&mut child, mv);
+
+        let result = tablebase.probe_wdl(&child)?.flip();
+        ranked.push((*mv, child, result));
+    }
+
+    let best_result = ranked.iter().map(|(_, _, result)| *result).max()?;
+    ranked.retain(|(_, _, result)| *result == best_result);
+    if best_result == Wdl::Draw {
+        return Some((ranked.into_iter().map(|(mv, _, _)| mv).collect(), best_result));
+    }
+
+    let mut distances: Vec<(Move, i32)> = Vec::with_capacity(ranked.len());
+    for (mv, child, _) in &ranked {
+        // A capture or pawn move starts the count again, so it's as close to zeroing as a move can get.
+        let distance = match tablebase.probe_dtm(child) {
+            Some(plies) => plies,
+            None if child.half_move_clock == 0 => 0,
+            None => tablebase.probe_dtz(child)?.abs(),
+        };
+        distances.push((*mv, distance));
+    }
+
+    let best_distance = match best_result {
+        Wdl::Win | Wdl::CursedWin => distances.iter().map(|(_, distance)| *distance).min(),
+        _ => distances.iter().map(|(_, distance)| *distance).max(),
+    };
+    distances.retain(|(_, distance)| Some(*distance) == best_distance);
+
+    Some((distances.into_iter().map(|(mv, _)| mv).collect(), best_result))
+}
+
+// Unit Tests! //
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use super::super::fen;
+    use super::super::piece::{self, Color, Piece};
+
+    /// Pretends to be a tablebase for positions of up to four pieces: whoever has more material wins, and every win
+    /// takes ten plies to the next capture or pawn move.
+    pub struct MaterialTablebase;
+
+    impl Tablebase for MaterialTablebase {
+        fn max_pieces(&self) -> usize {
+            4
+        }
+
+        fn probe_wdl(&self, position: &Position) -> Option<Wdl> {
+            let material = |color: &Color| -> i32 {
+                [(Piece::Pawn, 1), (Piece::Knight, 3), (Piece::Bishop, 3), (Piece::Rook, 5), (Piece::Queen, 9)].iter()
+                    .map(|(piece, value)| position.bitboards[piece::piece_id(piece, color) as usize].count_ones() as i32 * value)
+                    .sum()
+            };
+
+            let balance = material(&position.side_to_move) - material(&piece::opponent_of(&position.side_to_move));
+            Some(match balance {
+                balance if balance > 0 => Wdl::Win,
+                0 => Wdl::Draw,
+                _ => Wdl::Loss,
+            })
+        }
+
+        fn probe_dtz(&self, position: &Position) -> Option<i32> {
+            Some(match self.probe_wdl(position)? {
+                Wdl::Win => 10,
+                Wdl::Loss => -10,
+                _ => 0,
+            })
+        }
+    }
+
+    #[test]
+    fn test_filter_root_moves() {
+        // Every queen move keeps the win, but taking the rook gets there at once.
+        let position = fen::position_from_fen("3r3k/8/8/8/8/8/8/3Q2K1 w - - 0 1");
+        let root_moves = moves::get_legal_moves(&position);
+
+        let (kept, result) = filter_root_moves(&MaterialTablebase, &position, &root_moves).expect("Four pieces are in the tables.");
+        assert_eq!(result, Wdl::Win);
+        assert_eq!(kept.iter().map(|mv| mv.to_string()).collect::<Vec<_>>(), vec!["d1d8"]);
+
+        // Castling rights keep the tables out.
+        let castling = fen::position_from_fen("4k3/8/8/8/8/8/8/R3K3 w Q - 0 1");
+        assert!(filter_root_moves(&MaterialTablebase, &castling, &moves::get_legal_moves(&castling)).is_none());
+    }
+}
@@ -0,0 +1,276 @@
+use std::time::Instant;
+
+use super::moves::Move;
+use super::piece::Color;
+
+/// Moves left in the game to plan for when the time control doesn't say.
+const DEFAULT_MOVES_TO_GO: u64 = 30;
+
+/// Tells the time since the search started. Tests swap in a fake clock to simulate a whole game instantly.
+pub trait Clock: Send {
+    fn elapsed_ms(&self) -> u64;
+}
+
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        SystemClock { start: Instant::now() }
+    }
+}
+
+impl Clock for SystemClock {
+    fn elapsed_ms(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+}
+
+/// The clock situation handed to `go`, all times in milliseconds.
+#[derive(Clone, Debug, Default)]
+pub struct TimeControl {
+    pub white_time: Option<u64>,
+    pub black_time: Option<u64>,
+    pub white_increment: u64,
+    pub black_increment: u64,
+    pub moves_to_go: Option<u64>,
+    pub move_time: Option<u64>,
+}
+
+/// Decides how long to think about a move.
+///
+/// The soft limit is checked between iterations and stretched or shrunk depending on how the search is going;
+/// the hard limit is checked during the search and is never exceeded.
+pub struct TimeManager {
+    pub soft_limit: u64,
+    pub hard_limit: u64,
+    clock: Box<dyn Clock>,
+    previous_best_move: Option<Move>,
+    previous_score: Option<i32>,
+    /// Iterations in a row that ended with the same best move.
+    stable_iterations: u32,
+    /// How often the best move changed recently, decayed every iteration.
+    best_move_changes: f64,
+}
+
+impl TimeManager {
+    /// Creates a time manager for the side to move, or `None` if the time control puts no limit on the search.
+    /// `move_overhead` is kept in reserve for communication lag.
+    pub fn new(control: &TimeControl, side: &Color, move_overhead: u64, clock: Box<dyn Clock>) -> Option<Self> {
+        let (soft_limit, hard_limit) = if let Some(move_time) = control.move_time {
+            let limit = move_time.saturating_sub(move_overhead).max(1);
+            (limit, limit)
+        } else {
+            let (time, increment) = match side {
+                Color::Black => (control.black_time?, control.black_increment),
+                _ => (control.white_time?, control.white_increment),
+            };
+
+            let available = time.saturating_sub(move_overhead).max(1);
+            let moves_to_go = control.moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).clamp(1, 50);
+
+            // Spend an even share of the remaining time plus most of the increment, but never so much of the clock
+            // that a single move can flag.
+            let soft_limit = (available / moves_to_go + increment * 3 / 4).min(available * 6 / 10).max(1);
+            let hard_limit = (soft_limit * 4).min(available * 8 / 10).max(soft_limit);
+
+            (soft_limit, hard_limit)
+        };
+
+        Some(TimeManager {
+            soft_limit,
+            hard_limit,
+            clock,
+            previous_best_move: None,
+            previous_score: None,
+            stable_iterations: 0,
+            best_move_changes: 0.0,
+        })
+    }
+
+    pub fn elapsed(&self) -> u64 {
+        self.clock.elapsed_ms()
+    }
+
+    pub fn hard_limit_reached(&self) -> bool {
+        self.elapsed() >= self.hard_limit
+    }
+
+    /// Called after every completed iteration, with the fraction of the root's nodes spent on the best move.
+    /// Returns whether there is time for another iteration.
+    pub fn should_continue(&mut self, best_move: Option<Move>, score: i32, best_move_effort: f64) -> bool {
+        self.best_move_changes *= 0.5;
+        if self.previous_best_move.is_some() && best_move != self.previous_best_move {
+            self.best_move_changes += 1.0;
+            self.stable_iterations = 0;
+        } else {
+            self.stable_iterations += 1;
+        }
+
+        let score_drop = self.previous_score.map_or(0, |previous_score| previous_score - score);
+        self.previous_best_move = best_move;
+        self.previous_score = Some(score);
+
+        self.elapsed() < self.adjusted_soft_limit(score_drop, best_move_effort)
+    }
+
+    fn adjusted_soft_limit(&self, score_drop: i32, best_move_effort: f64) -> u64 {
+        // Think longer while the best move keeps changing.
+        let instability = 1.0 + self.best_move_changes * 0.5;
+
+        // Think longer when the score is dropping, there may be a problem the search hasn't solved yet.
+        let falling_score = (1.0 + score_drop.clamp(0, 100) as f64 / 100.0).min(2.0);
+
+        // Stop early when one move has been best for a while and nearly all the effort goes into proving it.
+        let dominance = if self.stable_iterations >= 4 && best_move_effort > 0.9 {
+            0.4
+        } else if self.stable_iterations >= 6 {
+            0.7
+        } else {
+            1.0
+        };
+
+        ((self.soft_limit as f64 * instability * falling_score * dominance) as u64).min(self.hard_limit)
+    }
+}
+
+// Unit Tests! //
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use super::super::moves::PromotionOption;
+
+    struct FakeClock(Arc<AtomicU64>);
+
+    impl Clock for FakeClock {
+        fn elapsed_ms(&self) -> u64 {
+            self.0.load(Ordering::Relaxed)
+        }
+    }
+
+    fn fake_manager(control: &TimeControl, side: &Color) -> (TimeManager, Arc<AtomicU64>) {
+        let now = Arc::new(AtomicU64::new(0));
+        let manager = TimeManager::new(control, side, 30, Box::new(FakeClock(now.clone()))).expect("The control should limit time.");
+        (manager, now)
+    }
+
+    fn some_move(to: i32) -> Option<Move> {
+        Some(Move { from: 12, to, promotion: PromotionOption::None })
+    }
+
+    /// Runs iterations that each take twice as long as the last, and returns when the manager stopped.
+    fn simulate(manager: &mut TimeManager, now: &AtomicU64, mut best_move_at: impl FnMut(u32) -> (Option<Move>, i32, f64)) -> u64 {
+        let mut iteration_time = 1;
+
+        for iteration in 0..64 {
+            now.fetch_add(iteration_time, Ordering::Relaxed);
+            iteration_time *= 2;
+
+            let (best_move, score, effort) = best_move_at(iteration);
+            if manager.hard_limit_reached() || !manager.should_continue(best_move, score, effort) {
+                break;
+            }
+        }
+
+        now.load(Ordering::Relaxed).min(manager.hard_limit)
+    }
+
+    #[test]
+    fn test_sudden_death() {
+        let control = TimeControl { white_time: Some(60_000), black_time: Some(1_000), ..Default::default() };
+
+        let (manager, _) = fake_manager(&control, &Color::White);
+        assert_eq!(manager.soft_limit, (60_000 - 30) / DEFAULT_MOVES_TO_GO);
+        assert!(manager.hard_limit > manager.soft_limit && manager.hard_limit <= 60_000 * 8 / 10);
+
+        // Black's clock is what matters when Black is to move.
+        let (manager, _) = fake_manager(&control, &Color::Black);
+        assert_eq!(manager.soft_limit, (1_000 - 30) / DEFAULT_MOVES_TO_GO);
+    }
+
+    #[test]
+    fn test_increment() {
+        let control = TimeControl { white_time: Some(10_000), black_time: Some(10_000), white_increment: 1_000, black_increment: 1_000, ..Default::default() };
+        let (manager, _) = fake_manager(&control, &Color::White);
+        assert_eq!(manager.soft_limit, (10_000 - 30) / DEFAULT_MOVES_TO_GO + 750);
+
+        // Low on time, the increment mustn't make us spend more than what's left on the clock.
+        let control = TimeControl { white_time: Some(500), white_increment: 2_000, ..Default::default() };
+        let (manager, _) = fake_manager(&control, &Color::White);
+        assert!(manager.hard_limit < 500, "Hard limit {} would lose on time.", manager.hard_limit);
+    }
+
+    #[test]
+    fn test_moves_to_go() {
+        let control = TimeControl { white_time: Some(60_000), moves_to_go: Some(20), ..Default::default() };
+        let (manager, _) = fake_manager(&control, &Color::White);
+        assert_eq!(manager.soft_limit, (60_000 - 30) / 20);
+
+        // The last move before the time control can use a good part of the clock, but not all of it.
+        let control = TimeControl { white_time: Some(60_000), moves_to_go: Some(1), ..Default::default() };
+        let (manager, _) = fake_manager(&control, &Color::White);
+        assert!(manager.soft_limit > 30_000 && manager.hard_limit < 60_000);
+    }
+
+    #[test]
+    fn test_move_time() {
+        let control = TimeControl { move_time: Some(1_000), ..Default::default() };
+        let (mut manager, now) = fake_manager(&control, &Color::White);
+        assert_eq!((manager.soft_limit, manager.hard_limit), (970, 970));
+
+        let stopped_at = simulate(&mut manager, &now, |_| (some_move(28), 0, 0.5));
+        assert!(stopped_at <= 970);
+
+        assert!(TimeManager::new(&TimeControl::default(), &Color::White, 30, Box::new(SystemClock::new())).is_none(), "No clock means no time limit.");
+    }
+
+    #[test]
+    fn test_unstable_best_move_thinks_longer() {
+        let control = TimeControl { white_time: Some(120_000), ..Default::default() };
+
+        let (mut stable, now) = fake_manager(&control, &Color::White);
+        let stable_time = simulate(&mut stable, &now, |_| (some_move(28), 20, 0.6));
+
+        let (mut unstable, now) = fake_manager(&control, &Color::White);
+        let unstable_time = simulate(&mut unstable, &now, |iteration| (some_move(20 + (iteration % 2) as i32 * 8), 20, 0.6));
+
+        assert!(unstable_time > stable_time, "Changing best moves took {}ms, a stable one {}ms.", unstable_time, stable_time);
+    }
+
+    #[test]
+    fn test_falling_score_thinks_longer() {
+        let control = TimeControl { white_time: Some(120_000), ..Default::default() };
+
+        let (mut steady, now) = fake_manager(&control, &Color::White);
+        let steady_time = simulate(&mut steady, &now, |_| (some_move(28), 20, 0.6));
+
+        let (mut falling, now) = fake_manager(&control, &Color::White);
+        let falling_time = simulate(&mut falling, &now, |iteration| (some_move(28), 20 - iteration as i32 * 100, 0.6));
+
+        assert!(falling_time > steady_time, "A falling score took {}ms, a steady one {}ms.", falling_time, steady_time);
+    }
+
+    #[test]
+    fn test_dominant_move_stops_early() {
+        let control = TimeControl { white_time: Some(120_000), ..Default::default() };
+
+        let (mut contested, now) = fake_manager(&control, &Color::White);
+        let contested_time = simulate(&mut contested, &now, |_| (some_move(28), 20, 0.5));
+
+        let (mut dominant, now) = fake_manager(&control, &Color::White);
+        let dominant_time = simulate(&mut dominant, &now, |_| (some_move(28), 20, 0.98));
+
+        assert!(dominant_time < contested_time, "A dominant move took {}ms, a contested one {}ms.", dominant_time, contested_time);
+    }
+}
@@ -0,0 +1,178 @@
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+use super::moves::{Move, PromotionOption};
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Bound {
+    None = 0,
+    Exact = 1,
+    /// The score is at least this good (the search failed high).
+    Lower = 2,
+    /// The score is at most this good (the search failed low).
+    Upper = 3,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Entry {
+    pub best_move: Option<Move>,
+    pub score: i32,
+    pub static_eval: i32,
+    pub depth: i32,
+    pub bound: Bound,
+}
+
+/// A fixed size hash table of search results.
+///
+/// Every slot is two atomics, the key stored xored with the data, so the table can be shared between threads
+/// without locks: a slot torn by two simultaneous writes just fails the key check on the next probe.
+pub struct TranspositionTable {
+    slots: Vec<[AtomicU64; 2]>,
+    generation: AtomicU8,
+}
+
+impl TranspositionTable {
+    pub fn new(size_mb: usize) -> Self {
+        let slot_count = (size_mb.max(1) * 1024 * 1024 / std::mem::size_of::<[AtomicU64; 2]>()).max(1);
+
+        TranspositionTable {
+            slots: (0..slot_count).map(|_| [AtomicU64::new(0), AtomicU64::new(0)]).collect(),
+            generation: AtomicU8::new(0),
+        }
+    }
+
+    pub fn clear(&self) {
+        for slot in &self.slots {
+            slot[0].store(0, Ordering::Relaxed);
+            slot[1].store(0, Ordering::Relaxed);
+        }
+        self.generation.store(0, Ordering::Relaxed);
+    }
+
+    /// Marks the start of a new search, so entries from older searches are replaced first.
+    pub fn new_search(&self) {
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn slot(&self, hash: u64) -> &[AtomicU64; 2] {
+        &self.slots[((hash as u128 * self.slots.len() as u128) >> 64) as usize]
+    }
+
+    pub fn probe(&self, hash: u64) -> Option<Entry> {
+        let slot = self.slot(hash);
+        let data = slot[1].load(Ordering::Relaxed);
+
+        if slot[0].load(Ordering::Relaxed) ^ data != hash || data == 0 {
+            return None;
+        }
+
+        Some(unpack_entry(data))
+    }
+
+    /// Scores are stored as given, so mate scores should be made relative to the node before storing.
+    pub fn store(&self, hash: u64, best_move: Option<Move>, score: i32, static_eval: i32, depth: i32, bound: Bound) {
+        let slot = self.slot(hash);
+        let generation = self.generation.load(Ordering::Relaxed) & 0x3f;
+
+        let old_data = slot[1].load(Ordering::Relaxed);
+        let old_key = slot[0].load(Ordering::Relaxed) ^ old_data;
+        if old_data != 0 && old_key == hash {
+            let old = unpack_entry(old_data);
+            let old_generation = (old_data >> 58) as u8;
+
+            // Keep a deeper result for the same position from this search, unless the new one is exact.
+            if old_generation == generation && old.depth > depth + 2 && bound != Bound::Exact {
+                return;
+            }
+        }
+
+        // Don't forget a known best move just because this search didn't find one.
+        let best_move = if best_move.is_none() && old_key == hash { unpack_entry(old_data).best_move } else { best_move };
+
+        let data = (encode_move(best_move) as u64)
+            | ((score as i16 as u16 as u64) << 16)
+            | ((static_eval as i16 as u16 as u64) << 32)
+            | (((depth + 1).clamp(0, 255) as u64) << 48)
+            | ((bound as u64) << 56)
+            | ((generation as u64) << 58);
+
+        slot[0].store(hash ^ data, Ordering::Relaxed);
+        slot[1].store(data, Ordering::Relaxed);
+    }
+
+    /// How full the table is in permille, sampled from the first thousand slots, for UCI `hashfull`.
+    pub fn hashfull(&self) -> usize {
+        let generation = self.generation.load(Ordering::Relaxed) & 0x3f;
+        let sample = self.slots.len().min(1000);
+
+        let used = self.slots[..sample].iter()
+            .filter(|slot| {
+                let data = slot[1].load(Ordering::Relaxed);
+                data != 0 && (data >> 58) as u8 == generation
+            })
+            .count();
+
+        used * 1000 / sample.max(1)
+    }
+}
+
+fn unpack_entry(data: u64) -> Entry {
+    Entry {
+        best_move: decode_move(data as u16),
+        score: (data >> 16) as u16 as i16 as i32,
+        static_eval: (data >> 32) as u16 as i16 as i32,
+        depth: ((data >> 48) & 0xff) as i32 - 1,
+        bound: match (data >> 56) & 0x3 {
+            1 => Bound::Exact,
+            2 => Bound::Lower,
+            3 => Bound::Upper,
+            _ => Bound::None,
+        },
+    }
+}
+
+fn encode_move(mv: Option<Move>) -> u16 {
+    match mv {
+        None => 0,
+        Some(mv) => (mv.from as u16) | ((mv.to as u16) << 6) | ((mv.promotion as u16) << 12) | 0x8000,
+    }
+}
+
+fn decode_move(encoded: u16) -> Option<Move> {
+    if encoded & 0x8000 == 0 {
+        return None;
+    }
+
+    let promotion = match (encoded >> 12) & 0x7 {
+        1 => PromotionOption::Knight,
+        2 => PromotionOption::Bishop,
+        3 => PromotionOption::Rook,
+        4 => PromotionOption::Queen,
+        _ => PromotionOption::None,
+    };
+
+    Some(Move { from: (encoded & 0x3f) as i32, to: ((encoded >> 6) & 0x3f) as i32, promotion })
+}
+
+// Unit Tests! //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_and_probe() {
+        let table = TranspositionTable::new(1);
+        let mv = Move { from: 52, to: 60, promotion: PromotionOption::Queen };
+
+        table.store(0x1234_5678_9abc_def0, Some(mv), -29950, 35, 7, Bound::Lower);
+        let entry = table.probe(0x1234_5678_9abc_def0).expect("The entry should still be in the table.");
+
+        assert_eq!(entry.best_move, Some(mv));
+        assert_eq!(entry.score, -29950);
+        assert_eq!(entry.static_eval, 35);
+        assert_eq!(entry.depth, 7);
+        assert_eq!(entry.bound, Bound::Lower);
+
+        assert!(table.probe(0x1234_5678_9abc_def1).is_none(), "A different hash shouldn't match the entry.");
+    }
+}
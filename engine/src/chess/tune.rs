@@ -0,0 +1,361 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::thread;
+
+use super::cli::Flags;
+use super::datagen::{self, Sample};
+use super::eval::{self, Parameter, Recorder, Score, MAX_PHASE, PARAMETERS};
+use super::piece::Color;
+use super::position::Position;
+
+const ADAM_BETA_1: f64 = 0.9;
+const ADAM_BETA_2: f64 = 0.999;
+const ADAM_EPSILON: f64 = 1e-8;
+
+/// Collects the recorded weights of one position as a sparse vector of counts, White's counting up and Black's down.
+#[derive(Default)]
+pub struct Coefficients {
+    offsets: Vec<usize>,
+    counts: Vec<(usize, i32)>,
+}
+
+impl Coefficients {
+    fn new(offsets: &[usize]) -> Self {
+        Coefficients { offsets: offsets.to_vec(), counts: Vec::new() }
+    }
+
+    /// The counts by index into the parameter vector, merged and without zeros.
+    fn into_counts(mut self) -> Vec<(usize, i32)> {
+        self.counts.sort_unstable_by_key(|(index, _)| *index);
+
+        let mut merged: Vec<(usize, i32)> = Vec::with_capacity(self.counts.len());
+        for (index, count) in self.counts {
+            match merged.last_mut() {
+                Some((last_index, last_count)) if *last_index == index => *last_count += count,
+                _ => merged.push((index, count)),
+            }
+        }
+
+        merged.retain(|(_, count)| *count != 0);
+        merged
+    }
+}
+
+impl Recorder for Coefficients {
+    fn record(&mut self, parameter: Parameter, index: usize, color: &Color, count: i32) {
+        let offset = self.offsets[parameter as usize];
+        self.counts.push((offset + index, if *color == Color::Black { -count } else { count }));
+    }
+}
+
+/// Where each parameter starts in the parameter vector, indexed by `Parameter`.
+pub fn parameter_offsets() -> Vec<usize> {
+    PARAMETERS.iter()
+        .scan(0, |offset, parameter| {
+            let start = *offset;
+            *offset += parameter.values().len();
+            Some(start)
+        })
+        .collect()
+}
+
+/// Every weight of the evaluation in one vector, as the evaluation uses it now.
+pub fn parameter_vector() -> Vec<Score> {
+    PARAMETERS.iter().flat_map(|parameter| parameter.values()).collect()
+}
+
+/// The position's evaluation as counts of the weights in the parameter vector.
+pub fn coefficients(position: &Position, offsets: &[usize]) -> Vec<(usize, i32)> {
+    let mut coefficients = Coefficients::new(offsets);
+    eval::record(position, &mut coefficients);
+    coefficients.into_counts()
+}
+
+/// A position ready for tuning: its evaluation as a linear function of the parameters, and the score it should get.
+pub struct TuningEntry {
+    pub coefficients: Vec<(usize, i32)>,
+    /// How much of the middlegame weights count, from 1 with every piece on the board down to 0.
+    pub middlegame: f64,
+    /// The game result from White's side, 1, 0.5 or 0.
+    pub result: f64,
+    /// The search score from White's side, in centipawns.
+    pub score: f64,
+}
+
+impl TuningEntry {
+    pub fn new(sample: &Sample, offsets: &[usize]) -> Self {
+        TuningEntry {
+            coefficients: coefficients(&sample.position, offsets),
+            middlegame: sample.position.phase.min(MAX_PHASE) as f64 / MAX_PHASE as f64,
+            result: sample.result.white_score(),
+            score: sample.score as f64,
+        }
+    }
+
+    /// The evaluation from White's side with the given parameters, which are `[middlegame, endgame]` pairs.
+    pub fn evaluate(&self, parameters: &[[f64; 2]]) -> f64 {
+        let (middlegame, endgame) = self.coefficients.iter().fold((0.0, 0.0), |(middlegame, endgame), (index, count)| {
+            (middlegame + parameters[*index][0] * *count as f64, endgame + parameters[*index][1] * *count as f64)
+        });
+
+        middlegame * self.middlegame + endgame * (1.0 - self.middlegame)
+    }
+}
+
+/// The expected score for a centipawn evaluation, scaled by `k`.
+pub fn sigmoid(k: f64, evaluation: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-k * evaluation / 400.0))
+}
+
+/// What the tuner fits the evaluation to: the game result, the search score, or a mix weighted by `lambda`, where 1
+/// is the result alone.
+fn target(entry: &TuningEntry, k: f64, lambda: f64) -> f64 {
+    lambda * entry.result + (1.0 - lambda) * sigmoid(k, entry.score)
+}
+
+pub fn mean_squared_error(entries: &[TuningEntry], parameters: &[[f64; 2]], k: f64, lambda: f64) -> f64 {
+    let total: f64 = entries.iter()
+        .map(|entry| (target(entry, k, lambda) - sigmoid(k, entry.evaluate(parameters))).powi(2))
+        .sum();
+    total / entries.len().max(1) as f64
+}
+
+/// Finds the scaling constant that makes the current evaluation predict the results best, by golden section search.
+/// The error is fitted against the results alone, since the scores already depend on it.
+pub fn fit_k(entries: &[TuningEntry], parameters: &[[f64; 2]]) -> f64 {
+    let ratio = (5f64.sqrt() - 1.0) / 2.0;
+    let (mut low, mut high) = (0.0, 10.0);
+
+    while high - low > 1e-4 {
+        let left = high - ratio * (high - low);
+        let right = low + ratio * (high - low);
+        if mean_squared_error(entries, parameters, left, 1.0) < mean_squared_error(entries, parameters, right, 1.0) {
+            high = right;
+        } else {
+            low = left;
+        }
+    }
+
+    (low + high) / 2.0
+}
+
+/// The gradient of the mean squared error with respect to every parameter, spread over the threads.
+fn gradient(entries: &[TuningEntry], parameters: &[[f64; 2]], k: f64, lambda: f64, threads: usize) -> Vec<[f64; 2]> {
+    let chunk_size = entries.len().div_ceil(threads.max(1)).max(1);
+
+    let partial_gradients: Vec<Vec<[f64; 2]>> = thread::scope(|scope| {
+        let workers: Vec<_> = entries.chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || {
+                let mut gradient = vec![[0.0; 2]; parameters.len()];
+
+                for entry in chunk {
+                    let predicted = sigmoid(k, entry.evaluate(parameters));
+                    // The derivative of (target - sigmoid)^2 by the evaluation.
+                    let error = 2.0 * (predicted - target(entry, k, lambda)) * predicted * (1.0 - predicted) * k * 10f64.ln() / 400.0;
+
+                    for (index, count) in &entry.coefficients {
+                        gradient[*index][0] += error * *count as f64 * entry.middlegame;
+                        gradient[*index][1] += error * *count as f64 * (1.0 - entry.middlegame);
+                    }
+                }
+
+                gradient
+            }))
+            .collect();
+
+        workers.into_iter().map(|worker| worker.join().expect("A tuning thread panicked.")).collect()
+    });
+
+    let mut gradient = vec![[0.0; 2]; parameters.len()];
+    for partial_gradient in partial_gradients {
+        for (total, partial) in gradient.iter_mut().zip(partial_gradient) {
+            total[0] += partial[0] / entries.len() as f64;
+            total[1] += partial[1] / entries.len() as f64;
+        }
+    }
+
+    gradient
+}
+
+#[derive(Clone, Debug)]
+pub struct TuneOptions {
+    pub epochs: usize,
+    pub learning_rate: f64,
+    pub lambda: f64,
+    pub threads: usize,
+}
+
+impl Default for TuneOptions {
+    fn default() -> Self {
+        TuneOptions { epochs: 1000, learning_rate: 1.0, lambda: 1.0, threads: 1 }
+    }
+}
+
+/// Minimises the mean squared error with Adam, starting from `parameters`. `progress` is told the error after every
+/// epoch.
+pub fn tune(entries: &[TuningEntry], parameters: &mut [[f64; 2]], k: f64, options: &TuneOptions, mut progress: impl FnMut(usize, f64)) {
+    let mut first_moments = vec![[0.0; 2]; parameters.len()];
+    let mut second_moments = vec![[0.0; 2]; parameters.len()];
+
+    for epoch in 1..=options.epochs {
+        let gradient = gradient(entries, parameters, k, options.lambda, options.threads);
+        let first_correction = 1.0 - ADAM_BETA_1.powi(epoch as i32);
+        let second_correction = 1.0 - ADAM_BETA_2.powi(epoch as i32);
+
+        for index in 0..parameters.len() {
+            for phase in 0..2 {
+                let gradient = gradient[index][phase];
+                first_moments[index][phase] = ADAM_BETA_1 * first_moments[index][phase] + (1.0 - ADAM_BETA_1) * gradient;
+                second_moments[index][phase] = ADAM_BETA_2 * second_moments[index][phase] + (1.0 - ADAM_BETA_2) * gradient * gradient;
+
+                let first_moment = first_moments[index][phase] / first_correction;
+                let second_moment = second_moments[index][phase] / second_correction;
+                parameters[index][phase] -= options.learning_rate * first_moment / (second_moment.sqrt() + ADAM_EPSILON);
+            }
+        }
+
+        progress(epoch, mean_squared_error(entries, parameters, k, options.lambda));
+    }
+}
+
+/// The tuned parameters as Rust constants, ready to be pasted over the ones in the evaluation modules.
+pub fn rust_source(parameters: &[[f64; 2]]) -> String {
+    let rounded: Vec<Score> = parameters.iter().map(|[middlegame, endgame]| eval::s(middlegame.round() as i32, endgame.round() as i32)).collect();
+    let format_score = |score: &Score| format!("s({}, {})", score.mg, score.eg);
+
+    let mut source = String::new();
+    for (parameter, offset) in PARAMETERS.iter().zip(parameter_offsets()) {
+        let values = &rounded[offset..offset + parameter.values().len()];
+
+        if *parameter == Parameter::PieceSquare {
+            // Written out like the tables in `eval`, with rank 8 at the top.
+            for (piece_index, piece_name) in ["PAWN", "KNIGHT", "BISHOP", "ROOK", "QUEEN", "KING"].iter().enumerate() {
+                for (phase_name, phase) in [("MG", 0), ("EG", 1)] {
+                    writeln!(source, "const {}_{}: [i32; 64] = [", phase_name, piece_name).unwrap();
+                    for rank in (0..8).rev() {
+                        let row: Vec<String> = (0..8)
+                            .map(|file| {
+                                let value = values[piece_index * 64 + rank * 8 + file];
+                                format!("{:>4}", if phase == 0 { value.mg } else { value.eg })
+                            })
+                            .collect();
+                        writeln!(source, "    {},", row.join(",")).unwrap();
+                    }
+                    writeln!(source, "];\n").unwrap();
+                }
+            }
+        } else if values.len() == 1 && *parameter != Parameter::PieceValue {
+            writeln!(source, "pub const {}: Score = {};", parameter.name(), format_score(&values[0])).unwrap();
+        } else {
+            let scores: Vec<String> = values.iter().map(format_score).collect();
+            writeln!(source, "pub const {}: [Score; {}] = [{}];", parameter.name(), values.len(), scores.join(", ")).unwrap();
+        }
+    }
+
+    source
+}
+
+/// `tune <data file> [--epochs N] [--learning-rate X] [--lambda X] [--threads N] [--output file.rs]`
+///
+/// The data is anything `datagen` writes.
+pub fn run(flags: &Flags) -> Result<(), String> {
+    let path = flags.positional.first().ok_or("tune needs a data file.")?;
+    let defaults = TuneOptions::default();
+    let options = TuneOptions {
+        epochs: flags.get("epochs", defaults.epochs)?,
+        learning_rate: flags.get("learning-rate", defaults.learning_rate)?,
+        lambda: flags.get("lambda", defaults.lambda)?.clamp(0.0, 1.0),
+        threads: flags.get("threads", defaults.threads)?.max(1),
+    };
+
+    let offsets = parameter_offsets();
+    let entries: Vec<TuningEntry> = datagen::read_samples(path)?.iter().map(|sample| TuningEntry::new(sample, &offsets)).collect();
+    if entries.is_empty() {
+        return Err(format!("{} has no positions.", path));
+    }
+
+    let mut parameters: Vec<[f64; 2]> = parameter_vector().iter().map(|score| [score.mg as f64, score.eg as f64]).collect();
+    eprintln!("{} positions, {} parameters.", entries.len(), parameters.len() * 2);
+
+    let k = fit_k(&entries, &parameters);
+    eprintln!("K = {:.4}, starting error {:.6}.", k, mean_squared_error(&entries, &parameters, k, options.lambda));
+
+    tune(&entries, &mut parameters, k, &options, |epoch, error| {
+        if epoch % 50 == 0 || epoch == options.epochs {
+            eprintln!("Epoch {}: error {:.6}.", epoch, error);
+        }
+    });
+
+    let source = rust_source(&parameters);
+    match flags.get("output", String::new())?.as_str() {
+        "" => print!("{}", source),
+        output => fs::write(output, source).map_err(|error| format!("Couldn't write {}: {}.", output, error))?,
+    }
+
+    Ok(())
+}
+
+// Unit Tests! //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::datagen::GameResult;
+    use super::super::{fen, pieces};
+
+    const TEST_FENS: [&str; 5] = [
+        fen::STARTING_FEN,
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1",
+        "4k3/8/8/8/6p1/7P/5P2/6K1 w - - 0 1",
+    ];
+
+    #[test]
+    fn test_coefficients_reproduce_the_evaluation() {
+        let offsets = parameter_offsets();
+        let parameters = parameter_vector();
+
+        for fen_string in TEST_FENS {
+            let position = fen::position_from_fen(fen_string);
+            let total = coefficients(&position, &offsets).iter().fold(Score::default(), |total, (index, count)| total + parameters[*index] * *count);
+
+            assert_eq!(total, eval::record(&position, &mut ()), "The weights recorded for {} should add up to its evaluation.", fen_string);
+
+            let white_score = eval::blend(total, position.phase);
+            let expected = if position.side_to_move == Color::Black { -white_score } else { white_score };
+            assert_eq!(expected, eval::evaluate(&position), "The parameter vector should evaluate {} like the evaluation does.", fen_string);
+        }
+    }
+
+    #[test]
+    fn test_tuning_reduces_the_error() {
+        let offsets = parameter_offsets();
+        // White wins every game with the bishop pair and loses every game without it.
+        let entries: Vec<TuningEntry> = [("4k3/8/8/8/8/8/8/2B1KB2 w - - 0 1", GameResult::WhiteWin), ("4k3/8/8/8/8/8/8/2N1KB2 w - - 0 1", GameResult::BlackWin)]
+            .iter()
+            .map(|(fen_string, result)| TuningEntry::new(&Sample { position: fen::position_from_fen(fen_string), score: 0, result: *result }, &offsets))
+            .collect();
+
+        let mut parameters: Vec<[f64; 2]> = parameter_vector().iter().map(|score| [score.mg as f64, score.eg as f64]).collect();
+        let k = fit_k(&entries, &parameters);
+        assert!(k > 0.0 && k < 10.0);
+
+        let before = mean_squared_error(&entries, &parameters, k, 1.0);
+        tune(&entries, &mut parameters, k, &TuneOptions { epochs: 50, threads: 2, ..Default::default() }, |_, _| {});
+        assert!(mean_squared_error(&entries, &parameters, k, 1.0) < before, "Tuning should bring the error down.");
+
+        let bishop_pair = parameters[offsets[Parameter::BishopPair as usize]];
+        assert!(bishop_pair[1] > pieces::BISHOP_PAIR.eg as f64, "The bishop pair should be worth more after these games.");
+    }
+
+    #[test]
+    fn test_rust_source() {
+        let parameters: Vec<[f64; 2]> = parameter_vector().iter().map(|score| [score.mg as f64, score.eg as f64]).collect();
+        let source = rust_source(&parameters);
+
+        assert!(source.contains("pub const BISHOP_PAIR: Score = s(28, 52);"));
+        assert!(source.contains("pub const PIECE_VALUES: [Score; 6] = [s(82, 94), s(337, 281),"));
+        assert!(source.contains("const MG_KNIGHT: [i32; 64] = [\n    -167, -89, -34, -49,  61, -97, -15,-107,"), "The tables should come out the way they're written in eval.");
+    }
+}
@@ -0,0 +1,495 @@
+use std::io::{self, BufRead};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::display;
+use super::dtm::DtmTablebase;
+use super::eval;
+use super::game::{self, Game};
+use super::moves::Move;
+use super::nnue::{self, Network};
+use super::polyglot::{Book, BookSelection};
+use super::position::Position;
+use super::search::{SearchLimits, Searcher};
+use super::syzygy::SyzygyTablebase;
+use super::tablebase::{Tablebase, Tablebases};
+use super::timing::TimeControl;
+use super::transposition::TranspositionTable;
+use super::variant::{self, Variant};
+use super::zobrist;
+
+const ENGINE_NAME: &str = "Macbeth";
+const ENGINE_AUTHOR: &str = "winkasador";
+
+const DEFAULT_HASH_MB: usize = 16;
+const MAX_HASH_MB: usize = 65536;
+const MAX_MULTI_PV: usize = 256;
+const MAX_THREADS: usize = 256;
+
+/// Every keyword `go` understands, so the list after `searchmoves` knows where it ends.
+const GO_KEYWORDS: &[&str] = &["searchmoves", "ponder", "wtime", "btime", "winc", "binc", "movestogo", "depth", "nodes", "mate", "movetime", "infinite"];
+
+/// What a `go` command asked for.
+#[derive(Clone, Debug, Default)]
+pub struct GoCommand {
+    pub limits: SearchLimits,
+    /// Think on the opponent's time, about the position after the move we expect them to play.
+    pub ponder: bool,
+    /// Keep searching until `stop`, whatever else the command says.
+    pub infinite: bool,
+}
+
+/// The engine's side of the UCI protocol.
+///
+/// Searches run on their own thread, which owns the searcher until the search ends and hands it back when joined,
+/// so the main thread stays free to read `stop` and `ponderhit`.
+pub struct Uci {
+    game: Game,
+    searcher: Option<Searcher>,
+    search_thread: Option<JoinHandle<Searcher>>,
+    stop: Arc<AtomicBool>,
+    pondering: Arc<AtomicBool>,
+    /// Whether the GUI lets us ponder. Only affects whether we suggest a move to ponder on.
+    ponder_enabled: bool,
+    /// The opening book from `BookFile`, played from when `OwnBook` is on.
+    book: Option<Book>,
+    own_book: bool,
+    book_selection: BookSelection,
+    /// The tables from `DtmPath` and `SyzygyPath`, each set and cleared by its own option.
+    tablebases: Tablebases,
+    /// Whether the GUI plays Chess960, where castling is sent and received as the king taking its rook.
+    chess960: bool,
+    /// The rules from `UCI_Variant`, which every new game is played by.
+    variant: &'static dyn Variant,
+}
+
+impl Default for Uci {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Uci {
+    pub fn new() -> Self {
+        let mut searcher = Searcher::new(DEFAULT_HASH_MB);
+        searcher.print_info = true;
+
+        Uci {
+            game: Game::new(),
+            stop: searcher.stop.clone(),
+            pondering: searcher.pondering.clone(),
+            searcher: Some(searcher),
+            search_thread: None,
+            ponder_enabled: false,
+            book: None,
+            own_book: false,
+            book_selection: BookSelection::Weighted,
+            tablebases: Tablebases::default(),
+            chess960: false,
+            variant: variant::standard(),
+        }
+    }
+
+    /// Reads commands from standard input until `quit` or the input ends.
+    pub fn run(&mut self) {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if !self.handle_command(&line) {
+                break;
+            }
+        }
+
+        self.stop_search();
+    }
+
+    /// Handles one line of input. Returns false when the engine should quit.
+    pub fn handle_command(&mut self, line: &str) -> bool {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let Some(command) = tokens.first() else { return true };
+
+        match *command {
+            "uci" => {
+                println!("id name {}", ENGINE_NAME);
+                println!("id author {}", ENGINE_AUTHOR);
+                println!("option name Hash type spin default {} min 1 max {}", DEFAULT_HASH_MB, MAX_HASH_MB);
+                println!("option name Move Overhead type spin default 30 min 0 max 5000");
+                println!("option name Ponder type check default false");
+                println!("option name Threads type spin default 1 min 1 max {}", MAX_THREADS);
+                println!("option name MultiPV type spin default 1 min 1 max {}", MAX_MULTI_PV);
+                println!("option name EvalFile type string default <empty>");
+                println!("option name SyzygyPath type string default <empty>");
+                println!("option name DtmPath type string default <empty>");
+                println!("option name OwnBook type check default false");
+                println!("option name BookFile type string default <empty>");
+                println!("option name Best Book Move type check default false");
+                println!("option name UCI_Chess960 type check default false");
+                let names: Vec<String> = variant::VARIANTS.iter().map(|variant| format!("var {}", variant.name())).collect();
+                println!("option name UCI_Variant type combo default {} {}", variant::standard().name(), names.join(" "));
+                println!("uciok");
+            }
+            "isready" => println!("readyok"),
+            "ucinewgame" => {
+                self.searcher().clear();
+                self.game = Game::for_variant(self.variant);
+            }
+            "setoption" => self.set_option(&tokens[1..]),
+            "position" => match parse_position(&tokens[1..], self.variant, self.chess960) {
+                Ok(game) => {
+                    self.wait_for_search();
+                    self.game = game;
+                }
+                Err(error) => println!("info string {}", error),
+            },
+            "go" => {
+                let command = parse_go(&tokens[1..], &self.game.position);
+                self.go(command);
+            }
+            "ponderhit" => self.pondering.store(false, Ordering::Relaxed),
+            "stop" => self.stop_search(),
+            "d" => println!("{}", display::ascii(&self.game.position)),
+            "eval" => {
+                let table = display::trace_table(&eval::trace(&self.game.position));
+                println!("{}", display::side_by_side(&display::ascii(&self.game.position), &table));
+
+                let position = self.game.position.clone();
+                if let Some(network) = self.searcher().network() {
+                    println!("\nNNUE evaluation: {} (side to move)", nnue::evaluate_position(network, &position));
+                }
+            }
+            "quit" => return false,
+            _ => println!("info string Unknown command {}", command),
+        }
+
+        true
+    }
+
+    /// Whether a search thread is still running.
+    pub fn is_searching(&self) -> bool {
+        self.search_thread.as_ref().is_some_and(|search_thread| !search_thread.is_finished())
+    }
+
+    /// The searcher, once any running search has finished.
+    fn searcher(&mut self) -> &mut Searcher {
+        self.wait_for_search();
+        self.searcher.as_mut().expect("The searcher should be back from the search thread.")
+    }
+
+    fn wait_for_search(&mut self) {
+        if let Some(search_thread) = self.search_thread.take() {
+            self.searcher = Some(search_thread.join().expect("The search thread panicked."));
+        }
+    }
+
+    fn stop_search(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.pondering.store(false, Ordering::Relaxed);
+        self.wait_for_search();
+    }
+
+    fn go(&mut self, command: GoCommand) {
+        self.wait_for_search();
+
+        // A book move is played at once, unless the GUI wants analysis or only some of the moves.
+        if !command.ponder && !command.infinite && command.limits.search_moves.is_empty() {
+            if let Some(book_move) = self.book_move() {
+                println!("bestmove {}", book_move);
+                return;
+            }
+        }
+
+        self.stop.store(false, Ordering::Relaxed);
+        self.pondering.store(command.ponder, Ordering::Relaxed);
+
+        let mut searcher = self.searcher.take().expect("The searcher should be back from the search thread.");
+        searcher.set_game_history(self.game.history.clone());
+        searcher.variant = self.game.variant;
+
+        let position = self.game.position.clone();
+        let stop = self.stop.clone();
+        let pondering = self.pondering.clone();
+        let ponder_enabled = self.ponder_enabled;
+
+        self.search_thread = Some(thread::spawn(move || {
+            let result = searcher.search(&position, &command.limits);
+
+            // UCI doesn't allow a best move while pondering or in an infinite search, even when the search is over.
+            while (command.infinite || pondering.load(Ordering::Relaxed)) && !stop.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(1));
+            }
+
+            match result.best_move {
+                Some(best_move) => match searcher.ponder_move(&position, &result).filter(|_| ponder_enabled || command.ponder) {
+                    Some(ponder_move) => println!("bestmove {} ponder {}", best_move, ponder_move),
+                    None => println!("bestmove {}", best_move),
+                },
+                None => println!("bestmove 0000"),
+            }
+
+            searcher
+        }));
+    }
+
+    fn book_move(&self) -> Option<Move> {
+        let book = self.book.as_ref().filter(|_| self.own_book && self.game.variant.is_standard())?;
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos() as u64);
+        book.pick(&self.game.position, self.book_selection, zobrist::splitmix64(time).1)
+    }
+
+    fn set_tablebases(&mut self, tablebases: Tablebases) {
+        self.searcher().tablebase = tablebases.combined();
+        self.tablebases = tablebases;
+    }
+
+    fn set_option(&mut self, tokens: &[&str]) {
+        // Option names can have spaces in them, like `Move Overhead`.
+        let value_index = tokens.iter().position(|token| *token == "value").unwrap_or(tokens.len());
+        let name = tokens.get(1..value_index).unwrap_or_default().join(" ");
+        let value = tokens.get(value_index + 1..).unwrap_or_default().join(" ");
+
+        match name.to_lowercase().as_str() {
+            "hash" => match value.parse::<usize>() {
+                Ok(size_mb) => {
+                    let searcher = self.searcher();
+                    searcher.transposition_table = Arc::new(TranspositionTable::new(size_mb.clamp(1, MAX_HASH_MB)));
+                }
+                Err(_) => println!("info string Invalid hash size {}", value),
+            },
+            "move overhead" => match value.parse::<u64>() {
+                Ok(move_overhead) => self.searcher().move_overhead = move_overhead,
+                Err(_) => println!("info string Invalid move overhead {}", value),
+            },
+            "ponder" => self.ponder_enabled = value == "true",
+            "threads" => match value.parse::<usize>() {
+                Ok(threads) => self.searcher().threads = threads.clamp(1, MAX_THREADS),
+                Err(_) => println!("info string Invalid thread count {}", value),
+            },
+            "multipv" => match value.parse::<usize>() {
+                Ok(multi_pv) => self.searcher().multi_pv = multi_pv.clamp(1, MAX_MULTI_PV),
+                Err(_) => println!("info string Invalid MultiPV {}", value),
+            },
+            "evalfile" if value.is_empty() || value == "<empty>" => self.searcher().set_network(None),
+            "evalfile" => match Network::load(&value) {
+                Ok(network) => {
+                    println!("info string Loaded network {} with {} king buckets and {} hidden neurons", value, network.king_buckets, network.hidden_size);
+                    self.searcher().set_network(Some(Arc::new(network)));
+                }
+                Err(error) => println!("info string {}", error),
+            },
+            "syzygypath" if value.is_empty() || value == "<empty>" => self.set_tablebases(Tablebases { syzygy: None, ..self.tablebases.clone() }),
+            "syzygypath" => match SyzygyTablebase::load(&value) {
+                Ok(tablebase) => {
+                    let (wdl, dtz) = tablebase.table_counts();
+                    println!("info string Found {} WDL and {} DTZ Syzygy tables of up to {} pieces", wdl, dtz, tablebase.max_pieces());
+                    self.set_tablebases(Tablebases { syzygy: Some(Arc::new(tablebase)), ..self.tablebases.clone() });
+                }
+                Err(error) => println!("info string {}", error),
+            },
+            "dtmpath" if value.is_empty() || value == "<empty>" => self.set_tablebases(Tablebases { dtm: None, ..self.tablebases.clone() }),
+            "dtmpath" => match DtmTablebase::load(&value) {
+                Ok(tablebase) => {
+                    println!("info string Loaded distance to mate tables of up to {} pieces", tablebase.max_pieces());
+                    self.set_tablebases(Tablebases { dtm: Some(Arc::new(tablebase)), ..self.tablebases.clone() });
+                }
+                Err(error) => println!("info string {}", error),
+            },
+            "ownbook" => self.own_book = value == "true",
+            "bookfile" if value.is_empty() || value == "<empty>" => self.book = None,
+            "bookfile" => match Book::load(&value) {
+                Ok(book) => {
+                    println!("info string Loaded book {} with {} entries", value, book.len());
+                    self.book = Some(book);
+                }
+                Err(error) => println!("info string {}", error),
+            },
+            "uci_chess960" => self.chess960 = value == "true",
+            "uci_variant" => match variant::from_name(&value) {
+                Some(variant) => {
+                    self.wait_for_search();
+                    self.variant = variant;
+                    self.game = Game::for_variant(variant);
+                }
+                None => println!("info string Unknown variant {}", value),
+            },
+            "best book move" => self.book_selection = if value == "true" { BookSelection::Best } else { BookSelection::Weighted },
+            _ => println!("info string Unknown option {}", name),
+        }
+    }
+}
+
+/// Parses the arguments of `position`: `startpos` or `fen <fen>`, optionally followed by `moves <moves>`, for a game
+/// of `variant`. With `chess960`, castling moves are the king taking its rook even when the position could be
+/// standard chess.
+pub fn parse_position(tokens: &[&str], variant: &'static dyn Variant, chess960: bool) -> Result<Game, String> {
+    let moves_index = tokens.iter().position(|token| *token == "moves").unwrap_or(tokens.len());
+
+    let mut game = match tokens.first() {
+        Some(&"startpos") => Game::for_variant(variant),
+        Some(&"fen") => Game::from_variant_fen(variant, &tokens[1..moves_index].join(" "))?,
+        _ => return Err("Expected startpos or fen.".to_string()),
+    };
+    game.position.chess960 |= chess960;
+
+    for name in tokens.iter().skip(moves_index + 1) {
+        game.play_move_name(name)?;
+    }
+
+    Ok(game)
+}
+
+/// Parses the arguments of `go` for a search of `position`. Unknown arguments, missing values and illegal
+/// `searchmoves` are ignored.
+pub fn parse_go(tokens: &[&str], position: &Position) -> GoCommand {
+    let mut command = GoCommand::default();
+    let mut time = TimeControl::default();
+    let mut has_time = false;
+
+    let mut tokens = tokens.iter().peekable();
+    while let Some(token) = tokens.next() {
+        if *token == "searchmoves" {
+            while let Some(name) = tokens.next_if(|name| !GO_KEYWORDS.contains(name)) {
+                command.limits.search_moves.extend(game::find_move(position, name));
+            }
+            continue;
+        }
+
+        let mut number = || tokens.next().and_then(|value| value.parse::<i64>().ok()).map(|value| value.max(0) as u64);
+
+        match *token {
+            "wtime" => { time.white_time = number(); has_time = true; }
+            "btime" => { time.black_time = number(); has_time = true; }
+            "winc" => time.white_increment = number().unwrap_or(0),
+            "binc" => time.black_increment = number().unwrap_or(0),
+            "movestogo" => time.moves_to_go = number(),
+            "movetime" => { time.move_time = number(); has_time = true; }
+            "depth" => command.limits.depth = number().map(|depth| depth as i32),
+            "nodes" => command.limits.nodes = number(),
+            "infinite" => command.infinite = true,
+            "ponder" => command.ponder = true,
+            _ => {}
+        }
+    }
+
+    if has_time && !command.infinite {
+        command.limits.time = Some(time);
+    }
+
+    command
+}
+
+// Unit Tests! //
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::*;
+    use super::super::dtm::{self, Material};
+    use super::super::fen;
+    use super::super::tablebase::Wdl;
+
+    #[test]
+    fn test_parse_position() {
+        let standard = variant::standard();
+        let game = parse_position(&["startpos", "moves", "e2e4", "e7e5", "g1f3"], standard, false).expect("The moves should be legal.");
+        assert_eq!(game.moves.len(), 3);
+        assert_eq!(game.history.len(), 3);
+
+        let fen_string = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let tokens: Vec<&str> = ["fen"].into_iter().chain(fen_string.split_whitespace()).collect();
+        let game = parse_position(&tokens, standard, false).expect("The FEN should be valid.");
+        assert_eq!(game.position.hash, fen::position_from_fen(fen_string).hash);
+
+        // With UCI_Chess960 castling is the king taking its rook, even from a standard position.
+        let castle = |name: &'static str| tokens.iter().copied().chain(["moves", name]).collect::<Vec<&str>>();
+        assert!(parse_position(&castle("e1h1"), standard, true).is_ok());
+        assert!(parse_position(&castle("e1g1"), standard, true).is_err());
+        assert!(parse_position(&castle("e1g1"), standard, false).is_ok());
+
+        assert!(parse_position(&["startpos", "moves", "e2e5"], standard, false).is_err(), "An illegal move should be rejected.");
+        assert!(parse_position(&["fen", "8/8/8/8/8/8/8/8", "w"], standard, false).is_err(), "A short FEN should be rejected.");
+        for broken in ["rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - x 1", "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq e9 0 1", "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 extra"] {
+            let tokens: Vec<&str> = ["fen"].into_iter().chain(broken.split(' ')).collect();
+            assert!(parse_position(&tokens, standard, false).is_err(), "{} should be rejected.", broken);
+        }
+
+        let three_check = variant::from_name("3check").unwrap();
+        let game = parse_position(&["startpos", "moves", "e2e4", "f7f6", "d1h5"], three_check, false).expect("The moves should be legal.");
+        assert_eq!(game.position.checks_given, [1, 0], "Qh5+ is a check.");
+        let fen_string = three_check.fen(&game.position);
+        let tokens: Vec<&str> = ["fen"].into_iter().chain(fen_string.split(' ')).collect();
+        let again = parse_position(&tokens, three_check, false).expect("The variant's FEN should read.");
+        assert_eq!(again.position.hash, game.position.hash, "The checks given should come back from the FEN.");
+    }
+
+    #[test]
+    fn test_parse_go() {
+        let start = Game::new().position;
+
+        let command = parse_go(&["wtime", "60000", "btime", "50000", "winc", "1000", "binc", "500", "movestogo", "20"], &start);
+        let time = command.limits.time.expect("The clock should limit the search.");
+        assert_eq!((time.white_time, time.black_time, time.white_increment, time.black_increment, time.moves_to_go), (Some(60000), Some(50000), 1000, 500, Some(20)));
+        assert!(!command.ponder && !command.infinite);
+
+        let command = parse_go(&["ponder", "wtime", "1000", "btime", "1000"], &start);
+        assert!(command.ponder && command.limits.time.is_some(), "A ponder search still gets the clock for after the ponderhit.");
+
+        let command = parse_go(&["infinite", "wtime", "1000"], &start);
+        assert!(command.infinite && command.limits.time.is_none());
+
+        let command = parse_go(&["depth", "7", "nodes", "10000"], &start);
+        assert_eq!((command.limits.depth, command.limits.nodes), (Some(7), Some(10000)));
+
+        let command = parse_go(&["searchmoves", "e2e4", "d2d4", "e2e5", "depth", "3"], &start);
+        let names: Vec<String> = command.limits.search_moves.iter().map(|mv| mv.to_string()).collect();
+        assert_eq!(names, ["e2e4", "d2d4"], "The illegal e2e5 should be dropped.");
+        assert_eq!(command.limits.depth, Some(3));
+    }
+
+    #[test]
+    fn test_ponderhit_switches_to_the_clock() {
+        let mut uci = Uci::new();
+        uci.searcher().print_info = false;
+
+        uci.handle_command("position startpos moves e2e4 e7e5");
+        uci.handle_command("go ponder wtime 1000 btime 1000");
+        thread::sleep(Duration::from_millis(300));
+        assert!(uci.is_searching(), "The engine must keep pondering until the ponderhit.");
+
+        // Well over the time for one move has been spent pondering, so the search ends soon after the ponderhit.
+        uci.handle_command("ponderhit");
+        thread::sleep(Duration::from_millis(300));
+        assert!(!uci.is_searching(), "The search should end once the clock runs.");
+
+        uci.handle_command("go ponder wtime 1000 btime 1000");
+        uci.handle_command("stop");
+        assert!(!uci.is_searching(), "Stop should end a ponder search.");
+    }
+
+    #[test]
+    fn test_tablebase_options_keep_their_own_tables() {
+        let directory = env::temp_dir().join(format!("macbeth-uci-tables-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let material = Material::parse("KQvK").unwrap();
+        let mut tables = DtmTablebase::default();
+        let entries = tables.generate(&material).expect("KQvK needs no other tables.");
+        fs::write(directory.join(format!("KQvK.{}", dtm::EXTENSION)), dtm::write_table(&material, &entries)).unwrap();
+        dtm::write_syzygy(&material, &tables, &directory).expect("The Syzygy tables should be written.");
+
+        let mut uci = Uci::new();
+        let position = fen::position_from_fen("8/8/8/8/8/8/8/KQ5k w - - 0 1");
+        let mut probe = |command: &str| {
+            uci.handle_command(command);
+            uci.searcher().tablebase.clone().map(|tablebase| (tablebase.probe_wdl(&position), tablebase.probe_dtz(&position).is_some(), tablebase.probe_dtm(&position).is_some()))
+        };
+
+        probe(&format!("setoption name SyzygyPath value {}", directory.display()));
+        assert_eq!(probe(&format!("setoption name DtmPath value {}", directory.display())), Some((Some(Wdl::Win), true, true)), "Both tables should be probed.");
+        assert_eq!(probe("setoption name DtmPath value <empty>"), Some((Some(Wdl::Win), true, false)), "Clearing DtmPath should keep the Syzygy tables.");
+        probe(&format!("setoption name DtmPath value {}", directory.display()));
+        assert_eq!(probe("setoption name SyzygyPath value <empty>"), Some((Some(Wdl::Win), false, true)), "Clearing SyzygyPath should keep the DTM tables.");
+        assert_eq!(probe("setoption name DtmPath value"), None, "With both cleared there should be no tables.");
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
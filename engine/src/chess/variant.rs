@@ -0,0 +1,388 @@
+use super::attacks;
+use super::datagen::GameResult;
+use super::endgame;
+use super::fen;
+use super::moves::{self, Move};
+use super::piece::{self, Color};
+use super::position::{self, Position};
+use super::zobrist;
+
+// A variant is the rules of a game played on the same board with the same pieces. The search, `Game` and UCI only
+// reach the rules through `Variant`, so a variant overrides the hooks its rules change and takes the standard ones
+// for the rest. Variants are stateless, and shared as `&'static dyn Variant`.
+
+/// The rules the engine plays by.
+pub trait Variant: Send + Sync {
+    /// The name `UCI_Variant` knows the variant by.
+    fn name(&self) -> &'static str;
+
+    fn starting_fen(&self) -> &'static str {
+        fen::STARTING_FEN
+    }
+
+    /// Whether these are the rules of standard chess, so opening books and tablebases made for it hold.
+    fn is_standard(&self) -> bool {
+        false
+    }
+
+    /// Every move the pieces can make, before `is_legal` has been asked about any of them.
+    fn unchecked_moves(&self, position: &Position) -> Vec<Move> {
+        moves::get_unchecked_moves(position)
+    }
+
+    fn make_move(&self, position: &mut Position, mv: &Move) {
+        moves::make_move(position, mv);
+    }
+
+    /// Whether the move that took `position` to `child` was legal.
+    fn is_legal(&self, position: &Position, child: &Position) -> bool {
+        !position::is_in_check(child, &position.side_to_move)
+    }
+
+    /// The result of a game the variant's own rules have ended, like a king reaching the hill. Checkmate and
+    /// stalemate are found by `outcome`, since they need the legal moves.
+    fn variant_end(&self, _position: &Position) -> Option<GameResult> {
+        None
+    }
+
+    fn position_from_fen(&self, fen_string: &str) -> Result<Position, String> {
+        fen::try_position_from_fen(fen_string)
+    }
+
+    fn fen(&self, position: &Position) -> String {
+        fen::fen_from_position(position)
+    }
+
+    /// Adjusts the static evaluation, from the side to move's point of view, for what the variant's rules make
+    /// worth more than standard chess does.
+    fn evaluate(&self, _position: &Position, score: i32) -> i32 {
+        score
+    }
+
+    /// Every legal move in the position, none once the game is over by the variant's rules.
+    fn legal_moves(&self, position: &Position) -> Vec<Move> {
+        if self.variant_end(position).is_some() {
+            return Vec::new();
+        }
+
+        self.unchecked_moves(position).into_iter()
+            .filter(|mv| {
+                let mut child = position.clone();
+                self.make_move(&mut child, mv);
+                self.is_legal(position, &child)
+            })
+            .collect()
+    }
+
+    /// How the game has ended, if it has: by the variant's rules, or by checkmate or stalemate.
+    fn outcome(&self, position: &Position) -> Option<GameResult> {
+        if let Some(result) = self.variant_end(position) {
+            return Some(result);
+        }
+        if !self.legal_moves(position).is_empty() {
+            return None;
+        }
+
+        Some(match (position::is_in_check(position, &position.side_to_move), position.side_to_move) {
+            (true, Color::White) => GameResult::BlackWin,
+            (true, _) => GameResult::WhiteWin,
+            (false, _) => GameResult::Draw,
+        })
+    }
+
+    /// Counts the leaf nodes of the legal move tree, like `moves::perft` does for standard chess.
+    fn perft(&self, position: &Position, depth: i32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+
+        self.legal_moves(position).iter()
+            .map(|mv| {
+                let mut child = position.clone();
+                self.make_move(&mut child, mv);
+                if depth == 1 { 1 } else { self.perft(&child, depth - 1) }
+            })
+            .sum()
+    }
+}
+
+/// Every variant, standard chess first.
+pub const VARIANTS: [&dyn Variant; 4] = [&Standard, &KingOfTheHill, &ThreeCheck, &RacingKings];
+
+/// The variant `UCI_Variant` calls `name`.
+pub fn from_name(name: &str) -> Option<&'static dyn Variant> {
+    VARIANTS.into_iter().find(|variant| variant.name().eq_ignore_ascii_case(name))
+}
+
+pub fn standard() -> &'static dyn Variant {
+    &Standard
+}
+
+fn winner(color: Color) -> GameResult {
+    if color == Color::White { GameResult::WhiteWin } else { GameResult::BlackWin }
+}
+
+/// Standard chess, which every hook defaults to.
+pub struct Standard;
+
+impl Variant for Standard {
+    fn name(&self) -> &'static str {
+        "chess"
+    }
+
+    fn is_standard(&self) -> bool {
+        true
+    }
+
+    /// The endgame evaluators know how standard chess endings are won, which says nothing about the other variants.
+    fn evaluate(&self, position: &Position, score: i32) -> i32 {
+        endgame::adjust(position, score)
+    }
+}
+
+/// d4, e4, d5 and e5.
+const HILL: i64 = (1 << 27) | (1 << 28) | (1 << 35) | (1 << 36);
+/// Indexed by how many king moves a king is from the hill.
+const HILL_BONUS: [i32; 4] = [0, 150, 60, 20];
+
+/// King of the Hill: standard chess, which a king also wins by reaching one of the four centre squares.
+pub struct KingOfTheHill;
+
+impl KingOfTheHill {
+    fn distance_to_hill(king_index: i32) -> usize {
+        let distance = |coordinate: i32| (3 - coordinate).max(coordinate - 4).max(0);
+        distance(king_index % 8).max(distance(king_index / 8)) as usize
+    }
+}
+
+impl Variant for KingOfTheHill {
+    fn name(&self) -> &'static str {
+        "kingofthehill"
+    }
+
+    fn variant_end(&self, position: &Position) -> Option<GameResult> {
+        [Color::White, Color::Black].into_iter()
+            .find(|color| position.bitboards[piece::piece_id(&piece::Piece::King, color) as usize] & HILL != 0)
+            .map(winner)
+    }
+
+    fn evaluate(&self, position: &Position, score: i32) -> i32 {
+        let us = position.side_to_move;
+        let them = piece::opponent_of(&us);
+        let bonus = |color: &Color| {
+            let king_index = position::get_king_index(position, color);
+            if king_index < 0 { 0 } else { HILL_BONUS[Self::distance_to_hill(king_index)] }
+        };
+        score + bonus(&us) - bonus(&them)
+    }
+}
+
+/// Indexed by the checks a side has given, short of the third that wins.
+const CHECK_BONUS: [i32; 3] = [0, 120, 350];
+
+/// Three-check: standard chess, which a side also wins by giving check three times. The checks left to give are
+/// an extra FEN field after the en passant square, like `3+3`, as lichess writes them.
+pub struct ThreeCheck;
+
+impl Variant for ThreeCheck {
+    fn name(&self) -> &'static str {
+        "3check"
+    }
+
+    fn starting_fen(&self) -> &'static str {
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 3+3 0 1"
+    }
+
+    fn make_move(&self, position: &mut Position, mv: &Move) {
+        let mover = position.side_to_move;
+        moves::make_move(position, mv);
+
+        if position::is_in_check(position, &position.side_to_move) {
+            let checks = position.checks_given[mover as usize];
+            position.hash ^= zobrist::check_key(mover, checks) ^ zobrist::check_key(mover, checks + 1);
+            position.checks_given[mover as usize] = checks + 1;
+        }
+    }
+
+    fn variant_end(&self, position: &Position) -> Option<GameResult> {
+        [Color::White, Color::Black].into_iter()
+            .find(|color| position.checks_given[*color as usize] >= 3)
+            .map(winner)
+    }
+
+    fn position_from_fen(&self, fen_string: &str) -> Result<Position, String> {
+        let mut parts: Vec<&str> = fen_string.split_whitespace().collect();
+        let checks_left = match parts.len() {
+            6 => None,
+            7 => Some(parts.remove(4)),
+            _ => return Err("A Three-check FEN must have 6 parts, or 7 with the checks left.".to_string()),
+        };
+
+        let mut position = Standard.position_from_fen(&parts.join(" "))?;
+        if let Some(checks_left) = checks_left {
+            let left = |checks: &str| checks.parse::<i32>().ok().filter(|checks| (0..=3).contains(checks));
+            let Some((Some(white), Some(black))) = checks_left.split_once('+').map(|(white, black)| (left(white), left(black))) else {
+                return Err(format!("Invalid checks left {} in FEN.", checks_left));
+            };
+            position.checks_given = [3 - white, 3 - black];
+            position.hash = zobrist::hash_position(&position);
+        }
+
+        Ok(position)
+    }
+
+    fn fen(&self, position: &Position) -> String {
+        let fen_string = fen::fen_from_position(position);
+        let mut parts: Vec<&str> = fen_string.split(' ').collect();
+        let checks_left = format!("{}+{}", 3 - position.checks_given[0], 3 - position.checks_given[1]);
+        parts.insert(4, &checks_left);
+        parts.join(" ")
+    }
+
+    fn evaluate(&self, position: &Position, score: i32) -> i32 {
+        let bonus = |color: Color| CHECK_BONUS[position.checks_given[color as usize].clamp(0, 2) as usize];
+        score + bonus(position.side_to_move) - bonus(piece::opponent_of(&position.side_to_move))
+    }
+}
+
+const EIGHTH_RANK: i64 = 0xff << 56;
+/// Indexed by the rank a king is on, counted from White's side for both.
+const RACE_BONUS: [i32; 8] = [0, 15, 35, 60, 95, 140, 200, 300];
+
+/// Racing Kings: both kings start on the first rank and race to the eighth. Giving check isn't allowed, so there's
+/// no checkmate. Black gets one more move to draw by reaching the eighth rank too, when White gets there first.
+pub struct RacingKings;
+
+impl Variant for RacingKings {
+    fn name(&self) -> &'static str {
+        "racingkings"
+    }
+
+    fn starting_fen(&self) -> &'static str {
+        "8/8/8/8/8/8/krbnNBRK/qrbnNBRQ w - - 0 1"
+    }
+
+    fn is_legal(&self, position: &Position, child: &Position) -> bool {
+        !position::is_in_check(child, &position.side_to_move) && !position::is_in_check(child, &child.side_to_move)
+    }
+
+    fn variant_end(&self, position: &Position) -> Option<GameResult> {
+        let white_king = position::get_king_index(position, &Color::White);
+        let black_king = position::get_king_index(position, &Color::Black);
+        let (white_home, black_home) = (white_king >= 56, black_king >= 56);
+        if !white_home && !black_home {
+            return None;
+        }
+
+        if position.side_to_move == Color::Black && !black_home && black_king >= 0 {
+            // Black can still draw by stepping onto the eighth rank somewhere White doesn't attack.
+            let mut steps = attacks::KING_ATTACKS[black_king as usize] & EIGHTH_RANK & !position::get_color_bitboard(position, &Color::Black);
+            while steps != 0 {
+                if !position::is_square_attacked_by(position, attacks::pop_lsb(&mut steps), &Color::White) {
+                    return None;
+                }
+            }
+        }
+
+        Some(match (white_home, black_home) {
+            (true, true) => GameResult::Draw,
+            (true, false) => GameResult::WhiteWin,
+            _ => GameResult::BlackWin,
+        })
+    }
+
+    fn evaluate(&self, position: &Position, score: i32) -> i32 {
+        let bonus = |color: &Color| {
+            let king_index = position::get_king_index(position, color);
+            if king_index < 0 { 0 } else { RACE_BONUS[(king_index / 8) as usize] }
+        };
+        score + bonus(&position.side_to_move) - bonus(&piece::opponent_of(&position.side_to_move))
+    }
+}
+
+// Unit Tests! //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_perft(variant: &dyn Variant, cases: &[(&str, i32, u64)]) {
+        for (fen_string, depth, nodes) in cases {
+            let position = variant.position_from_fen(fen_string).unwrap_or_else(|error| panic!("{} doesn't read: {}", fen_string, error));
+            assert_eq!(variant.perft(&position, *depth), *nodes, "Wrong {} perft at depth {} from {}.", variant.name(), depth, fen_string);
+        }
+    }
+
+    #[test]
+    fn test_standard_perft() {
+        let start = Standard.position_from_fen(Standard.starting_fen()).unwrap();
+        assert_eq!(Standard.perft(&start, 4), moves::perft(&start, 4), "The standard hooks should be the standard rules.");
+        assert_perft(&Standard, &[
+            (fen::STARTING_FEN, 4, 197281),
+            ("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", 3, 97862),
+        ]);
+    }
+
+    #[test]
+    fn test_king_of_the_hill_perft() {
+        assert_perft(&KingOfTheHill, &[
+            (fen::STARTING_FEN, 4, 197281),
+            ("rnbq1bnr/ppp2ppp/3k4/3pp3/4P3/3K4/PPPP1PPP/RNBQ1BNR w - - 0 5", 3, 26546),
+            ("8/8/8/8/8/8/8/k5K1 w - - 0 1", 6, 18067),
+        ]);
+    }
+
+    #[test]
+    fn test_three_check_perft() {
+        assert_perft(&ThreeCheck, &[
+            (ThreeCheck.starting_fen(), 4, 197281),
+            ("r1bqkbnr/pppp1ppp/2n5/4p3/2B1P3/5Q2/PPPP1PPP/RNB1K1NR w KQkq - 1+3 2 3", 3, 49106),
+            ("r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 2+2 4 4", 3, 45695),
+        ]);
+    }
+
+    #[test]
+    fn test_racing_kings_perft() {
+        assert_perft(&RacingKings, &[
+            (RacingKings.starting_fen(), 3, 11264),
+            ("8/5k1K/8/8/8/8/8/8 w - - 0 1", 5, 349),
+            ("8/8/1k6/8/3K4/8/3R4/r1n5 w - - 0 1", 3, 3751),
+        ]);
+    }
+
+    #[test]
+    fn test_game_ends() {
+        let hill = KingOfTheHill.position_from_fen("8/8/8/3K4/8/8/8/k7 b - - 0 1").unwrap();
+        assert_eq!(KingOfTheHill.outcome(&hill), Some(GameResult::WhiteWin), "A king on d5 has reached the hill.");
+        assert_eq!(Standard.outcome(&hill), None);
+
+        let mut checks = ThreeCheck.position_from_fen("rnbqkbnr/ppp2ppp/8/3pp3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 1+3 0 3").unwrap();
+        assert_eq!(ThreeCheck.fen(&checks), "rnbqkbnr/ppp2ppp/8/3pp3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 1+3 0 3");
+        let check = ThreeCheck.legal_moves(&checks).into_iter().find(|mv| mv.to_string() == "f1b5").expect("Bb5+ should be legal.");
+        ThreeCheck.make_move(&mut checks, &check);
+        assert_eq!(checks.checks_given, [3, 0]);
+        assert_eq!(checks.hash, zobrist::hash_position(&checks), "Counting a check should keep the hash up to date.");
+        assert_eq!(ThreeCheck.outcome(&checks), Some(GameResult::WhiteWin), "The third check wins.");
+        assert!(ThreeCheck.position_from_fen("8/8/8/8/8/8/8/k6K w - - 4+3 0 1").is_err(), "There are only three checks to give.");
+        assert!(ThreeCheck.position_from_fen("8/8/8/8/8/8/8/k6K w - - 3+3 x 1").is_err(), "The FEN under the checks should be checked too.");
+
+        let race = RacingKings.position_from_fen("6K1/8/8/8/8/8/8/k7 b - - 0 1").unwrap();
+        assert_eq!(RacingKings.outcome(&race), Some(GameResult::WhiteWin), "Black is too far away to reach the eighth rank in time.");
+        let catching_up = RacingKings.position_from_fen("6K1/1k6/8/8/8/8/8/8 b - - 0 1").unwrap();
+        assert_eq!(RacingKings.outcome(&catching_up), None, "Black can still draw.");
+        let drawn = RacingKings.position_from_fen("1k4K1/8/8/8/8/8/8/8 w - - 0 1").unwrap();
+        assert_eq!(RacingKings.outcome(&drawn), Some(GameResult::Draw));
+
+        let start = RacingKings.position_from_fen(RacingKings.starting_fen()).unwrap();
+        let unchecked = RacingKings.unchecked_moves(&start).into_iter().filter(|mv| {
+            let mut child = start.clone();
+            moves::make_move(&mut child, mv);
+            position::is_in_check(&child, &Color::Black)
+        });
+        assert!(unchecked.count() > 0 && RacingKings.legal_moves(&start).iter().all(|mv| {
+            let mut child = start.clone();
+            moves::make_move(&mut child, mv);
+            !position::is_in_check(&child, &Color::Black)
+        }), "Giving check isn't allowed.");
+    }
+}
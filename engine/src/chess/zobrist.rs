@@ -0,0 +1,112 @@
+use super::attacks;
+use super::piece::Color;
+use super::position::{Position, NO_CASTLING};
+
+// Zobrist keys, generated at compile time from a fixed seed so hashes are stable between runs.
+
+const SEED: u64 = 0x4d61_6362_6574_6821;
+
+/// Indexed by piece id, then square.
+pub const PIECE_KEYS: [[u64; 64]; 12] = generate_piece_keys();
+/// Indexed like `Position::castling_rooks`.
+pub const CASTLING_KEYS: [u64; 4] = generate_keys::<4>(SEED ^ 0xc0ff_ee00);
+/// Indexed by the file of the en passant square.
+pub const EN_PASSANT_KEYS: [u64; 8] = generate_keys::<8>(SEED ^ 0xe9a5_5a17);
+pub const SIDE_KEY: u64 = generate_keys::<1>(SEED ^ 0x51de)[0];
+/// Indexed by color, then the checks given less one, for Three-check.
+const CHECK_KEYS: [u64; 6] = generate_keys::<6>(SEED ^ 0xc4ec_c4ec);
+
+/// One step of the SplitMix64 generator: the next state and the number it produced.
+pub const fn splitmix64(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    (state, z ^ (z >> 31))
+}
+
+const fn generate_keys<const N: usize>(seed: u64) -> [u64; N] {
+    let mut keys = [0u64; N];
+    let mut state = seed;
+
+    let mut index = 0;
+    while index < N {
+        let (next_state, key) = splitmix64(state);
+        state = next_state;
+        keys[index] = key;
+        index += 1;
+    }
+
+    keys
+}
+
+const fn generate_piece_keys() -> [[u64; 64]; 12] {
+    let mut keys = [[0u64; 64]; 12];
+
+    let mut piece_id = 0;
+    while piece_id < 12 {
+        keys[piece_id] = generate_keys::<64>(SEED.wrapping_add(piece_id as u64 * 0x1000));
+        piece_id += 1;
+    }
+
+    keys
+}
+
+/// Computes the hash of a position from scratch. `Position::hash` is kept up to date incrementally,
+/// so this is only needed after setting up a position by hand.
+pub fn hash_position(position: &Position) -> u64 {
+    let mut hash = 0;
+
+    for (piece_id, bitboard) in position.bitboards.iter().enumerate() {
+        let mut bitboard = *bitboard;
+        while bitboard != 0 {
+            hash ^= PIECE_KEYS[piece_id][attacks::pop_lsb(&mut bitboard) as usize];
+        }
+    }
+
+    hash ^ hash_state(position)
+}
+
+/// Computes the pawn-only hash from scratch, like `hash_position` does for `Position::hash`.
+pub fn hash_pawns(position: &Position) -> u64 {
+    let mut hash = 0;
+
+    for piece_id in [0, 6] {
+        let mut bitboard = position.bitboards[piece_id];
+        while bitboard != 0 {
+            hash ^= PIECE_KEYS[piece_id][attacks::pop_lsb(&mut bitboard) as usize];
+        }
+    }
+
+    hash
+}
+
+/// The part of the hash that isn't about piece placement: castling rights, en passant, side to move and checks given.
+pub fn hash_state(position: &Position) -> u64 {
+    let mut hash = 0;
+
+    for (index, file) in position.castling_rooks.iter().enumerate() {
+        if *file != NO_CASTLING {
+            hash ^= CASTLING_KEYS[index];
+        }
+    }
+
+    if position.en_passant_index >= 0 {
+        hash ^= EN_PASSANT_KEYS[(position.en_passant_index % 8) as usize];
+    }
+
+    if position.side_to_move == Color::Black {
+        hash ^= SIDE_KEY;
+    }
+
+    hash ^ check_key(Color::White, position.checks_given[0]) ^ check_key(Color::Black, position.checks_given[1])
+}
+
+/// The key for a side having given some checks. No checks has no key, so variants that don't count them hash the
+/// same as standard chess.
+pub fn check_key(color: Color, checks: i32) -> u64 {
+    if checks <= 0 {
+        return 0;
+    }
+    CHECK_KEYS[color as usize * 3 + (checks.min(3) - 1) as usize]
+}
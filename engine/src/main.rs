@@ -3,7 +3,14 @@ pub mod chess;
 use chess::*;
 
 fn main() {
-    let mut position = fen::position_from_fen("8/8/8/8/4N3/8/8/8 w KQkq - 0 1");
-    println!("{}", display::ascii(&position));
-    println!("Legal Moves: {}", display::list_moves(&moves::get_unchecked_moves(&position)));
-}
\ No newline at end of file
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.is_empty() {
+        uci::Uci::new().run();
+        return;
+    }
+
+    if let Err(error) = cli::run(&args) {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+}